@@ -0,0 +1,369 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::elements::lookup;
+use crate::error::{Mol2Error, Result};
+use crate::graph::rings;
+use crate::{IdInt, Mol2};
+use std::collections::{HashMap, HashSet};
+
+struct Bonds {
+    // Bonded atoms of every atom with the position of the bond
+    neighbours: HashMap<IdInt, Vec<(IdInt, usize)>>,
+    // Bond positions of pairs of atoms, lower id first
+    pairs: HashMap<(IdInt, IdInt), usize>,
+}
+
+impl Bonds {
+    fn new(mol2: &Mol2) -> Bonds {
+        let mut bonds = Bonds {
+            neighbours: HashMap::new(),
+            pairs: HashMap::new(),
+        };
+        for (index, bond) in mol2.bond.iter().enumerate() {
+            let (origin, target) = (bond.origin_atom_id, bond.target_atom_id);
+            bonds
+                .neighbours
+                .entry(origin)
+                .or_default()
+                .push((target, index));
+            bonds
+                .neighbours
+                .entry(target)
+                .or_default()
+                .push((origin, index));
+            bonds
+                .pairs
+                .entry((origin.min(target), origin.max(target)))
+                .or_insert(index);
+        }
+        bonds
+    }
+
+    fn of(&self, atom_id: IdInt) -> &[(IdInt, usize)] {
+        self.neighbours
+            .get(&atom_id)
+            .map(|neighbours| &neighbours[..])
+            .unwrap_or(&[])
+    }
+
+    fn ring_bonds(&self, ring: &[IdInt]) -> Vec<usize> {
+        // Positions of the bonds closing a ring, in ring order
+        (0..ring.len())
+            .filter_map(|index| {
+                let (first, second) = (ring[index], ring[(index + 1) % ring.len()]);
+                self.pairs
+                    .get(&(first.min(second), first.max(second)))
+                    .copied()
+            })
+            .collect()
+    }
+}
+
+fn symbols(mol2: &Mol2) -> HashMap<IdInt, &'static str> {
+    // Element symbols of atoms that have one
+    mol2.atom
+        .iter()
+        .filter_map(|atom| lookup(&atom.atom_type).map(|(symbol, _, _)| (atom.atom_id, symbol)))
+        .collect()
+}
+
+fn pi_electrons(
+    mol2: &Mol2,
+    bonds: &Bonds,
+    symbols: &HashMap<IdInt, &str>,
+    ring: &[IdInt],
+    aromatic_atoms: &HashSet<IdInt>,
+) -> Option<usize> {
+    // Number of pi electrons a ring contributes to the Hückel rule, None if
+    // an atom of the ring cannot be part of an aromatic ring
+    let members: HashSet<IdInt> = ring.iter().copied().collect();
+    let mut electrons = 0;
+    for atom_id in ring {
+        let atom_bonds = bonds.of(*atom_id);
+        let bond_type = |index: usize| mol2.bond[index].bond_type.as_str();
+        if atom_bonds.iter().any(|(_, index)| bond_type(*index) == "3") {
+            return None;
+        }
+        let in_ring = |neighbour: &IdInt| members.contains(neighbour);
+        let double = atom_bonds
+            .iter()
+            .find(|(neighbour, index)| bond_type(*index) == "2" && !in_ring(neighbour));
+        electrons += if atom_bonds
+            .iter()
+            .any(|(neighbour, index)| in_ring(neighbour) && matches!(bond_type(*index), "2" | "ar"))
+        {
+            1
+        } else if let Some((neighbour, _)) = double {
+            // Double bonds out of the ring count when they go into another
+            // aromatic ring, to heteroatoms they take the electrons away
+            match symbols.get(neighbour) {
+                _ if aromatic_atoms.contains(neighbour) => 1,
+                Some(&"O") | Some(&"S") | Some(&"N") => 0,
+                _ => return None,
+            }
+        } else {
+            // Lone pairs of heteroatoms with single bonds only
+            match symbols.get(atom_id) {
+                Some(&"N") | Some(&"O") | Some(&"S") | Some(&"P") | Some(&"Se") => 2,
+                _ => return None,
+            }
+        };
+    }
+    Some(electrons)
+}
+
+fn aromatic_rings(mol2: &Mol2, bonds: &Bonds, rings: &[Vec<IdInt>]) -> Vec<bool> {
+    // Rings that are aromatic by the Hückel rule, or have aromatic bonds
+    // only. Rings fused to aromatic rings are checked again with double
+    // bonds into those rings counted until nothing changes.
+    let symbols = symbols(mol2);
+    let mut aromatic = vec![false; rings.len()];
+    let mut aromatic_atoms: HashSet<IdInt> = HashSet::new();
+    loop {
+        let mut changed = false;
+        for (index, ring) in rings.iter().enumerate() {
+            if aromatic[index] {
+                continue;
+            }
+            let ring_bonds = bonds.ring_bonds(ring);
+            let all_aromatic = ring_bonds.len() == ring.len()
+                && ring_bonds
+                    .iter()
+                    .all(|bond| mol2.bond[*bond].bond_type == "ar");
+            let huckel = pi_electrons(mol2, bonds, &symbols, ring, &aromatic_atoms)
+                .is_some_and(|electrons| electrons % 4 == 2);
+            if all_aromatic || huckel {
+                aromatic[index] = true;
+                aromatic_atoms.extend(ring.iter().copied());
+                changed = true;
+            }
+        }
+        if !changed {
+            return aromatic;
+        }
+    }
+}
+
+pub fn aromatic_atoms(mol2: &Mol2) -> HashSet<IdInt> {
+    // Atoms of aromatic rings, see aromatic_rings
+    let bonds = Bonds::new(mol2);
+    let rings = rings(mol2);
+    let aromatic = aromatic_rings(mol2, &bonds, &rings);
+    rings
+        .into_iter()
+        .zip(aromatic)
+        .filter(|(_, aromatic)| *aromatic)
+        .flat_map(|(ring, _)| ring)
+        .collect()
+}
+
+fn to_aromatic(mol2: &mut Mol2) -> usize {
+    // Set bonds of aromatic rings to "ar", returns the number of bonds changed
+    let bonds = Bonds::new(mol2);
+    let rings = rings(mol2);
+    let aromatic = aromatic_rings(mol2, &bonds, &rings);
+    let mut changed = 0;
+    for (ring, _) in rings.iter().zip(aromatic).filter(|(_, aromatic)| *aromatic) {
+        for index in bonds.ring_bonds(ring) {
+            let bond = &mut mol2.bond[index];
+            if matches!(bond.bond_type.as_str(), "1" | "2") {
+                bond.bond_type = "ar".to_owned();
+                changed += 1;
+            }
+        }
+    }
+    changed
+}
+
+fn assign_double_bonds(
+    atom: usize,
+    required: &[IdInt],
+    candidates: &HashMap<IdInt, Vec<(IdInt, usize)>>,
+    matched: &mut HashMap<IdInt, usize>,
+) -> bool {
+    // Backtracking search for a double bond to every required atom, each
+    // atom taking part in at most one
+    // Input:
+    //     atom: position of the next required atom to look at
+    //     required: atoms that need a double bond, most constrained first
+    //     candidates: aromatic bonds and their partners that may be doubled
+    //     matched: double bond chosen for every atom so far
+    let atom_id = match required.get(atom) {
+        Some(atom_id) => *atom_id,
+        None => return true,
+    };
+    if matched.contains_key(&atom_id) {
+        return assign_double_bonds(atom + 1, required, candidates, matched);
+    }
+    for (neighbour, bond) in candidates.get(&atom_id).into_iter().flatten() {
+        if matched.contains_key(neighbour) {
+            continue;
+        }
+        matched.insert(atom_id, *bond);
+        matched.insert(*neighbour, *bond);
+        if assign_double_bonds(atom + 1, required, candidates, matched) {
+            return true;
+        }
+        matched.remove(&atom_id);
+        matched.remove(neighbour);
+    }
+    false
+}
+
+fn to_kekule(mol2: &mut Mol2) -> Result<usize> {
+    // Replace aromatic bonds of aromatic rings by alternating single and
+    // double bonds, returns the number of bonds changed. Carbons need a
+    // double bond, nitrogens with at most two bonds may take one.
+    let bonds = Bonds::new(mol2);
+    let symbols = symbols(mol2);
+    let rings = rings(mol2);
+    let aromatic = aromatic_rings(mol2, &bonds, &rings);
+    let mut ring_bonds: Vec<usize> = rings
+        .iter()
+        .zip(aromatic)
+        .filter(|(_, aromatic)| *aromatic)
+        .flat_map(|(ring, _)| bonds.ring_bonds(ring))
+        .filter(|bond| mol2.bond[*bond].bond_type == "ar")
+        .collect();
+    ring_bonds.sort_unstable();
+    ring_bonds.dedup();
+    let has_double = |atom_id: &IdInt| {
+        bonds
+            .of(*atom_id)
+            .iter()
+            .any(|(_, bond)| mol2.bond[*bond].bond_type == "2")
+    };
+    let may_double = |atom_id: &IdInt| {
+        !has_double(atom_id)
+            && match symbols.get(atom_id) {
+                Some(&"C") => true,
+                Some(&"N") => bonds.of(*atom_id).len() <= 2,
+                _ => false,
+            }
+    };
+    let mut candidates: HashMap<IdInt, Vec<(IdInt, usize)>> = HashMap::new();
+    for bond in &ring_bonds {
+        let (origin, target) = (
+            mol2.bond[*bond].origin_atom_id,
+            mol2.bond[*bond].target_atom_id,
+        );
+        if may_double(&origin) && may_double(&target) {
+            candidates.entry(origin).or_default().push((target, *bond));
+            candidates.entry(target).or_default().push((origin, *bond));
+        }
+    }
+    // Aromatic atoms of every system of bonded aromatic atoms
+    let mut systems: Vec<Vec<IdInt>> = Vec::new();
+    let mut system_of: HashMap<IdInt, usize> = HashMap::new();
+    for bond in &ring_bonds {
+        let (origin, target) = (
+            mol2.bond[*bond].origin_atom_id,
+            mol2.bond[*bond].target_atom_id,
+        );
+        let system = match (system_of.get(&origin), system_of.get(&target)) {
+            (Some(first), Some(second)) if first != second => {
+                let (kept, merged) = (*first.min(second), *first.max(second));
+                for atom_id in std::mem::take(&mut systems[merged]) {
+                    system_of.insert(atom_id, kept);
+                    systems[kept].push(atom_id);
+                }
+                kept
+            }
+            (Some(system), _) | (_, Some(system)) => *system,
+            (None, None) => {
+                systems.push(Vec::new());
+                systems.len() - 1
+            }
+        };
+        for atom_id in [origin, target] {
+            if system_of.insert(atom_id, system).is_none() {
+                systems[system].push(atom_id);
+            }
+        }
+    }
+
+    let mut matched: HashMap<IdInt, usize> = HashMap::new();
+    let mut unmatched: Vec<IdInt> = Vec::new();
+    for system in systems {
+        let mut required: Vec<IdInt> = system
+            .into_iter()
+            .filter(|atom_id| symbols.get(atom_id) == Some(&"C") && !has_double(atom_id))
+            .collect();
+        required.sort_by_key(|atom_id| {
+            let choices = candidates.get(atom_id).map_or(0, |bonds| bonds.len());
+            (choices, *atom_id)
+        });
+        if !assign_double_bonds(0, &required, &candidates, &mut matched) {
+            unmatched.extend(required);
+        }
+    }
+    if !unmatched.is_empty() {
+        unmatched.sort_unstable();
+        return Err(Mol2Error::InvalidArgument(format!(
+            "cannot assign alternating bonds to the aromatic atoms {}",
+            unmatched
+                .iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        )));
+    }
+    let doubles: HashSet<usize> = matched.into_values().collect();
+    for bond in &ring_bonds {
+        mol2.bond[*bond].bond_type = match doubles.contains(bond) {
+            true => "2",
+            false => "1",
+        }
+        .to_owned();
+    }
+    Ok(ring_bonds.len())
+}
+
+// Representations of bonds in aromatic rings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BondConvention {
+    // Bonds of aromatic rings are "ar"
+    Aromatic,
+    // "ar" bonds in rings are alternating "1" and "2"
+    Kekule,
+}
+
+impl BondConvention {
+    pub fn from_name(name: &str) -> Result<BondConvention> {
+        // Translate a user given bond type mode
+        match name {
+            "aromatic" => Ok(BondConvention::Aromatic),
+            "kekule" => Ok(BondConvention::Kekule),
+            _ => Err(Mol2Error::InvalidArgument(format!(
+                "unknown bond type mode {}, expected aromatic or kekule",
+                name
+            ))),
+        }
+    }
+}
+
+pub fn normalize_bond_types(mol2: &mut Mol2, mode: BondConvention) -> Result<usize> {
+    // Convert bonds of aromatic rings between the Kekulé and the aromatic
+    // representation, returns the number of bonds changed. Aromatic bonds
+    // outside of aromatic rings, e.g. in carboxylates, are left alone.
+    // Input:
+    //     mol2: structure to modify
+    //     mode: representation to convert to
+    match mode {
+        BondConvention::Aromatic => Ok(to_aromatic(mol2)),
+        BondConvention::Kekule => to_kekule(mol2),
+    }
+}
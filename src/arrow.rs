@@ -0,0 +1,141 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::error::Result;
+use crate::Mol2;
+use arrow_array::builder::{
+    Float32Builder, Float64Builder, StringBuilder, UInt16Builder, UInt32Builder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_ipc::writer::StreamWriter;
+use std::sync::Arc;
+
+// Tables of a list of structures, rows of atoms and bonds refer to
+// the position of their structure in the list in the molecule column
+pub struct ArrowTables {
+    pub molecules: RecordBatch,
+    pub atoms: RecordBatch,
+    pub bonds: RecordBatch,
+}
+
+pub fn to_arrow(mol2_list: &[Mol2]) -> Result<ArrowTables> {
+    // Collect structures into molecule, atom and bond tables
+    // Input:
+    //     mol2_list: structures to convert
+    let mut molecule_index = UInt32Builder::new();
+    let mut mol_name = StringBuilder::new();
+    let mut desc = StringBuilder::new();
+    let mut mol_type = StringBuilder::new();
+    let mut charge_type = StringBuilder::new();
+    let mut mol_comment = StringBuilder::new();
+
+    let mut atom_molecule = UInt32Builder::new();
+    let mut atom_id = UInt16Builder::new();
+    let mut atom_name = StringBuilder::new();
+    let mut x = Float64Builder::new();
+    let mut y = Float64Builder::new();
+    let mut z = Float64Builder::new();
+    let mut atom_type = StringBuilder::new();
+    let mut subst_id = UInt16Builder::new();
+    let mut subst_name = StringBuilder::new();
+    let mut charge = Float32Builder::new();
+
+    let mut bond_molecule = UInt32Builder::new();
+    let mut bond_id = UInt16Builder::new();
+    let mut origin_atom_id = UInt16Builder::new();
+    let mut target_atom_id = UInt16Builder::new();
+    let mut bond_type = StringBuilder::new();
+
+    for (index, mol2) in mol2_list.iter().enumerate() {
+        let index = index as u32;
+        let molecule = mol2.molecule.as_ref();
+        molecule_index.append_value(index);
+        mol_name.append_option(molecule.map(|molecule| &molecule.mol_name));
+        desc.append_option(mol2.desc.as_ref());
+        mol_type.append_option(molecule.and_then(|molecule| molecule.mol_type.as_ref()));
+        charge_type.append_option(molecule.and_then(|molecule| molecule.charge_type.as_ref()));
+        mol_comment.append_option(molecule.and_then(|molecule| molecule.mol_comment.as_ref()));
+
+        for atom in &mol2.atom {
+            atom_molecule.append_value(index);
+            atom_id.append_value(atom.atom_id);
+            atom_name.append_value(&atom.atom_name);
+            x.append_value(atom.x);
+            y.append_value(atom.y);
+            z.append_value(atom.z);
+            atom_type.append_value(&atom.atom_type);
+            subst_id.append_option(atom.subst_id);
+            subst_name.append_option(atom.subst_name.as_ref());
+            charge.append_option(atom.charge);
+        }
+        for bond in &mol2.bond {
+            bond_molecule.append_value(index);
+            bond_id.append_value(bond.bond_id);
+            origin_atom_id.append_value(bond.origin_atom_id);
+            target_atom_id.append_value(bond.target_atom_id);
+            bond_type.append_value(&bond.bond_type);
+        }
+    }
+
+    let molecules = RecordBatch::try_from_iter([
+        ("molecule", Arc::new(molecule_index.finish()) as ArrayRef),
+        ("mol_name", Arc::new(mol_name.finish()) as ArrayRef),
+        ("desc", Arc::new(desc.finish()) as ArrayRef),
+        ("mol_type", Arc::new(mol_type.finish()) as ArrayRef),
+        ("charge_type", Arc::new(charge_type.finish()) as ArrayRef),
+        ("mol_comment", Arc::new(mol_comment.finish()) as ArrayRef),
+    ])?;
+    let atoms = RecordBatch::try_from_iter([
+        ("molecule", Arc::new(atom_molecule.finish()) as ArrayRef),
+        ("atom_id", Arc::new(atom_id.finish()) as ArrayRef),
+        ("atom_name", Arc::new(atom_name.finish()) as ArrayRef),
+        ("x", Arc::new(x.finish()) as ArrayRef),
+        ("y", Arc::new(y.finish()) as ArrayRef),
+        ("z", Arc::new(z.finish()) as ArrayRef),
+        ("atom_type", Arc::new(atom_type.finish()) as ArrayRef),
+        ("subst_id", Arc::new(subst_id.finish()) as ArrayRef),
+        ("subst_name", Arc::new(subst_name.finish()) as ArrayRef),
+        ("charge", Arc::new(charge.finish()) as ArrayRef),
+    ])?;
+    let bonds = RecordBatch::try_from_iter([
+        ("molecule", Arc::new(bond_molecule.finish()) as ArrayRef),
+        ("bond_id", Arc::new(bond_id.finish()) as ArrayRef),
+        (
+            "origin_atom_id",
+            Arc::new(origin_atom_id.finish()) as ArrayRef,
+        ),
+        (
+            "target_atom_id",
+            Arc::new(target_atom_id.finish()) as ArrayRef,
+        ),
+        ("bond_type", Arc::new(bond_type.finish()) as ArrayRef),
+    ])?;
+
+    Ok(ArrowTables {
+        molecules,
+        atoms,
+        bonds,
+    })
+}
+
+pub fn to_ipc_stream(batch: &RecordBatch) -> Result<Vec<u8>> {
+    // Serialize a table in the Arrow IPC streaming format
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut writer = StreamWriter::try_new(&mut buffer, &batch.schema())?;
+    writer.write(batch)?;
+    writer.finish()?;
+    drop(writer);
+    Ok(buffer)
+}
@@ -52,11 +52,77 @@ fn main() {
                 .long("append")
                 .help("Append to mol2 files when writing rather than truncate"),
         )
+        .arg(
+            Arg::new("aligned")
+                .long("aligned")
+                .help("Write mol2 records in fixed-width columns"),
+        )
+        .arg(
+            Arg::new("coord_precision")
+                .long("coord-precision")
+                .value_name("DECIMALS")
+                .default_value("4")
+                .help("Number of decimals of atom coordinates in written mol2 files")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("charge_precision")
+                .long("charge-precision")
+                .value_name("DECIMALS")
+                .default_value("4")
+                .help("Number of decimals of atom charges in written mol2 files")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("keep_source")
+                .long("keep-source")
+                .help("Store the original text of structures to write them back unchanged"),
+        )
         .arg(
             Arg::new("no_shm")
                 .long("no-shm")
                 .help("Do not try using shm device when writing to databases"),
         )
+        .arg(
+            Arg::new("tmp_dir")
+                .long("tmp-dir")
+                .value_name("DIR")
+                .help("Directory for temporary copies of databases instead of shm, e.g. local NVMe scratch. Defaults to SERDE_MOL2_TMPDIR if set")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("read_only")
+                .long("read-only")
+                .help("Open the database read-only where it is, e.g. on a read-only shared filesystem"),
+        )
+        .arg(
+            Arg::new("wal")
+                .long("wal")
+                .help("Switch the database to the write-ahead log journal mode"),
+        )
+        .arg(
+            Arg::new("synchronous")
+                .long("synchronous")
+                .value_name("MODE")
+                .possible_values(["off", "normal", "full", "extra"])
+                .help("How carefully SQLite syncs the database to disk")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("cache_size")
+                .long("cache-size")
+                .value_name("PAGES")
+                .help("SQLite page cache size in pages, or in KiB if negative")
+                .allow_hyphen_values(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("mmap_size")
+                .long("mmap-size")
+                .value_name("BYTES")
+                .help("Memory map up to this many bytes of the database")
+                .takes_value(true),
+        )
         .arg(
             Arg::new("desc")
                 .long("desc")
@@ -80,6 +146,15 @@ fn main() {
                 .help("Level of compression for BLOB data, 0 means no compression")
                 .takes_value(true),
         )
+        .arg(
+            Arg::new("codec")
+                .long("codec")
+                .value_name("CODEC")
+                .possible_values(["none", "zstd", "lz4", "gzip"])
+                .default_value("zstd")
+                .help("Codec compressing BLOB data")
+                .takes_value(true),
+        )
         .arg(
             Arg::new("limit")
                 .long("limit")
@@ -93,7 +168,91 @@ fn main() {
                 .long("offset")
                 .value_name("OFFSET")
                 .default_value("0")
-                .help("Offset when limiting the number of structures retrieved from the database. Zero means no offset.")
+                .help("Skip this many matching structures when reading from the database. Zero means no offset.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("filter_mode")
+                .long("filter-mode")
+                .value_name("MODE")
+                .possible_values(["substring", "glob", "regex"])
+                .default_value("substring")
+                .help("How --desc and --comment filter structures read from the database")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("order_by")
+                .long("order-by")
+                .value_name("ORDER")
+                .default_value("id")
+                .help("Order of structures read from the database, by id, name, num_atoms, desc or a property column. Ties are kept in insertion order.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("min_atoms")
+                .long("min-atoms")
+                .value_name("ATOMS")
+                .default_value("0")
+                .help("Minimum number of atoms of structures read from the database.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("max_atoms")
+                .long("max-atoms")
+                .value_name("ATOMS")
+                .default_value("0")
+                .help("Maximum number of atoms of structures read from the database. Zero means no maximum.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("min_bonds")
+                .long("min-bonds")
+                .value_name("BONDS")
+                .default_value("0")
+                .help("Minimum number of bonds of structures read from the database.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("max_bonds")
+                .long("max-bonds")
+                .value_name("BONDS")
+                .default_value("0")
+                .help("Maximum number of bonds of structures read from the database. Zero means no maximum.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("where_extras")
+                .long("where-extras")
+                .value_name("CONDITION")
+                .help("Only read structures with matching extras from the database, e.g. \"$.vendor == 'enamine'\"")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("tag")
+                .long("tag")
+                .value_name("TAG")
+                .help("Only read structures with this tag from the database")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("source_file")
+                .long("source-file")
+                .value_name("PATH")
+                .help("Only read structures from a file with the path containing this from the database")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("inserted_after")
+                .long("inserted-after")
+                .value_name("TIME")
+                .help("Only read structures inserted at or after this UTC time from the database, e.g. \"2024-01-01\"")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("inserted_before")
+                .long("inserted-before")
+                .value_name("TIME")
+                .help("Only read structures inserted before this UTC time from the database, e.g. \"2024-01-01 12:00\"")
                 .takes_value(true),
         )
         .arg(
@@ -101,6 +260,50 @@ fn main() {
                 "Add filename to the desc field when adding a batch of files to the database",
             ),
         )
+        .arg(
+            Arg::new("threads")
+                .short('t')
+                .long("threads")
+                .value_name("THREADS")
+                .default_value("1")
+                .help("Number of threads parsing input files or decoding structures read from the database. A batch of files is parsed one file per thread, a single file is split into chunks. Zero means all cores.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("parse_mode")
+                .long("parse-mode")
+                .value_name("MODE")
+                .possible_values(["strict", "lenient"])
+                .default_value("strict")
+                .help("Fail on malformed lines (strict) or skip structures containing them (lenient)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_name("FORMAT")
+                .possible_values(["mol2", "xyz"])
+                .default_value("mol2")
+                .help("Format of input and output files")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("merge")
+                .long("merge")
+                .value_name("SQLITE_FILE")
+                .help("Databases to copy into the --sqlite database, e.g. ones written by separate jobs")
+                .takes_value(true)
+                .multiple_values(true),
+        )
+        .arg(
+            Arg::new("on_conflict")
+                .long("on-conflict")
+                .value_name("MODE")
+                .possible_values(["append", "skip_duplicates"])
+                .default_value("append")
+                .help("Copy every structure when merging (append) or leave out ones already in the database (skip_duplicates)")
+                .takes_value(true),
+        )
         .arg(
             Arg::new("list_desc")
                 .long("list-desc")
@@ -108,14 +311,77 @@ fn main() {
         )
         .get_matches();
 
+    // Connection settings for every database opened below
+    serde_mol2::db_pragmas(
+        args.is_present("wal"),
+        args.value_of("synchronous").unwrap_or(""),
+        args.value_of("cache_size")
+            .map(|size| size.parse::<i64>().expect("Failed to parse --cache-size")),
+        args.value_of("mmap_size")
+            .map(|size| size.parse::<i64>().expect("Failed to parse --mmap-size")),
+    )
+    .expect("Invalid database settings");
+    if let Some(tmp_dir) = args.value_of("tmp_dir") {
+        serde_mol2::db_tmp_dir(tmp_dir).expect("Invalid temporary directory");
+    }
+
     // different variants I guess... might be a long tree of if's. Hopefully later will make it nicer
 
     // simple reading input files into the database
     if args.is_present("input_file") && args.is_present("sqlite") {
         let input_files = args.values_of("input_file");
         let input_files: Vec<&str> = input_files.expect("No input files after all").collect();
-        if input_files.len() > 1 {
-            serde_mol2::read_file_to_db_batch(
+        let codec = serde_mol2::Codec::from_name(args.value_of("codec").expect("Missing codec..."))
+            .expect("Invalid codec");
+        let options = serde_mol2::ReadOptions {
+            desc: args.value_of("desc").unwrap_or("").to_owned(),
+            comment: args.value_of("comment").unwrap_or("").to_owned(),
+            threads: args
+                .value_of("threads")
+                .expect("Missing number of threads...")
+                .parse::<usize>()
+                .expect("Failed to parse --threads"),
+            mode: serde_mol2::ParseMode::from_name(
+                args.value_of("parse_mode").expect("Missing parse mode..."),
+            )
+            .expect("Invalid parse mode"),
+            keep_source: args.is_present("keep_source"),
+            ..Default::default()
+        };
+        if args.value_of("format") == Some("xyz") {
+            for input_file in input_files {
+                let desc = args.value_of("desc").unwrap_or("");
+                // Same description as for a batch of mol2 files
+                let filename = std::path::Path::new(input_file)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| input_file.to_owned());
+                let desc = match args.is_present("filename_desc") {
+                    true if desc.is_empty() => filename,
+                    true => format!("{}; {}", desc, filename),
+                    false => desc.to_owned(),
+                };
+                let mol2_list =
+                    serde_mol2::read_xyz(input_file, &desc, args.value_of("comment").unwrap_or(""))
+                        .expect("Failed to read the input file");
+                serde_mol2::db_insert(
+                    mol2_list,
+                    args.value_of("sqlite").expect(
+                        "There seem to be input files given but no sqlite file to operate with",
+                    ),
+                    args.value_of("compression")
+                        .expect("Missing compression level...")
+                        .parse::<i32>()
+                        .expect("Failed to parse compression level"),
+                    !args.is_present("no_shm"),
+                    false,
+                    input_file,
+                    codec,
+                )
+                .expect("Failed to insert structures into the database");
+            }
+        } else if input_files.len() > 1 {
+            let skipped = serde_mol2::read_file_to_db_batch(
                 input_files,
                 args.value_of("sqlite").expect(
                     "There seem to be input files given but no sqlite file to operate with",
@@ -125,12 +391,16 @@ fn main() {
                     .parse::<i32>()
                     .expect("Failed to parse compression level"),
                 !args.is_present("no_shm"),
-                args.value_of("desc").unwrap_or(""),
                 args.is_present("filename_desc"),
-                args.value_of("comment").unwrap_or(""),
-            );
+                &options,
+                codec,
+            )
+            .expect("Failed to read the input files into the database");
+            for entry in skipped {
+                eprintln!("Skipped {}", entry);
+            }
         } else {
-            serde_mol2::read_file_to_db(
+            let skipped = serde_mol2::read_file_to_db(
                 input_files[0],
                 args.value_of("sqlite").expect(
                     "There seem to be input files given but no sqlite file to operate with",
@@ -140,34 +410,125 @@ fn main() {
                     .parse::<i32>()
                     .expect("Failed to parse compression level"),
                 !args.is_present("no_shm"),
-                args.value_of("desc").unwrap_or(""),
-                args.value_of("comment").unwrap_or(""),
-            );
+                &options,
+                codec,
+            )
+            .expect("Failed to read the input file into the database");
+            for entry in skipped {
+                eprintln!("Skipped {}", entry);
+            }
+        }
+    }
+    // merging other databases into the database, before anything is read from it
+    if args.is_present("merge") && args.is_present("sqlite") {
+        for src in args
+            .values_of("merge")
+            .expect("No databases to merge after all")
+        {
+            serde_mol2::db_merge(
+                src,
+                args.value_of("sqlite")
+                    .expect("Missing sqlite db filename after all..."),
+                serde_mol2::OnConflict::from_name(
+                    args.value_of("on_conflict")
+                        .expect("Missing conflict mode..."),
+                )
+                .expect("Invalid conflict mode"),
+                !args.is_present("no_shm"),
+            )
+            .expect("Failed to merge the databases");
         }
     }
     // simple reading database into mol2 file
     if args.is_present("output_file") && args.is_present("sqlite") {
-        let mol2_list = serde_mol2::read_db_all(
-            args.value_of("sqlite")
-                .expect("Missing sqlite db filename after all..."),
-            !args.is_present("no_shm"),
-            args.value_of("desc").unwrap_or(""),
-            args.value_of("comment").unwrap_or(""),
-            args.value_of("limit")
+        let query = serde_mol2::DbQuery {
+            desc: args.value_of("desc").unwrap_or("").to_owned(),
+            comment: args.value_of("comment").unwrap_or("").to_owned(),
+            filter_mode: serde_mol2::FilterMode::from_name(
+                args.value_of("filter_mode")
+                    .expect("Missing filter mode..."),
+            )
+            .expect("Invalid filter mode"),
+            order_by: serde_mol2::OrderBy::from_name(
+                args.value_of("order_by").expect("Missing order..."),
+            ),
+            limit: args
+                .value_of("limit")
                 .expect("Missing limit...")
                 .parse::<usize>()
                 .expect("Failed to parse --limit"),
-            args.value_of("offset")
+            offset: args
+                .value_of("offset")
                 .expect("Missing offset...")
                 .parse::<usize>()
                 .expect("Failed to parse --offset"),
-        );
-        serde_mol2::write_mol2(
-            mol2_list,
-            args.value_of("output_file")
-                .expect("Missing output file argument after all"),
-            args.is_present("append"),
-        );
+            min_atoms: args
+                .value_of("min_atoms")
+                .expect("Missing min atoms...")
+                .parse::<usize>()
+                .expect("Failed to parse --min-atoms"),
+            max_atoms: args
+                .value_of("max_atoms")
+                .expect("Missing max atoms...")
+                .parse::<usize>()
+                .expect("Failed to parse --max-atoms"),
+            min_bonds: args
+                .value_of("min_bonds")
+                .expect("Missing min bonds...")
+                .parse::<usize>()
+                .expect("Failed to parse --min-bonds"),
+            max_bonds: args
+                .value_of("max_bonds")
+                .expect("Missing max bonds...")
+                .parse::<usize>()
+                .expect("Failed to parse --max-bonds"),
+            where_extras: args.value_of("where_extras").unwrap_or("").to_owned(),
+            tag: args.value_of("tag").unwrap_or("").to_owned(),
+            source_file: args.value_of("source_file").unwrap_or("").to_owned(),
+            inserted_after: args.value_of("inserted_after").unwrap_or("").to_owned(),
+            inserted_before: args.value_of("inserted_before").unwrap_or("").to_owned(),
+            ..Default::default()
+        };
+        let mol2_list = serde_mol2::read_db_all(
+            args.value_of("sqlite")
+                .expect("Missing sqlite db filename after all..."),
+            !args.is_present("no_shm"),
+            &query,
+            args.value_of("threads")
+                .expect("Missing number of threads...")
+                .parse::<usize>()
+                .expect("Failed to parse --threads"),
+            args.is_present("read_only"),
+        )
+        .expect("Failed to read structures from the database");
+        if args.value_of("format") == Some("xyz") {
+            serde_mol2::write_xyz(
+                mol2_list,
+                args.value_of("output_file")
+                    .expect("Missing output file argument after all"),
+                args.is_present("append"),
+                None,
+            )
+            .expect("Failed to write the output file");
+        } else {
+            serde_mol2::write_mol2(
+                mol2_list,
+                args.value_of("output_file")
+                    .expect("Missing output file argument after all"),
+                args.is_present("append"),
+                args.is_present("aligned"),
+                args.value_of("coord_precision")
+                    .expect("Missing coordinate precision...")
+                    .parse::<usize>()
+                    .expect("Failed to parse --coord-precision"),
+                args.value_of("charge_precision")
+                    .expect("Missing charge precision...")
+                    .parse::<usize>()
+                    .expect("Failed to parse --charge-precision"),
+                None,
+            )
+            .expect("Failed to write the output file");
+        }
     }
 
     // At the end list available desc fields if requested
@@ -176,7 +537,9 @@ fn main() {
             args.value_of("sqlite")
                 .expect("Missing sqlite db filename after all..."),
             !args.is_present("no_shm"),
-        );
+            args.is_present("read_only"),
+        )
+        .expect("Failed to list descriptions in the database");
         for desc in desc_list {
             println!("{}", desc);
         }
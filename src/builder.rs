@@ -0,0 +1,236 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::error::{Mol2Error, Result};
+use crate::{Atom, Bond, ChargeFloat, CoordFloat, IdInt, Mol2, Molecule, Substructure};
+use std::collections::HashSet;
+
+// Fluent construction of structures from rust, e.g.
+//     Mol2Builder::new()
+//         .name("LIG")
+//         .atom(1, "C1", "C.3", [0.0, 0.0, 0.0])
+//         .atom(2, "O1", "O.3", [1.4, 0.0, 0.0])
+//         .with_charge(-0.4)
+//         .bond(1, 2, "1")
+//         .build()?
+// Counts in the MOLECULE section are filled in by build.
+pub struct Mol2Builder {
+    mol2: Mol2,
+    molecule: Molecule,
+    // Mistakes that can only be reported once build is called
+    errors: Vec<String>,
+}
+
+impl Default for Mol2Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mol2Builder {
+    pub fn new() -> Mol2Builder {
+        let mut molecule = Molecule::new();
+        molecule.mol_type = Some("SMALL".to_owned());
+        Mol2Builder {
+            mol2: Mol2::new(""),
+            molecule,
+            errors: Vec::new(),
+        }
+    }
+    pub fn name(mut self, name: &str) -> Mol2Builder {
+        self.molecule.mol_name = name.to_owned();
+        self
+    }
+    pub fn mol_type(mut self, mol_type: &str) -> Mol2Builder {
+        // Molecule type, SMALL unless given
+        self.molecule.mol_type = Some(mol_type.to_owned());
+        self
+    }
+    pub fn charge_type(mut self, charge_type: &str) -> Mol2Builder {
+        // Charge type, USER_CHARGES if any atom has a charge and NO_CHARGES otherwise unless given
+        self.molecule.charge_type = Some(charge_type.to_owned());
+        self
+    }
+    pub fn comment(mut self, comment: &str) -> Mol2Builder {
+        self.molecule.mol_comment = Some(comment.to_owned());
+        self
+    }
+    pub fn desc(mut self, desc: &str) -> Mol2Builder {
+        self.mol2.desc = Some(desc.to_owned());
+        self
+    }
+    pub fn atom(
+        mut self,
+        atom_id: IdInt,
+        atom_name: &str,
+        atom_type: &str,
+        coords: [CoordFloat; 3],
+    ) -> Mol2Builder {
+        // Add an atom without a charge or substructure, see with_charge and in_substructure
+        let [x, y, z] = coords;
+        self.mol2.atom.push(Atom {
+            atom_id,
+            atom_name: atom_name.to_owned(),
+            x,
+            y,
+            z,
+            atom_type: atom_type.to_owned(),
+            subst_id: None,
+            subst_name: None,
+            charge: None,
+            status_bit: None,
+        });
+        self
+    }
+    pub fn with_charge(mut self, charge: ChargeFloat) -> Mol2Builder {
+        // Set the charge of the atom added last. Without any substructures
+        // charged atoms are put into substructure 1 named UNL1 by build.
+        match self.mol2.atom.last_mut() {
+            Some(atom) => atom.charge = Some(charge),
+            None => self
+                .errors
+                .push("with_charge called before adding any atom".to_owned()),
+        }
+        self
+    }
+    pub fn in_substructure(mut self, subst_id: IdInt, subst_name: &str) -> Mol2Builder {
+        // Put the atom added last into a substructure
+        match self.mol2.atom.last_mut() {
+            Some(atom) => {
+                atom.subst_id = Some(subst_id);
+                atom.subst_name = Some(subst_name.to_owned());
+            }
+            None => self
+                .errors
+                .push("in_substructure called before adding any atom".to_owned()),
+        }
+        self
+    }
+    pub fn bond(
+        mut self,
+        origin_atom_id: IdInt,
+        target_atom_id: IdInt,
+        bond_type: &str,
+    ) -> Mol2Builder {
+        // Add a bond, bonds are numbered in the order they are added
+        let bond_id = self.mol2.bond.len() + 1;
+        if bond_id > IdInt::MAX as usize {
+            self.errors.push(format!("more than {} bonds", IdInt::MAX));
+            return self;
+        }
+        self.mol2.bond.push(Bond {
+            bond_id: bond_id as IdInt,
+            origin_atom_id,
+            target_atom_id,
+            bond_type: bond_type.to_owned(),
+            status_bit: None,
+        });
+        self
+    }
+    pub fn substructure(
+        mut self,
+        subst_id: IdInt,
+        subst_name: &str,
+        root_atom: IdInt,
+        subst_type: &str,
+    ) -> Mol2Builder {
+        // Add a substructure, an empty type is left out
+        self.mol2.substructure.push(Substructure {
+            subst_id,
+            subst_name: subst_name.to_owned(),
+            root_atom,
+            subst_type: match subst_type.is_empty() {
+                true => None,
+                false => Some(subst_type.to_owned()),
+            },
+            dict_type: None,
+            chain: None,
+            sub_type: None,
+            inter_bonds: None,
+            status: None,
+            comment: None,
+        });
+        self
+    }
+    pub fn build(self) -> Result<Mol2> {
+        // Check ids and references and fill in the counts. All problems
+        // found are reported together.
+        let mut errors = self.errors;
+        let mut mol2 = self.mol2;
+        let mut molecule = self.molecule;
+
+        let mut atom_ids: HashSet<IdInt> = HashSet::new();
+        for atom in &mol2.atom {
+            if atom.atom_id == 0 {
+                errors.push("ATOM: atom ids start at 1".to_owned());
+            } else if !atom_ids.insert(atom.atom_id) {
+                errors.push(format!("ATOM {}: duplicate atom id", atom.atom_id));
+            }
+        }
+        let mut subst_ids: HashSet<IdInt> = HashSet::new();
+        for substructure in &mol2.substructure {
+            if substructure.subst_id == 0 {
+                errors.push("SUBSTRUCTURE: substructure ids start at 1".to_owned());
+            } else if !subst_ids.insert(substructure.subst_id) {
+                errors.push(format!(
+                    "SUBSTRUCTURE {}: duplicate substructure id",
+                    substructure.subst_id
+                ));
+            }
+        }
+
+        // Charges are written after the substructure columns, so charged atoms
+        // need a substructure. Without any substructures they get the usual
+        // placeholder for unknown ligands.
+        let with_substructures = !mol2.substructure.is_empty();
+        for atom in mol2.atom.iter_mut() {
+            if atom.charge.is_none() || atom.subst_id.is_some() {
+                continue;
+            }
+            match with_substructures {
+                true => errors.push(format!(
+                    "ATOM {}: charged atom needs a substructure",
+                    atom.atom_id
+                )),
+                false => {
+                    atom.subst_id = Some(1);
+                    atom.subst_name = Some("UNL1".to_owned());
+                }
+            }
+        }
+
+        molecule.num_atoms = Some(mol2.atom.len());
+        molecule.num_bonds = Some(mol2.bond.len());
+        molecule.num_subst = Some(mol2.substructure.len());
+        if molecule.charge_type.is_none() {
+            molecule.charge_type = match mol2.atom.iter().any(|atom| atom.charge.is_some()) {
+                true => Some("USER_CHARGES".to_owned()),
+                false => Some("NO_CHARGES".to_owned()),
+            };
+        }
+        mol2.molecule = Some(molecule);
+        // References between sections are checked the same way as for any structure
+        errors.extend(
+            mol2.validate()
+                .iter()
+                .map(|violation| violation.to_string()),
+        );
+
+        match errors.is_empty() {
+            true => Ok(mol2),
+            false => Err(Mol2Error::InvalidArgument(errors.join("; "))),
+        }
+    }
+}
@@ -0,0 +1,299 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::{IdInt, Mol2};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+// 64 bit FNV-1a over explicit little-endian bytes of fixed width, so that
+// hash values depend neither on the platform nor on how the standard
+// library hashes values, and can be stored
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Fnv {
+        Fnv(0xcbf29ce484222325)
+    }
+    fn bytes(&mut self, bytes: &[u8]) -> &mut Fnv {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+        self
+    }
+    fn u64(&mut self, value: u64) -> &mut Fnv {
+        self.bytes(&value.to_le_bytes())
+    }
+    fn i64(&mut self, value: i64) -> &mut Fnv {
+        self.bytes(&value.to_le_bytes())
+    }
+    fn str(&mut self, value: &str) -> &mut Fnv {
+        // Length first, so that consecutive strings cannot run into each other
+        self.u64(value.len() as u64).bytes(value.as_bytes())
+    }
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+fn bridges(neighbours: &[Vec<(usize, &str)>]) -> HashSet<(usize, usize)> {
+    // Bonds in no ring, as pairs of atom positions with the smaller first,
+    // found by depth-first search comparing the order atoms are reached in
+    // with the earliest atom reachable below them
+    let unseen = usize::MAX;
+    let mut order: Vec<usize> = vec![unseen; neighbours.len()];
+    let mut lowest: Vec<usize> = vec![0; neighbours.len()];
+    let mut bridges: HashSet<(usize, usize)> = HashSet::new();
+    let mut counter = 0;
+    for root in 0..neighbours.len() {
+        if order[root] != unseen {
+            continue;
+        }
+        order[root] = counter;
+        lowest[root] = counter;
+        counter += 1;
+        // Atom, the atom it was reached from and its next neighbour to visit
+        let mut stack: Vec<(usize, usize, usize)> = vec![(root, unseen, 0)];
+        while let Some(&(atom, parent, next)) = stack.last() {
+            if let Some(&(other, _)) = neighbours[atom].get(next) {
+                stack.last_mut().unwrap().2 += 1;
+                if other == parent {
+                    continue;
+                }
+                if order[other] == unseen {
+                    order[other] = counter;
+                    lowest[other] = counter;
+                    counter += 1;
+                    stack.push((other, atom, 0));
+                } else {
+                    lowest[atom] = lowest[atom].min(order[other]);
+                }
+                continue;
+            }
+            stack.pop();
+            if parent != unseen {
+                lowest[parent] = lowest[parent].min(lowest[atom]);
+                if lowest[atom] > order[parent] {
+                    bridges.insert((parent.min(atom), parent.max(atom)));
+                }
+            }
+        }
+    }
+    bridges
+}
+
+fn smallest_ring(
+    neighbours: &[Vec<(usize, &str)>],
+    bridges: &HashSet<(usize, usize)>,
+    atom: usize,
+) -> u64 {
+    // Size of the smallest ring through an atom, 0 if it is in none. Unlike
+    // a set of smallest rings this does not depend on the order of atoms.
+    let mut smallest = 0;
+    for &(first, _) in &neighbours[atom] {
+        if bridges.contains(&(atom.min(first), atom.max(first))) {
+            continue;
+        }
+        // Shortest way back to the atom without the bond to the first atom
+        let mut distances: HashMap<usize, u64> = HashMap::from([(first, 1)]);
+        let mut queue: VecDeque<usize> = VecDeque::from([first]);
+        'search: while let Some(current) = queue.pop_front() {
+            let distance = distances[&current];
+            if smallest > 0 && distance >= smallest {
+                break;
+            }
+            for &(next, _) in &neighbours[current] {
+                if next == atom {
+                    if current != first {
+                        smallest = distance + 1;
+                        break 'search;
+                    }
+                    continue;
+                }
+                if let Entry::Vacant(entry) = distances.entry(next) {
+                    entry.insert(distance + 1);
+                    queue.push_back(next);
+                }
+            }
+        }
+    }
+    smallest
+}
+
+fn components(neighbours: &[Vec<(usize, &str)>]) -> Vec<(u64, u64)> {
+    // Numbers of atoms and bonds of the connected component of every atom
+    let mut sizes: Vec<(u64, u64)> = vec![(0, 0); neighbours.len()];
+    let mut seen: Vec<bool> = vec![false; neighbours.len()];
+    for start in 0..neighbours.len() {
+        if seen[start] {
+            continue;
+        }
+        seen[start] = true;
+        let mut component: Vec<usize> = vec![start];
+        let mut index = 0;
+        while index < component.len() {
+            for &(next, _) in &neighbours[component[index]] {
+                if !seen[next] {
+                    seen[next] = true;
+                    component.push(next);
+                }
+            }
+            index += 1;
+        }
+        let bonds: usize = component.iter().map(|&atom| neighbours[atom].len()).sum();
+        for &atom in &component {
+            sizes[atom] = (component.len() as u64, bonds as u64 / 2);
+        }
+    }
+    sizes
+}
+
+fn atom_labels(mol2: &Mol2) -> Vec<u64> {
+    // Label of every atom from its type and surroundings, refined with the
+    // labels of bonded atoms until no more atoms can be told apart. Labels
+    // do not depend on the order or the ids of atoms. Refinement alone does
+    // not tell apart graphs where every atom has the same neighbourhood,
+    // e.g. two cyclopropanes and cyclohexane, so labels start from the
+    // smallest ring through the atom and the size of its component too.
+    let positions: HashMap<IdInt, usize> = mol2
+        .atom
+        .iter()
+        .enumerate()
+        .map(|(index, atom)| (atom.atom_id, index))
+        .collect();
+    let mut neighbours: Vec<Vec<(usize, &str)>> = vec![Vec::new(); mol2.atom.len()];
+    for bond in &mol2.bond {
+        if let (Some(&origin), Some(&target)) = (
+            positions.get(&bond.origin_atom_id),
+            positions.get(&bond.target_atom_id),
+        ) {
+            neighbours[origin].push((target, &bond.bond_type));
+            neighbours[target].push((origin, &bond.bond_type));
+        }
+    }
+    let components = components(&neighbours);
+    let bridges = bridges(&neighbours);
+    let mut labels: Vec<u64> = mol2
+        .atom
+        .iter()
+        .enumerate()
+        .map(|(index, atom)| {
+            let (atoms, bonds) = components[index];
+            Fnv::new()
+                .str(&atom.atom_type)
+                .u64(neighbours[index].len() as u64)
+                .u64(smallest_ring(&neighbours, &bridges, index))
+                .u64(atoms)
+                .u64(bonds)
+                .finish()
+        })
+        .collect();
+    let classes = |labels: &[u64]| labels.iter().collect::<HashSet<_>>().len();
+    loop {
+        let refined: Vec<u64> = neighbours
+            .iter()
+            .enumerate()
+            .map(|(index, bonded)| {
+                let mut around: Vec<(u64, &str)> = bonded
+                    .iter()
+                    .map(|&(other, bond_type)| (labels[other], bond_type))
+                    .collect();
+                around.sort_unstable();
+                let mut hasher = Fnv::new();
+                hasher.u64(labels[index]).u64(around.len() as u64);
+                for (label, bond_type) in around {
+                    hasher.u64(label).str(bond_type);
+                }
+                hasher.finish()
+            })
+            .collect();
+        if classes(&refined) == classes(&labels) {
+            return labels;
+        }
+        labels = refined;
+    }
+}
+
+pub fn structure_hash(mol2: &Mol2, coordinates: bool, desc: bool) -> String {
+    // Hash of the bond graph of a structure with atom and bond types, the
+    // same whatever the order and ids of atoms, bonds and substructures.
+    // Names, charges and substructures are not part of the hash.
+    // Input:
+    //     mol2: structure to hash
+    //     coordinates: include coordinates of atoms, rounded to 3 decimals
+    //     desc: include the description of the structure
+    let labels = atom_labels(mol2);
+    let positions: HashMap<IdInt, usize> = mol2
+        .atom
+        .iter()
+        .enumerate()
+        .map(|(index, atom)| (atom.atom_id, index))
+        .collect();
+    let mut atoms: Vec<(u64, Option<[i64; 3]>)> = labels
+        .iter()
+        .zip(&mol2.atom)
+        .map(|(label, atom)| {
+            let position = coordinates
+                .then(|| [atom.x, atom.y, atom.z].map(|value| (value * 1000.0).round() as i64));
+            (*label, position)
+        })
+        .collect();
+    atoms.sort_unstable();
+    let mut bonds: Vec<(u64, u64, &str)> = mol2
+        .bond
+        .iter()
+        .filter_map(|bond| {
+            let origin = labels[*positions.get(&bond.origin_atom_id)?];
+            let target = labels[*positions.get(&bond.target_atom_id)?];
+            Some((
+                origin.min(target),
+                origin.max(target),
+                bond.bond_type.as_str(),
+            ))
+        })
+        .collect();
+    bonds.sort_unstable();
+    let desc = match desc {
+        true => mol2.desc.as_deref(),
+        false => None,
+    };
+    let mut hasher = Fnv::new();
+    hasher.u64(atoms.len() as u64);
+    for (label, position) in atoms {
+        hasher.u64(label);
+        if let Some(position) = position {
+            for value in position {
+                hasher.i64(value);
+            }
+        }
+    }
+    hasher.u64(bonds.len() as u64);
+    for (origin, target, bond_type) in bonds {
+        hasher.u64(origin).u64(target).str(bond_type);
+    }
+    if let Some(desc) = desc {
+        hasher.bytes(&[1]).str(desc);
+    }
+    format!("{:016x}", hasher.finish())
+}
+
+pub fn deduplicate(mol2_list: Vec<Mol2>, coordinates: bool, desc: bool) -> Vec<Mol2> {
+    // Keep the first of structures with the same structure_hash
+    let mut seen: HashSet<String> = HashSet::new();
+    mol2_list
+        .into_iter()
+        .filter(|mol2| seen.insert(structure_hash(mol2, coordinates, desc)))
+        .collect()
+}
@@ -0,0 +1,334 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::error::{Mol2Error, Result};
+use fs2::FileExt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+
+// Using a rather large buffer but for our applications should be fine.
+static READER_BUFFER: usize = 100 * 1024 * 1024;
+
+// Compression formats understood when reading mol2 files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileCompression {
+    None,
+    Bzip2,
+    Xz,
+    Gzip,
+    Zstd,
+}
+
+impl FileCompression {
+    pub fn from_name(name: &str) -> Result<FileCompression> {
+        // Translate a user given compression name
+        // Input:
+        //     name: one of "none", "bz2"/"bzip2", "xz", "gz"/"gzip" or "zst"/"zstd"
+        match &name.to_lowercase()[..] {
+            "none" => Ok(FileCompression::None),
+            "bz2" | "bzip2" => Ok(FileCompression::Bzip2),
+            "xz" => Ok(FileCompression::Xz),
+            "gz" | "gzip" => Ok(FileCompression::Gzip),
+            "zst" | "zstd" => Ok(FileCompression::Zstd),
+            _ => Err(Mol2Error::InvalidArgument(format!(
+                "Unknown compression format: {}",
+                name
+            ))),
+        }
+    }
+    pub fn from_extension(filename: &str) -> FileCompression {
+        // Guess the compression format from the file extension
+        // Input:
+        //     filename: path to a file
+        match std::path::Path::new(filename)
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("bz2") => FileCompression::Bzip2,
+            Some("xz") => FileCompression::Xz,
+            Some("gz") => FileCompression::Gzip,
+            Some("zst") => FileCompression::Zstd,
+            _ => FileCompression::None,
+        }
+    }
+    pub fn from_arg(name: &str) -> Result<Option<FileCompression>> {
+        // Translate a compression argument of the python functions, an
+        // empty name means guessing from the extension
+        match name.is_empty() {
+            true => Ok(None),
+            false => FileCompression::from_name(name).map(Some),
+        }
+    }
+    pub fn detect(filename: &str, compression: Option<FileCompression>) -> FileCompression {
+        // Pick the compression format, None means guessing from the extension
+        // Input:
+        //     filename: path to a file
+        //     compression: compression format given by the user
+        compression.unwrap_or_else(|| FileCompression::from_extension(filename))
+    }
+}
+
+pub fn open_input(
+    filename: &str,
+    compression: Option<FileCompression>,
+) -> Result<Box<dyn BufRead + Send>> {
+    // Open a (possibly compressed) file for reading
+    // Input:
+    //     filename: path to a file
+    //     compression: compression format, None means guess from the extension
+    let compression = FileCompression::detect(filename, compression);
+    let file = File::open(filename)?;
+    let reader: Box<dyn Read + Send> = match compression {
+        FileCompression::None => Box::new(file),
+        FileCompression::Bzip2 => bzip2_reader(file)?,
+        FileCompression::Xz => xz_reader(file)?,
+        FileCompression::Gzip => gzip_reader(file)?,
+        FileCompression::Zstd => Box::new(zstd::stream::read::Decoder::new(file)?),
+    };
+    Ok(Box::new(BufReader::with_capacity(READER_BUFFER, reader)))
+}
+
+// Output file, compressed formats need to write out their trailing data when done
+pub trait OutputFile: Write {
+    fn finish(self: Box<Self>) -> std::io::Result<()>;
+}
+
+impl OutputFile for BufWriter<File> {
+    fn finish(mut self: Box<Self>) -> std::io::Result<()> {
+        self.flush()
+    }
+}
+
+impl OutputFile for zstd::stream::write::Encoder<'static, BufWriter<File>> {
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        (*self).finish()?.flush()
+    }
+}
+
+#[cfg(feature = "gzip")]
+impl OutputFile for flate2::write::GzEncoder<BufWriter<File>> {
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        (*self).finish()?.flush()
+    }
+}
+
+pub fn open_output(
+    filename: &str,
+    append: bool,
+    compression: Option<FileCompression>,
+) -> Result<Box<dyn OutputFile>> {
+    // Open a (possibly compressed) file for writing
+    // Input:
+    //     filename: path to a file to write to
+    //     append: append to the file rather than truncate it
+    //     compression: compression format, None means guess from the extension
+    //
+    // Compressed data is appended as a new stream, which readers of
+    // both gzip and zstd files handle as a continuation of the file
+    let compression = FileCompression::detect(filename, compression);
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(!append)
+        .append(append)
+        .open(filename)?;
+    // Several processes appending to the same file would interleave
+    // their structures, so appends wait for each other. The lock is
+    // released when the file is closed after everything is written.
+    if append {
+        file.lock_exclusive()?;
+    }
+    let file = BufWriter::new(file);
+    match compression {
+        FileCompression::None => Ok(Box::new(file)),
+        FileCompression::Gzip => gzip_writer(file),
+        FileCompression::Zstd => Ok(Box::new(zstd::stream::write::Encoder::new(file, 0)?)),
+        FileCompression::Bzip2 | FileCompression::Xz => Err(Mol2Error::Compression(format!(
+            "Writing {:?} compressed files is not supported",
+            compression
+        ))),
+    }
+}
+
+#[cfg(feature = "bzip2")]
+fn bzip2_reader(file: File) -> Result<Box<dyn Read + Send>> {
+    // Multi stream decoder so that files from parallel bzip2 tools are read whole
+    Ok(Box::new(bzip2::read::MultiBzDecoder::new(file)))
+}
+
+#[cfg(not(feature = "bzip2"))]
+fn bzip2_reader(_file: File) -> Result<Box<dyn Read + Send>> {
+    Err(Mol2Error::Compression(
+        "Reading bzip2 files requires serde_mol2 built with the \"bzip2\" feature".to_owned(),
+    ))
+}
+
+#[cfg(feature = "xz")]
+fn xz_reader(file: File) -> Result<Box<dyn Read + Send>> {
+    Ok(Box::new(xz2::read::XzDecoder::new_multi_decoder(file)))
+}
+
+#[cfg(not(feature = "xz"))]
+fn xz_reader(_file: File) -> Result<Box<dyn Read + Send>> {
+    Err(Mol2Error::Compression(
+        "Reading xz files requires serde_mol2 built with the \"xz\" feature".to_owned(),
+    ))
+}
+
+#[cfg(feature = "gzip")]
+fn gzip_reader(file: File) -> Result<Box<dyn Read + Send>> {
+    // Multi member decoder so that appended output is read whole
+    Ok(Box::new(flate2::read::MultiGzDecoder::new(file)))
+}
+
+#[cfg(not(feature = "gzip"))]
+fn gzip_reader(_file: File) -> Result<Box<dyn Read + Send>> {
+    Err(Mol2Error::Compression(
+        "Reading gzip files requires serde_mol2 built with the \"gzip\" feature".to_owned(),
+    ))
+}
+
+#[cfg(feature = "gzip")]
+fn gzip_writer(file: BufWriter<File>) -> Result<Box<dyn OutputFile>> {
+    Ok(Box::new(flate2::write::GzEncoder::new(
+        file,
+        flate2::Compression::default(),
+    )))
+}
+
+#[cfg(not(feature = "gzip"))]
+fn gzip_writer(_file: BufWriter<File>) -> Result<Box<dyn OutputFile>> {
+    Err(Mol2Error::Compression(
+        "Writing gzip files requires serde_mol2 built with the \"gzip\" feature".to_owned(),
+    ))
+}
+
+// Codecs the blobs of structures are compressed with in databases. Rows
+// stored before codecs were introduced and binary records use zstd.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Codec {
+    None,
+    #[default]
+    Zstd,
+    Lz4,
+    Gzip,
+}
+
+impl Codec {
+    pub fn from_name(name: &str) -> Result<Codec> {
+        // Translate a user given codec name
+        // Input:
+        //     name: one of "none", "zstd", "lz4" or "gzip"
+        match &name.to_lowercase()[..] {
+            "none" => Ok(Codec::None),
+            "zstd" => Ok(Codec::Zstd),
+            "lz4" => Ok(Codec::Lz4),
+            "gzip" => Ok(Codec::Gzip),
+            _ => Err(Mol2Error::InvalidArgument(format!(
+                "Unknown codec: {}",
+                name
+            ))),
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Codec::None => "none",
+            Codec::Zstd => "zstd",
+            Codec::Lz4 => "lz4",
+            Codec::Gzip => "gzip",
+        }
+    }
+    pub fn level(self, level: i32) -> i32 {
+        // Compression level actually used by the codec, 0 means no compression
+        // Input:
+        //     level: level given by the user
+        match self {
+            _ if level <= 0 => 0,
+            Codec::None => 0,
+            Codec::Zstd => level.min(*zstd::compression_level_range().end()),
+            // lz4 has a single level
+            Codec::Lz4 => 1,
+            Codec::Gzip => level.min(9),
+        }
+    }
+}
+
+impl rusqlite::types::ToSql for Codec {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        Ok(self.name().into())
+    }
+}
+
+impl rusqlite::types::FromSql for Codec {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        Codec::from_name(value.as_str()?)
+            .map_err(|error| rusqlite::types::FromSqlError::Other(Box::new(error)))
+    }
+}
+
+#[cfg(feature = "lz4")]
+pub fn lz4_compress(data: &[u8]) -> Result<Vec<u8>> {
+    // The size is stored in front, so decompressing allocates only once
+    Ok(lz4_flex::compress_prepend_size(data))
+}
+
+#[cfg(feature = "lz4")]
+pub fn lz4_decompress(data: &[u8]) -> Result<Vec<u8>> {
+    lz4_flex::decompress_size_prepended(data)
+        .map_err(|error| Mol2Error::Compression(error.to_string()))
+}
+
+#[cfg(not(feature = "lz4"))]
+pub fn lz4_compress(_data: &[u8]) -> Result<Vec<u8>> {
+    Err(Mol2Error::Compression(
+        "The lz4 codec requires serde_mol2 built with the \"lz4\" feature".to_owned(),
+    ))
+}
+
+#[cfg(not(feature = "lz4"))]
+pub fn lz4_decompress(data: &[u8]) -> Result<Vec<u8>> {
+    lz4_compress(data)
+}
+
+#[cfg(feature = "gzip")]
+pub fn gzip_compress(data: &[u8], level: i32) -> Result<Vec<u8>> {
+    let mut encoder =
+        flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::new(level as u32));
+    encoder
+        .write_all(data)
+        .and_then(|_| encoder.finish())
+        .map_err(|error| Mol2Error::Compression(error.to_string()))
+}
+
+#[cfg(feature = "gzip")]
+pub fn gzip_decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    flate2::read::GzDecoder::new(data)
+        .read_to_end(&mut buffer)
+        .map_err(|error| Mol2Error::Compression(error.to_string()))?;
+    Ok(buffer)
+}
+
+#[cfg(not(feature = "gzip"))]
+pub fn gzip_compress(_data: &[u8], _level: i32) -> Result<Vec<u8>> {
+    Err(Mol2Error::Compression(
+        "The gzip codec requires serde_mol2 built with the \"gzip\" feature".to_owned(),
+    ))
+}
+
+#[cfg(not(feature = "gzip"))]
+pub fn gzip_decompress(data: &[u8]) -> Result<Vec<u8>> {
+    gzip_compress(data, 0)
+}
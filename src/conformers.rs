@@ -0,0 +1,192 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::error::{Mol2Error, Result};
+use crate::{same_content, Atom, CoordFloat, Mol2};
+use numpy::ndarray::Array3;
+use numpy::{IntoPyArray, PyArray3};
+use pyo3::prelude::*;
+use std::collections::HashMap;
+
+// Poses of a single molecule, e.g. out of docking, as one topology with many
+// frames of coordinates. Molecule comments are kept per frame since they
+// often hold scores.
+#[pyclass]
+#[derive(Debug, Clone)]
+pub struct Conformers {
+    // Structure of the first frame
+    #[pyo3(get)]
+    topology: Mol2,
+    frames: Vec<Vec<[CoordFloat; 3]>>,
+    comments: Vec<Option<String>>,
+}
+
+fn coordinates(mol2: &Mol2) -> Vec<[CoordFloat; 3]> {
+    mol2.atom
+        .iter()
+        .map(|atom| [atom.x, atom.y, atom.z])
+        .collect()
+}
+
+fn comment(mol2: &Mol2) -> Option<String> {
+    mol2.molecule
+        .as_ref()
+        .and_then(|molecule| molecule.mol_comment.clone())
+}
+
+fn same_topology(first: &Mol2, second: &Mol2) -> bool {
+    // Same name, atoms apart from their coordinates, bonds and substructures
+    let without_coordinates = |mol2: &Mol2| -> Vec<Atom> {
+        mol2.atom
+            .iter()
+            .map(|atom| Atom {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+                ..atom.clone()
+            })
+            .collect()
+    };
+    let name = |mol2: &Mol2| {
+        mol2.molecule
+            .as_ref()
+            .map(|molecule| molecule.mol_name.clone())
+    };
+    name(first) == name(second)
+        && same_content(&without_coordinates(first), &without_coordinates(second))
+        && same_content(&first.bond, &second.bond)
+        && same_content(&first.substructure, &second.substructure)
+}
+
+impl Conformers {
+    pub fn new(topology: Mol2) -> Conformers {
+        Conformers {
+            frames: vec![coordinates(&topology)],
+            comments: vec![comment(&topology)],
+            topology,
+        }
+    }
+    pub fn push(&mut self, mol2: &Mol2) -> Result<()> {
+        // Add the coordinates and the comment of a structure as a new frame
+        // Input:
+        //     mol2: structure with the same topology, see same_topology
+        if !same_topology(&self.topology, mol2) {
+            return Err(Mol2Error::InvalidArgument(
+                "structure differs from the topology in more than coordinates".to_owned(),
+            ));
+        }
+        self.frames.push(coordinates(mol2));
+        self.comments.push(comment(mol2));
+        Ok(())
+    }
+    pub fn get(&self, index: usize) -> Option<Mol2> {
+        // Structure of a single frame, the topology with the frame's
+        // coordinates and comment
+        let frame = self.frames.get(index)?;
+        let mut mol2 = self.topology.clone();
+        for (atom, [x, y, z]) in mol2.atom.iter_mut().zip(frame) {
+            atom.x = *x;
+            atom.y = *y;
+            atom.z = *z;
+        }
+        if let Some(molecule) = mol2.molecule.as_mut() {
+            molecule.mol_comment = self.comments[index].clone();
+        }
+        Some(mol2)
+    }
+    pub fn expand(&self) -> Vec<Mol2> {
+        // Structures of all frames
+        (0..self.frames.len())
+            .filter_map(|index| self.get(index))
+            .collect()
+    }
+}
+
+#[pymethods]
+impl Conformers {
+    #[new]
+    fn py_new(topology: Mol2) -> Conformers {
+        // Conformers with the structure as the topology and first frame
+        Conformers::new(topology)
+    }
+    fn __len__(&self) -> usize {
+        self.frames.len()
+    }
+    fn add_frame(&mut self, mol2: &Mol2) -> Result<()> {
+        // Add a structure with the same topology as a new frame
+        self.push(mol2)
+    }
+    fn frame(&self, index: isize) -> Result<Mol2> {
+        // Structure of a single frame, negative indices count from the end
+        let position = match index < 0 {
+            true => self.frames.len() as isize + index,
+            false => index,
+        };
+        usize::try_from(position)
+            .ok()
+            .and_then(|position| self.get(position))
+            .ok_or_else(|| {
+                Mol2Error::InvalidArgument(format!(
+                    "frame {} out of range for {} frames",
+                    index,
+                    self.frames.len()
+                ))
+            })
+    }
+    fn frames(&self) -> Vec<Mol2> {
+        // Structures of all frames
+        self.expand()
+    }
+    fn coordinates<'py>(&self, py: Python<'py>) -> &'py PyArray3<CoordFloat> {
+        // Coordinates of all frames as a frames x atoms x 3 numpy array
+        let coordinates: Vec<CoordFloat> =
+            self.frames.iter().flatten().flatten().copied().collect();
+        Array3::from_shape_vec(
+            (self.frames.len(), self.topology.atom.len(), 3),
+            coordinates,
+        )
+        .expect("Coordinates do not fit the array shape")
+        .into_pyarray(py)
+    }
+}
+
+pub fn collapse(mol2_list: Vec<Mol2>) -> Vec<Conformers> {
+    // Group structures sharing a name and topology into conformers, in the
+    // order the molecules first appear. Structures with a name seen before
+    // but a different topology start new conformers.
+    let mut conformers: Vec<Conformers> = Vec::new();
+    let mut by_name: HashMap<Option<String>, Vec<usize>> = HashMap::new();
+    for mol2 in mol2_list {
+        let name = mol2
+            .molecule
+            .as_ref()
+            .map(|molecule| molecule.mol_name.clone());
+        let positions = by_name.entry(name).or_default();
+        match positions
+            .iter()
+            .find(|&&position| same_topology(&conformers[position].topology, &mol2))
+        {
+            Some(&position) => {
+                conformers[position].frames.push(coordinates(&mol2));
+                conformers[position].comments.push(comment(&mol2));
+            }
+            None => {
+                positions.push(conformers.len());
+                conformers.push(Conformers::new(mol2));
+            }
+        }
+    }
+    conformers
+}
@@ -0,0 +1,385 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::error::{Mol2Error, Result};
+use crate::{
+    count_structures, db_cleanup, insert_structures, open_db, read_structures, Codec, Db, DbQuery,
+    DbReader, Mol2, SharedDb,
+};
+use pyo3::prelude::*;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// Database kept open for several reads and writes in a row. The functions
+// taking a filename open the database and check its tables on every call,
+// and every call is a transaction of its own.
+#[pyclass]
+pub struct Mol2Db {
+    filename: String,
+    // None once closed
+    db: Option<SharedDb>,
+    read_only: bool,
+}
+
+impl Mol2Db {
+    pub fn open(filename: &str, shm: bool, read_only: bool) -> Result<Mol2Db> {
+        // Open or create a database
+        // Input:
+        //     filename: path to the database
+        //     shm: should we try and use the database out of a temporary
+        //          location? Changes are copied back on close.
+        //     read_only: open an existing database read-only where it is,
+        //                shm is ignored and inserts fail
+        Ok(Mol2Db {
+            filename: filename.to_owned(),
+            db: Some(Arc::new(Mutex::new(open_db(filename, shm, read_only)?))),
+            read_only,
+        })
+    }
+    fn shared(&self) -> Result<&SharedDb> {
+        self.db
+            .as_ref()
+            .ok_or_else(|| Mol2Error::InvalidArgument("Database is closed".to_owned()))
+    }
+    fn connection(&self) -> Result<MutexGuard<'_, Db>> {
+        Ok(self
+            .shared()?
+            .lock()
+            .unwrap_or_else(|error| error.into_inner()))
+    }
+    pub fn insert(
+        &self,
+        mol2_list: &[Mol2],
+        compression: i32,
+        replace: bool,
+        source_file: &str,
+        codec: Codec,
+    ) -> Result<Vec<i64>> {
+        // Insert structures as db_insert does, returns their row ids
+        insert_structures(
+            &*self.connection()?,
+            mol2_list,
+            compression,
+            codec,
+            replace,
+            source_file,
+        )
+    }
+    pub fn query(&self, query: &DbQuery, threads: usize) -> Result<Vec<Mol2>> {
+        // Read structures as read_db_all does
+        read_structures(&*self.connection()?, query, threads)
+    }
+    pub fn iter(&self, query: &DbQuery, batch_size: usize, threads: usize) -> Result<DbReader> {
+        // Iterate over structures as read_db_iter does. The iterator reads
+        // through this connection, so it sees changes not committed yet.
+        DbReader::with_connection(self.shared()?.clone(), query, batch_size, threads)
+    }
+    pub fn count(&self, query: &DbQuery) -> Result<usize> {
+        // Count structures as db_count does
+        count_structures(&*self.connection()?, query)
+    }
+    pub fn begin(&self) -> Result<()> {
+        // Start a transaction, inserts until commit or rollback are part of
+        // it instead of being committed one call at a time
+        self.connection()?.execute_batch("BEGIN")?;
+        Ok(())
+    }
+    pub fn commit(&self) -> Result<()> {
+        self.connection()?.execute_batch("COMMIT")?;
+        Ok(())
+    }
+    pub fn rollback(&self) -> Result<()> {
+        self.connection()?.execute_batch("ROLLBACK")?;
+        Ok(())
+    }
+    pub fn in_transaction(&self) -> Result<bool> {
+        Ok(!self.connection()?.is_autocommit())
+    }
+    pub fn close(&mut self) -> Result<()> {
+        // Close the database, a transaction still open is rolled back. A
+        // database used out of a temporary location is copied back. Closing
+        // again does nothing.
+        let shared = match self.db.take() {
+            Some(shared) => shared,
+            None => return Ok(()),
+        };
+        let db = shared.lock().unwrap_or_else(|error| error.into_inner());
+        if !db.is_autocommit() {
+            db.execute_batch("ROLLBACK")?;
+        }
+        // Read-only databases are never copied anywhere
+        match self.read_only {
+            true => Ok(()),
+            false => db_cleanup(&self.filename, &db),
+        }
+    }
+}
+
+impl Drop for Mol2Db {
+    fn drop(&mut self) {
+        // Errors cannot be reported here, close explicitly to see them
+        self.close().ok();
+    }
+}
+
+#[pymethods]
+impl Mol2Db {
+    #[new]
+    #[args(shm = "false", read_only = "false")]
+    fn py_new(filename: &str, shm: bool, read_only: bool) -> PyResult<Mol2Db> {
+        Ok(Mol2Db::open(filename, shm, read_only)?)
+    }
+    fn __enter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+    fn __exit__(
+        &mut self,
+        exc_type: &PyAny,
+        _exc_value: &PyAny,
+        _traceback: &PyAny,
+    ) -> PyResult<bool> {
+        // Commit a transaction left open if the block finished, close rolls
+        // it back after an exception. Exceptions are not suppressed.
+        if exc_type.is_none() && self.db.is_some() && self.in_transaction()? {
+            self.commit()?;
+        }
+        self.close()?;
+        Ok(false)
+    }
+    #[args(
+        compression = "3",
+        replace = "false",
+        source_file = "\"\"",
+        codec = "\"zstd\""
+    )]
+    #[pyo3(name = "insert")]
+    fn py_insert(
+        &self,
+        mol2_list: Vec<Mol2>,
+        compression: i32,
+        replace: bool,
+        source_file: &str,
+        codec: &str,
+    ) -> PyResult<Vec<i64>> {
+        Ok(self.insert(
+            &mol2_list,
+            compression,
+            replace,
+            source_file,
+            Codec::from_name(codec)?,
+        )?)
+    }
+    #[args(
+        desc = "\"\"",
+        comment = "\"\"",
+        name = "\"\"",
+        limit = "0",
+        offset = "0",
+        filter_mode = "\"substring\"",
+        order_by = "\"id\"",
+        min_atoms = "0",
+        max_atoms = "0",
+        min_bonds = "0",
+        max_bonds = "0",
+        property_ranges = "Vec::new()",
+        where_extras = "\"\"",
+        tag = "\"\"",
+        source_file = "\"\"",
+        inserted_after = "\"\"",
+        inserted_before = "\"\"",
+        threads = "1"
+    )]
+    #[pyo3(name = "query")]
+    #[allow(clippy::too_many_arguments)]
+    fn py_query(
+        &self,
+        desc: &str,
+        comment: &str,
+        name: &str,
+        limit: usize,
+        offset: usize,
+        filter_mode: &str,
+        order_by: &str,
+        min_atoms: usize,
+        max_atoms: usize,
+        min_bonds: usize,
+        max_bonds: usize,
+        property_ranges: Vec<(String, Option<f64>, Option<f64>)>,
+        where_extras: &str,
+        tag: &str,
+        source_file: &str,
+        inserted_after: &str,
+        inserted_before: &str,
+        threads: usize,
+    ) -> PyResult<Vec<Mol2>> {
+        let query = DbQuery::from_args(
+            desc,
+            comment,
+            name,
+            filter_mode,
+            order_by,
+            limit,
+            offset,
+            min_atoms,
+            max_atoms,
+            min_bonds,
+            max_bonds,
+            property_ranges,
+            where_extras,
+            tag,
+            source_file,
+            inserted_after,
+            inserted_before,
+        )?;
+        Ok(self.query(&query, threads)?)
+    }
+    #[args(
+        desc = "\"\"",
+        comment = "\"\"",
+        name = "\"\"",
+        limit = "0",
+        offset = "0",
+        batch_size = "1000",
+        filter_mode = "\"substring\"",
+        order_by = "\"id\"",
+        min_atoms = "0",
+        max_atoms = "0",
+        min_bonds = "0",
+        max_bonds = "0",
+        property_ranges = "Vec::new()",
+        where_extras = "\"\"",
+        tag = "\"\"",
+        source_file = "\"\"",
+        inserted_after = "\"\"",
+        inserted_before = "\"\"",
+        threads = "1"
+    )]
+    #[pyo3(name = "iter")]
+    #[allow(clippy::too_many_arguments)]
+    fn py_iter(
+        &self,
+        desc: &str,
+        comment: &str,
+        name: &str,
+        limit: usize,
+        offset: usize,
+        batch_size: usize,
+        filter_mode: &str,
+        order_by: &str,
+        min_atoms: usize,
+        max_atoms: usize,
+        min_bonds: usize,
+        max_bonds: usize,
+        property_ranges: Vec<(String, Option<f64>, Option<f64>)>,
+        where_extras: &str,
+        tag: &str,
+        source_file: &str,
+        inserted_after: &str,
+        inserted_before: &str,
+        threads: usize,
+    ) -> PyResult<DbReader> {
+        let query = DbQuery::from_args(
+            desc,
+            comment,
+            name,
+            filter_mode,
+            order_by,
+            limit,
+            offset,
+            min_atoms,
+            max_atoms,
+            min_bonds,
+            max_bonds,
+            property_ranges,
+            where_extras,
+            tag,
+            source_file,
+            inserted_after,
+            inserted_before,
+        )?;
+        Ok(self.iter(&query, batch_size, threads)?)
+    }
+    #[args(
+        desc = "\"\"",
+        comment = "\"\"",
+        name = "\"\"",
+        filter_mode = "\"substring\"",
+        min_atoms = "0",
+        max_atoms = "0",
+        min_bonds = "0",
+        max_bonds = "0",
+        property_ranges = "Vec::new()",
+        where_extras = "\"\"",
+        tag = "\"\"",
+        source_file = "\"\"",
+        inserted_after = "\"\"",
+        inserted_before = "\"\""
+    )]
+    #[pyo3(name = "count")]
+    #[allow(clippy::too_many_arguments)]
+    fn py_count(
+        &self,
+        desc: &str,
+        comment: &str,
+        name: &str,
+        filter_mode: &str,
+        min_atoms: usize,
+        max_atoms: usize,
+        min_bonds: usize,
+        max_bonds: usize,
+        property_ranges: Vec<(String, Option<f64>, Option<f64>)>,
+        where_extras: &str,
+        tag: &str,
+        source_file: &str,
+        inserted_after: &str,
+        inserted_before: &str,
+    ) -> PyResult<usize> {
+        let query = DbQuery::from_args(
+            desc,
+            comment,
+            name,
+            filter_mode,
+            "id",
+            0,
+            0,
+            min_atoms,
+            max_atoms,
+            min_bonds,
+            max_bonds,
+            property_ranges,
+            where_extras,
+            tag,
+            source_file,
+            inserted_after,
+            inserted_before,
+        )?;
+        Ok(self.count(&query)?)
+    }
+    #[pyo3(name = "begin")]
+    fn py_begin(&self) -> PyResult<()> {
+        Ok(self.begin()?)
+    }
+    #[pyo3(name = "commit")]
+    fn py_commit(&self) -> PyResult<()> {
+        Ok(self.commit()?)
+    }
+    #[pyo3(name = "rollback")]
+    fn py_rollback(&self) -> PyResult<()> {
+        Ok(self.rollback()?)
+    }
+    #[pyo3(name = "close")]
+    fn py_close(&mut self) -> PyResult<()> {
+        Ok(self.close()?)
+    }
+}
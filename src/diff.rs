@@ -0,0 +1,410 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::{Atom, Bond, ChargeFloat, CoordFloat, IdInt, Mol2, Molecule, Substructure};
+use pyo3::prelude::*;
+use std::collections::HashMap;
+use std::fmt::Display;
+
+// Single difference between two structures
+#[pyclass]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    #[pyo3(get)]
+    pub section: String,
+    // Id of the record, None for the MOLECULE section. Bonds are matched by
+    // their atoms and carry the bond id of the first structure, or of the
+    // second one for added bonds.
+    #[pyo3(get)]
+    pub id: Option<IdInt>,
+    // Name of the differing field, "added" or "removed" for whole records
+    #[pyo3(get)]
+    pub field: String,
+    #[pyo3(get)]
+    pub old: Option<String>,
+    #[pyo3(get)]
+    pub new: Option<String>,
+    // New minus old value of coordinates and charges
+    #[pyo3(get)]
+    pub delta: Option<f64>,
+}
+
+impl std::fmt::Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let old = self.old.as_deref().unwrap_or("None");
+        let new = self.new.as_deref().unwrap_or("None");
+        match self.id {
+            Some(id) => write!(f, "{} {} ", self.section, id)?,
+            None => write!(f, "{} ", self.section)?,
+        }
+        match &self.field[..] {
+            "added" => write!(f, "added: {}", new),
+            "removed" => write!(f, "removed: {}", old),
+            field => write!(f, "{}: {} -> {}", field, old, new),
+        }
+    }
+}
+
+#[pymethods]
+impl Difference {
+    fn __str__(&self) -> String {
+        self.to_string()
+    }
+}
+
+fn text<T: Display>(value: &Option<T>) -> Option<String> {
+    value.as_ref().map(|value| value.to_string())
+}
+
+// Collects differences of one section
+struct Report<'a> {
+    section: &'a str,
+    differences: &'a mut Vec<Difference>,
+}
+
+impl Report<'_> {
+    fn field(&mut self, id: Option<IdInt>, field: &str, old: Option<String>, new: Option<String>) {
+        if old != new {
+            self.differences.push(Difference {
+                section: self.section.to_owned(),
+                id,
+                field: field.to_owned(),
+                old,
+                new,
+                delta: None,
+            });
+        }
+    }
+    fn number<T: Into<f64> + Display + Copy>(
+        &mut self,
+        id: IdInt,
+        field: &str,
+        old: Option<T>,
+        new: Option<T>,
+        tol: f64,
+    ) {
+        let delta = match (old.map(Into::into), new.map(Into::into)) {
+            (Some(old), Some(new)) if (new - old).abs() <= tol => return,
+            (None, None) => return,
+            (Some(old), Some(new)) => Some(new - old),
+            _ => None,
+        };
+        self.differences.push(Difference {
+            section: self.section.to_owned(),
+            id: Some(id),
+            field: field.to_owned(),
+            old: text(&old),
+            new: text(&new),
+            delta,
+        });
+    }
+    fn record(&mut self, id: IdInt, old: Option<String>, new: Option<String>) {
+        self.differences.push(Difference {
+            section: self.section.to_owned(),
+            id: Some(id),
+            field: match old {
+                Some(_) => "removed",
+                None => "added",
+            }
+            .to_owned(),
+            old,
+            new,
+            delta: None,
+        });
+    }
+}
+
+fn molecule_fields(molecule: Option<&Molecule>) -> Vec<(&'static str, Option<String>)> {
+    vec![
+        (
+            "mol_name",
+            molecule.map(|molecule| molecule.mol_name.clone()),
+        ),
+        (
+            "num_atoms",
+            molecule.and_then(|molecule| text(&molecule.num_atoms)),
+        ),
+        (
+            "num_bonds",
+            molecule.and_then(|molecule| text(&molecule.num_bonds)),
+        ),
+        (
+            "num_subst",
+            molecule.and_then(|molecule| text(&molecule.num_subst)),
+        ),
+        (
+            "num_feat",
+            molecule.and_then(|molecule| text(&molecule.num_feat)),
+        ),
+        (
+            "num_sets",
+            molecule.and_then(|molecule| text(&molecule.num_sets)),
+        ),
+        (
+            "mol_type",
+            molecule.and_then(|molecule| molecule.mol_type.clone()),
+        ),
+        (
+            "charge_type",
+            molecule.and_then(|molecule| molecule.charge_type.clone()),
+        ),
+        (
+            "status_bits",
+            molecule.and_then(|molecule| molecule.status_bits.clone()),
+        ),
+        (
+            "mol_comment",
+            molecule.and_then(|molecule| molecule.mol_comment.clone()),
+        ),
+    ]
+}
+
+fn atom_text(atom: &Atom) -> String {
+    format!("{} {}", atom.atom_name, atom.atom_type)
+}
+
+fn bond_text(bond: &Bond) -> String {
+    format!(
+        "{}-{} {}",
+        bond.origin_atom_id, bond.target_atom_id, bond.bond_type
+    )
+}
+
+fn bond_key(bond: &Bond) -> (IdInt, IdInt) {
+    (
+        bond.origin_atom_id.min(bond.target_atom_id),
+        bond.origin_atom_id.max(bond.target_atom_id),
+    )
+}
+
+fn compare_atoms(
+    report: &mut Report,
+    first: &Atom,
+    second: &Atom,
+    coord_tol: CoordFloat,
+    charge_tol: ChargeFloat,
+) {
+    let id = first.atom_id;
+    report.field(
+        Some(id),
+        "atom_name",
+        Some(first.atom_name.clone()),
+        Some(second.atom_name.clone()),
+    );
+    report.number(id, "x", Some(first.x), Some(second.x), coord_tol);
+    report.number(id, "y", Some(first.y), Some(second.y), coord_tol);
+    report.number(id, "z", Some(first.z), Some(second.z), coord_tol);
+    report.field(
+        Some(id),
+        "atom_type",
+        Some(first.atom_type.clone()),
+        Some(second.atom_type.clone()),
+    );
+    report.field(
+        Some(id),
+        "subst_id",
+        text(&first.subst_id),
+        text(&second.subst_id),
+    );
+    report.field(
+        Some(id),
+        "subst_name",
+        first.subst_name.clone(),
+        second.subst_name.clone(),
+    );
+    report.number(id, "charge", first.charge, second.charge, charge_tol as f64);
+    report.field(
+        Some(id),
+        "status_bit",
+        first.status_bit.clone(),
+        second.status_bit.clone(),
+    );
+}
+
+fn compare_substructures(report: &mut Report, first: &Substructure, second: &Substructure) {
+    let id = Some(first.subst_id);
+    report.field(
+        id,
+        "subst_name",
+        Some(first.subst_name.clone()),
+        Some(second.subst_name.clone()),
+    );
+    report.field(
+        id,
+        "root_atom",
+        Some(first.root_atom.to_string()),
+        Some(second.root_atom.to_string()),
+    );
+    report.field(
+        id,
+        "subst_type",
+        first.subst_type.clone(),
+        second.subst_type.clone(),
+    );
+    report.field(
+        id,
+        "dict_type",
+        text(&first.dict_type),
+        text(&second.dict_type),
+    );
+    report.field(id, "chain", first.chain.clone(), second.chain.clone());
+    report.field(
+        id,
+        "sub_type",
+        first.sub_type.clone(),
+        second.sub_type.clone(),
+    );
+    report.field(
+        id,
+        "inter_bonds",
+        text(&first.inter_bonds),
+        text(&second.inter_bonds),
+    );
+    report.field(id, "status", first.status.clone(), second.status.clone());
+    report.field(id, "comment", first.comment.clone(), second.comment.clone());
+}
+
+pub fn diff(
+    first: &Mol2,
+    second: &Mol2,
+    coord_tol: CoordFloat,
+    charge_tol: ChargeFloat,
+) -> Vec<Difference> {
+    // Differences between two structures, section by section. Atoms and
+    // substructures are matched by id and bonds by the ids of their atoms,
+    // in either direction, so a renumbering shows up as many differences.
+    // Descriptions are not compared.
+    // Input:
+    //     first: structure before the change
+    //     second: structure after the change
+    //     coord_tol: largest difference in each coordinate not reported
+    //     charge_tol: largest difference in charges not reported
+    let mut differences: Vec<Difference> = Vec::new();
+
+    let mut report = Report {
+        section: "MOLECULE",
+        differences: &mut differences,
+    };
+    for ((field, old), (_, new)) in molecule_fields(first.molecule.as_ref())
+        .into_iter()
+        .zip(molecule_fields(second.molecule.as_ref()))
+    {
+        report.field(None, field, old, new);
+    }
+
+    let mut report = Report {
+        section: "ATOM",
+        differences: &mut differences,
+    };
+    let atoms: HashMap<IdInt, &Atom> = second
+        .atom
+        .iter()
+        .map(|atom| (atom.atom_id, atom))
+        .collect();
+    for atom in &first.atom {
+        match atoms.get(&atom.atom_id) {
+            Some(other) => compare_atoms(&mut report, atom, other, coord_tol, charge_tol),
+            None => report.record(atom.atom_id, Some(atom_text(atom)), None),
+        }
+    }
+    let ids: HashMap<IdInt, &Atom> = first.atom.iter().map(|atom| (atom.atom_id, atom)).collect();
+    for atom in second
+        .atom
+        .iter()
+        .filter(|atom| !ids.contains_key(&atom.atom_id))
+    {
+        report.record(atom.atom_id, None, Some(atom_text(atom)));
+    }
+
+    let mut report = Report {
+        section: "BOND",
+        differences: &mut differences,
+    };
+    let bonds: HashMap<(IdInt, IdInt), &Bond> = second
+        .bond
+        .iter()
+        .map(|bond| (bond_key(bond), bond))
+        .collect();
+    for bond in &first.bond {
+        match bonds.get(&bond_key(bond)) {
+            Some(other) => {
+                let id = Some(bond.bond_id);
+                report.field(
+                    id,
+                    "bond_type",
+                    Some(bond.bond_type.clone()),
+                    Some(other.bond_type.clone()),
+                );
+                report.field(
+                    id,
+                    "status_bit",
+                    bond.status_bit.clone(),
+                    other.status_bit.clone(),
+                );
+            }
+            None => report.record(bond.bond_id, Some(bond_text(bond)), None),
+        }
+    }
+    let keys: HashMap<(IdInt, IdInt), &Bond> = first
+        .bond
+        .iter()
+        .map(|bond| (bond_key(bond), bond))
+        .collect();
+    for bond in second
+        .bond
+        .iter()
+        .filter(|bond| !keys.contains_key(&bond_key(bond)))
+    {
+        report.record(bond.bond_id, None, Some(bond_text(bond)));
+    }
+
+    let mut report = Report {
+        section: "SUBSTRUCTURE",
+        differences: &mut differences,
+    };
+    let substructures: HashMap<IdInt, &Substructure> = second
+        .substructure
+        .iter()
+        .map(|substructure| (substructure.subst_id, substructure))
+        .collect();
+    for substructure in &first.substructure {
+        match substructures.get(&substructure.subst_id) {
+            Some(other) => compare_substructures(&mut report, substructure, other),
+            None => report.record(
+                substructure.subst_id,
+                Some(substructure.subst_name.clone()),
+                None,
+            ),
+        }
+    }
+    let ids: HashMap<IdInt, &Substructure> = first
+        .substructure
+        .iter()
+        .map(|substructure| (substructure.subst_id, substructure))
+        .collect();
+    for substructure in second
+        .substructure
+        .iter()
+        .filter(|substructure| !ids.contains_key(&substructure.subst_id))
+    {
+        report.record(
+            substructure.subst_id,
+            None,
+            Some(substructure.subst_name.clone()),
+        );
+    }
+
+    differences
+}
@@ -0,0 +1,566 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::elements::lookup;
+use crate::error::{Mol2Error, Result};
+use crate::graph::adjacency;
+use crate::pdb::residue_name;
+use crate::sdf::element;
+use crate::{AltType, Atom, Bond, IdInt, Mol2, Substructure};
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+
+fn renumber_atoms(mol2: &mut Mol2) -> usize {
+    // Number atoms from 1 in their current order and update the bonds and
+    // substructure roots referencing them. References to atoms that do not
+    // exist are left alone. Returns the number of atoms with a new id.
+    mol2.graph_changed();
+    let mut new_ids: HashMap<IdInt, IdInt> = HashMap::new();
+    let mut changed = 0;
+    for (index, atom) in mol2.atom.iter_mut().enumerate() {
+        let atom_id = (index + 1) as IdInt;
+        // With duplicate ids references go to the first atom
+        new_ids.entry(atom.atom_id).or_insert(atom_id);
+        if atom.atom_id != atom_id {
+            atom.atom_id = atom_id;
+            changed += 1;
+        }
+    }
+    for bond in mol2.bond.iter_mut() {
+        bond.origin_atom_id = *new_ids
+            .get(&bond.origin_atom_id)
+            .unwrap_or(&bond.origin_atom_id);
+        bond.target_atom_id = *new_ids
+            .get(&bond.target_atom_id)
+            .unwrap_or(&bond.target_atom_id);
+    }
+    for substructure in mol2.substructure.iter_mut() {
+        substructure.root_atom = *new_ids
+            .get(&substructure.root_atom)
+            .unwrap_or(&substructure.root_atom);
+    }
+    for (atom_id, _) in mol2
+        .alt_type
+        .iter_mut()
+        .flat_map(|set| set.types.iter_mut())
+    {
+        *atom_id = *new_ids.get(atom_id).unwrap_or(atom_id);
+    }
+    changed
+}
+
+fn renumber_bonds(mol2: &mut Mol2) -> usize {
+    // Number bonds from 1 in their current order, returns the number of
+    // bonds with a new id
+    let mut changed = 0;
+    for (index, bond) in mol2.bond.iter_mut().enumerate() {
+        let bond_id = (index + 1) as IdInt;
+        if bond.bond_id != bond_id {
+            bond.bond_id = bond_id;
+            changed += 1;
+        }
+    }
+    changed
+}
+
+fn renumber_substructures(mol2: &mut Mol2) -> usize {
+    // Number substructures from 1 in their current order and update the
+    // atoms belonging to them, returns the number of substructures with a new id
+    let mut new_ids: HashMap<IdInt, IdInt> = HashMap::new();
+    let mut changed = 0;
+    for (index, substructure) in mol2.substructure.iter_mut().enumerate() {
+        let subst_id = (index + 1) as IdInt;
+        new_ids.entry(substructure.subst_id).or_insert(subst_id);
+        if substructure.subst_id != subst_id {
+            substructure.subst_id = subst_id;
+            changed += 1;
+        }
+    }
+    for atom in mol2.atom.iter_mut() {
+        if let Some(subst_id) = atom.subst_id {
+            atom.subst_id = Some(*new_ids.get(&subst_id).unwrap_or(&subst_id));
+        }
+    }
+    changed
+}
+
+fn update_counts(mol2: &mut Mol2, all: bool) -> Vec<String> {
+    // Set counts in the MOLECULE section to the number of records, returns
+    // the changes made
+    // Input:
+    //     all: also set counts that were not given, otherwise they are left out
+    let mut changes: Vec<String> = Vec::new();
+    let found = [mol2.atom.len(), mol2.bond.len(), mol2.substructure.len()];
+    let molecule = match mol2.molecule.as_mut() {
+        Some(molecule) => molecule,
+        None => return changes,
+    };
+    for ((name, count), found) in [
+        ("num_atoms", &mut molecule.num_atoms),
+        ("num_bonds", &mut molecule.num_bonds),
+        ("num_subst", &mut molecule.num_subst),
+    ]
+    .into_iter()
+    .zip(found)
+    {
+        match *count {
+            Some(declared) if declared == found => continue,
+            Some(declared) => {
+                changes.push(format!("{} changed from {} to {}", name, declared, found))
+            }
+            None if all => changes.push(format!("{} set to {}", name, found)),
+            None => continue,
+        }
+        *count = Some(found);
+    }
+    changes
+}
+
+fn trim(text: &mut String) -> bool {
+    // Strip surrounding whitespace in place, returns whether anything was removed
+    let trimmed = text.trim();
+    if trimmed.len() == text.len() {
+        return false;
+    }
+    *text = trimmed.to_owned();
+    true
+}
+
+pub fn remove_atoms(mol2: &mut Mol2, ids: &[IdInt]) -> Result<()> {
+    // Remove atoms together with the bonds touching them. Remaining atoms
+    // and bonds are renumbered from 1 in their current order, substructures
+    // left without atoms are removed and counts in the MOLECULE section are
+    // updated.
+    // Input:
+    //     mol2: structure to modify
+    //     ids: ids of the atoms to remove
+    let atom_ids: HashSet<IdInt> = mol2.atom.iter().map(|atom| atom.atom_id).collect();
+    let mut missing: Vec<IdInt> = ids
+        .iter()
+        .copied()
+        .filter(|id| !atom_ids.contains(id))
+        .collect();
+    if !missing.is_empty() {
+        missing.sort_unstable();
+        missing.dedup();
+        return Err(Mol2Error::InvalidArgument(format!(
+            "no atoms with ids {}",
+            missing
+                .iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        )));
+    }
+    let removed: HashSet<IdInt> = ids.iter().copied().collect();
+
+    mol2.atom.retain(|atom| !removed.contains(&atom.atom_id));
+    for set in mol2.alt_type.iter_mut() {
+        set.types.retain(|(atom_id, _)| !removed.contains(atom_id));
+    }
+    mol2.bond.retain(|bond| {
+        !removed.contains(&bond.origin_atom_id) && !removed.contains(&bond.target_atom_id)
+    });
+
+    // First remaining atom of every substructure, a new root if the old one was removed
+    let mut first_atoms: HashMap<IdInt, IdInt> = HashMap::new();
+    for atom in &mol2.atom {
+        if let Some(subst_id) = atom.subst_id {
+            first_atoms.entry(subst_id).or_insert(atom.atom_id);
+        }
+    }
+    mol2.substructure.retain(|substructure| {
+        !removed.contains(&substructure.root_atom)
+            || first_atoms.contains_key(&substructure.subst_id)
+    });
+    for substructure in mol2.substructure.iter_mut() {
+        if removed.contains(&substructure.root_atom) {
+            substructure.root_atom = first_atoms[&substructure.subst_id];
+        }
+    }
+
+    renumber_atoms(mol2);
+    renumber_bonds(mol2);
+    update_counts(mol2, false);
+    Ok(())
+}
+
+pub fn keep_atoms(mol2: &Mol2, ids: &HashSet<IdInt>) -> Mol2 {
+    // Copy of a structure with only the given atoms and the bonds between
+    // them. Substructures follow the rules of remove_atoms, atoms, bonds and
+    // substructures are renumbered from 1 and counts are updated.
+    // Input:
+    //     mol2: structure to copy from
+    //     ids: ids of the atoms to keep
+    let atom: Vec<Atom> = mol2
+        .atom
+        .iter()
+        .filter(|atom| ids.contains(&atom.atom_id))
+        .cloned()
+        .collect();
+    let bond: Vec<Bond> = mol2
+        .bond
+        .iter()
+        .filter(|bond| ids.contains(&bond.origin_atom_id) && ids.contains(&bond.target_atom_id))
+        .cloned()
+        .collect();
+    let mut first_atoms: HashMap<IdInt, IdInt> = HashMap::new();
+    for atom in &atom {
+        if let Some(subst_id) = atom.subst_id {
+            first_atoms.entry(subst_id).or_insert(atom.atom_id);
+        }
+    }
+    let substructure: Vec<Substructure> = mol2
+        .substructure
+        .iter()
+        .filter_map(|substructure| {
+            let mut substructure = substructure.clone();
+            if !ids.contains(&substructure.root_atom) {
+                substructure.root_atom = *first_atoms.get(&substructure.subst_id)?;
+            }
+            Some(substructure)
+        })
+        .collect();
+    let mut kept = Mol2 {
+        molecule: mol2.molecule.clone(),
+        atom,
+        bond,
+        substructure,
+        desc: mol2.desc.clone(),
+        alt_type: mol2
+            .alt_type
+            .iter()
+            .map(|set| AltType {
+                scheme: set.scheme.clone(),
+                types: set
+                    .types
+                    .iter()
+                    .filter(|(atom_id, _)| ids.contains(atom_id))
+                    .cloned()
+                    .collect(),
+            })
+            .collect(),
+        crysin: mol2.crysin.clone(),
+        source: None,
+        source_hash: None,
+        adjacency: OnceLock::new(),
+        extras: mol2.extras.clone(),
+    };
+    renumber_atoms(&mut kept);
+    renumber_bonds(&mut kept);
+    renumber_substructures(&mut kept);
+    update_counts(&mut kept, false);
+    kept
+}
+
+pub fn split_components(mol2: &Mol2) -> Vec<Mol2> {
+    // One structure per connected component of the bond graph, in the order
+    // of their first atoms, see keep_atoms
+    let neighbours = adjacency(mol2);
+    let mut seen: HashSet<IdInt> = HashSet::new();
+    let mut components: Vec<Mol2> = Vec::new();
+    for atom in &mol2.atom {
+        if !seen.insert(atom.atom_id) {
+            continue;
+        }
+        let mut component: HashSet<IdInt> = HashSet::from([atom.atom_id]);
+        let mut queue: Vec<IdInt> = vec![atom.atom_id];
+        while let Some(atom_id) = queue.pop() {
+            for neighbour in neighbours.get(&atom_id).into_iter().flatten() {
+                if seen.insert(*neighbour) {
+                    component.insert(*neighbour);
+                    queue.push(*neighbour);
+                }
+            }
+        }
+        components.push(keep_atoms(mol2, &component));
+    }
+    components
+}
+
+// Substructure given either by its id or by its name
+pub enum SubstKey<'a> {
+    Id(IdInt),
+    Name(&'a str),
+}
+
+pub fn extract_substructure(mol2: &Mol2, key: SubstKey) -> Result<Mol2> {
+    // Copy of a single substructure, e.g. a ligand out of a complex, with
+    // the bonds between its atoms, see keep_atoms. A name matches all
+    // substructures of that name.
+    let atoms: HashSet<IdInt> = match key {
+        SubstKey::Id(subst_id) => mol2
+            .atom
+            .iter()
+            .filter(|atom| atom.subst_id == Some(subst_id))
+            .map(|atom| atom.atom_id)
+            .collect(),
+        SubstKey::Name(subst_name) => {
+            let subst_ids: HashSet<IdInt> = mol2
+                .substructure
+                .iter()
+                .filter(|substructure| substructure.subst_name == subst_name)
+                .map(|substructure| substructure.subst_id)
+                .collect();
+            mol2.atom
+                .iter()
+                .filter(|atom| {
+                    atom.subst_name.as_deref() == Some(subst_name)
+                        || atom
+                            .subst_id
+                            .is_some_and(|subst_id| subst_ids.contains(&subst_id))
+                })
+                .map(|atom| atom.atom_id)
+                .collect()
+        }
+    };
+    if atoms.is_empty() {
+        return Err(Mol2Error::InvalidArgument(match key {
+            SubstKey::Id(subst_id) => format!("no atoms in substructure {}", subst_id),
+            SubstKey::Name(subst_name) => format!("no atoms in substructure {}", subst_name),
+        }));
+    }
+    Ok(keep_atoms(mol2, &atoms))
+}
+
+pub fn hydrogens(mol2: &Mol2, polar_only: bool) -> Vec<IdInt> {
+    // Ids of hydrogen atoms
+    // Input:
+    //     polar_only: only hydrogens bonded to nitrogen, oxygen or sulfur
+    let types: HashMap<IdInt, &str> = mol2
+        .atom
+        .iter()
+        .map(|atom| (atom.atom_id, element(&atom.atom_type)))
+        .collect();
+    let mut polar: HashSet<IdInt> = HashSet::new();
+    for bond in &mol2.bond {
+        for (atom, partner) in [
+            (bond.origin_atom_id, bond.target_atom_id),
+            (bond.target_atom_id, bond.origin_atom_id),
+        ] {
+            if matches!(types.get(&partner), Some(&"N") | Some(&"O") | Some(&"S")) {
+                polar.insert(atom);
+            }
+        }
+    }
+    mol2.atom
+        .iter()
+        .filter(|atom| types[&atom.atom_id] == "H")
+        .filter(|atom| !polar_only || polar.contains(&atom.atom_id))
+        .map(|atom| atom.atom_id)
+        .collect()
+}
+
+pub fn remove_solvent(mol2: &mut Mol2, names: &[String]) -> Result<usize> {
+    // Remove the atoms of substructures with the given residue names, e.g.
+    // waters and ions, see remove_atoms. Returns the number of removed atoms.
+    // Input:
+    //     mol2: structure to modify
+    //     names: residue names, matched without regard to case against the
+    //            substructure name without its residue number ("HOH" for
+    //            "HOH301") or the substructure sub_type
+    let matches = |name: &str| {
+        names
+            .iter()
+            .any(|solvent| solvent.eq_ignore_ascii_case(residue_name(name)))
+    };
+    let subst_ids: HashSet<IdInt> = mol2
+        .substructure
+        .iter()
+        .filter(|substructure| {
+            matches(&substructure.subst_name)
+                || substructure.sub_type.as_deref().is_some_and(matches)
+        })
+        .map(|substructure| substructure.subst_id)
+        .collect();
+    let ids: Vec<IdInt> = mol2
+        .atom
+        .iter()
+        .filter(|atom| {
+            atom.subst_name.as_deref().is_some_and(matches)
+                || atom
+                    .subst_id
+                    .is_some_and(|subst_id| subst_ids.contains(&subst_id))
+        })
+        .map(|atom| atom.atom_id)
+        .collect();
+    if !ids.is_empty() {
+        remove_atoms(mol2, &ids)?;
+    }
+    Ok(ids.len())
+}
+
+pub fn renumber(mol2: &mut Mol2) -> Vec<String> {
+    // Number atoms, bonds and substructures from 1 in their current order
+    // and update all references, returns the changes made. With duplicate
+    // ids references go to the first record with that id.
+    let mut changes: Vec<String> = Vec::new();
+    for (name, changed) in [
+        ("atoms", renumber_atoms(mol2)),
+        ("bonds", renumber_bonds(mol2)),
+        ("substructures", renumber_substructures(mol2)),
+    ] {
+        if changed > 0 {
+            changes.push(format!("renumbered {} {}", changed, name));
+        }
+    }
+    changes
+}
+
+// Keys atoms can be sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AtomOrder {
+    // Carbon, hydrogen, then alphabetically, atoms without element last
+    #[default]
+    Element,
+    // Atom name
+    Name,
+    // Substructure id, atoms without substructure last
+    Subst,
+}
+
+impl AtomOrder {
+    pub fn from_name(name: &str) -> Result<AtomOrder> {
+        // Translate a user given sort key
+        match name {
+            "element" => Ok(AtomOrder::Element),
+            "name" => Ok(AtomOrder::Name),
+            "subst" => Ok(AtomOrder::Subst),
+            _ => Err(Mol2Error::InvalidArgument(format!(
+                "unknown sort key {}, expected element, name or subst",
+                name
+            ))),
+        }
+    }
+}
+
+// Keys substructures can be sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SubstructureOrder {
+    // Chain, substructures without a chain last
+    #[default]
+    Chain,
+    // Substructure id
+    SubstId,
+    // Substructure name
+    Name,
+}
+
+impl SubstructureOrder {
+    pub fn from_name(name: &str) -> Result<SubstructureOrder> {
+        // Translate a user given sort key
+        match name {
+            "chain" => Ok(SubstructureOrder::Chain),
+            "subst_id" => Ok(SubstructureOrder::SubstId),
+            "name" => Ok(SubstructureOrder::Name),
+            _ => Err(Mol2Error::InvalidArgument(format!(
+                "unknown sort key {}, expected chain, subst_id or name",
+                name
+            ))),
+        }
+    }
+}
+
+pub fn sort_atoms(mol2: &mut Mol2, by: AtomOrder) {
+    // Reorder atoms and number them from 1 in the new order, bonds and
+    // substructure roots are updated. The sort is stable so atoms with the
+    // same key keep their relative order.
+    // Input:
+    //     mol2: structure to modify
+    //     by: key to sort by
+    match by {
+        AtomOrder::Element => mol2.atom.sort_by_cached_key(|atom| {
+            match lookup(&atom.atom_type).map(|(symbol, _, _)| symbol) {
+                Some("C") => (0, ""),
+                Some("H") => (1, ""),
+                Some(symbol) => (2, symbol),
+                None => (3, ""),
+            }
+        }),
+        AtomOrder::Name => mol2
+            .atom
+            .sort_by(|first, second| first.atom_name.cmp(&second.atom_name)),
+        AtomOrder::Subst => mol2
+            .atom
+            .sort_by_key(|atom| (atom.subst_id.is_none(), atom.subst_id)),
+    }
+    renumber_atoms(mol2);
+}
+
+pub fn sort_substructures(mol2: &mut Mol2, by: SubstructureOrder) {
+    // Reorder substructures and number them from 1 in the new order, atoms
+    // are updated to the new ids but keep their order. The sort is stable
+    // so substructures with the same key keep their relative order.
+    // Input:
+    //     mol2: structure to modify
+    //     by: key to sort by
+    match by {
+        SubstructureOrder::Chain => mol2.substructure.sort_by(|first, second| {
+            (first.chain.is_none(), &first.chain).cmp(&(second.chain.is_none(), &second.chain))
+        }),
+        SubstructureOrder::SubstId => mol2
+            .substructure
+            .sort_by_key(|substructure| substructure.subst_id),
+        SubstructureOrder::Name => mol2
+            .substructure
+            .sort_by(|first, second| first.subst_name.cmp(&second.subst_name)),
+    }
+    renumber_substructures(mol2);
+}
+
+pub fn normalize(mol2: &mut Mol2, counts: bool, renumber: bool, trim_names: bool) -> Vec<String> {
+    // Clean up a structure before storing it, returns a description of
+    // every change made, empty if nothing had to be changed
+    // Input:
+    //     mol2: structure to modify
+    //     counts: set all counts in the MOLECULE section to the number of records
+    //     renumber: number atoms, bonds and substructures from 1 in their current order
+    //     trim_names: strip whitespace around molecule, atom and substructure names
+    let mut changes: Vec<String> = Vec::new();
+    if trim_names {
+        if let Some(molecule) = mol2.molecule.as_mut() {
+            if trim(&mut molecule.mol_name) {
+                changes.push("trimmed molecule name".to_owned());
+            }
+        }
+        let mut trimmed = 0;
+        for atom in mol2.atom.iter_mut() {
+            let name = trim(&mut atom.atom_name);
+            let subst_name = atom.subst_name.as_mut().is_some_and(trim);
+            if name || subst_name {
+                trimmed += 1;
+            }
+        }
+        if trimmed > 0 {
+            changes.push(format!("trimmed names of {} atoms", trimmed));
+        }
+        let trimmed = mol2
+            .substructure
+            .iter_mut()
+            .map(|substructure| trim(&mut substructure.subst_name))
+            .filter(|trimmed| *trimmed)
+            .count();
+        if trimmed > 0 {
+            changes.push(format!("trimmed names of {} substructures", trimmed));
+        }
+    }
+    if renumber {
+        changes.extend(self::renumber(mol2));
+    }
+    if counts {
+        changes.extend(update_counts(mol2, true));
+    }
+    changes
+}
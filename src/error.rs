@@ -0,0 +1,70 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use pyo3::exceptions::{PyIOError, PyRuntimeError, PyValueError};
+use pyo3::PyErr;
+use thiserror::Error;
+
+// Errors returned by the library functions
+#[derive(Debug, Error)]
+pub enum Mol2Error {
+    // Malformed line in a mol2 file
+    #[error("line {line}: {message}")]
+    Parse { line: usize, message: String },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("compression error: {0}")]
+    Compression(String),
+    #[error("database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    // Failure to (de)serialize database blobs
+    #[error("serialization error: {0}")]
+    Serialization(#[from] bincode::Error),
+    // Failure to build or serialize Arrow tables
+    #[cfg(feature = "arrow")]
+    #[error("arrow error: {0}")]
+    Arrow(#[from] arrow_schema::ArrowError),
+    #[cfg(feature = "hdf5")]
+    #[error("HDF5 error: {0}")]
+    Hdf5(#[from] hdf5::Error),
+    #[cfg(feature = "npz")]
+    #[error("npz error: {0}")]
+    Npz(#[from] zip::result::ZipError),
+    // Database written by a newer version of the library, see db_migrate
+    #[error("database has schema version {found}, newer than version {supported} read by serde_mol2 {library}; upgrade serde_mol2 to open it")]
+    NewerSchema {
+        found: i64,
+        supported: i64,
+        library: &'static str,
+    },
+    // Unknown option values and similar mistakes of the caller
+    #[error("{0}")]
+    InvalidArgument(String),
+}
+
+pub type Result<T> = std::result::Result<T, Mol2Error>;
+
+impl From<Mol2Error> for PyErr {
+    fn from(error: Mol2Error) -> PyErr {
+        // Translate errors into python exceptions at the module boundary
+        match error {
+            Mol2Error::Io(error) => PyIOError::new_err(error.to_string()),
+            Mol2Error::Parse { .. } | Mol2Error::InvalidArgument(_) => {
+                PyValueError::new_err(error.to_string())
+            }
+            _ => PyRuntimeError::new_err(error.to_string()),
+        }
+    }
+}
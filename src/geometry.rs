@@ -0,0 +1,479 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::elements::masses;
+use crate::error::{Mol2Error, Result};
+use crate::sdf::element;
+use crate::{CoordFloat, IdInt, Mol2};
+use rayon::prelude::*;
+use std::collections::{HashMap, VecDeque};
+
+type Matrix = [[CoordFloat; 3]; 3];
+
+fn weighted_center(mol2: &Mol2, weights: &[CoordFloat]) -> Result<[CoordFloat; 3]> {
+    // Weighted average of atom positions
+    let total: CoordFloat = weights.iter().sum();
+    if total <= 0.0 {
+        return Err(Mol2Error::InvalidArgument(
+            "cannot compute the center of a structure without atoms".to_owned(),
+        ));
+    }
+    let mut center = [0.0; 3];
+    for (atom, weight) in mol2.atom.iter().zip(weights) {
+        center[0] += atom.x * weight;
+        center[1] += atom.y * weight;
+        center[2] += atom.z * weight;
+    }
+    Ok(center.map(|value| value / total))
+}
+
+pub fn centroid(mol2: &Mol2) -> Result<[CoordFloat; 3]> {
+    // Geometric center of all atoms
+    weighted_center(mol2, &vec![1.0; mol2.atom.len()])
+}
+
+pub fn center_of_mass(mol2: &Mol2) -> Result<[CoordFloat; 3]> {
+    // Center of mass with elements taken from SYBYL atom types
+    weighted_center(mol2, &masses(mol2)?)
+}
+
+fn apply(mol2: &mut Mol2, matrix: &Matrix, shift: [CoordFloat; 3]) {
+    // Replace every position r with matrix * r + shift
+    for atom in mol2.atom.iter_mut() {
+        let position = [atom.x, atom.y, atom.z];
+        let [x, y, z] = [0, 1, 2].map(|row| {
+            matrix[row][0] * position[0]
+                + matrix[row][1] * position[1]
+                + matrix[row][2] * position[2]
+                + shift[row]
+        });
+        atom.x = x;
+        atom.y = y;
+        atom.z = z;
+    }
+}
+
+pub fn translate(mol2: &mut Mol2, shift: [CoordFloat; 3]) {
+    // Move all atoms by the same vector
+    for atom in mol2.atom.iter_mut() {
+        atom.x += shift[0];
+        atom.y += shift[1];
+        atom.z += shift[2];
+    }
+}
+
+pub fn rotate(mol2: &mut Mol2, axis: [CoordFloat; 3], angle: CoordFloat) -> Result<()> {
+    // Rotate all atoms around an axis through the origin, counterclockwise
+    // when looking against the axis
+    // Input:
+    //     axis: direction of the axis, does not have to be normalized
+    //     angle: rotation angle in radians
+    let length = axis
+        .iter()
+        .map(|value| value * value)
+        .sum::<CoordFloat>()
+        .sqrt();
+    if length == 0.0 || !length.is_finite() {
+        return Err(Mol2Error::InvalidArgument(format!(
+            "cannot rotate around axis {:?}",
+            axis
+        )));
+    }
+    let [x, y, z] = axis.map(|value| value / length);
+    let (sin, cos) = angle.sin_cos();
+    let rest = 1.0 - cos;
+    // Rodrigues' rotation formula
+    let matrix = [
+        [
+            cos + x * x * rest,
+            x * y * rest - z * sin,
+            x * z * rest + y * sin,
+        ],
+        [
+            y * x * rest + z * sin,
+            cos + y * y * rest,
+            y * z * rest - x * sin,
+        ],
+        [
+            z * x * rest - y * sin,
+            z * y * rest + x * sin,
+            cos + z * z * rest,
+        ],
+    ];
+    apply(mol2, &matrix, [0.0; 3]);
+    Ok(())
+}
+
+pub fn transform(mol2: &mut Mol2, matrix: [[CoordFloat; 4]; 4]) -> Result<()> {
+    // Apply an affine transformation given as a 4x4 matrix acting on
+    // column vectors (x, y, z, 1), the last row must be (0, 0, 0, 1)
+    if matrix[3] != [0.0, 0.0, 0.0, 1.0] {
+        return Err(Mol2Error::InvalidArgument(format!(
+            "last row of the transformation matrix must be [0, 0, 0, 1], got {:?}",
+            matrix[3]
+        )));
+    }
+    let linear = [0, 1, 2].map(|row| [matrix[row][0], matrix[row][1], matrix[row][2]]);
+    let shift = [0, 1, 2].map(|row| matrix[row][3]);
+    apply(mol2, &linear, shift);
+    Ok(())
+}
+
+pub fn distance_matrix(mol2: &Mol2, heavy_only: bool) -> (usize, Vec<CoordFloat>) {
+    // Distances between all pairs of atoms, row by row. Rows are computed in
+    // parallel as protein sized structures have millions of pairs.
+    // Input:
+    //     heavy_only: leave out hydrogens, dummy atoms and lone pairs
+    // Output: number of atoms included and the flattened matrix
+    let positions: Vec<[CoordFloat; 3]> = mol2
+        .atom
+        .iter()
+        .filter(|atom| !heavy_only || !matches!(element(&atom.atom_type), "H" | "*"))
+        .map(|atom| [atom.x, atom.y, atom.z])
+        .collect();
+    let count = positions.len();
+    let mut matrix: Vec<CoordFloat> = vec![0.0; count * count];
+    if count == 0 {
+        return (0, matrix);
+    }
+    matrix
+        .par_chunks_mut(count)
+        .zip(positions.par_iter())
+        .for_each(|(row, origin)| {
+            for (distance, target) in row.iter_mut().zip(&positions) {
+                *distance = ((origin[0] - target[0]).powi(2)
+                    + (origin[1] - target[1]).powi(2)
+                    + (origin[2] - target[2]).powi(2))
+                .sqrt();
+            }
+        });
+    (count, matrix)
+}
+
+pub fn atoms_within(
+    mol2: &Mol2,
+    points: &[[CoordFloat; 3]],
+    cutoff: CoordFloat,
+) -> Result<Vec<usize>> {
+    // Find atoms closer than the cutoff to any of the points. Atoms are
+    // sorted into a grid of cells as large as the cutoff, so only the cells
+    // around a point have to be checked.
+    // Input:
+    //     points: positions to search around
+    //     cutoff: largest distance included
+    // Output: positions of the atoms found in the atom list, in order
+    if !(cutoff > 0.0 && cutoff.is_finite()) {
+        return Err(Mol2Error::InvalidArgument(format!(
+            "cutoff must be a positive number, got {}",
+            cutoff
+        )));
+    }
+    let cell = |position: [CoordFloat; 3]| position.map(|value| (value / cutoff).floor() as i64);
+    let mut grid: HashMap<[i64; 3], Vec<usize>> = HashMap::new();
+    for (index, atom) in mol2.atom.iter().enumerate() {
+        grid.entry(cell([atom.x, atom.y, atom.z]))
+            .or_default()
+            .push(index);
+    }
+    let mut found = vec![false; mol2.atom.len()];
+    for point in points {
+        let [x, y, z] = cell(*point);
+        for neighbor in (-1..=1).flat_map(|dx| {
+            (-1..=1).flat_map(move |dy| (-1..=1).map(move |dz| [x + dx, y + dy, z + dz]))
+        }) {
+            for &index in grid.get(&neighbor).into_iter().flatten() {
+                let atom = &mol2.atom[index];
+                let distance = (atom.x - point[0]).powi(2)
+                    + (atom.y - point[1]).powi(2)
+                    + (atom.z - point[2]).powi(2);
+                if distance <= cutoff * cutoff {
+                    found[index] = true;
+                }
+            }
+        }
+    }
+    Ok((0..found.len()).filter(|index| found[*index]).collect())
+}
+
+fn position(mol2: &Mol2, atom_id: IdInt) -> Result<[CoordFloat; 3]> {
+    // Coordinates of an atom given by its id
+    mol2.atom
+        .iter()
+        .find(|atom| atom.atom_id == atom_id)
+        .map(|atom| [atom.x, atom.y, atom.z])
+        .ok_or_else(|| Mol2Error::InvalidArgument(format!("no atom with id {}", atom_id)))
+}
+
+fn difference(a: [CoordFloat; 3], b: [CoordFloat; 3]) -> [CoordFloat; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: [CoordFloat; 3], b: [CoordFloat; 3]) -> CoordFloat {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [CoordFloat; 3], b: [CoordFloat; 3]) -> [CoordFloat; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+pub fn distance(mol2: &Mol2, ids: [IdInt; 2]) -> Result<CoordFloat> {
+    // Distance between two atoms
+    let vector = difference(position(mol2, ids[0])?, position(mol2, ids[1])?);
+    Ok(dot(vector, vector).sqrt())
+}
+
+pub fn angle(mol2: &Mol2, ids: [IdInt; 3]) -> Result<CoordFloat> {
+    // Angle between three atoms in degrees, the second one is the vertex
+    let vertex = position(mol2, ids[1])?;
+    let first = difference(position(mol2, ids[0])?, vertex);
+    let second = difference(position(mol2, ids[2])?, vertex);
+    // atan2 is more accurate than acos for angles close to 0 and 180
+    let sin = dot(cross(first, second), cross(first, second)).sqrt();
+    Ok(sin.atan2(dot(first, second)).to_degrees())
+}
+
+pub fn dihedral(mol2: &Mol2, ids: [IdInt; 4]) -> Result<CoordFloat> {
+    // Dihedral angle of four atoms in degrees, between -180 and 180, positive
+    // for a clockwise rotation of the first bond looking along the middle one
+    let [a, b, c, d] = [
+        position(mol2, ids[0])?,
+        position(mol2, ids[1])?,
+        position(mol2, ids[2])?,
+        position(mol2, ids[3])?,
+    ];
+    let first = difference(b, a);
+    let middle = difference(c, b);
+    let last = difference(d, c);
+    let normal_first = cross(first, middle);
+    let normal_last = cross(middle, last);
+    let length = dot(middle, middle).sqrt();
+    let y = dot(cross(normal_first, normal_last), middle) / length;
+    let x = dot(normal_first, normal_last);
+    Ok(y.atan2(x).to_degrees())
+}
+
+// Periodic cell with lattice vectors as columns, and its inverse
+struct Cell {
+    vectors: Matrix,
+    inverse: Matrix,
+}
+
+impl Cell {
+    fn new(parameters: [CoordFloat; 6]) -> Result<Cell> {
+        // Cell from lengths a, b, c and angles alpha, beta, gamma in degrees,
+        // the same parameters as in a CRYSIN section. Vector a is along x
+        // and b lies in the xy plane.
+        let invalid = || {
+            Mol2Error::InvalidArgument(format!(
+                "invalid cell parameters {:?}, expected lengths a, b, c and angles alpha, beta, gamma in degrees",
+                parameters
+            ))
+        };
+        let [a, b, c, alpha, beta, gamma] = parameters;
+        if [a, b, c]
+            .iter()
+            .any(|length| !(*length > 0.0 && length.is_finite()))
+            || [alpha, beta, gamma]
+                .iter()
+                .any(|angle| !(*angle > 0.0 && *angle < 180.0))
+        {
+            return Err(invalid());
+        }
+        let [cos_alpha, cos_beta, cos_gamma] =
+            [alpha, beta, gamma].map(|angle| angle.to_radians().cos());
+        let sin_gamma = gamma.to_radians().sin();
+        let cy = (cos_alpha - cos_beta * cos_gamma) / sin_gamma;
+        let cz_squared = 1.0 - cos_beta * cos_beta - cy * cy;
+        if cz_squared <= 0.0 {
+            return Err(invalid());
+        }
+        let vectors = [
+            [a, b * cos_gamma, c * cos_beta],
+            [0.0, b * sin_gamma, c * cy],
+            [0.0, 0.0, c * cz_squared.sqrt()],
+        ];
+        // Upper triangular, so the inverse is upper triangular as well
+        let [[xa, xb, xc], [_, yb, yc], [_, _, zc]] = vectors;
+        let inverse = [
+            [
+                1.0 / xa,
+                -xb / (xa * yb),
+                (xb * yc - xc * yb) / (xa * yb * zc),
+            ],
+            [0.0, 1.0 / yb, -yc / (yb * zc)],
+            [0.0, 0.0, 1.0 / zc],
+        ];
+        Ok(Cell { vectors, inverse })
+    }
+    fn multiply(matrix: &Matrix, vector: [CoordFloat; 3]) -> [CoordFloat; 3] {
+        [0, 1, 2].map(|row| dot(matrix[row], vector))
+    }
+    fn fractional(&self, position: [CoordFloat; 3]) -> [CoordFloat; 3] {
+        Cell::multiply(&self.inverse, position)
+    }
+    fn cartesian(&self, fractional: [CoordFloat; 3]) -> [CoordFloat; 3] {
+        Cell::multiply(&self.vectors, fractional)
+    }
+}
+
+pub fn wrap(mol2: &mut Mol2, cell: [CoordFloat; 6]) -> Result<()> {
+    // Move every atom into the periodic cell spanned from the origin
+    // Input:
+    //     cell: lengths a, b, c and angles alpha, beta, gamma in degrees
+    let cell = Cell::new(cell)?;
+    for atom in mol2.atom.iter_mut() {
+        let fractional = cell
+            .fractional([atom.x, atom.y, atom.z])
+            .map(|value| value - value.floor());
+        [atom.x, atom.y, atom.z] = cell.cartesian(fractional);
+    }
+    Ok(())
+}
+
+pub fn unwrap(mol2: &mut Mol2, cell: [CoordFloat; 6]) -> Result<()> {
+    // Make molecules split across the cell boundary whole. Starting from the
+    // first atom of every group of bonded atoms, each bonded atom is moved by
+    // whole cell vectors next to the atom it was reached from.
+    // Input:
+    //     cell: lengths a, b, c and angles alpha, beta, gamma in degrees
+    let cell = Cell::new(cell)?;
+    let index: HashMap<IdInt, usize> = mol2
+        .atom
+        .iter()
+        .enumerate()
+        .map(|(position, atom)| (atom.atom_id, position))
+        .collect();
+    let mut neighbors: Vec<Vec<usize>> = vec![Vec::new(); mol2.atom.len()];
+    for bond in &mol2.bond {
+        // Bonds to missing atoms are ignored
+        if let (Some(&origin), Some(&target)) = (
+            index.get(&bond.origin_atom_id),
+            index.get(&bond.target_atom_id),
+        ) {
+            neighbors[origin].push(target);
+            neighbors[target].push(origin);
+        }
+    }
+    let mut visited = vec![false; mol2.atom.len()];
+    for start in 0..mol2.atom.len() {
+        if visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        let mut queue = VecDeque::from([start]);
+        while let Some(current) = queue.pop_front() {
+            let origin = &mol2.atom[current];
+            let origin = [origin.x, origin.y, origin.z];
+            for &next in &neighbors[current] {
+                if visited[next] {
+                    continue;
+                }
+                visited[next] = true;
+                let atom = &mut mol2.atom[next];
+                // Nearest image of the bond vector
+                let shift = cell
+                    .fractional(difference([atom.x, atom.y, atom.z], origin))
+                    .map(|value| value.round());
+                let [dx, dy, dz] = cell.cartesian(shift);
+                atom.x -= dx;
+                atom.y -= dy;
+                atom.z -= dz;
+                queue.push_back(next);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn symmetric_eigen(mut matrix: Matrix) -> ([CoordFloat; 3], Matrix) {
+    // Eigenvalues and eigenvectors (as columns) of a symmetric matrix with
+    // the cyclic Jacobi method, sorted by increasing eigenvalue
+    let mut vectors: Matrix = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+    for _ in 0..50 {
+        let off_diagonal = matrix[0][1].powi(2) + matrix[0][2].powi(2) + matrix[1][2].powi(2);
+        if off_diagonal < 1e-30 {
+            break;
+        }
+        for (p, q) in [(0, 1), (0, 2), (1, 2)] {
+            if matrix[p][q] == 0.0 {
+                continue;
+            }
+            // Rotation in the p-q plane zeroing the element p, q
+            let theta = (matrix[q][q] - matrix[p][p]) / (2.0 * matrix[p][q]);
+            let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+            let cos = 1.0 / (t * t + 1.0).sqrt();
+            let sin = t * cos;
+            for row in matrix.iter_mut().chain(vectors.iter_mut()) {
+                let (kp, kq) = (row[p], row[q]);
+                row[p] = cos * kp - sin * kq;
+                row[q] = sin * kp + cos * kq;
+            }
+            let (row_p, row_q) = (matrix[p], matrix[q]);
+            matrix[p] = [0, 1, 2].map(|k| cos * row_p[k] - sin * row_q[k]);
+            matrix[q] = [0, 1, 2].map(|k| sin * row_p[k] + cos * row_q[k]);
+        }
+    }
+    let mut order = [0, 1, 2];
+    order.sort_by(|a, b| matrix[*a][*a].total_cmp(&matrix[*b][*b]));
+    (
+        order.map(|column| matrix[column][column]),
+        [0, 1, 2].map(|row| order.map(|column| vectors[row][column])),
+    )
+}
+
+pub fn align_principal_axes(mol2: &mut Mol2) -> Result<[CoordFloat; 3]> {
+    // Move the center of mass to the origin and rotate the structure so that
+    // its principal axes of inertia lie along x, y and z, in the order of
+    // increasing moments of inertia. Axes point towards the side with more
+    // mass far from the center, which makes the orientation unique for
+    // asymmetric structures.
+    // Output: principal moments of inertia
+    let masses = masses(mol2)?;
+    let [cx, cy, cz] = weighted_center(mol2, &masses)?;
+    translate(mol2, [-cx, -cy, -cz]);
+    let mut inertia: Matrix = [[0.0; 3]; 3];
+    for (atom, mass) in mol2.atom.iter().zip(&masses) {
+        let position = [atom.x, atom.y, atom.z];
+        let squared = dot(position, position);
+        for row in 0..3 {
+            inertia[row][row] += mass * squared;
+            for column in 0..3 {
+                inertia[row][column] -= mass * position[row] * position[column];
+            }
+        }
+    }
+    let (moments, vectors) = symmetric_eigen(inertia);
+    let mut axes = [0, 1, 2].map(|column| [0, 1, 2].map(|row| vectors[row][column]));
+    for axis in axes.iter_mut().take(2) {
+        let skewness: CoordFloat = mol2
+            .atom
+            .iter()
+            .zip(&masses)
+            .map(|(atom, mass)| mass * dot(*axis, [atom.x, atom.y, atom.z]).powi(3))
+            .sum();
+        if skewness < 0.0 {
+            *axis = axis.map(|value| -value);
+        }
+    }
+    // Keep the handedness, so that the structure is not mirrored
+    axes[2] = cross(axes[0], axes[1]);
+    // Rows of the rotation are the new axes
+    apply(mol2, &axes, [0.0; 3]);
+    Ok(moments)
+}
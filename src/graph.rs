@@ -0,0 +1,547 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::elements::lookup;
+use crate::error::{Mol2Error, Result};
+use crate::{IdInt, Mol2};
+use std::collections::{HashMap, HashSet};
+
+// Neighbours of every atom by atom id, in the order of the bonds
+pub type Adjacency = HashMap<IdInt, Vec<IdInt>>;
+
+pub fn adjacency(mol2: &Mol2) -> Adjacency {
+    // Build the adjacency list of the bond graph. Every atom has an entry,
+    // atoms without bonds an empty one.
+    let mut neighbours: Adjacency = mol2
+        .atom
+        .iter()
+        .map(|atom| (atom.atom_id, Vec::new()))
+        .collect();
+    for bond in &mol2.bond {
+        neighbours
+            .entry(bond.origin_atom_id)
+            .or_default()
+            .push(bond.target_atom_id);
+        neighbours
+            .entry(bond.target_atom_id)
+            .or_default()
+            .push(bond.origin_atom_id);
+    }
+    neighbours
+}
+
+pub fn neighbours(adjacency: &Adjacency, atom_id: IdInt) -> Result<&[IdInt]> {
+    // Ids of atoms bonded to the given atom
+    adjacency
+        .get(&atom_id)
+        .map(|neighbours| &neighbours[..])
+        .ok_or_else(|| Mol2Error::InvalidArgument(format!("no atom with id {}", atom_id)))
+}
+
+struct Graph {
+    // Bond graph with atoms and bonds by their position, without bonds to
+    // missing atoms, bonds of an atom to itself and repeated bonds
+    atom_ids: Vec<IdInt>,
+    edges: Vec<(usize, usize)>,
+    // Position of the bond of every edge
+    bonds: Vec<usize>,
+    // Neighbour and the edge leading to it for every atom
+    neighbours: Vec<Vec<(usize, usize)>>,
+}
+
+impl Graph {
+    fn new(mol2: &Mol2) -> Graph {
+        let mut positions: HashMap<IdInt, usize> = HashMap::new();
+        for (index, atom) in mol2.atom.iter().enumerate() {
+            positions.entry(atom.atom_id).or_insert(index);
+        }
+        let mut graph = Graph {
+            atom_ids: mol2.atom.iter().map(|atom| atom.atom_id).collect(),
+            edges: Vec::new(),
+            bonds: Vec::new(),
+            neighbours: vec![Vec::new(); mol2.atom.len()],
+        };
+        let mut seen: HashSet<(usize, usize)> = HashSet::new();
+        for (index, bond) in mol2.bond.iter().enumerate() {
+            let (origin, target) = match (
+                positions.get(&bond.origin_atom_id),
+                positions.get(&bond.target_atom_id),
+            ) {
+                (Some(origin), Some(target)) if origin != target => (*origin, *target),
+                _ => continue,
+            };
+            if !seen.insert((origin.min(target), origin.max(target))) {
+                continue;
+            }
+            graph.neighbours[origin].push((target, graph.edges.len()));
+            graph.neighbours[target].push((origin, graph.edges.len()));
+            graph.edges.push((origin, target));
+            graph.bonds.push(index);
+        }
+        graph
+    }
+
+    fn bridges(&self) -> Vec<bool> {
+        // Edges that are not part of any cycle, found with an iterative
+        // Tarjan depth first search
+        let mut discovered = vec![usize::MAX; self.atom_ids.len()];
+        let mut low = vec![0; self.atom_ids.len()];
+        let mut bridges = vec![false; self.edges.len()];
+        let mut time = 0;
+        for root in 0..self.atom_ids.len() {
+            if discovered[root] != usize::MAX {
+                continue;
+            }
+            discovered[root] = time;
+            low[root] = time;
+            time += 1;
+            // Atom, edge it was reached by and the next neighbour to visit
+            let mut stack: Vec<(usize, usize, usize)> = vec![(root, usize::MAX, 0)];
+            while let Some(&(atom, edge, next)) = stack.last() {
+                if let Some(&(neighbour, neighbour_edge)) = self.neighbours[atom].get(next) {
+                    stack.last_mut().unwrap().2 += 1;
+                    if neighbour_edge == edge {
+                        continue;
+                    }
+                    if discovered[neighbour] == usize::MAX {
+                        discovered[neighbour] = time;
+                        low[neighbour] = time;
+                        time += 1;
+                        stack.push((neighbour, neighbour_edge, 0));
+                    } else {
+                        low[atom] = low[atom].min(discovered[neighbour]);
+                    }
+                    continue;
+                }
+                stack.pop();
+                if let Some(&(parent, _, _)) = stack.last() {
+                    low[parent] = low[parent].min(low[atom]);
+                    if low[atom] > discovered[parent] {
+                        bridges[edge] = true;
+                    }
+                }
+            }
+        }
+        bridges
+    }
+
+    fn ring_systems(&self) -> Vec<Vec<usize>> {
+        // Edges of every set of rings sharing atoms, connected through edges
+        // that are part of a cycle
+        let bridges = self.bridges();
+        let mut visited = vec![false; self.atom_ids.len()];
+        let mut systems: Vec<Vec<usize>> = Vec::new();
+        for start in 0..self.atom_ids.len() {
+            if visited[start] {
+                continue;
+            }
+            visited[start] = true;
+            let mut edges: Vec<usize> = Vec::new();
+            let mut queue: Vec<usize> = vec![start];
+            while let Some(atom) = queue.pop() {
+                for &(neighbour, edge) in &self.neighbours[atom] {
+                    if bridges[edge] {
+                        continue;
+                    }
+                    if self.edges[edge].0 == atom {
+                        edges.push(edge);
+                    }
+                    if !visited[neighbour] {
+                        visited[neighbour] = true;
+                        queue.push(neighbour);
+                    }
+                }
+            }
+            if !edges.is_empty() {
+                systems.push(edges);
+            }
+        }
+        systems
+    }
+}
+
+fn smallest_rings(graph: &Graph, edges: &[usize]) -> Vec<Vec<usize>> {
+    // Smallest set of smallest rings of a ring system as atom positions.
+    // Candidates are Horton's cycles, a shortest path from an atom to both
+    // ends of an edge, which contain a minimum cycle basis. The shortest
+    // candidates linearly independent of those already chosen make up the set.
+    let mut local: HashMap<usize, usize> = HashMap::new();
+    for &edge in edges {
+        let (origin, target) = graph.edges[edge];
+        let next = local.len();
+        local.entry(origin).or_insert(next);
+        let next = local.len();
+        local.entry(target).or_insert(next);
+    }
+    let edge_bits: HashMap<usize, usize> = edges
+        .iter()
+        .enumerate()
+        .map(|(bit, edge)| (*edge, bit))
+        .collect();
+    let size = edges.len() + 1 - local.len();
+    let words = edges.len().div_ceil(64);
+
+    // Same order of candidates on every run, equally small rings are
+    // chosen in the order they were found
+    let mut starts: Vec<usize> = local.keys().copied().collect();
+    starts.sort_unstable();
+    let mut candidates: Vec<(Vec<usize>, Vec<u64>)> = Vec::new();
+    for start in starts {
+        // Shortest path tree from the start atom within the ring system
+        let mut parents: HashMap<usize, (usize, usize)> = HashMap::new();
+        let mut queue = std::collections::VecDeque::from([start]);
+        let mut reached: HashSet<usize> = HashSet::from([start]);
+        while let Some(atom) = queue.pop_front() {
+            for &(neighbour, edge) in &graph.neighbours[atom] {
+                if edge_bits.contains_key(&edge) && reached.insert(neighbour) {
+                    parents.insert(neighbour, (atom, edge));
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+        let path = |mut atom: usize| {
+            // Atoms and edges from an atom back to the start
+            let mut atoms = vec![atom];
+            let mut path_edges = Vec::new();
+            while let Some(&(parent, edge)) = parents.get(&atom) {
+                atoms.push(parent);
+                path_edges.push(edge);
+                atom = parent;
+            }
+            (atoms, path_edges)
+        };
+        for &edge in edges {
+            let (origin, target) = graph.edges[edge];
+            let (origin_atoms, origin_edges) = path(origin);
+            let (target_atoms, target_edges) = path(target);
+            let on_origin: HashSet<usize> = origin_atoms.iter().copied().collect();
+            // Paths may only meet at the start atom
+            if target_atoms[..target_atoms.len() - 1]
+                .iter()
+                .any(|atom| on_origin.contains(atom))
+                || origin_atoms.len() + target_atoms.len() < 4
+            {
+                continue;
+            }
+            let mut ring: Vec<usize> = origin_atoms;
+            ring.extend(target_atoms[..target_atoms.len() - 1].iter().rev());
+            let mut bits = vec![0u64; words];
+            for ring_edge in origin_edges.iter().chain(&target_edges).chain([&edge]) {
+                let bit = edge_bits[ring_edge];
+                bits[bit / 64] |= 1 << (bit % 64);
+            }
+            candidates.push((ring, bits));
+        }
+    }
+    candidates.sort_by_key(|(ring, _)| ring.len());
+
+    // Reduced rings chosen so far with their lowest edge, each has the
+    // lowest edges of those before cleared
+    let mut basis: Vec<(usize, Vec<u64>)> = Vec::new();
+    let mut rings: Vec<Vec<usize>> = Vec::new();
+    for (ring, bits) in candidates {
+        if rings.len() == size {
+            break;
+        }
+        let mut reduced = bits;
+        for (lowest, vector) in &basis {
+            if reduced[lowest / 64] & (1 << (lowest % 64)) != 0 {
+                for (word, other) in reduced.iter_mut().zip(vector) {
+                    *word ^= other;
+                }
+            }
+        }
+        let lowest = match reduced.iter().position(|word| *word != 0) {
+            Some(word) => word * 64 + reduced[word].trailing_zeros() as usize,
+            None => continue,
+        };
+        basis.push((lowest, reduced));
+        rings.push(ring);
+    }
+    rings
+}
+
+pub fn rings(mol2: &Mol2) -> Vec<Vec<IdInt>> {
+    // Smallest set of smallest rings as lists of atom ids in the order they
+    // are bonded, starting from the lowest id towards its lower neighbour.
+    // Rings are sorted by size and then by their atom ids.
+    let graph = Graph::new(mol2);
+    let mut rings: Vec<Vec<IdInt>> = graph
+        .ring_systems()
+        .iter()
+        .flat_map(|edges| smallest_rings(&graph, edges))
+        .map(|ring| {
+            let mut ring: Vec<IdInt> = ring.iter().map(|atom| graph.atom_ids[*atom]).collect();
+            let lowest = (0..ring.len()).min_by_key(|index| ring[*index]).unwrap();
+            ring.rotate_left(lowest);
+            if ring[ring.len() - 1] < ring[1] {
+                ring[1..].reverse();
+            }
+            ring
+        })
+        .collect();
+    rings.sort_by(|first, second| first.len().cmp(&second.len()).then(first.cmp(second)));
+    rings
+}
+
+pub fn rotatable_bonds(mol2: &Mol2) -> Vec<IdInt> {
+    // Ids of rotatable bonds: single bonds outside of rings between heavy
+    // atoms that both have other heavy neighbours. Amide C-N bonds and bonds
+    // of atoms with a triple bond are not rotatable.
+    let heavy: HashSet<IdInt> = mol2
+        .atom
+        .iter()
+        .filter(|atom| lookup(&atom.atom_type).is_some_and(|(symbol, _, _)| symbol != "H"))
+        .map(|atom| atom.atom_id)
+        .collect();
+    let symbols: HashMap<IdInt, &str> = mol2
+        .atom
+        .iter()
+        .filter_map(|atom| lookup(&atom.atom_type).map(|(symbol, _, _)| (atom.atom_id, symbol)))
+        .collect();
+    let mut heavy_degree: HashMap<IdInt, usize> = HashMap::new();
+    let mut triple: HashSet<IdInt> = HashSet::new();
+    let mut carbonyl: HashSet<IdInt> = HashSet::new();
+    for bond in &mol2.bond {
+        let (origin, target) = (bond.origin_atom_id, bond.target_atom_id);
+        if heavy.contains(&origin) && heavy.contains(&target) {
+            *heavy_degree.entry(origin).or_default() += 1;
+            *heavy_degree.entry(target).or_default() += 1;
+        }
+        match bond.bond_type.as_str() {
+            "3" => triple.extend([origin, target]),
+            "2" => {
+                for (atom, partner) in [(origin, target), (target, origin)] {
+                    if symbols.get(&atom) == Some(&"C")
+                        && matches!(symbols.get(&partner), Some(&"O") | Some(&"S"))
+                    {
+                        carbonyl.insert(atom);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    let ring_bonds: HashSet<(IdInt, IdInt)> = rings(mol2)
+        .iter()
+        .flat_map(|ring| {
+            (0..ring.len()).map(|index| {
+                let (first, second) = (ring[index], ring[(index + 1) % ring.len()]);
+                (first.min(second), first.max(second))
+            })
+        })
+        .collect();
+    let amide = |atom: IdInt, partner: IdInt| {
+        carbonyl.contains(&atom) && symbols.get(&partner) == Some(&"N")
+    };
+    mol2.bond
+        .iter()
+        .filter(|bond| {
+            let (origin, target) = (bond.origin_atom_id, bond.target_atom_id);
+            bond.bond_type == "1"
+                && [origin, target].iter().all(|atom| {
+                    heavy_degree.get(atom).copied().unwrap_or(0) > 1 && !triple.contains(atom)
+                })
+                && !ring_bonds.contains(&(origin.min(target), origin.max(target)))
+                && !amide(origin, target)
+                && !amide(target, origin)
+        })
+        .map(|bond| bond.bond_id)
+        .collect()
+}
+
+struct Pattern<'a> {
+    // Bond graph with the element and bond types matched by substructure search
+    graph: Graph,
+    symbols: Vec<Option<&'static str>>,
+    bond_types: Vec<&'a str>,
+}
+
+impl<'a> Pattern<'a> {
+    fn new(mol2: &'a Mol2) -> Pattern<'a> {
+        let graph = Graph::new(mol2);
+        let bond_types = graph
+            .bonds
+            .iter()
+            .map(|bond| match mol2.bond[*bond].bond_type.as_str() {
+                "am" => "1",
+                bond_type => bond_type,
+            })
+            .collect();
+        Pattern {
+            graph,
+            symbols: mol2
+                .atom
+                .iter()
+                .map(|atom| lookup(&atom.atom_type).map(|(symbol, _, _)| symbol))
+                .collect(),
+            bond_types,
+        }
+    }
+
+    fn edge(&self, first: usize, second: usize) -> Option<usize> {
+        self.graph.neighbours[first]
+            .iter()
+            .find(|(neighbour, _)| *neighbour == second)
+            .map(|(_, edge)| *edge)
+    }
+}
+
+struct Matcher<'a> {
+    target: Pattern<'a>,
+    query: Pattern<'a>,
+    // Query atoms in the order they are matched, each but the first of a
+    // fragment bonded to an earlier one
+    order: Vec<usize>,
+    // Number of bonds of every query atom to atoms taking part in the match
+    degrees: Vec<usize>,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(target: &'a Mol2, query: &'a Mol2, hydrogens: bool) -> Matcher<'a> {
+        let target = Pattern::new(target);
+        let query = Pattern::new(query);
+        let used: Vec<bool> = query
+            .symbols
+            .iter()
+            .map(|symbol| hydrogens || *symbol != Some("H"))
+            .collect();
+        let mut order: Vec<usize> = Vec::new();
+        let mut visited = vec![false; used.len()];
+        for start in 0..used.len() {
+            if !used[start] || visited[start] {
+                continue;
+            }
+            visited[start] = true;
+            let mut queue = std::collections::VecDeque::from([start]);
+            while let Some(atom) = queue.pop_front() {
+                order.push(atom);
+                for &(neighbour, _) in &query.graph.neighbours[atom] {
+                    if used[neighbour] && !visited[neighbour] {
+                        visited[neighbour] = true;
+                        queue.push_back(neighbour);
+                    }
+                }
+            }
+        }
+        let degrees = (0..used.len())
+            .map(|atom| {
+                query.graph.neighbours[atom]
+                    .iter()
+                    .filter(|(neighbour, _)| used[*neighbour])
+                    .count()
+            })
+            .collect();
+        Matcher {
+            target,
+            query,
+            order,
+            degrees,
+        }
+    }
+
+    fn compatible(&self, query_atom: usize, target_atom: usize) -> bool {
+        // Query atoms without an element match any atom
+        (self.query.symbols[query_atom].is_none()
+            || self.query.symbols[query_atom] == self.target.symbols[target_atom])
+            && self.target.graph.neighbours[target_atom].len() >= self.degrees[query_atom]
+    }
+
+    fn extend(
+        &self,
+        position: usize,
+        mapping: &mut Vec<Option<usize>>,
+        taken: &mut [bool],
+    ) -> bool {
+        // Backtracking search matching query atoms from the given position on
+        let query_atom = match self.order.get(position) {
+            Some(atom) => *atom,
+            None => return true,
+        };
+        // Candidates are neighbours of a matched neighbour, or any atom when
+        // starting a new fragment
+        let anchor = self.query.graph.neighbours[query_atom]
+            .iter()
+            .find_map(|(neighbour, _)| mapping[*neighbour]);
+        let candidates: Vec<usize> = match anchor {
+            Some(anchor) => self.target.graph.neighbours[anchor]
+                .iter()
+                .map(|(neighbour, _)| *neighbour)
+                .collect(),
+            None => (0..self.target.symbols.len()).collect(),
+        };
+        for target_atom in candidates {
+            if taken[target_atom] || !self.compatible(query_atom, target_atom) {
+                continue;
+            }
+            let bonds_match =
+                self.query.graph.neighbours[query_atom]
+                    .iter()
+                    .all(|(neighbour, query_edge)| match mapping[*neighbour] {
+                        None => true,
+                        Some(target_neighbour) => self
+                            .target
+                            .edge(target_atom, target_neighbour)
+                            .is_some_and(|target_edge| {
+                                matches!(self.query.bond_types[*query_edge], "un" | "du")
+                                    || self.query.bond_types[*query_edge]
+                                        == self.target.bond_types[target_edge]
+                            }),
+                    });
+            if !bonds_match {
+                continue;
+            }
+            mapping[query_atom] = Some(target_atom);
+            taken[target_atom] = true;
+            if self.extend(position + 1, mapping, taken) {
+                return true;
+            }
+            mapping[query_atom] = None;
+            taken[target_atom] = false;
+        }
+        false
+    }
+}
+
+pub fn match_atoms(mol2: &Mol2, query: &Mol2, hydrogens: bool) -> Option<HashMap<IdInt, IdInt>> {
+    // Ids of atoms of a structure matching every atom of a query, by query
+    // atom id, None if the query is not a substructure. Elements and bond
+    // types must be equal, query atoms without an element and query bonds
+    // of unknown type match anything.
+    // Input:
+    //     mol2: structure to search in
+    //     query: substructure to look for
+    //     hydrogens: also match hydrogens of the query, otherwise only heavy atoms
+    let matcher = Matcher::new(mol2, query, hydrogens);
+    let mut mapping: Vec<Option<usize>> = vec![None; matcher.query.symbols.len()];
+    let mut taken = vec![false; matcher.target.symbols.len()];
+    if !matcher.extend(0, &mut mapping, &mut taken) {
+        return None;
+    }
+    Some(
+        mapping
+            .iter()
+            .enumerate()
+            .filter_map(|(query_atom, target_atom)| {
+                target_atom.map(|target_atom| {
+                    (
+                        matcher.query.graph.atom_ids[query_atom],
+                        matcher.target.graph.atom_ids[target_atom],
+                    )
+                })
+            })
+            .collect(),
+    )
+}
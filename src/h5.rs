@@ -0,0 +1,120 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::error::Result;
+use crate::Mol2;
+use hdf5::types::VarLenUnicode;
+
+fn unicode(text: &str) -> VarLenUnicode {
+    // HDF5 string of a rust one, text with null characters is cut at the first one
+    let text = text.split('\0').next().unwrap_or("");
+    text.parse().unwrap_or_default()
+}
+
+pub fn write_hdf5(mol2_list: &[Mol2], filename: &str) -> Result<()> {
+    // Write structures into an HDF5 file, one group per structure. Groups
+    // are named after the position of the structure in the list, padded
+    // with zeros so that they sort in the same order.
+    // Input:
+    //     mol2_list: structures to write
+    //     filename: path to the HDF5 file, an existing file is overwritten
+    let file = hdf5::File::create(filename)?;
+    let width = mol2_list.len().saturating_sub(1).to_string().len();
+    for (index, mol2) in mol2_list.iter().enumerate() {
+        let group = file.create_group(&format!("molecule_{:0width$}", index, width = width))?;
+        if let Some(molecule) = &mol2.molecule {
+            group
+                .new_attr::<VarLenUnicode>()
+                .create("mol_name")?
+                .write_scalar(&unicode(&molecule.mol_name))?;
+            if let Some(mol_comment) = &molecule.mol_comment {
+                group
+                    .new_attr::<VarLenUnicode>()
+                    .create("mol_comment")?
+                    .write_scalar(&unicode(mol_comment))?;
+            }
+        }
+        if let Some(desc) = &mol2.desc {
+            group
+                .new_attr::<VarLenUnicode>()
+                .create("desc")?
+                .write_scalar(&unicode(desc))?;
+        }
+
+        let coordinates: Vec<[f64; 3]> = mol2
+            .atom
+            .iter()
+            .map(|atom| [atom.x, atom.y, atom.z])
+            .collect();
+        let atom_ids: Vec<u16> = mol2.atom.iter().map(|atom| atom.atom_id).collect();
+        let atom_names: Vec<VarLenUnicode> = mol2
+            .atom
+            .iter()
+            .map(|atom| unicode(&atom.atom_name))
+            .collect();
+        let atom_types: Vec<VarLenUnicode> = mol2
+            .atom
+            .iter()
+            .map(|atom| unicode(&atom.atom_type))
+            .collect();
+        // Missing charges are stored as NaN
+        let charges: Vec<f32> = mol2
+            .atom
+            .iter()
+            .map(|atom| atom.charge.unwrap_or(f32::NAN))
+            .collect();
+        let bonds: Vec<[u16; 2]> = mol2
+            .bond
+            .iter()
+            .map(|bond| [bond.origin_atom_id, bond.target_atom_id])
+            .collect();
+        let bond_types: Vec<VarLenUnicode> = mol2
+            .bond
+            .iter()
+            .map(|bond| unicode(&bond.bond_type))
+            .collect();
+
+        group
+            .new_dataset_builder()
+            .with_data(&coordinates[..])
+            .create("coordinates")?;
+        group
+            .new_dataset_builder()
+            .with_data(&atom_ids[..])
+            .create("atom_ids")?;
+        group
+            .new_dataset_builder()
+            .with_data(&atom_names[..])
+            .create("atom_names")?;
+        group
+            .new_dataset_builder()
+            .with_data(&atom_types[..])
+            .create("atom_types")?;
+        group
+            .new_dataset_builder()
+            .with_data(&charges[..])
+            .create("charges")?;
+        group
+            .new_dataset_builder()
+            .with_data(&bonds[..])
+            .create("bonds")?;
+        group
+            .new_dataset_builder()
+            .with_data(&bond_types[..])
+            .create("bond_types")?;
+    }
+    file.close()?;
+    Ok(())
+}
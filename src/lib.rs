@@ -13,63 +13,98 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use numpy::ndarray::Array2;
+use numpy::{IntoPyArray, PyArray2, PyReadonlyArray2};
 use pyo3::prelude::*;
 use pyo3::types::*;
 use pyo3::wrap_pyfunction;
+use rayon::prelude::*;
+use rusqlite::OptionalExtension;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::fs::File;
-use std::fs::OpenOptions;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
 use std::io::prelude::*;
-use std::io::{BufRead, BufReader};
-use std::os::unix::fs::PermissionsExt;
+use std::io::BufRead;
+use std::sync::OnceLock;
+
+mod aromatic;
+#[cfg(feature = "arrow")]
+mod arrow;
+mod builder;
+mod canonical;
+mod compression;
+mod conformers;
+mod connection;
+mod diff;
+mod edit;
+mod elements;
+mod error;
+mod geometry;
+mod graph;
+#[cfg(feature = "hdf5")]
+mod h5;
+#[cfg(feature = "npz")]
+mod npz;
+mod pdb;
+mod residues;
+mod sdf;
+mod smiles;
+mod typetables;
+mod typing;
+mod validate;
+mod xyz;
+
+pub use aromatic::BondConvention;
+#[cfg(feature = "arrow")]
+pub use arrow::{to_arrow, ArrowTables};
+pub use builder::Mol2Builder;
+pub use compression::{Codec, FileCompression};
+pub use conformers::Conformers;
+pub use connection::Mol2Db;
+pub use diff::Difference;
+pub use edit::{AtomOrder, SubstructureOrder};
+pub use error::{Mol2Error, Result};
+pub use validate::Violation;
 
 type IdInt = u16;
 type ChargeFloat = f32;
 type CoordFloat = f64;
 
-// Using a rather large buffer but for our applications should be fine.
-static DECOMPRESSOR_BUFFER: usize = 100 * 1024 * 1024;
-
-fn write_string(text: &str, filename: &str, append: bool) {
-    // Helper function to standardize writing strings to files
-    // Input:
-    //     text: string to write
-    //     filename: path to a file to write to
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(!append)
-        .append(append)
-        .open(filename)
-        .expect("Failed to open tmp file");
-    file.write_all(text.as_bytes())
-        .expect("Failed to write to a mol2 file");
-}
-
 // Struct for holding data from MOLECULE sections
 #[pyclass]
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct Molecule {
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Molecule {
     #[pyo3(get, set)]
-    mol_name: String,
+    pub mol_name: String,
     #[pyo3(get, set)]
-    num_atoms: Option<usize>,
+    pub num_atoms: Option<usize>,
     #[pyo3(get, set)]
-    num_bonds: Option<usize>,
+    pub num_bonds: Option<usize>,
     #[pyo3(get, set)]
-    num_subst: Option<usize>,
+    pub num_subst: Option<usize>,
     #[pyo3(get, set)]
-    num_feat: Option<usize>,
+    pub num_feat: Option<usize>,
     #[pyo3(get, set)]
-    num_sets: Option<usize>,
+    pub num_sets: Option<usize>,
     #[pyo3(get, set)]
-    mol_type: Option<String>,
+    pub mol_type: Option<String>,
     #[pyo3(get, set)]
-    charge_type: Option<String>,
+    pub charge_type: Option<String>,
     #[pyo3(get, set)]
-    status_bits: Option<String>,
+    pub status_bits: Option<String>,
     #[pyo3(get, set)]
-    mol_comment: Option<String>,
+    pub mol_comment: Option<String>,
+}
+
+#[pymethods]
+impl Molecule {
+    fn __copy__(&self) -> Molecule {
+        self.clone()
+    }
+    fn __deepcopy__(&self, _memo: &PyAny) -> Molecule {
+        self.clone()
+    }
 }
 
 impl Molecule {
@@ -118,13 +153,14 @@ impl Molecule {
                             4 => self.num_sets,
                             _ => continue,
                         };
-                        if number.is_none() {
-                            break;
-                        } else {
-                            if nnum > 0 {
-                                text.push(' ');
+                        match number {
+                            None => break,
+                            Some(number) => {
+                                if nnum > 0 {
+                                    text.push(' ');
+                                }
+                                text.push_str(&format!("{}", number)[..]);
                             }
-                            text.push_str(&format!("{}", number.unwrap())[..]);
                         }
                     }
                 }
@@ -148,40 +184,81 @@ impl Molecule {
     }
 }
 
+fn same_content<T: Serialize>(first: &T, second: &T) -> bool {
+    // Compare records by their serialized form, as they are not comparable otherwise
+    bincode::serialize(first).ok() == bincode::serialize(second).ok()
+}
+
+// Options controlling how records are written out
+struct WriteFormat {
+    aligned: bool,
+    coord_precision: usize,
+    charge_precision: usize,
+}
+
 // Struct holding data for a single atom entry in the ATOM section of the mol2 format
 #[pyclass]
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct Atom {
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Atom {
     #[pyo3(get, set)]
-    atom_id: IdInt,
+    pub atom_id: IdInt,
     #[pyo3(get, set)]
-    atom_name: String,
+    pub atom_name: String,
     #[pyo3(get, set)]
-    x: CoordFloat,
+    pub x: CoordFloat,
     #[pyo3(get, set)]
-    y: CoordFloat,
+    pub y: CoordFloat,
     #[pyo3(get, set)]
-    z: CoordFloat,
+    pub z: CoordFloat,
     #[pyo3(get, set)]
-    atom_type: String,
+    pub atom_type: String,
     #[pyo3(get, set)]
-    subst_id: Option<IdInt>,
+    pub subst_id: Option<IdInt>,
     #[pyo3(get, set)]
-    subst_name: Option<String>,
+    pub subst_name: Option<String>,
     #[pyo3(get, set)]
-    charge: Option<ChargeFloat>,
+    pub charge: Option<ChargeFloat>,
     #[pyo3(get, set)]
-    status_bit: Option<String>,
+    pub status_bit: Option<String>,
 }
 
+#[pymethods]
 impl Atom {
-    fn as_string(&self) -> String {
+    fn __copy__(&self) -> Atom {
+        self.clone()
+    }
+    fn __deepcopy__(&self, _memo: &PyAny) -> Atom {
+        self.clone()
+    }
+    pub fn element(&self) -> Option<String> {
+        // Element symbol of the SYBYL atom type, e.g. "N" for "N.ar". None for
+        // dummy atoms, lone pairs and generic or unknown types.
+        elements::lookup(&self.atom_type).map(|(symbol, _, _)| symbol.to_owned())
+    }
+    pub fn atomic_number(&self) -> Option<u8> {
+        // Atomic number of the element of the SYBYL atom type, None if there is no element
+        elements::lookup(&self.atom_type).map(|(_, number, _)| number)
+    }
+}
+
+impl Atom {
+    fn as_string(&self, format: &WriteFormat) -> String {
         // Show atom entry as a string in mol2 ATOM section
+        // Input:
+        //     format: layout and precision of the written fields
         let mut text = String::new();
+        // Zero width means no padding
+        let (id_width, name_width, coord_width, type_width, subst_width, charge_width) =
+            match format.aligned {
+                true => (7, 8, 10, 5, 5, 9),
+                false => (0, 0, 0, 0, 0, 0),
+            };
+        let coord_precision = format.coord_precision;
+        let charge_precision = format.charge_precision;
 
         text.push_str(
             &format!(
-                "{} {} {} {} {} {}",
+                "{:>id_width$} {:<name_width$} {:>coord_width$.coord_precision$} {:>coord_width$.coord_precision$} {:>coord_width$.coord_precision$} {:<type_width$}",
                 self.atom_id, self.atom_name, self.x, self.y, self.z, self.atom_type
             )[..],
         );
@@ -198,13 +275,18 @@ impl Atom {
             }
             text.push(' ');
             match n {
-                0 => text.push_str(&format!("{}", self.subst_id.as_ref().unwrap())[..]),
-                1 => text.push_str(self.subst_name.as_ref().unwrap()),
-                2 => text.push_str(&format!("{}", self.charge.as_ref().unwrap())[..]),
+                0 => text.push_str(&format!("{:>subst_width$}", self.subst_id.unwrap())[..]),
+                1 => text
+                    .push_str(&format!("{:<name_width$}", self.subst_name.as_ref().unwrap())[..]),
+                2 => text.push_str(
+                    &format!("{:>charge_width$.charge_precision$}", self.charge.unwrap())[..],
+                ),
                 3 => text.push_str(self.status_bit.as_ref().unwrap()),
                 _ => continue,
             }
         }
+        // Padding of the last column is not needed
+        text.truncate(text.trim_end().len());
         text.push('\n');
 
         text
@@ -213,35 +295,57 @@ impl Atom {
 
 // Struct holding data for a single entry in BOND section of the mol2 file
 #[pyclass]
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct Bond {
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Bond {
     #[pyo3(get, set)]
-    bond_id: IdInt,
+    pub bond_id: IdInt,
     #[pyo3(get, set)]
-    origin_atom_id: IdInt,
+    pub origin_atom_id: IdInt,
     #[pyo3(get, set)]
-    target_atom_id: IdInt,
+    pub target_atom_id: IdInt,
     #[pyo3(get, set)]
-    bond_type: String,
+    pub bond_type: String,
     #[pyo3(get, set)]
-    status_bit: Option<String>,
+    pub status_bit: Option<String>,
 }
 
+#[pymethods]
 impl Bond {
-    fn as_string(&self) -> String {
+    fn __copy__(&self) -> Bond {
+        self.clone()
+    }
+    fn __deepcopy__(&self, _memo: &PyAny) -> Bond {
+        self.clone()
+    }
+}
+
+impl Bond {
+    fn as_string(&self, format: &WriteFormat) -> String {
         // Show bond entry as a string in mol2 BOND section
+        // Input:
+        //     format: layout of the written fields
         let mut text = String::new();
 
-        text.push_str(
-            &format!(
-                "{} {} {} {}",
-                self.bond_id, self.origin_atom_id, self.target_atom_id, self.bond_type
-            )[..],
-        );
+        if format.aligned {
+            text.push_str(
+                &format!(
+                    "{:>6} {:>5} {:>5} {:<4}",
+                    self.bond_id, self.origin_atom_id, self.target_atom_id, self.bond_type
+                )[..],
+            );
+        } else {
+            text.push_str(
+                &format!(
+                    "{} {} {} {}",
+                    self.bond_id, self.origin_atom_id, self.target_atom_id, self.bond_type
+                )[..],
+            );
+        }
 
-        if self.status_bit.is_some() {
-            text.push_str(&format!(" {}", self.status_bit.as_ref().unwrap())[..]);
+        if let Some(status_bit) = &self.status_bit {
+            text.push_str(&format!(" {}", status_bit)[..]);
         }
+        text.truncate(text.trim_end().len());
         text.push('\n');
 
         text
@@ -250,36 +354,57 @@ impl Bond {
 
 // Struct holding data for a single entry in SUBSTRUCTURE section of the mol2 file
 #[pyclass]
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct Substructure {
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Substructure {
     #[pyo3(get, set)]
-    subst_id: IdInt,
+    pub subst_id: IdInt,
     #[pyo3(get, set)]
-    subst_name: String,
+    pub subst_name: String,
     #[pyo3(get, set)]
-    root_atom: IdInt,
+    pub root_atom: IdInt,
     #[pyo3(get, set)]
-    subst_type: Option<String>,
+    pub subst_type: Option<String>,
     #[pyo3(get, set)]
-    dict_type: Option<i64>,
+    pub dict_type: Option<i64>,
     #[pyo3(get, set)]
-    chain: Option<String>,
+    pub chain: Option<String>,
     #[pyo3(get, set)]
-    sub_type: Option<String>,
+    pub sub_type: Option<String>,
     #[pyo3(get, set)]
-    inter_bonds: Option<IdInt>,
+    pub inter_bonds: Option<IdInt>,
     #[pyo3(get, set)]
-    status: Option<String>,
+    pub status: Option<String>,
     #[pyo3(get, set)]
-    comment: Option<String>,
+    pub comment: Option<String>,
 }
 
+#[pymethods]
 impl Substructure {
-    fn as_string(&self) -> String {
+    fn __copy__(&self) -> Substructure {
+        self.clone()
+    }
+    fn __deepcopy__(&self, _memo: &PyAny) -> Substructure {
+        self.clone()
+    }
+}
+
+impl Substructure {
+    fn as_string(&self, format: &WriteFormat) -> String {
         // Show substructure entry as a string in mol2 SUBSTRUCTURE section
+        // Input:
+        //     format: layout of the written fields
         let mut text = String::new();
 
-        text.push_str(&format!("{} {} {}", self.subst_id, self.subst_name, self.root_atom)[..]);
+        if format.aligned {
+            text.push_str(
+                &format!(
+                    "{:>6} {:<8} {:>6}",
+                    self.subst_id, self.subst_name, self.root_atom
+                )[..],
+            );
+        } else {
+            text.push_str(&format!("{} {} {}", self.subst_id, self.subst_name, self.root_atom)[..]);
+        }
 
         for n in 0..7 {
             if match n {
@@ -295,37 +420,152 @@ impl Substructure {
                 break;
             }
             text.push(' ');
+            // Column widths, comment is free text and is never padded
+            let width = match (n, format.aligned) {
+                (0, true) => 8,
+                (1, true) => 2,
+                (2, true) => 4,
+                (3, true) => 8,
+                (4, true) => 2,
+                (5, true) => 8,
+                _ => 0,
+            };
             match n {
-                0 => text.push_str(self.subst_type.as_ref().unwrap()),
-                1 => text.push_str(&format!("{}", self.dict_type.as_ref().unwrap())[..]),
-                2 => text.push_str(self.chain.as_ref().unwrap()),
-                3 => text.push_str(self.sub_type.as_ref().unwrap()),
-                4 => text.push_str(&format!("{}", self.inter_bonds.as_ref().unwrap())[..]),
-                5 => text.push_str(self.status.as_ref().unwrap()),
+                0 => text.push_str(&format!("{:<width$}", self.subst_type.as_ref().unwrap())[..]),
+                1 => text.push_str(&format!("{:>width$}", self.dict_type.unwrap())[..]),
+                2 => text.push_str(&format!("{:<width$}", self.chain.as_ref().unwrap())[..]),
+                3 => text.push_str(&format!("{:<width$}", self.sub_type.as_ref().unwrap())[..]),
+                4 => text.push_str(&format!("{:>width$}", self.inter_bonds.unwrap())[..]),
+                5 => text.push_str(&format!("{:<width$}", self.status.as_ref().unwrap())[..]),
                 6 => text.push_str(self.comment.as_ref().unwrap()),
                 _ => continue,
             }
         }
+        text.truncate(text.trim_end().len());
+        text.push('\n');
+
+        text
+    }
+}
+
+// Struct holding one set of alternative atom types out of the ALT_TYPE
+// section of the mol2 file, e.g. GAFF types next to the SYBYL ones
+#[pyclass]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AltType {
+    #[pyo3(get, set)]
+    pub scheme: String,
+    // Pairs of atom id and the atom type in the scheme
+    #[pyo3(get, set)]
+    pub types: Vec<(IdInt, String)>,
+}
+
+#[pymethods]
+impl AltType {
+    fn __copy__(&self) -> AltType {
+        self.clone()
+    }
+    fn __deepcopy__(&self, _memo: &PyAny) -> AltType {
+        self.clone()
+    }
+}
+
+impl AltType {
+    fn as_string(&self) -> String {
+        // Show the set as lines of the mol2 ALT_TYPE section
+        let mut text = format!(
+            "{}_ALT_TYPE_SET\n{} {}",
+            self.scheme,
+            self.scheme,
+            self.types.len()
+        );
+        for (atom_id, atom_type) in &self.types {
+            text.push_str(&format!(" {} {}", atom_id, atom_type));
+        }
         text.push('\n');
+        text
+    }
+}
+
+// Struct for the CRYSIN section of the mol2 file, the unit cell of a
+// crystal structure
+#[pyclass]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Crysin {
+    // Lengths a, b and c and angles alpha, beta and gamma in degrees
+    #[pyo3(get, set)]
+    pub cell: [CoordFloat; 6],
+    #[pyo3(get, set)]
+    pub space_group: u32,
+    #[pyo3(get, set)]
+    pub setting: u32,
+}
 
+#[pymethods]
+impl Crysin {
+    #[new]
+    #[args(space_group = "1", setting = "1")]
+    fn py_new(cell: [CoordFloat; 6], space_group: u32, setting: u32) -> Crysin {
+        Crysin {
+            cell,
+            space_group,
+            setting,
+        }
+    }
+    fn __copy__(&self) -> Crysin {
+        self.clone()
+    }
+    fn __deepcopy__(&self, _memo: &PyAny) -> Crysin {
+        self.clone()
+    }
+}
+
+impl Crysin {
+    fn as_string(&self) -> String {
+        // Show the cell as the line of the mol2 CRYSIN section
+        let mut text = String::new();
+        for value in self.cell {
+            text.push_str(&format!("{:.4} ", value));
+        }
+        text.push_str(&format!("{} {}\n", self.space_group, self.setting));
         text
     }
 }
 
 // Struct for holding data for a single structure out of a mol2 file
 #[pyclass]
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Mol2 {
     #[pyo3(get, set)]
     molecule: Option<Molecule>,
-    #[pyo3(get, set)]
+    #[pyo3(get)]
     atom: Vec<Atom>,
-    #[pyo3(get, set)]
+    #[pyo3(get)]
     bond: Vec<Bond>,
     #[pyo3(get, set)]
     substructure: Vec<Substructure>,
     #[pyo3(get, set)]
     desc: Option<String>,
+    #[pyo3(get, set)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    alt_type: Vec<AltType>,
+    #[pyo3(get, set)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    crysin: Option<Crysin>,
+    // Original text of the structure, kept when reading with keep_source
+    #[pyo3(get)]
+    #[serde(skip)]
+    source: Option<String>,
+    // Fingerprint of the content at the time the source was kept
+    #[serde(skip)]
+    source_hash: Option<u64>,
+    // Adjacency list of the bond graph, built on first use and dropped
+    // whenever atom ids or bonds change
+    #[serde(skip)]
+    adjacency: OnceLock<graph::Adjacency>,
+    // User metadata such as scores or provenance, stored as JSON in databases
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    extras: Option<HashMap<String, serde_json::Value>>,
 }
 
 impl Mol2 {
@@ -340,7 +580,187 @@ impl Mol2 {
             bond: Vec::new(),
             substructure: Vec::new(),
             desc: description,
+            alt_type: Vec::new(),
+            crysin: None,
+            source: None,
+            source_hash: None,
+            adjacency: OnceLock::new(),
+            extras: None,
+        }
+    }
+    pub fn normalize_bond_types(&mut self, mode: BondConvention) -> Result<usize> {
+        // Convert bonds of aromatic rings between Kekulé and aromatic form,
+        // returns the number of bonds changed
+        aromatic::normalize_bond_types(self, mode)
+    }
+    pub fn sort_atoms(&mut self, by: AtomOrder) {
+        // Reorder atoms, renumber them from 1 and update the bonds
+        edit::sort_atoms(self, by)
+    }
+    pub fn sort_substructures(&mut self, by: SubstructureOrder) {
+        // Reorder substructures, renumber them from 1 and update the atoms
+        edit::sort_substructures(self, by)
+    }
+    fn charge_warning(&self) -> Option<String> {
+        // Reason why summed charges may not be meaningful, if any
+        let charge_type = self
+            .molecule
+            .as_ref()
+            .and_then(|molecule| molecule.charge_type.as_deref());
+        let missing = self
+            .atom
+            .iter()
+            .filter(|atom| atom.charge.is_none())
+            .count();
+        if charge_type == Some("NO_CHARGES") {
+            Some("charge type is NO_CHARGES, charges are not meaningful".to_owned())
+        } else if missing > 0 {
+            Some(format!("{} atoms have no charge, counted as zero", missing))
+        } else {
+            None
+        }
+    }
+    pub fn total_charge(&self) -> (f64, Option<String>) {
+        // Sum of all partial charges, with the reason why it may not be
+        // meaningful if the charges are missing or of the NO_CHARGES type
+        let sum = self
+            .atom
+            .iter()
+            .map(|atom| atom.charge.unwrap_or(0.0) as f64)
+            .sum();
+        (sum, self.charge_warning())
+    }
+    pub fn charge_by_substructure(&self) -> (Vec<(Option<String>, f64)>, Option<String>) {
+        // Sum of charges of every substructure, in the order the substructures
+        // first appear among the atoms, with the same warning as total_charge.
+        // Atoms without a substructure are summed under None.
+        let mut sums: Vec<(Option<String>, f64)> = Vec::new();
+        // Position of every substructure in sums
+        let mut index: HashMap<Option<&str>, usize> = HashMap::new();
+        for atom in &self.atom {
+            let position = *index.entry(atom.subst_name.as_deref()).or_insert_with(|| {
+                sums.push((atom.subst_name.clone(), 0.0));
+                sums.len() - 1
+            });
+            sums[position].1 += atom.charge.unwrap_or(0.0) as f64;
+        }
+        (sums, self.charge_warning())
+    }
+    fn content_hash(&self) -> u64 {
+        // Fingerprint of everything that ends up in a written mol2 file
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        bincode::serialize(&(
+            &self.molecule,
+            &self.atom,
+            &self.bond,
+            &self.substructure,
+            &self.alt_type,
+            &self.crysin,
+        ))
+        .unwrap_or_default()
+        .hash(&mut hasher);
+        hasher.finish()
+    }
+    fn cached_adjacency(&self) -> &graph::Adjacency {
+        // Adjacency list of the bond graph, built once until atom ids or
+        // bonds change
+        self.adjacency.get_or_init(|| graph::adjacency(self))
+    }
+    fn graph_changed(&mut self) {
+        // Drop the cached adjacency list, called by everything changing
+        // atom ids or bonds of an existing structure
+        self.adjacency = OnceLock::new();
+    }
+    fn keep_source(&mut self, source: String) {
+        // Remember the text the structure was read from
+        self.source_hash = Some(self.content_hash());
+        self.source = Some(source);
+    }
+    fn write_to<W: Write>(&self, writer: &mut W, format: &WriteFormat) -> std::io::Result<()> {
+        // Write the structure in the mol2 format, record by record
+        // Input:
+        //     writer: where to write to
+        //     format: layout and precision of the written fields
+        //
+        // Structures read with keep_source and not modified since are
+        // written exactly as they were read, ignoring the format
+        if let Some(source) = self.unedited_source() {
+            return writer.write_all(source.as_bytes());
+        }
+        let molecule = match &self.molecule {
+            Some(molecule) => molecule,
+            None => return Ok(()),
+        };
+
+        writer.write_all(molecule.as_string().as_bytes())?;
+        // We should probably have a generic function for these section thingies...
+        if !self.atom.is_empty() {
+            writer.write_all(b"@<TRIPOS>ATOM\n")?;
+            for entry in &self.atom {
+                writer.write_all(entry.as_string(format).as_bytes())?;
+            }
+            writer.write_all(b"\n")?;
+        }
+        if !self.bond.is_empty() {
+            writer.write_all(b"@<TRIPOS>BOND\n")?;
+            for entry in &self.bond {
+                writer.write_all(entry.as_string(format).as_bytes())?;
+            }
+            writer.write_all(b"\n")?;
+        }
+        if !self.substructure.is_empty() {
+            writer.write_all(b"@<TRIPOS>SUBSTRUCTURE\n")?;
+            for entry in &self.substructure {
+                writer.write_all(entry.as_string(format).as_bytes())?;
+            }
+            writer.write_all(b"\n")?;
+        }
+        if let Some(crysin) = &self.crysin {
+            writer.write_all(b"@<TRIPOS>CRYSIN\n")?;
+            writer.write_all(crysin.as_string().as_bytes())?;
+            writer.write_all(b"\n")?;
+        }
+        if !self.alt_type.is_empty() {
+            writer.write_all(b"@<TRIPOS>ALT_TYPE\n")?;
+            for entry in &self.alt_type {
+                writer.write_all(entry.as_string().as_bytes())?;
+            }
+            writer.write_all(b"\n")?;
         }
+
+        Ok(())
+    }
+    fn unedited_source(&self) -> Option<&str> {
+        // Original text of the structure, unless it was modified since reading
+        match (&self.source, self.source_hash) {
+            (Some(source), Some(hash)) if hash == self.content_hash() => Some(source),
+            _ => None,
+        }
+    }
+    pub fn molecule(&self) -> Option<&Molecule> {
+        // Header of the MOLECULE section, if the structure had one
+        self.molecule.as_ref()
+    }
+    pub fn atoms(&self) -> &[Atom] {
+        // Entries of the ATOM section in file order
+        &self.atom
+    }
+    pub fn bonds(&self) -> &[Bond] {
+        // Entries of the BOND section in file order
+        &self.bond
+    }
+    pub fn substructures(&self) -> &[Substructure] {
+        // Entries of the SUBSTRUCTURE section in file order
+        &self.substructure
+    }
+    fn cell(&self, cell: Option<[CoordFloat; 6]>) -> Result<[CoordFloat; 6]> {
+        // Cell given to wrap and unwrap, or the one of the CRYSIN section
+        cell.or_else(|| self.crysin.as_ref().map(|crysin| crysin.cell))
+            .ok_or_else(|| {
+                Mol2Error::InvalidArgument(
+                    "No cell given and the structure has no CRYSIN section".to_owned(),
+                )
+            })
     }
     fn add_comment(&mut self, comment: &str) {
         if self.molecule.is_none() || comment.is_empty() {
@@ -365,90 +785,881 @@ impl Mol2 {
             .unwrap()
             .push_str(comment);
     }
+    pub fn filter_atoms<F: FnMut(&Atom) -> bool>(&self, mut predicate: F) -> Mol2 {
+        // Copy of the structure with only the atoms the predicate holds for,
+        // the bonds between them and renumbered ids, see edit::keep_atoms
+        let ids: HashSet<IdInt> = self
+            .atom
+            .iter()
+            .filter(|atom| predicate(atom))
+            .map(|atom| atom.atom_id)
+            .collect();
+        edit::keep_atoms(self, &ids)
+    }
+    pub fn to_bytes(&self, compression: i32) -> Result<Vec<u8>> {
+        // Encode the structure into the same binary record that is stored in
+        // databases. The record carries its compression level, so from_bytes
+        // needs no options.
+        // Input:
+        //     compression: level of zstd compression, 0 means no compression
+        Ok(bincode::serialize(&RawRow::encode(
+            self,
+            compression,
+            Codec::Zstd,
+            None,
+        )?)?)
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Result<Mol2> {
+        // Decode a structure from a record made by to_bytes
+        // Input:
+        //     bytes: binary record
+        bincode::deserialize::<RawRow>(bytes)?.decode(&Dictionaries::new())
+    }
 }
 
 #[pymethods]
 impl Mol2 {
-    fn to_json(&self) -> String {
+    #[getter]
+    fn get_extras(&self, py: Python) -> PyResult<PyObject> {
+        // User metadata as a dictionary, None if there is none
+        match &self.extras {
+            Some(extras) => Ok(PyModule::import(py, "json")?
+                .getattr("loads")?
+                .call1((serde_json::to_string(extras)
+                    .expect("Failed to translate extras into json format"),))?
+                .to_object(py)),
+            None => Ok(py.None()),
+        }
+    }
+    #[setter]
+    fn set_atom(&mut self, atom: Vec<Atom>) {
+        self.atom = atom;
+        self.graph_changed();
+    }
+    #[setter]
+    fn set_bond(&mut self, bond: Vec<Bond>) {
+        self.bond = bond;
+        self.graph_changed();
+    }
+    #[setter]
+    fn set_extras(&mut self, py: Python, extras: &PyAny) -> PyResult<()> {
+        // Set user metadata from a dictionary of values json can hold, or None
+        if extras.is_none() {
+            self.extras = None;
+            return Ok(());
+        }
+        let text: String = PyModule::import(py, "json")?
+            .getattr("dumps")?
+            .call1((extras,))?
+            .extract()?;
+        self.extras = Some(serde_json::from_str(&text).map_err(|_| {
+            Mol2Error::InvalidArgument("extras have to be a dictionary".to_owned())
+        })?);
+        Ok(())
+    }
+    fn __copy__(&self) -> Mol2 {
+        // Python objects share nothing with the structure, so a shallow
+        // copy is a deep one as well
+        self.clone()
+    }
+    fn __deepcopy__(&self, _memo: &PyAny) -> Mol2 {
+        self.clone()
+    }
+    fn coordinates<'py>(&self, py: Python<'py>) -> &'py PyArray2<CoordFloat> {
+        // Atom coordinates as an atoms x 3 numpy array
+        let coordinates: Vec<CoordFloat> = self
+            .atom
+            .iter()
+            .flat_map(|atom| [atom.x, atom.y, atom.z])
+            .collect();
+        Array2::from_shape_vec((self.atom.len(), 3), coordinates)
+            .expect("Coordinates do not fit the array shape")
+            .into_pyarray(py)
+    }
+    fn set_coordinates(&mut self, coordinates: PyReadonlyArray2<CoordFloat>) -> Result<()> {
+        // Set atom coordinates from an atoms x 3 numpy array
+        let coordinates = coordinates.as_array();
+        if coordinates.shape() != [self.atom.len(), 3] {
+            return Err(Mol2Error::InvalidArgument(format!(
+                "expected coordinates of shape ({}, 3), got {:?}",
+                self.atom.len(),
+                coordinates.shape()
+            )));
+        }
+        for (atom, row) in self.atom.iter_mut().zip(coordinates.rows()) {
+            atom.x = row[0];
+            atom.y = row[1];
+            atom.z = row[2];
+        }
+        Ok(())
+    }
+    #[args(charge_type = "None")]
+    pub fn set_charges(
+        &mut self,
+        charges: Vec<ChargeFloat>,
+        charge_type: Option<String>,
+    ) -> Result<()> {
+        // Set charges of all atoms at once
+        // Input:
+        //     charges: charge of every atom, e.g. a numpy array
+        //     charge_type: new charge type of the molecule, if not given
+        //                  NO_CHARGES or a missing one becomes USER_CHARGES
+        if charges.len() != self.atom.len() {
+            return Err(Mol2Error::InvalidArgument(format!(
+                "{} atoms but {} charges",
+                self.atom.len(),
+                charges.len()
+            )));
+        }
+        for (atom, charge) in self.atom.iter_mut().zip(charges) {
+            atom.charge = Some(charge);
+        }
+        let molecule = self.molecule.get_or_insert(Molecule::new());
+        match charge_type {
+            Some(charge_type) => molecule.charge_type = Some(charge_type),
+            None => {
+                if matches!(molecule.charge_type.as_deref(), None | Some("NO_CHARGES")) {
+                    molecule.charge_type = Some("USER_CHARGES".to_owned());
+                }
+            }
+        }
+        Ok(())
+    }
+    #[args(heavy_only = "false")]
+    fn distance_matrix<'py>(&self, py: Python<'py>, heavy_only: bool) -> &'py PyArray2<CoordFloat> {
+        // Distances between all pairs of atoms as an atoms x atoms numpy array
+        // Input:
+        //     heavy_only: leave out hydrogens, dummy atoms and lone pairs
+        let (count, matrix) = py.allow_threads(|| geometry::distance_matrix(self, heavy_only));
+        Array2::from_shape_vec((count, count), matrix)
+            .expect("Distances do not fit the array shape")
+            .into_pyarray(py)
+    }
+    fn atoms_within(&self, center: &PyAny, cutoff: CoordFloat) -> PyResult<Vec<IdInt>> {
+        // Ids of atoms within the cutoff distance
+        // Input:
+        //     center: atom id, a point (x, y, z) or a list of points, e.g.
+        //             coordinates of a ligand. The atom itself is not included.
+        //     cutoff: largest distance included
+        let (points, skipped) = if let Ok(atom_id) = center.extract::<IdInt>() {
+            let atom = self
+                .atom
+                .iter()
+                .find(|atom| atom.atom_id == atom_id)
+                .ok_or_else(|| {
+                    Mol2Error::InvalidArgument(format!("no atom with id {}", atom_id))
+                })?;
+            (vec![[atom.x, atom.y, atom.z]], Some(atom_id))
+        } else if let Ok(point) = center.extract::<[CoordFloat; 3]>() {
+            (vec![point], None)
+        } else if let Ok(points) = center.extract::<Vec<[CoordFloat; 3]>>() {
+            (points, None)
+        } else {
+            return Err(Mol2Error::InvalidArgument(
+                "expected an atom id, a point or a list of points".to_owned(),
+            )
+            .into());
+        };
+        Ok(geometry::atoms_within(self, &points, cutoff)?
+            .into_iter()
+            .map(|index| self.atom[index].atom_id)
+            .filter(|atom_id| Some(*atom_id) != skipped)
+            .collect())
+    }
+    pub fn distance(&self, i: IdInt, j: IdInt) -> Result<CoordFloat> {
+        // Distance between two atoms given by their ids
+        geometry::distance(self, [i, j])
+    }
+    pub fn angle(&self, i: IdInt, j: IdInt, k: IdInt) -> Result<CoordFloat> {
+        // Angle i-j-k in degrees, atoms are given by their ids
+        geometry::angle(self, [i, j, k])
+    }
+    pub fn dihedral(&self, i: IdInt, j: IdInt, k: IdInt, l: IdInt) -> Result<CoordFloat> {
+        // Dihedral angle i-j-k-l in degrees between -180 and 180, atoms are given by their ids
+        geometry::dihedral(self, [i, j, k, l])
+    }
+    #[args(cell = "None")]
+    pub fn wrap(&mut self, cell: Option<[CoordFloat; 6]>) -> Result<()> {
+        // Move all atoms into a periodic cell
+        // Input:
+        //     cell: lengths a, b, c and angles alpha, beta, gamma in degrees,
+        //           the cell of the CRYSIN section if not given
+        let cell = self.cell(cell)?;
+        geometry::wrap(self, cell)
+    }
+    #[args(cell = "None")]
+    pub fn unwrap(&mut self, cell: Option<[CoordFloat; 6]>) -> Result<()> {
+        // Make bonded atoms split across the boundary of a periodic cell whole
+        // Input:
+        //     cell: lengths a, b, c and angles alpha, beta, gamma in degrees,
+        //           the cell of the CRYSIN section if not given
+        let cell = self.cell(cell)?;
+        geometry::unwrap(self, cell)
+    }
+    pub fn align_principal_axes(&mut self) -> Result<(CoordFloat, CoordFloat, CoordFloat)> {
+        // Move the center of mass to the origin and rotate the structure so
+        // that its principal axes of inertia lie along x, y and z. Returns
+        // the principal moments of inertia in increasing order.
+        let [x, y, z] = geometry::align_principal_axes(self)?;
+        Ok((x, y, z))
+    }
+    pub fn translate(&mut self, dx: CoordFloat, dy: CoordFloat, dz: CoordFloat) {
+        // Move all atoms by (dx, dy, dz)
+        geometry::translate(self, [dx, dy, dz])
+    }
+    pub fn rotate(&mut self, axis: [CoordFloat; 3], angle: CoordFloat) -> Result<()> {
+        // Rotate all atoms around an axis through the origin
+        // Input:
+        //     axis: direction of the rotation axis
+        //     angle: rotation angle in radians, counterclockwise looking against the axis
+        geometry::rotate(self, axis, angle)
+    }
+    pub fn transform(&mut self, matrix: [[CoordFloat; 4]; 4]) -> Result<()> {
+        // Apply a 4x4 affine transformation matrix to all atom coordinates
+        geometry::transform(self, matrix)
+    }
+    #[pyo3(name = "total_charge")]
+    fn py_total_charge(&self, py: Python) -> PyResult<f64> {
+        // Charges that may not be meaningful raise a UserWarning
+        let (sum, warning) = self.total_charge();
+        if let Some(warning) = warning {
+            user_warning(py, &warning)?;
+        }
+        Ok(sum)
+    }
+    #[pyo3(name = "charge_by_substructure")]
+    fn py_charge_by_substructure(&self, py: Python) -> PyResult<PyObject> {
+        // Sums as a dictionary keyed by substructure name, with the same
+        // warnings as total_charge
+        let (sums, warning) = self.charge_by_substructure();
+        if let Some(warning) = warning {
+            user_warning(py, &warning)?;
+        }
+        let dict = PyDict::new(py);
+        for (name, sum) in sums {
+            dict.set_item(name, sum)?;
+        }
+        Ok(dict.into())
+    }
+    pub fn molecular_weight(&self) -> Result<f64> {
+        // Sum of standard atomic weights of all atoms, elements are inferred
+        // from the atom types and dummy atoms and lone pairs have no mass
+        elements::molecular_weight(self)
+    }
+    pub fn centroid(&self) -> Result<(CoordFloat, CoordFloat, CoordFloat)> {
+        // Geometric center of all atoms
+        let [x, y, z] = geometry::centroid(self)?;
+        Ok((x, y, z))
+    }
+    #[args(recenter = "false")]
+    pub fn center_of_mass(
+        &mut self,
+        recenter: bool,
+    ) -> Result<(CoordFloat, CoordFloat, CoordFloat)> {
+        // Center of mass with elements inferred from the atom types
+        // Input:
+        //     recenter: move the structure so that its center of mass is at the origin
+        let [x, y, z] = geometry::center_of_mass(self)?;
+        if recenter {
+            geometry::translate(self, [-x, -y, -z]);
+        }
+        Ok((x, y, z))
+    }
+    fn __len__(&self) -> usize {
+        // A structure is a sequence of its atoms
+        self.atom.len()
+    }
+    fn __getitem__(&self, index: isize) -> PyResult<Atom> {
+        // Single atom by its position, negative positions count from the end
+        let position = match index < 0 {
+            true => self.atom.len() as isize + index,
+            false => index,
+        };
+        match position >= 0 && (position as usize) < self.atom.len() {
+            true => Ok(self.atom[position as usize].clone()),
+            false => Err(pyo3::exceptions::PyIndexError::new_err(
+                "atom index out of range",
+            )),
+        }
+    }
+    fn __iter__(slf: PyRef<Self>) -> SectionIter {
+        SectionIter::new(slf, Section::Atom)
+    }
+    #[pyo3(name = "atoms")]
+    fn py_atoms(slf: PyRef<Self>) -> SectionIter {
+        // Iterate over atoms without copying the whole list first
+        SectionIter::new(slf, Section::Atom)
+    }
+    #[pyo3(name = "bonds")]
+    fn py_bonds(slf: PyRef<Self>) -> SectionIter {
+        // Iterate over bonds without copying the whole list first
+        SectionIter::new(slf, Section::Bond)
+    }
+    pub fn to_json(&self) -> String {
         // Convert to a json string, useful in some cases. But in most cases one should probably use _serialized version of the read function
         let json_str: String =
             serde_json::to_string(self).expect("Failed to translate mol2 into json format");
         json_str
     }
-    fn as_string(&self) -> String {
+    pub fn to_json_pretty(&self) -> String {
+        // Convert to an indented json string, easier to read and edit
+        serde_json::to_string_pretty(self).expect("Failed to translate mol2 into json format")
+    }
+    #[staticmethod]
+    pub fn from_json(json: &str) -> Result<Mol2> {
+        // Create a structure from its json form, as given by to_json.
+        // Structures failing validation are refused.
+        let mol2: Mol2 = serde_json::from_str(json).map_err(|error| Mol2Error::Parse {
+            line: error.line(),
+            message: error.to_string(),
+        })?;
+        let violations = mol2.validate();
+        if !violations.is_empty() {
+            return Err(Mol2Error::InvalidArgument(
+                violations
+                    .iter()
+                    .map(|violation| violation.to_string())
+                    .collect::<Vec<String>>()
+                    .join("; "),
+            ));
+        }
+        Ok(mol2)
+    }
+    #[args(compression = "3")]
+    #[pyo3(name = "to_bytes")]
+    fn py_to_bytes(&self, py: Python, compression: i32) -> PyResult<PyObject> {
+        Ok(PyBytes::new(py, &self.to_bytes(compression)?).into())
+    }
+    #[staticmethod]
+    #[pyo3(name = "from_bytes")]
+    fn py_from_bytes(bytes: &[u8]) -> PyResult<Mol2> {
+        Ok(Mol2::from_bytes(bytes)?)
+    }
+    #[args(aligned = "false", coord_precision = "4", charge_precision = "4")]
+    pub fn as_string(
+        &self,
+        aligned: bool,
+        coord_precision: usize,
+        charge_precision: usize,
+    ) -> String {
         // Show whole structure in a mol2 compliant string
-        let mut text = String::new();
-
-        if self.molecule.is_none() {
-            return text;
+        // Input:
+        //     aligned: write ATOM, BOND and SUBSTRUCTURE records in fixed-width columns
+        //     coord_precision: number of decimals of atom coordinates
+        //     charge_precision: number of decimals of atom charges
+        let format = WriteFormat {
+            aligned,
+            coord_precision,
+            charge_precision,
+        };
+        let mut text: Vec<u8> = Vec::new();
+        self.write_to(&mut text, &format)
+            .expect("Failed to write mol2 into memory");
+        String::from_utf8_lossy(&text).into_owned()
+    }
+    #[args(
+        filename,
+        append = "false",
+        aligned = "false",
+        coord_precision = "4",
+        charge_precision = "4",
+        compression = "\"\""
+    )]
+    fn write_mol2(
+        &self,
+        filename: &str,
+        append: bool,
+        aligned: bool,
+        coord_precision: usize,
+        charge_precision: usize,
+        compression: &str,
+    ) -> PyResult<()> {
+        // Write structure as a mol2 file
+        // Input:
+        //     compression: compression of the file ("none", "gz", "zst"), empty means guess from the extension
+        let format = WriteFormat {
+            aligned,
+            coord_precision,
+            charge_precision,
+        };
+        let compression = FileCompression::from_arg(compression)?;
+        let mut file = compression::open_output(filename, append, compression)?;
+        self.write_to(&mut file, &format)?;
+        file.finish()?;
+        Ok(())
+    }
+    pub fn to_sdf(&self) -> Result<String> {
+        // Show structure as an SDF record, desc and mol_comment are added
+        // as data fields. The molfile is V2000 unless the structure has more
+        // than 999 atoms or bonds, which only fit a V3000 molfile.
+        let mut text: Vec<u8> = Vec::new();
+        sdf::write_sdf_entry(self, &mut text)?;
+        Ok(String::from_utf8_lossy(&text).into_owned())
+    }
+    pub fn to_pdb(&self) -> Result<String> {
+        // Show structure as PDB records, substructures are written as
+        // residues and the text ends with an END record
+        let mut text: Vec<u8> = Vec::new();
+        pdb::write_pdb_entry(self, &mut text)?;
+        writeln!(text, "END")?;
+        Ok(String::from_utf8_lossy(&text).into_owned())
+    }
+    #[args(type_map = "None")]
+    pub fn to_pdbqt(&self, type_map: Option<HashMap<String, String>>) -> Result<String> {
+        // Show structure as PDBQT records for AutoDock and Vina, with
+        // partial charges and AutoDock atom types. No torsion tree is written.
+        // Input:
+        //     type_map: AutoDock types of SYBYL atom types replacing the default mapping
+        let mut text: Vec<u8> = Vec::new();
+        pdb::write_pdbqt_entry(self, &mut text, &type_map.unwrap_or_default())?;
+        Ok(String::from_utf8_lossy(&text).into_owned())
+    }
+    #[staticmethod]
+    pub fn from_smiles(smiles: &str) -> Result<Mol2> {
+        // Build a structure from a SMILES with hydrogens added, SYBYL atom
+        // types guessed from the bonds and all coordinates set to zero
+        smiles::from_smiles(smiles)
+    }
+    #[staticmethod]
+    #[args(charges = "None", name = "\"\"")]
+    pub fn from_arrays(
+        coords: Vec<Vec<CoordFloat>>,
+        atom_types: Vec<String>,
+        atom_names: Vec<String>,
+        bonds: Vec<Vec<usize>>,
+        charges: Option<Vec<ChargeFloat>>,
+        name: &str,
+    ) -> Result<Mol2> {
+        // Build a structure from per-atom arrays, e.g. numpy arrays
+        // Input:
+        //     coords: atoms x 3 coordinates
+        //     atom_types: SYBYL atom type of every atom
+        //     atom_names: name of every atom
+        //     bonds: rows of two atom indices counted from zero, optionally
+        //            followed by the bond order (1, 2, 3 or 4 for aromatic)
+        //     charges: charge of every atom
+        //     name: molecule name
+        let num_atoms = coords.len();
+        if atom_types.len() != num_atoms || atom_names.len() != num_atoms {
+            return Err(Mol2Error::InvalidArgument(format!(
+                "{} coordinates but {} atom types and {} atom names",
+                num_atoms,
+                atom_types.len(),
+                atom_names.len()
+            )));
         }
-
-        text.push_str(&self.molecule.as_ref().unwrap().as_string());
-        // We should probably have a generic function for these section thingies...
-        if !self.atom.is_empty() {
-            text.push_str("@<TRIPOS>ATOM\n");
-            for entry in &self.atom {
-                text.push_str(&entry.as_string());
+        if let Some(charges) = &charges {
+            if charges.len() != num_atoms {
+                return Err(Mol2Error::InvalidArgument(format!(
+                    "{} coordinates but {} charges",
+                    num_atoms,
+                    charges.len()
+                )));
             }
-            text.push('\n');
         }
-        if !self.bond.is_empty() {
-            text.push_str("@<TRIPOS>BOND\n");
-            for entry in &self.bond {
-                text.push_str(&entry.as_string());
-            }
-            text.push('\n');
+        if num_atoms > IdInt::MAX as usize {
+            return Err(Mol2Error::InvalidArgument(format!(
+                "more than {} atoms",
+                IdInt::MAX
+            )));
         }
-        if !self.substructure.is_empty() {
-            text.push_str("@<TRIPOS>SUBSTRUCTURE\n");
-            for entry in &self.substructure {
-                text.push_str(&entry.as_string());
+        let mut builder = Mol2Builder::new().name(name);
+        for (index, xyz) in coords.iter().enumerate() {
+            let xyz: [CoordFloat; 3] = xyz.as_slice().try_into().map_err(|_| {
+                Mol2Error::InvalidArgument(format!(
+                    "atom {}: expected 3 coordinates, got {}",
+                    index,
+                    xyz.len()
+                ))
+            })?;
+            builder = builder.atom(
+                (index + 1) as IdInt,
+                &atom_names[index],
+                &atom_types[index],
+                xyz,
+            );
+            if let Some(charges) = &charges {
+                builder = builder.with_charge(charges[index]);
             }
-            text.push('\n');
         }
-
-        text
+        for (index, bond) in bonds.iter().enumerate() {
+            let bond_type = match bond.len() {
+                2 => "un",
+                3 => sdf::sybyl_bond_type(bond[2].min(u8::MAX as usize) as u8),
+                _ => {
+                    return Err(Mol2Error::InvalidArgument(format!(
+                    "bond {}: expected 2 atom indices and an optional bond order, got {} values",
+                    index,
+                    bond.len()
+                )))
+                }
+            };
+            let atom_id = |atom_index: usize| match atom_index < num_atoms {
+                true => Ok((atom_index + 1) as IdInt),
+                false => Err(Mol2Error::InvalidArgument(format!(
+                    "bond {}: atom index {} out of range",
+                    index, atom_index
+                ))),
+            };
+            builder = builder.bond(atom_id(bond[0])?, atom_id(bond[1])?, bond_type);
+        }
+        builder.build()
     }
-    #[args(filename, append = "false")]
-    fn write_mol2(&self, filename: &str, append: bool) {
-        // Write structure as a mol2 file
-        write_string(&self.as_string(), filename, append);
+    pub fn to_smiles(&self) -> String {
+        // Show structure as a SMILES derived from its atoms and bonds,
+        // without stereochemistry
+        smiles::to_smiles(self)
     }
-    fn serialized(&self) -> PyResult<PyObject> {
-        // give a serialized version of the structure rather than binary form
-        Python::with_gil(|py| {
-            let json = PyModule::import(py, "json").expect("Failed to import python json module");
-            let json = json
-                .getattr("loads")?
-                .call1((self.to_json(),))?
-                .downcast::<PyDict>()?
-                .to_object(py);
-            Ok(json)
-        })
+    pub fn to_xyz(&self) -> Result<String> {
+        // Show structure as a single XYZ frame with elements and coordinates
+        let mut text: Vec<u8> = Vec::new();
+        xyz::write_xyz_entry(self, &mut text)?;
+        Ok(String::from_utf8_lossy(&text).into_owned())
     }
-}
-
-fn read_molecule_section(nline: usize, line: &str, mol2: &mut Mol2) {
-    // Reading lines from a MOLECULE section
-    // Input:
-    //     nline: line number within the section
-    //     line: line string to parse
-    //     mol2: structure to update
-    if line.is_empty() {
-        return;
+    pub fn neighbors(&self, atom_id: IdInt) -> Result<Vec<IdInt>> {
+        // Ids of atoms bonded to the atom with the given id
+        Ok(graph::neighbours(self.cached_adjacency(), atom_id)?.to_vec())
     }
-    match nline {
-        0 => mol2.molecule.get_or_insert(Molecule::new()).mol_name = line.to_owned(),
-        1 => mol2
-            .molecule
-            .as_mut()
-            .expect("You seem to be reading lines in the wrong order...?")
-            .read_nums(line),
-        2 => {
-            mol2.molecule.get_or_insert(Molecule::new()).mol_type =
-                Some(line.split_whitespace().next().get_or_insert("").to_owned())
-        }
-        3 => {
+    pub fn degree(&self, atom_id: IdInt) -> Result<usize> {
+        // Number of bonds of the atom with the given id
+        Ok(graph::neighbours(self.cached_adjacency(), atom_id)?.len())
+    }
+    pub fn adjacency(&self) -> graph::Adjacency {
+        // Ids of bonded atoms for every atom id
+        self.cached_adjacency().clone()
+    }
+    pub fn rings(&self) -> Vec<Vec<IdInt>> {
+        // Smallest set of smallest rings as lists of atom ids in bond order
+        graph::rings(self)
+    }
+    pub fn rotatable_bonds(&self) -> Vec<IdInt> {
+        // Ids of acyclic single bonds between non-terminal heavy atoms,
+        // without amide bonds and bonds next to triple bonds
+        graph::rotatable_bonds(self)
+    }
+    pub fn num_rotatable_bonds(&self) -> usize {
+        // Number of rotatable bonds, see rotatable_bonds
+        graph::rotatable_bonds(self).len()
+    }
+    #[args(coord_tol = "0.001", charge_tol = "0.001")]
+    pub fn close_to(&self, other: &Mol2, coord_tol: CoordFloat, charge_tol: ChargeFloat) -> bool {
+        // Compare with another structure, coordinates and charges within
+        // tolerances and everything else exactly, in the same order.
+        // Descriptions are not compared.
+        // Input:
+        //     other: structure to compare with
+        //     coord_tol: largest difference allowed in each coordinate
+        //     charge_tol: largest difference allowed in charges
+        let atoms_close = self.atom.len() == other.atom.len()
+            && self.atom.iter().zip(&other.atom).all(|(first, second)| {
+                first.atom_id == second.atom_id
+                    && first.atom_name == second.atom_name
+                    && first.atom_type == second.atom_type
+                    && first.subst_id == second.subst_id
+                    && first.subst_name == second.subst_name
+                    && first.status_bit == second.status_bit
+                    && [first.x - second.x, first.y - second.y, first.z - second.z]
+                        .iter()
+                        .all(|difference| difference.abs() <= coord_tol)
+                    && match (first.charge, second.charge) {
+                        (Some(first), Some(second)) => (first - second).abs() <= charge_tol,
+                        (None, None) => true,
+                        _ => false,
+                    }
+            });
+        atoms_close
+            && same_content(&self.molecule, &other.molecule)
+            && same_content(&self.bond, &other.bond)
+            && same_content(&self.substructure, &other.substructure)
+    }
+    #[args(coord_tol = "0.001", charge_tol = "0.001")]
+    pub fn diff(
+        &self,
+        other: &Mol2,
+        coord_tol: CoordFloat,
+        charge_tol: ChargeFloat,
+    ) -> Vec<diff::Difference> {
+        // Differences from this structure to another one, see diff::diff
+        // Input:
+        //     other: structure to compare with, e.g. after preparation
+        //     coord_tol: largest difference in each coordinate not reported
+        //     charge_tol: largest difference in charges not reported
+        diff::diff(self, other, coord_tol, charge_tol)
+    }
+    #[args(coordinates = "false", desc = "true")]
+    pub fn structure_hash(&self, coordinates: bool, desc: bool) -> String {
+        // Hash of atom and bond types of the bond graph, independent of the
+        // order of atoms
+        // Input:
+        //     coordinates: include coordinates rounded to 3 decimals
+        //     desc: include the description
+        canonical::structure_hash(self, coordinates, desc)
+    }
+    #[args(hydrogens = "false")]
+    pub fn match_atoms(&self, query: &Mol2, hydrogens: bool) -> Option<HashMap<IdInt, IdInt>> {
+        // Ids of atoms matching the atoms of a query substructure, by query
+        // atom id, None if the query is not found
+        // Input:
+        //     query: substructure to look for
+        //     hydrogens: also match hydrogens of the query
+        graph::match_atoms(self, query, hydrogens)
+    }
+    #[args(hydrogens = "false")]
+    pub fn contains_substructure(&self, query: &Mol2, hydrogens: bool) -> bool {
+        // Whether the structure contains the query substructure, see match_atoms
+        graph::match_atoms(self, query, hydrogens).is_some()
+    }
+    pub fn assign_sybyl_types(&mut self) -> usize {
+        // Derive SYBYL atom types from elements and the bond graph, returns
+        // the number of atoms with a new type
+        typing::assign_sybyl_types(self)
+    }
+    #[args(scheme = "None", replace = "false")]
+    fn translate_atom_types(
+        &mut self,
+        table: &PyAny,
+        scheme: Option<String>,
+        replace: bool,
+    ) -> PyResult<usize> {
+        // Translate atom types to another typing scheme, returns the number
+        // of translated atoms, see typetables::translate_types
+        // Input:
+        //     table: name of a built-in table, e.g. "sybyl_to_gaff", or a
+        //            dictionary of atom types in the current scheme to the new
+        //     scheme: name of the new scheme, needed for user tables written
+        //             to the ALT_TYPE section
+        //     replace: replace atom types instead of adding an ALT_TYPE set
+        let (builtin, table) = match table.extract::<&str>() {
+            Ok(name) => {
+                let (scheme, table) = typetables::builtin_table(name)?;
+                (Some(scheme.to_owned()), table)
+            }
+            Err(_) => (None, table.extract::<HashMap<String, String>>()?),
+        };
+        let scheme = match (scheme.or(builtin), replace) {
+            (Some(scheme), _) => scheme,
+            (None, true) => String::new(),
+            (None, false) => {
+                return Err(Mol2Error::InvalidArgument(
+                    "scheme is needed to add an ALT_TYPE set with a user table".to_owned(),
+                )
+                .into())
+            }
+        };
+        Ok(typetables::translate_types(self, &table, &scheme, replace))
+    }
+    #[pyo3(name = "normalize_bond_types")]
+    fn py_normalize_bond_types(&mut self, mode: &str) -> Result<usize> {
+        self.normalize_bond_types(BondConvention::from_name(mode)?)
+    }
+    pub fn remove_atoms(&mut self, ids: Vec<IdInt>) -> Result<()> {
+        // Remove atoms and the bonds touching them, renumber the remaining
+        // atoms and bonds from 1 and update the counts in the MOLECULE section
+        // Input:
+        //     ids: ids of the atoms to remove
+        edit::remove_atoms(self, &ids)
+    }
+    #[args(polar_only = "false")]
+    pub fn remove_hydrogens(&self, polar_only: bool) -> Result<Mol2> {
+        // Copy of the structure without hydrogens, see remove_atoms
+        // Input:
+        //     polar_only: only remove hydrogens bonded to nitrogen, oxygen or sulfur
+        let mut mol2 = self.clone();
+        edit::remove_atoms(&mut mol2, &edit::hydrogens(self, polar_only))?;
+        Ok(mol2)
+    }
+    pub fn residues(&self) -> Vec<residues::Residue> {
+        // Atoms grouped by substructure, see residues::residues
+        residues::residues(self)
+    }
+    pub fn sequences(&self) -> Vec<(Option<String>, String)> {
+        // Pairs of chain and its one letter amino acid sequence
+        residues::sequences(self)
+    }
+    #[args(
+        names = "vec![\"HOH\".to_owned(), \"WAT\".to_owned(), \"NA\".to_owned(), \"CL\".to_owned()]"
+    )]
+    pub fn remove_solvent(&mut self, names: Vec<String>) -> Result<usize> {
+        // Remove waters and ions, or other substructures by residue name,
+        // returns the number of removed atoms, see edit::remove_solvent
+        // Input:
+        //     names: residue names of the substructures to remove
+        edit::remove_solvent(self, &names)
+    }
+    fn extract_substructure(&self, subst: &PyAny) -> PyResult<Mol2> {
+        // Copy of a single substructure with the bonds between its atoms,
+        // renumbered as in remove_atoms
+        // Input:
+        //     subst: substructure id or name, a name matches all substructures of that name
+        let key = if let Ok(subst_id) = subst.extract::<IdInt>() {
+            edit::SubstKey::Id(subst_id)
+        } else if let Ok(subst_name) = subst.extract::<&str>() {
+            edit::SubstKey::Name(subst_name)
+        } else {
+            return Err(Mol2Error::InvalidArgument(
+                "expected a substructure id or name".to_owned(),
+            )
+            .into());
+        };
+        Ok(edit::extract_substructure(self, key)?)
+    }
+    #[pyo3(name = "filter_atoms")]
+    fn py_filter_atoms(&self, predicate: &PyAny) -> PyResult<Mol2> {
+        // Copy of the structure with only the atoms for which the python
+        // callable returns True, see filter_atoms
+        let mut ids: HashSet<IdInt> = HashSet::new();
+        for atom in &self.atom {
+            if predicate.call1((atom.clone(),))?.is_true()? {
+                ids.insert(atom.atom_id);
+            }
+        }
+        Ok(edit::keep_atoms(self, &ids))
+    }
+    pub fn split_components(&self) -> Vec<Mol2> {
+        // One structure per connected component of the bond graph, e.g. to
+        // separate counter ions and waters, renumbered as in remove_atoms
+        edit::split_components(self)
+    }
+    pub fn renumber(&mut self) -> Vec<String> {
+        // Number atoms, bonds and substructures from 1 in their current
+        // order, fixing bonds, substructure roots and atom substructure ids,
+        // and return a list of changes made
+        edit::renumber(self)
+    }
+    #[args(by = "\"element\"")]
+    #[pyo3(name = "sort_atoms")]
+    fn py_sort_atoms(&mut self, by: &str) -> Result<()> {
+        self.sort_atoms(AtomOrder::from_name(by)?);
+        Ok(())
+    }
+    #[args(by = "\"chain\"")]
+    #[pyo3(name = "sort_substructures")]
+    fn py_sort_substructures(&mut self, by: &str) -> Result<()> {
+        self.sort_substructures(SubstructureOrder::from_name(by)?);
+        Ok(())
+    }
+    #[args(counts = "true", renumber = "true", trim = "true")]
+    pub fn normalize(&mut self, counts: bool, renumber: bool, trim: bool) -> Vec<String> {
+        // Clean up the structure and return a list of changes made, empty if
+        // it was clean already
+        // Input:
+        //     counts: set counts in the MOLECULE section to the number of records
+        //     renumber: number atoms, bonds and substructures from 1 in their current order
+        //     trim: strip whitespace around molecule, atom and substructure names
+        edit::normalize(self, counts, renumber, trim)
+    }
+    pub fn validate(&self) -> Vec<Violation> {
+        // Check that counts in the MOLECULE section match the number of
+        // records and that bonds, atoms and substructures only reference
+        // records that exist. Returns an empty list for a consistent structure.
+        validate::validate(self)
+    }
+    pub fn validate_types(&self) -> Vec<Violation> {
+        // Check atom and bond types against the mol2 format specification,
+        // returns an empty list if all types are known
+        validate::validate_types(self)
+    }
+    fn serialized(&self) -> PyResult<PyObject> {
+        // give a serialized version of the structure rather than binary form
+        Python::with_gil(|py| {
+            let json = PyModule::import(py, "json").expect("Failed to import python json module");
+            let json = json
+                .getattr("loads")?
+                .call1((self.to_json(),))?
+                .downcast::<PyDict>()?
+                .to_object(py);
+            Ok(json)
+        })
+    }
+}
+
+// Records a SectionIter goes through
+enum Section {
+    Atom,
+    Bond,
+}
+
+// Iterator over atoms or bonds of a structure, records are copied one at a time
+#[pyclass]
+pub struct SectionIter {
+    mol2: Py<Mol2>,
+    section: Section,
+    index: usize,
+}
+
+impl SectionIter {
+    fn new(mol2: PyRef<Mol2>, section: Section) -> SectionIter {
+        SectionIter {
+            mol2: mol2.into(),
+            section,
+            index: 0,
+        }
+    }
+}
+
+#[pymethods]
+impl SectionIter {
+    fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+    fn __next__(mut slf: PyRefMut<Self>) -> Option<PyObject> {
+        let py = slf.py();
+        let index = slf.index;
+        let record = {
+            let mol2 = slf.mol2.borrow(py);
+            match slf.section {
+                Section::Atom => mol2.atom.get(index).map(|atom| atom.clone().into_py(py)),
+                Section::Bond => mol2.bond.get(index).map(|bond| bond.clone().into_py(py)),
+            }
+        };
+        slf.index += 1;
+        record
+    }
+}
+
+fn parse_field<T: std::str::FromStr>(word: &str, field: &str) -> std::result::Result<T, String> {
+    // Parse a single field of a section line, with a readable error message
+    word.parse::<T>()
+        .map_err(|_| format!("Failed to parse {} from \"{}\"", field, word))
+}
+
+fn check_fields(line: &str, required: usize, section: &str) -> std::result::Result<(), String> {
+    // Make sure a section line has at least all the required fields
+    let nfields = line.split_whitespace().count();
+    match nfields < required {
+        true => Err(format!(
+            "Expected at least {} fields in the {} section, found {}",
+            required, section, nfields
+        )),
+        false => Ok(()),
+    }
+}
+
+fn read_molecule_section(
+    nline: usize,
+    line: &str,
+    mol2: &mut Mol2,
+) -> std::result::Result<(), String> {
+    // Reading lines from a MOLECULE section
+    // Input:
+    //     nline: line number within the section
+    //     line: line string to parse
+    //     mol2: structure to update
+    if line.is_empty() {
+        return Ok(());
+    }
+    match nline {
+        0 => mol2.molecule.get_or_insert(Molecule::new()).mol_name = line.to_owned(),
+        1 => mol2
+            .molecule
+            .as_mut()
+            .ok_or("Counts line found before the molecule name")?
+            .read_nums(line),
+        2 => {
+            mol2.molecule.get_or_insert(Molecule::new()).mol_type =
+                Some(line.split_whitespace().next().get_or_insert("").to_owned())
+        }
+        3 => {
             mol2.molecule.get_or_insert(Molecule::new()).charge_type =
                 Some(line.split_whitespace().next().get_or_insert("").to_owned())
         }
@@ -459,16 +1670,18 @@ fn read_molecule_section(nline: usize, line: &str, mol2: &mut Mol2) {
         5 => mol2.molecule.get_or_insert(Molecule::new()).mol_comment = Some(line.to_owned()),
         _ => {}
     }
+    Ok(())
 }
 
-fn read_atom_section(line: &str, mol2: &mut Mol2) {
+fn read_atom_section(line: &str, mol2: &mut Mol2) -> std::result::Result<(), String> {
     // Reading lines from an ATOM section
     // Input:
     //     line: line string to parse
     //     mol2: structure to update
     if line.is_empty() {
-        return;
+        return Ok(());
     }
+    check_fields(line, 6, "ATOM")?;
 
     let mut atom = Atom {
         atom_id: 0,
@@ -485,41 +1698,33 @@ fn read_atom_section(line: &str, mol2: &mut Mol2) {
 
     for (index, word) in line.split_whitespace().enumerate() {
         match index {
-            0 => atom.atom_id = word.parse::<IdInt>().expect("Failed to parse atom id"),
+            0 => atom.atom_id = parse_field(word, "atom id")?,
             1 => atom.atom_name.push_str(word),
-            2 => atom.x = word.parse::<CoordFloat>().expect("Failed to parse atom x"),
-            3 => atom.y = word.parse::<CoordFloat>().expect("Failed to parse atom y"),
-            4 => atom.z = word.parse::<CoordFloat>().expect("Failed to parse atom z"),
+            2 => atom.x = parse_field(word, "atom x")?,
+            3 => atom.y = parse_field(word, "atom y")?,
+            4 => atom.z = parse_field(word, "atom z")?,
             5 => atom.atom_type.push_str(word),
-            6 => {
-                atom.subst_id = Some(
-                    word.parse::<IdInt>()
-                        .expect("Failed to parse atom subst_id"),
-                )
-            }
+            6 => atom.subst_id = Some(parse_field(word, "atom subst_id")?),
             7 => atom.subst_name = Some(word.to_owned()),
-            8 => {
-                atom.charge = Some(
-                    word.parse::<ChargeFloat>()
-                        .expect("Failed to parse atom charge"),
-                )
-            }
+            8 => atom.charge = Some(parse_field(word, "atom charge")?),
             9 => atom.status_bit = Some(word.to_owned()),
             _ => continue,
         };
     }
 
     mol2.atom.push(atom);
+    Ok(())
 }
 
-fn read_bond_section(line: &str, mol2: &mut Mol2) {
+fn read_bond_section(line: &str, mol2: &mut Mol2) -> std::result::Result<(), String> {
     // Reading lines from a BOND section
     // Input:
     //     line: line string to parse
     //     mol2: structure to update
     if line.is_empty() {
-        return;
+        return Ok(());
     }
+    check_fields(line, 4, "BOND")?;
     let mut bond = Bond {
         bond_id: 0,
         origin_atom_id: 0,
@@ -529,37 +1734,50 @@ fn read_bond_section(line: &str, mol2: &mut Mol2) {
     };
     for (index, word) in line.split_whitespace().enumerate() {
         match index {
-            0 => {
-                bond.bond_id = word
-                    .parse::<IdInt>()
-                    .expect("Failed to get bond id from the bond section line")
-            }
-            1 => {
-                bond.origin_atom_id = word
-                    .parse::<IdInt>()
-                    .expect("Failed to get origin atom id from the bond section line")
-            }
-            2 => {
-                bond.target_atom_id = word
-                    .parse::<IdInt>()
-                    .expect("Failed to get target atom id from the bond section line")
-            }
+            0 => bond.bond_id = parse_field(word, "bond id")?,
+            1 => bond.origin_atom_id = parse_field(word, "bond origin atom id")?,
+            2 => bond.target_atom_id = parse_field(word, "bond target atom id")?,
             3 => bond.bond_type.push_str(word),
             4 => bond.status_bit = Some(word.to_owned()),
             _ => continue,
         };
     }
     mol2.bond.push(bond);
+    Ok(())
+}
+
+fn read_crysin_section(line: &str, mol2: &mut Mol2) -> std::result::Result<(), String> {
+    // Reading the line of a CRYSIN section, the cell parameters followed by
+    // the space group and its setting
+    // Input:
+    //     line: line string to parse
+    //     mol2: structure to update
+    if line.is_empty() {
+        return Ok(());
+    }
+    check_fields(line, 8, "CRYSIN")?;
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let mut cell: [CoordFloat; 6] = [0.0; 6];
+    for (value, word) in cell.iter_mut().zip(&words) {
+        *value = parse_field(word, "cell parameter")?;
+    }
+    mol2.crysin = Some(Crysin {
+        cell,
+        space_group: parse_field(words[6], "space group")?,
+        setting: parse_field(words[7], "space group setting")?,
+    });
+    Ok(())
 }
 
-fn read_substructure_section(line: &str, mol2: &mut Mol2) {
+fn read_substructure_section(line: &str, mol2: &mut Mol2) -> std::result::Result<(), String> {
     // Reading lines from a SUBSTRUCTURE section
     // Input:
     //     line: line string to parse
     //     mol2: structure to update
     if line.is_empty() {
-        return;
+        return Ok(());
     }
+    check_fields(line, 3, "SUBSTRUCTURE")?;
     let mut comment = String::new();
     let mut subs = Substructure {
         subst_id: 0,
@@ -575,17 +1793,9 @@ fn read_substructure_section(line: &str, mol2: &mut Mol2) {
     };
     for (index, word) in line.split_whitespace().enumerate() {
         match index {
-            0 => {
-                subs.subst_id = word
-                    .parse::<IdInt>()
-                    .expect("Failed to get subst_id from a substructure section line")
-            }
+            0 => subs.subst_id = parse_field(word, "substructure subst_id")?,
             1 => subs.subst_name = word.to_owned(),
-            2 => {
-                subs.root_atom = word
-                    .parse::<IdInt>()
-                    .expect("Failed to get root atom from a substructure section line")
-            }
+            2 => subs.root_atom = parse_field(word, "substructure root atom")?,
             3 => subs.subst_type = Some(word.to_owned()),
             4 => subs.dict_type = word.parse::<i64>().ok(),
             5 => subs.chain = Some(word.to_owned()),
@@ -600,236 +1810,3451 @@ fn read_substructure_section(line: &str, mol2: &mut Mol2) {
         subs.comment = Some(comment);
     }
     mol2.substructure.push(subs);
+    Ok(())
 }
 
-fn create_table(db: &rusqlite::Connection) -> Result<(), ()> {
-    // Create a table in the database
+fn read_alt_type_section(line: &str, mol2: &mut Mol2) -> std::result::Result<(), String> {
+    // Reading lines from an ALT_TYPE section, the set name lines are skipped
     // Input:
-    //     db: connection to the database
-    match db.execute("CREATE TABLE structures (id INTEGER PRIMARY KEY, mol_name TEXT, num_atoms INTEGER, num_bonds INTEGER, num_subst INTEGER, num_feat INTEGER, num_sets INTEGER, mol_type TEXT, charge_type TEXT, status_bits TEXT, mol_comment TEXT, atom BLOB, bond BLOB, substructure BLOB, extras BLOB, compression INTEGER, desc TEXT)", []) {
-        Ok(_) => Ok(()),
-        _ => Err(()),
+    //     line: line string to parse
+    //     mol2: structure to update
+    if line.is_empty() || line.trim_end().ends_with("_ALT_TYPE_SET") {
+        return Ok(());
+    }
+    check_fields(line, 2, "ALT_TYPE")?;
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let count: usize = parse_field(words[1], "alternative type count")?;
+    if words.len() < 2 + 2 * count {
+        return Err(format!(
+            "Expected {} alternative types in the ALT_TYPE section, found {}",
+            count,
+            (words.len() - 2) / 2
+        ));
     }
+    let mut types: Vec<(IdInt, String)> = Vec::with_capacity(count);
+    for pair in words[2..2 + 2 * count].chunks(2) {
+        types.push((
+            parse_field(pair[0], "alternative type atom id")?,
+            pair[1].to_owned(),
+        ));
+    }
+    mol2.alt_type.push(AltType {
+        scheme: words[0].to_owned(),
+        types,
+    });
+    Ok(())
 }
 
-fn get_db(filename: &str, in_mem: bool) -> rusqlite::Connection {
-    // Get a connection to the database
-    // Input:
-    //     filename: location on the filesystem
-    //     in_mem: should we try to make a temporary copy of the database on faster filesystem before opening?
-    //
-    // in_mem by default will attempt to copy a database to /dev/shm
-    // and work there, that is why it is called 'in_mem'. In the
-    // future we probably want to allow other temporary folders to
-    // allow for example work on NVMe
-    let mut real_path = filename.to_owned();
-    if in_mem {
-        real_path = "/dev/shm/tmp.sqlite".to_owned();
-        if std::path::Path::new(&real_path).exists() {
-            std::fs::remove_file(&real_path)
-                .expect("Failed to delete existing db file on the shm device...");
+// How to react to malformed lines when reading mol2 files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParseMode {
+    // Fail on the first malformed line
+    #[default]
+    Strict,
+    // Skip structures with malformed lines and keep going
+    Lenient,
+}
+
+impl ParseMode {
+    pub fn from_name(name: &str) -> Result<ParseMode> {
+        // Translate a user given parse mode, empty means strict
+        match &name.to_lowercase()[..] {
+            "" | "strict" => Ok(ParseMode::Strict),
+            "lenient" => Ok(ParseMode::Lenient),
+            _ => Err(Mol2Error::InvalidArgument(format!(
+                "Unknown parse mode: {}",
+                name
+            ))),
         }
-        if std::path::Path::new(filename).exists() && std::fs::copy(filename, &real_path).is_err() {
-            real_path = filename.to_owned();
+    }
+}
+
+// Options of reading mol2 files, shared by read_file and the functions
+// built on it. The default reads a file on one thread in the strict mode
+// and guesses the compression from the extension.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadOptions {
+    // Description added to every structure
+    pub desc: String,
+    // Comment added to the molecule comment of every structure
+    pub comment: String,
+    // Compression of the file, None means guess from the extension
+    pub compression: Option<FileCompression>,
+    // Number of threads parsing the file, 1 means streaming the file and 0 means all cores
+    pub threads: usize,
+    pub mode: ParseMode,
+    // Keep the original text of every structure, so that unmodified
+    // structures are written back byte for byte
+    pub keep_source: bool,
+}
+
+impl Default for ReadOptions {
+    fn default() -> ReadOptions {
+        ReadOptions {
+            desc: String::new(),
+            comment: String::new(),
+            compression: None,
+            threads: 1,
+            mode: ParseMode::Strict,
+            keep_source: false,
         }
     }
+}
 
-    let db = rusqlite::Connection::open(&real_path).expect("Connection to the db failed");
-    std::fs::set_permissions(&real_path, std::fs::Permissions::from_mode(0o600)).unwrap();
-    let _ = create_table(&db);
-    db
+impl ReadOptions {
+    pub(crate) fn from_args(
+        desc: &str,
+        comment: &str,
+        compression: &str,
+        threads: usize,
+        mode: &str,
+        keep_source: bool,
+    ) -> Result<ReadOptions> {
+        // Options from the arguments of the python functions
+        Ok(ReadOptions {
+            desc: desc.to_owned(),
+            comment: comment.to_owned(),
+            compression: FileCompression::from_arg(compression)?,
+            threads,
+            mode: ParseMode::from_name(mode)?,
+            keep_source,
+        })
+    }
 }
 
-fn db_cleanup(filename: &str, db: &rusqlite::Connection) {
-    // Cleanup the connection with the database. Checks if the
-    // database is where it should or in a temporary location. If it
-    // is the temporary location let's copy it back to where it should
-    // be.
-    //
+// Structure dropped in the lenient parse mode
+#[pyclass]
+#[derive(Debug, Clone)]
+pub struct SkippedMolecule {
+    #[pyo3(get)]
+    pub line: usize,
+    #[pyo3(get)]
+    pub mol_name: Option<String>,
+    #[pyo3(get)]
+    pub reason: String,
+}
+
+impl std::fmt::Display for SkippedMolecule {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "molecule {} at line {}: {}",
+            self.mol_name.as_deref().unwrap_or("<unnamed>"),
+            self.line,
+            self.reason
+        )
+    }
+}
+
+#[pymethods]
+impl SkippedMolecule {
+    fn __str__(&self) -> String {
+        self.to_string()
+    }
+}
+
+// Line by line parser state shared by the mol2 readers
+struct Mol2Parser {
+    desc: String,
+    comment: String,
+    mode: ParseMode,
+    keep_source: bool,
+    section_name: String,
+    section_line: usize,
+    line_number: usize,
+    entry: Mol2,
+    // Raw lines of the current structure, only filled with keep_source
+    source: String,
+    // Line number and message of the first problem in the current structure
+    entry_error: Option<(usize, String)>,
+    skipped: Vec<SkippedMolecule>,
+}
+
+impl Mol2Parser {
+    fn new(options: &ReadOptions, line_number: usize) -> Mol2Parser {
+        // Input:
+        //     options: description, comment, parse mode and keeping the source
+        //     line_number: number of lines already read before the first line fed to the parser
+        Mol2Parser {
+            desc: options.desc.clone(),
+            comment: options.comment.clone(),
+            mode: options.mode,
+            keep_source: options.keep_source,
+            section_name: String::new(),
+            section_line: 0,
+            line_number,
+            entry: Mol2::new(&options.desc),
+            source: String::new(),
+            entry_error: None,
+            skipped: Vec::new(),
+        }
+    }
+    fn parse_line(&mut self, raw: &str) -> Result<Option<Mol2>> {
+        // Feed a single line to the parser
+        // Input:
+        //     raw: line string, including the line ending if there is one
+        // Returns a finished structure once the next MOLECULE section starts
+        let line = raw.strip_suffix('\n').unwrap_or(raw);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let finished = self.read_line(line)?;
+        // Lines before the first MOLECULE section and after the last
+        // one end up with the first and the last structure
+        if self.keep_source {
+            self.source.push_str(raw);
+        }
+        Ok(finished)
+    }
+    fn read_line(&mut self, line: &str) -> Result<Option<Mol2>> {
+        self.line_number += 1;
+        if line.starts_with('#') {
+            return Ok(None);
+        }
+        if line.len() > 11 && line.starts_with("@<TRIPOS>") {
+            // make sure to not use any extra characters...
+            self.section_name = line[9..].split_whitespace().next().unwrap_or("").to_owned();
+            self.section_line = 0;
+            if self.section_name == "MOLECULE" && self.entry.molecule.is_some() {
+                return Ok(self.take_entry());
+            }
+        } else if !self.section_name.is_empty() {
+            let result = match &self.section_name[..] {
+                "MOLECULE" => read_molecule_section(self.section_line, line, &mut self.entry),
+                "ATOM" => read_atom_section(line, &mut self.entry),
+                "BOND" => read_bond_section(line, &mut self.entry),
+                "SUBSTRUCTURE" => read_substructure_section(line, &mut self.entry),
+                "CRYSIN" => read_crysin_section(line, &mut self.entry),
+                "ALT_TYPE" => read_alt_type_section(line, &mut self.entry),
+                _ => Ok(()),
+            };
+            self.section_line += 1;
+            if let Err(message) = result {
+                match self.mode {
+                    ParseMode::Strict => {
+                        return Err(Mol2Error::Parse {
+                            line: self.line_number,
+                            message,
+                        })
+                    }
+                    // Only the first problem of a structure is remembered
+                    ParseMode::Lenient => {
+                        self.entry_error.get_or_insert((self.line_number, message));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+    fn finish(&mut self) -> Option<Mol2> {
+        // Give back the last structure when we are at the end of the input
+        match self.entry.molecule.is_some() {
+            true => self.take_entry(),
+            false => None,
+        }
+    }
+    fn take_entry(&mut self) -> Option<Mol2> {
+        let mut entry = std::mem::replace(&mut self.entry, Mol2::new(&self.desc));
+        let source = std::mem::take(&mut self.source);
+        if let Some((line, reason)) = self.entry_error.take() {
+            self.skipped.push(SkippedMolecule {
+                line,
+                mol_name: entry.molecule.map(|molecule| molecule.mol_name),
+                reason,
+            });
+            return None;
+        }
+        // Before the comment is added, so that adding one counts as a modification
+        if self.keep_source {
+            entry.keep_source(source);
+        }
+        entry.add_comment(&self.comment);
+        Some(entry)
+    }
+}
+
+// Settings of database connections, see db_pragmas
+struct Pragmas {
+    wal: bool,
+    synchronous: Option<String>,
+    cache_size: Option<i64>,
+    mmap_size: Option<i64>,
+}
+
+// Applied by get_db to every connection the process opens
+static PRAGMAS: std::sync::Mutex<Pragmas> = std::sync::Mutex::new(Pragmas {
+    wal: false,
+    synchronous: None,
+    cache_size: None,
+    mmap_size: None,
+});
+
+const SYNCHRONOUS_MODES: [&str; 4] = ["OFF", "NORMAL", "FULL", "EXTRA"];
+
+pub fn db_pragmas(
+    wal: bool,
+    synchronous: &str,
+    cache_size: Option<i64>,
+    mmap_size: Option<i64>,
+) -> Result<()> {
+    // Configure all databases opened afterwards, e.g. for bulk imports or
+    // databases on network filesystems
+    // Input:
+    //     wal: switch databases to the write-ahead log journal mode, which
+    //          lets reads go on during writes. The mode stays with the database.
+    //     synchronous: OFF, NORMAL, FULL or EXTRA, empty for the SQLite default
+    //     cache_size: pages, or KiB if negative, as in the SQLite pragma
+    //     mmap_size: bytes of the database to memory map
+    let synchronous = synchronous.to_uppercase();
+    if !synchronous.is_empty() && !SYNCHRONOUS_MODES.contains(&&synchronous[..]) {
+        return Err(Mol2Error::InvalidArgument(format!(
+            "unknown synchronous mode {}, expected one of {}",
+            synchronous,
+            SYNCHRONOUS_MODES.join(", ")
+        )));
+    }
+    if mmap_size.unwrap_or(0) < 0 {
+        return Err(Mol2Error::InvalidArgument(
+            "mmap size cannot be negative".to_owned(),
+        ));
+    }
+    let mut pragmas = PRAGMAS.lock().unwrap_or_else(|error| error.into_inner());
+    *pragmas = Pragmas {
+        wal,
+        synchronous: match synchronous.is_empty() {
+            true => None,
+            false => Some(synchronous),
+        },
+        cache_size,
+        mmap_size,
+    };
+    Ok(())
+}
+
+#[pyfunction(
+    wal = "false",
+    synchronous = "\"\"",
+    cache_size = "None",
+    mmap_size = "None"
+)]
+#[pyo3(name = "db_pragmas")]
+fn py_db_pragmas(
+    wal: bool,
+    synchronous: &str,
+    cache_size: Option<i64>,
+    mmap_size: Option<i64>,
+) -> PyResult<()> {
+    Ok(db_pragmas(wal, synchronous, cache_size, mmap_size)?)
+}
+
+fn apply_pragmas(db: &rusqlite::Connection, read_only: bool) -> Result<()> {
+    // Set the pragmas given with db_pragmas on a new connection. The journal
+    // mode is written to the database, so it is left alone on read-only ones.
+    let pragmas = PRAGMAS.lock().unwrap_or_else(|error| error.into_inner());
+    if pragmas.wal && !read_only {
+        db.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+    }
+    if let Some(synchronous) = &pragmas.synchronous {
+        db.pragma_update(None, "synchronous", synchronous)?;
+    }
+    if let Some(cache_size) = pragmas.cache_size {
+        db.pragma_update(None, "cache_size", cache_size)?;
+    }
+    if let Some(mmap_size) = pragmas.mmap_size {
+        db.pragma_update_and_check(None, "mmap_size", mmap_size, |_| Ok(()))?;
+    }
+    Ok(())
+}
+
+// Version of the database layout written by this version of the library,
+// raised whenever tables or columns are added. Databases created before
+// versions were recorded, e.g. by 0.1.x, count as version 0.
+const SCHEMA_VERSION: i64 = 1;
+
+fn schema_version(db: &rusqlite::Connection) -> Result<i64> {
+    // Schema version recorded in a database, 0 if there is none
+    if db
+        .prepare("SELECT version FROM schema_version LIMIT 0")
+        .is_err()
+    {
+        return Ok(0);
+    }
+    Ok(db
+        .query_row("SELECT max(version) FROM schema_version", [], |row| {
+            row.get::<_, Option<i64>>(0)
+        })?
+        .unwrap_or(0))
+}
+
+fn check_schema_version(db: &rusqlite::Connection) -> Result<i64> {
+    // Schema version of a database this version of the library can use.
+    // Columns or tables we do not know about could be written inconsistently.
+    let version = schema_version(db)?;
+    if version > SCHEMA_VERSION {
+        return Err(Mol2Error::NewerSchema {
+            found: version,
+            supported: SCHEMA_VERSION,
+            library: env!("CARGO_PKG_VERSION"),
+        });
+    }
+    Ok(version)
+}
+
+// Columns added to the structures table after the first release and their
+// types. Rows of databases without them read as if the columns were empty.
+const ADDED_COLUMNS: [(&str, &str); 9] = [
+    ("source", "BLOB"),
+    ("molecular_weight", "REAL"),
+    ("rotatable_bonds", "INTEGER"),
+    ("alt_type", "BLOB"),
+    // Provenance of rows, left empty for rows inserted by older versions
+    ("inserted_at", "TEXT"),
+    ("source_file", "TEXT"),
+    // Dictionary the blobs of a row are compressed with, see
+    // db_train_dictionary
+    ("dict_id", "INTEGER"),
+    // Codec of the blobs of a row, rows without one use zstd
+    ("codec", "TEXT"),
+    // CRYSIN section as JSON text
+    ("crysin", "TEXT"),
+];
+
+// Tables added after the first release, empty in databases upgraded from it
+const ADDED_TABLES: [&str; 4] = [
+    "dictionaries (id INTEGER PRIMARY KEY, dictionary BLOB NOT NULL)",
+    // Property columns declared with db_add_property and their types
+    "properties (name TEXT PRIMARY KEY, type TEXT)",
+    // Tags of structures, any number per structure, see db_tag. Links are
+    // keyed by tag first since reads look structures up by tag.
+    "tags (id INTEGER PRIMARY KEY, name TEXT UNIQUE NOT NULL)",
+    "structure_tags (tag_id INTEGER NOT NULL, structure_id INTEGER NOT NULL, PRIMARY KEY (tag_id, structure_id)) WITHOUT ROWID",
+];
+
+fn can_select(db: &rusqlite::Connection, columns: &str, table: &str) -> bool {
+    // Whether a table or view of a database has the columns
+    db.prepare(&format!("SELECT {} FROM {} LIMIT 0", columns, table))
+        .is_ok()
+}
+
+fn check_tables(db: &rusqlite::Connection) -> Result<()> {
+    // Databases opened read-only cannot be upgraded. Older ones are read
+    // through temporary views and tables, which live in memory and leave
+    // the database as it is, giving the missing columns as NULL and the
+    // missing tables empty.
+    if check_schema_version(db)? == SCHEMA_VERSION {
+        return Ok(());
+    }
+    let missing: Vec<String> = ADDED_COLUMNS
+        .iter()
+        .filter(|(column, _)| !can_select(db, column, "main.structures"))
+        .map(|(column, _)| format!(", NULL AS {}", column))
+        .collect();
+    if !missing.is_empty() {
+        // Unqualified names find temporary views before the tables of the
+        // database
+        db.execute_batch(&format!(
+            "CREATE TEMP VIEW structures AS SELECT *{} FROM main.structures",
+            missing.concat()
+        ))?;
+    }
+    for table in ADDED_TABLES {
+        let name = table.split(' ').next().unwrap_or_default();
+        if !can_select(db, "*", &format!("main.{}", name)) {
+            db.execute_batch(&format!("CREATE TEMP TABLE {}", table))?;
+        }
+    }
+    Ok(())
+}
+
+fn create_table(db: &rusqlite::Connection) -> Result<i64> {
+    // Create the tables in the database, or bring the tables of a database
+    // created by an older version up to date. Returns the schema version the
+    // database had.
     // Input:
-    //     filename: where the database should be
     //     db: connection to the database
-    let db_path = db
-        .path()
-        .expect("Seems we had no database to work with....")
-        .to_str()
-        .expect("Failed to convert path to str");
-    if db_path != filename {
-        std::fs::copy(&db_path, filename)
-            .expect("Failed to copy the db file to the final location");
-        std::fs::remove_file(db_path).expect("Failed to delete temporary file on the shm device");
+    let version = check_schema_version(db)?;
+    // Upgrades of older databases either finish or leave them as they were
+    let transaction = db.unchecked_transaction()?;
+    db.execute("CREATE TABLE IF NOT EXISTS structures (id INTEGER PRIMARY KEY, mol_name TEXT, num_atoms INTEGER, num_bonds INTEGER, num_subst INTEGER, num_feat INTEGER, num_sets INTEGER, mol_type TEXT, charge_type TEXT, status_bits TEXT, mol_comment TEXT, atom BLOB, bond BLOB, substructure BLOB, extras BLOB, compression INTEGER, desc TEXT, source BLOB, molecular_weight REAL, rotatable_bonds INTEGER, alt_type BLOB, inserted_at TEXT, source_file TEXT, dict_id INTEGER, codec TEXT, crysin TEXT)", [])?;
+    // Databases created by older versions lack the columns and tables added
+    // since
+    for (column, kind) in ADDED_COLUMNS {
+        if !can_select(db, column, "structures") {
+            db.execute(
+                &format!("ALTER TABLE structures ADD COLUMN {} {}", column, kind),
+                [],
+            )?;
+        }
     }
+    for table in ADDED_TABLES {
+        db.execute(&format!("CREATE TABLE IF NOT EXISTS {}", table), [])?;
+    }
+    // The version is recorded with the library version that wrote it
+    if version < SCHEMA_VERSION {
+        db.execute(
+            "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL, library TEXT)",
+            [],
+        )?;
+        db.execute("DELETE FROM schema_version", [])?;
+        db.execute(
+            "INSERT INTO schema_version (version, library) VALUES (?1, ?2)",
+            rusqlite::params![SCHEMA_VERSION, env!("CARGO_PKG_VERSION")],
+        )?;
+    }
+    transaction.commit()?;
+    Ok(version)
 }
 
-#[pyfunction(mol2_list, filename, append = "false")]
-pub fn write_mol2(mol2_list: Vec<Mol2>, filename: &str, append: bool) {
-    // Write a vector of mol2 structures to a single mol2 file
+pub fn db_migrate(filename: &str) -> Result<i64> {
+    // Upgrade a database created by an older version to the current schema
+    // in place. Databases are also upgraded whenever they are opened, but
+    // reads with shm only upgrade the temporary copy.
+    // Returns the schema version the database had.
     // Input:
-    //     mol2_list: vector with structures
-    //     filename: desired path for the final mol2 file
-    //
-    // TODO: At some point we might need to add some buffering in case
-    // the list is too large...
-    //
-    // TODO: At some point we probably want an 'append' option.
-    let mut text = String::new();
-    for entry in &mol2_list {
-        text.push_str(&entry.as_string());
+    //     filename: path to the database
+    if !std::path::Path::new(filename).is_file() {
+        return Err(Mol2Error::InvalidArgument(format!(
+            "No database at {}",
+            filename
+        )));
+    }
+    let db = rusqlite::Connection::open(filename)?;
+    apply_pragmas(&db, false)?;
+    create_table(&db)
+}
+
+#[pyfunction(filename)]
+#[pyo3(name = "db_migrate")]
+fn py_db_migrate(filename: &str) -> PyResult<i64> {
+    Ok(db_migrate(filename)?)
+}
+
+// Connection to a database. A temporary copy made by get_db is removed once
+// the connection is closed, so that reads do not leave copies behind.
+struct Db {
+    // Only None while being dropped
+    connection: Option<rusqlite::Connection>,
+    temporary: Option<String>,
+}
+
+impl Db {
+    fn new(connection: rusqlite::Connection, temporary: Option<String>) -> Db {
+        Db {
+            connection: Some(connection),
+            temporary,
+        }
+    }
+}
+
+impl std::ops::Deref for Db {
+    type Target = rusqlite::Connection;
+
+    fn deref(&self) -> &rusqlite::Connection {
+        self.connection
+            .as_ref()
+            .expect("Database connection used after closing")
+    }
+}
+
+impl Drop for Db {
+    fn drop(&mut self) {
+        // SQLite has to be done with the files before they are removed
+        drop(self.connection.take());
+        if let Some(path) = &self.temporary {
+            remove_db_files(path).ok();
+        }
+    }
+}
+
+fn remove_db_files(path: &str) -> Result<()> {
+    // Remove a database file, including the journal files of a write-ahead
+    // log left behind
+    for suffix in ["", "-wal", "-shm"] {
+        let path = format!("{}{}", path, suffix);
+        if std::path::Path::new(&path).exists() {
+            std::fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+// Directory for temporary copies of databases set with db_tmp_dir
+static TMP_DIR: std::sync::Mutex<Option<std::path::PathBuf>> = std::sync::Mutex::new(None);
+
+// Temporary copies opened so far, part of their names so that connections
+// of the same process do not share a copy
+static TMP_COUNT: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+pub fn db_tmp_dir(path: &str) -> Result<()> {
+    // Set the directory databases are copied to when opened with shm, for
+    // example local NVMe scratch space for databases too large for memory
+    // Input:
+    //     path: existing directory, empty for the default: the directory in
+    //           the SERDE_MOL2_TMPDIR environment variable if set,
+    //           otherwise /dev/shm or the system temporary directory
+    if !path.is_empty() && !std::path::Path::new(path).is_dir() {
+        return Err(Mol2Error::InvalidArgument(format!(
+            "No directory at {}",
+            path
+        )));
+    }
+    let mut tmp_dir = TMP_DIR.lock().unwrap_or_else(|error| error.into_inner());
+    *tmp_dir = match path.is_empty() {
+        true => None,
+        false => Some(std::path::PathBuf::from(path)),
+    };
+    Ok(())
+}
+
+#[pyfunction(path)]
+#[pyo3(name = "db_tmp_dir")]
+fn py_db_tmp_dir(path: &str) -> PyResult<()> {
+    Ok(db_tmp_dir(path)?)
+}
+
+fn tmp_dir() -> std::path::PathBuf {
+    // Directory for temporary copies of databases, see db_tmp_dir
+    if let Some(dir) = TMP_DIR
+        .lock()
+        .unwrap_or_else(|error| error.into_inner())
+        .as_ref()
+    {
+        return dir.clone();
+    }
+    match std::env::var_os("SERDE_MOL2_TMPDIR") {
+        Some(dir) if !dir.is_empty() => std::path::PathBuf::from(dir),
+        _ => shm_dir(),
+    }
+}
+
+fn tmp_path() -> Result<String> {
+    // Path of a new temporary copy, unique among the processes sharing the
+    // directory and the connections of this process
+    Ok(tmp_dir()
+        .join(format!(
+            "serde_mol2-{}-{}.sqlite",
+            std::process::id(),
+            TMP_COUNT.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+        ))
+        .to_str()
+        .ok_or_else(|| Mol2Error::InvalidArgument("Temporary path is not valid UTF-8".to_owned()))?
+        .to_owned())
+}
+
+fn get_db(filename: &str, in_mem: bool) -> Result<Db> {
+    // Get a connection to the database
+    // Input:
+    //     filename: location on the filesystem
+    //     in_mem: should we try to make a temporary copy of the database on faster filesystem before opening?
+    //
+    // in_mem by default will attempt to copy a database to /dev/shm
+    // and work there, that is why it is called 'in_mem'. Another
+    // directory, e.g. on NVMe, can be chosen with db_tmp_dir.
+    let mut real_path = filename.to_owned();
+    let mut temporary = None;
+    if in_mem {
+        real_path = tmp_path()?;
+        // Left behind by an earlier process with the same id
+        remove_db_files(&real_path)?;
+        if std::path::Path::new(filename).exists() && std::fs::copy(filename, &real_path).is_err() {
+            real_path = filename.to_owned();
+        } else {
+            temporary = Some(real_path.clone());
+        }
+    }
+
+    let db = Db::new(rusqlite::Connection::open(&real_path)?, temporary);
+    restrict_permissions(&real_path)?;
+    apply_pragmas(&db, false)?;
+    create_table(&db)?;
+    add_regexp(&db)?;
+    Ok(db)
+}
+
+fn get_db_read_only(filename: &str) -> Result<Db> {
+    // Get a connection that never writes to the database, e.g. on a
+    // read-only shared filesystem. The database is used where it is, its
+    // tables are neither created nor upgraded and its permissions are left
+    // as they are.
+    // Input:
+    //     filename: location on the filesystem
+    if !std::path::Path::new(filename).is_file() {
+        return Err(Mol2Error::InvalidArgument(format!(
+            "No database at {}",
+            filename
+        )));
+    }
+    let db = Db::new(
+        rusqlite::Connection::open_with_flags(
+            filename,
+            rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY | rusqlite::OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?,
+        None,
+    );
+    apply_pragmas(&db, true)?;
+    check_tables(&db)?;
+    add_regexp(&db)?;
+    Ok(db)
+}
+
+fn open_db(filename: &str, shm: bool, read_only: bool) -> Result<Db> {
+    // Connection for reads, shm is ignored for read-only connections
+    match read_only {
+        true => get_db_read_only(filename),
+        false => get_db(filename, shm),
+    }
+}
+
+fn add_regexp(db: &rusqlite::Connection) -> Result<()> {
+    // SQLite has the REGEXP operator but no function behind it, "X REGEXP Y"
+    // calls regexp(Y, X). Patterns are compiled once per statement.
+    db.create_scalar_function(
+        "regexp",
+        2,
+        rusqlite::functions::FunctionFlags::SQLITE_UTF8
+            | rusqlite::functions::FunctionFlags::SQLITE_DETERMINISTIC,
+        |context| {
+            let pattern: std::sync::Arc<regex::Regex> = context.get_or_create_aux(
+                0,
+                |pattern| -> std::result::Result<
+                    regex::Regex,
+                    Box<dyn std::error::Error + Send + Sync + 'static>,
+                > { Ok(regex::Regex::new(pattern.as_str()?)?) },
+            )?;
+            // NULL values do not match
+            Ok(match context.get_raw(1) {
+                rusqlite::types::ValueRef::Null => false,
+                value => pattern.is_match(
+                    value
+                        .as_str()
+                        .map_err(|error| rusqlite::Error::UserFunctionError(error.into()))?,
+                ),
+            })
+        },
+    )?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn shm_dir() -> std::path::PathBuf {
+    // Temporary location for databases, memory backed if available
+    let shm = std::path::PathBuf::from("/dev/shm");
+    match shm.is_dir() {
+        true => shm,
+        false => std::env::temp_dir(),
+    }
+}
+
+#[cfg(not(unix))]
+fn shm_dir() -> std::path::PathBuf {
+    std::env::temp_dir()
+}
+
+#[cfg(unix)]
+fn restrict_permissions(path: &str) -> Result<()> {
+    // Make the database readable only by the owner
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn restrict_permissions(_path: &str) -> Result<()> {
+    // Permissions are inherited from the parent folder on other platforms
+    Ok(())
+}
+
+fn db_cleanup(filename: &str, db: &rusqlite::Connection) -> Result<()> {
+    // Cleanup the connection with the database. Checks if the
+    // database is where it should or in a temporary location. If it
+    // is the temporary location let's copy it back to where it should
+    // be.
+    //
+    // Input:
+    //     filename: where the database should be
+    //     db: connection to the database
+    let db_path = db
+        .path()
+        .and_then(|path| path.to_str())
+        .ok_or_else(|| Mol2Error::InvalidArgument("Database has no usable path".to_owned()))?;
+    if db_path != filename {
+        // Changes still in a write-ahead log have to be in the copied file
+        db.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        std::fs::copy(db_path, filename)?;
+        remove_db_files(db_path)?;
+    }
+    Ok(())
+}
+
+pub fn write_mol2(
+    mol2_list: Vec<Mol2>,
+    filename: &str,
+    append: bool,
+    aligned: bool,
+    coord_precision: usize,
+    charge_precision: usize,
+    compression: Option<FileCompression>,
+) -> Result<()> {
+    // Write a vector of mol2 structures to a single mol2 file
+    // Input:
+    //     mol2_list: vector with structures
+    //     filename: desired path for the final mol2 file
+    //     aligned: write records in fixed-width columns
+    //     coord_precision: number of decimals of atom coordinates
+    //     charge_precision: number of decimals of atom charges
+    //     compression: compression of the file, None means guess from the extension
+    //
+    // Structures are written one by one through a buffer, and
+    // compressed on the fly, so the whole file is never held in memory
+    let format = WriteFormat {
+        aligned,
+        coord_precision,
+        charge_precision,
+    };
+    let mut file = compression::open_output(filename, append, compression)?;
+    for entry in &mol2_list {
+        entry.write_to(&mut file, &format)?;
+    }
+    file.finish()?;
+    Ok(())
+}
+
+#[pyfunction(
+    mol2_list,
+    filename,
+    append = "false",
+    aligned = "false",
+    coord_precision = "4",
+    charge_precision = "4",
+    compression = "\"\""
+)]
+#[pyo3(name = "write_mol2")]
+fn py_write_mol2(
+    mol2_list: Vec<&PyAny>,
+    filename: &str,
+    append: bool,
+    aligned: bool,
+    coord_precision: usize,
+    charge_precision: usize,
+    compression: &str,
+) -> PyResult<()> {
+    // Conformers are written as one structure per frame
+    let mut structures: Vec<Mol2> = Vec::with_capacity(mol2_list.len());
+    for entry in mol2_list {
+        match entry.extract::<PyRef<conformers::Conformers>>() {
+            Ok(conformers) => structures.extend(conformers.expand()),
+            Err(_) => structures.push(entry.extract()?),
+        }
+    }
+    Ok(write_mol2(
+        structures,
+        filename,
+        append,
+        aligned,
+        coord_precision,
+        charge_precision,
+        FileCompression::from_arg(compression)?,
+    )?)
+}
+
+pub fn write_ndjson(
+    mol2_list: Vec<Mol2>,
+    filename: &str,
+    append: bool,
+    compression: Option<FileCompression>,
+) -> Result<()> {
+    // Write a vector of mol2 structures as newline-delimited json, one structure per line
+    // Input:
+    //     mol2_list: vector with structures
+    //     filename: desired path for the final json file
+    //     append: append to the file rather than truncate it
+    //     compression: compression of the file, None means guess from the extension
+    let mut file = compression::open_output(filename, append, compression)?;
+    for entry in &mol2_list {
+        writeln!(file, "{}", entry.to_json())?;
+    }
+    file.finish()?;
+    Ok(())
+}
+
+#[pyfunction(mol2_list, filename, append = "false", compression = "\"\"")]
+#[pyo3(name = "write_ndjson")]
+fn py_write_ndjson(
+    mol2_list: Vec<Mol2>,
+    filename: &str,
+    append: bool,
+    compression: &str,
+) -> PyResult<()> {
+    Ok(write_ndjson(
+        mol2_list,
+        filename,
+        append,
+        FileCompression::from_arg(compression)?,
+    )?)
+}
+
+pub fn mol2_json_schema() -> String {
+    // JSON Schema of structures in the form given by to_json and accepted by from_json
+    serde_json::to_string_pretty(&schemars::schema_for!(Mol2))
+        .expect("Failed to translate json schema into json format")
+}
+
+#[pyfunction]
+#[pyo3(name = "mol2_json_schema")]
+fn py_mol2_json_schema() -> String {
+    mol2_json_schema()
+}
+
+pub fn deduplicate(mol2_list: Vec<Mol2>, coordinates: bool, desc: bool) -> Vec<Mol2> {
+    // Drop structures with the same structure hash as an earlier one
+    // Input:
+    //     mol2_list: structures to deduplicate
+    //     coordinates: structures with different coordinates are different
+    //     desc: structures with different descriptions are different
+    canonical::deduplicate(mol2_list, coordinates, desc)
+}
+
+#[pyfunction(mol2_list, coordinates = "false", desc = "true")]
+#[pyo3(name = "deduplicate")]
+fn py_deduplicate(mol2_list: Vec<Mol2>, coordinates: bool, desc: bool) -> Vec<Mol2> {
+    deduplicate(mol2_list, coordinates, desc)
+}
+
+pub fn collapse_conformers(mol2_list: Vec<Mol2>) -> Vec<conformers::Conformers> {
+    // Group structures sharing a name and topology, e.g. docking poses,
+    // into conformers with one topology and many frames
+    // Input:
+    //     mol2_list: structures to group
+    conformers::collapse(mol2_list)
+}
+
+#[pyfunction(mol2_list)]
+#[pyo3(name = "collapse_conformers")]
+fn py_collapse_conformers(mol2_list: Vec<Mol2>) -> Vec<conformers::Conformers> {
+    collapse_conformers(mol2_list)
+}
+
+#[cfg(feature = "arrow")]
+#[pyfunction(mol2_list)]
+#[pyo3(name = "to_arrow")]
+fn py_to_arrow(py: Python, mol2_list: Vec<Mol2>) -> PyResult<PyObject> {
+    // Tables are handed over as Arrow IPC streams, pyarrow reads them
+    // with pyarrow.ipc.open_stream without copying the data
+    let tables = to_arrow(&mol2_list)?;
+    let streams = PyDict::new(py);
+    for (name, batch) in [
+        ("molecules", &tables.molecules),
+        ("atoms", &tables.atoms),
+        ("bonds", &tables.bonds),
+    ] {
+        streams.set_item(name, PyBytes::new(py, &arrow::to_ipc_stream(batch)?))?;
+    }
+    Ok(streams.into())
+}
+
+#[cfg(feature = "hdf5")]
+pub fn write_hdf5(mol2_list: Vec<Mol2>, filename: &str) -> Result<()> {
+    // Write a vector of mol2 structures to an HDF5 file, one group per structure
+    // Input:
+    //     mol2_list: vector with structures
+    //     filename: desired path for the HDF5 file, an existing file is overwritten
+    h5::write_hdf5(&mol2_list, filename)
+}
+
+#[cfg(feature = "hdf5")]
+#[pyfunction(mol2_list, filename)]
+#[pyo3(name = "write_hdf5")]
+fn py_write_hdf5(mol2_list: Vec<Mol2>, filename: &str) -> PyResult<()> {
+    Ok(write_hdf5(mol2_list, filename)?)
+}
+
+#[cfg(feature = "npz")]
+pub fn write_npz(mol2_list: Vec<Mol2>, filename: &str) -> Result<()> {
+    // Write coordinates, charges and atom types of a vector of mol2 structures
+    // to a compressed numpy npz archive
+    // Input:
+    //     mol2_list: vector with structures
+    //     filename: desired path for the npz file, an existing file is overwritten
+    npz::write_npz(&mol2_list, filename)
+}
+
+#[cfg(feature = "npz")]
+#[pyfunction(mol2_list, filename)]
+#[pyo3(name = "write_npz")]
+fn py_write_npz(mol2_list: Vec<Mol2>, filename: &str) -> PyResult<()> {
+    Ok(write_npz(mol2_list, filename)?)
+}
+
+pub fn read_sdf(filename: &str, desc: &str, comment: &str) -> Result<Vec<Mol2>> {
+    // Read an SDF file and return a vector of structures
+    // Input:
+    //     filename: path to an SDF file, compression is guessed from the extension
+    //     desc: description added to structures, taken from the desc data field if empty
+    //     comment: comment added to the molecule comment field
+    let mut text = String::new();
+    compression::open_input(filename, None)?.read_to_string(&mut text)?;
+    sdf::read_sdf_text(&text, desc, comment)
+}
+
+#[pyfunction(filename, desc = "\"\"", comment = "\"\"")]
+#[pyo3(name = "read_sdf")]
+fn py_read_sdf(filename: &str, desc: &str, comment: &str) -> PyResult<Vec<Mol2>> {
+    Ok(read_sdf(filename, desc, comment)?)
+}
+
+pub fn write_sdf(
+    mol2_list: Vec<Mol2>,
+    filename: &str,
+    append: bool,
+    compression: Option<FileCompression>,
+) -> Result<()> {
+    // Write a vector of mol2 structures to a single SDF file
+    // Input:
+    //     mol2_list: vector with structures
+    //     filename: desired path for the final SDF file
+    //     append: append to the file rather than truncate it
+    //     compression: compression of the file, None means guess from the extension
+    let mut file = compression::open_output(filename, append, compression)?;
+    for entry in &mol2_list {
+        sdf::write_sdf_entry(entry, &mut file)?;
+    }
+    file.finish()?;
+    Ok(())
+}
+
+#[pyfunction(mol2_list, filename, append = "false", compression = "\"\"")]
+#[pyo3(name = "write_sdf")]
+fn py_write_sdf(
+    mol2_list: Vec<Mol2>,
+    filename: &str,
+    append: bool,
+    compression: &str,
+) -> PyResult<()> {
+    Ok(write_sdf(
+        mol2_list,
+        filename,
+        append,
+        FileCompression::from_arg(compression)?,
+    )?)
+}
+
+pub fn read_pdb(filename: &str, desc: &str, comment: &str, conect: bool) -> Result<Vec<Mol2>> {
+    // Read a PDB file and return a vector of structures, one per MODEL
+    // Input:
+    //     filename: path to a PDB file, compression is guessed from the extension
+    //     desc: description added to structures
+    //     comment: comment added to the molecule comment field
+    //     conect: add bonds from CONECT records
+    let mut text = String::new();
+    compression::open_input(filename, None)?.read_to_string(&mut text)?;
+    // Structures are named after the file unless it has a HEADER or COMPND record
+    let name = std::path::Path::new(filename)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    pdb::read_pdb_text(&text, &name, desc, comment, conect)
+}
+
+#[pyfunction(filename, desc = "\"\"", comment = "\"\"", conect = "true")]
+#[pyo3(name = "read_pdb")]
+fn py_read_pdb(filename: &str, desc: &str, comment: &str, conect: bool) -> PyResult<Vec<Mol2>> {
+    Ok(read_pdb(filename, desc, comment, conect)?)
+}
+
+pub fn write_pdb(
+    mol2_list: Vec<Mol2>,
+    filename: &str,
+    append: bool,
+    compression: Option<FileCompression>,
+) -> Result<()> {
+    // Write a vector of mol2 structures to a single PDB file, one MODEL per structure
+    // Input:
+    //     mol2_list: vector with structures
+    //     filename: desired path for the final PDB file
+    //     append: append to the file rather than truncate it
+    //     compression: compression of the file, None means guess from the extension
+    let mut file = compression::open_output(filename, append, compression)?;
+    for (index, entry) in mol2_list.iter().enumerate() {
+        writeln!(file, "MODEL     {:>4}", index + 1)?;
+        pdb::write_pdb_entry(entry, &mut file)?;
+        writeln!(file, "ENDMDL")?;
+    }
+    file.finish()?;
+    Ok(())
+}
+
+#[pyfunction(mol2_list, filename, append = "false", compression = "\"\"")]
+#[pyo3(name = "write_pdb")]
+fn py_write_pdb(
+    mol2_list: Vec<Mol2>,
+    filename: &str,
+    append: bool,
+    compression: &str,
+) -> PyResult<()> {
+    Ok(write_pdb(
+        mol2_list,
+        filename,
+        append,
+        FileCompression::from_arg(compression)?,
+    )?)
+}
+
+pub fn read_xyz(filename: &str, desc: &str, comment: &str) -> Result<Vec<Mol2>> {
+    // Read an XYZ file and return a vector of structures, one per frame
+    // Input:
+    //     filename: path to an XYZ file, compression is guessed from the extension
+    //     desc: description added to structures
+    //     comment: comment added to the molecule comment field
+    let mut text = String::new();
+    compression::open_input(filename, None)?.read_to_string(&mut text)?;
+    // Frames with an empty comment line are named after the file
+    let name = std::path::Path::new(filename)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    xyz::read_xyz_text(&text, &name, desc, comment)
+}
+
+#[pyfunction(filename, desc = "\"\"", comment = "\"\"")]
+#[pyo3(name = "read_xyz")]
+fn py_read_xyz(filename: &str, desc: &str, comment: &str) -> PyResult<Vec<Mol2>> {
+    Ok(read_xyz(filename, desc, comment)?)
+}
+
+pub fn write_xyz(
+    mol2_list: Vec<Mol2>,
+    filename: &str,
+    append: bool,
+    compression: Option<FileCompression>,
+) -> Result<()> {
+    // Write a vector of mol2 structures to a single XYZ file, one frame per structure
+    // Input:
+    //     mol2_list: vector with structures
+    //     filename: desired path for the final XYZ file
+    //     append: append to the file rather than truncate it
+    //     compression: compression of the file, None means guess from the extension
+    let mut file = compression::open_output(filename, append, compression)?;
+    for entry in &mol2_list {
+        xyz::write_xyz_entry(entry, &mut file)?;
+    }
+    file.finish()?;
+    Ok(())
+}
+
+#[pyfunction(mol2_list, filename, append = "false", compression = "\"\"")]
+#[pyo3(name = "write_xyz")]
+fn py_write_xyz(
+    mol2_list: Vec<Mol2>,
+    filename: &str,
+    append: bool,
+    compression: &str,
+) -> PyResult<()> {
+    Ok(write_xyz(
+        mol2_list,
+        filename,
+        append,
+        FileCompression::from_arg(compression)?,
+    )?)
+}
+
+pub fn db_insert(
+    mol2_list: Vec<Mol2>,
+    filename: &str,
+    compression: i32,
+    shm: bool,
+    replace: bool,
+    source_file: &str,
+    codec: Codec,
+) -> Result<Vec<i64>> {
+    // Insert vector of structures into a database. Append if the database exists.
+    // Returns the row ids of the inserted structures, in the order of the list.
+    // Input:
+    //     mol2_list: vector of structures
+    //     filename: path to the database
+    //     compression: compression level. NOTE: 0 means no compression and not default level as in zstd library
+    //     shm: should be try and use a database out from a temporary location
+    //     replace: overwrite structures with the same mol_name and desc,
+    //              e.g. when importing the same files again
+    //     source_file: file the structures come from, recorded with the
+    //                  time of insertion, empty if not known
+    //     codec: codec to compress the blobs with, stored with every row
+    let db = get_db(filename, shm)?;
+    let ids = insert_structures(&db, &mol2_list, compression, codec, replace, source_file)?;
+    db_cleanup(filename, &db)?;
+    Ok(ids)
+}
+
+fn compression_error(error: std::io::Error) -> Mol2Error {
+    Mol2Error::Compression(error.to_string())
+}
+
+// Compression dictionary stored in a database. Row ids of dictionaries
+// repeat across databases, so contexts set up with a dictionary tell them
+// apart by a key unique within the process.
+struct Dictionary {
+    id: i64,
+    key: u64,
+    data: Vec<u8>,
+}
+
+static DICTIONARY_KEYS: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+impl Dictionary {
+    fn new(id: i64, data: Vec<u8>) -> Dictionary {
+        Dictionary {
+            id,
+            key: DICTIONARY_KEYS.fetch_add(1, std::sync::atomic::Ordering::Relaxed),
+            data,
+        }
+    }
+}
+
+// Dictionaries of a database by row id
+type Dictionaries = HashMap<i64, Dictionary>;
+
+fn dictionaries(db: &rusqlite::Connection) -> Result<Dictionaries> {
+    // All dictionaries of a database, needed to decode rows
+    let mut stmt = db.prepare("SELECT id, dictionary FROM dictionaries")?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    let mut dictionaries = Dictionaries::new();
+    for row in rows {
+        let (id, data) = row?;
+        dictionaries.insert(id, Dictionary::new(id, data));
+    }
+    Ok(dictionaries)
+}
+
+fn latest_dictionary(db: &rusqlite::Connection) -> Result<Option<Dictionary>> {
+    // The dictionary trained last, new rows are compressed with it
+    Ok(db
+        .query_row(
+            "SELECT id, dictionary FROM dictionaries ORDER BY id DESC LIMIT 1",
+            [],
+            |row| Ok(Dictionary::new(row.get(0)?, row.get(1)?)),
+        )
+        .optional()?)
+}
+
+// Compression level and dictionary key a compressor is set up with, none
+// before the first blob
+type CompressorSetup = Option<(i32, Option<u64>)>;
+
+// zstd contexts are reused for every blob compressed or decompressed by a
+// thread. They are set up again only when the compression level or the
+// dictionary, given by its key, changes.
+thread_local! {
+    static COMPRESSOR: std::cell::RefCell<(zstd::bulk::Compressor<'static>, CompressorSetup)> =
+        std::cell::RefCell::new(Default::default());
+    static DECOMPRESSOR: std::cell::RefCell<(zstd::bulk::Decompressor<'static>, Option<u64>)> =
+        std::cell::RefCell::new(Default::default());
+}
+
+fn compress_blob(
+    data: &[u8],
+    codec: Codec,
+    level: i32,
+    dictionary: Option<&Dictionary>,
+) -> Result<Vec<u8>> {
+    // Compress a blob, dictionaries are only used by zstd
+    match codec {
+        Codec::None => Ok(data.to_vec()),
+        Codec::Zstd => zstd_compress(data, level, dictionary),
+        Codec::Lz4 => compression::lz4_compress(data),
+        Codec::Gzip => compression::gzip_compress(data, level),
+    }
+}
+
+fn decompress_blob(data: &[u8], codec: Codec, dictionary: Option<&Dictionary>) -> Result<Vec<u8>> {
+    match codec {
+        Codec::None => Ok(data.to_vec()),
+        Codec::Zstd => zstd_decompress(data, dictionary),
+        Codec::Lz4 => compression::lz4_decompress(data),
+        Codec::Gzip => compression::gzip_decompress(data),
+    }
+}
+
+fn zstd_compress(data: &[u8], level: i32, dictionary: Option<&Dictionary>) -> Result<Vec<u8>> {
+    let setup = Some((level, dictionary.map(|dictionary| dictionary.key)));
+    COMPRESSOR
+        .with(|context| {
+            let (compressor, current) = &mut *context.borrow_mut();
+            if *current != setup {
+                compressor
+                    .set_dictionary(level, dictionary.map_or(&[], |dictionary| &dictionary.data))?;
+                *current = setup;
+            }
+            compressor.compress(data)
+        })
+        .map_err(compression_error)
+}
+
+fn zstd_decompress(data: &[u8], dictionary: Option<&Dictionary>) -> Result<Vec<u8>> {
+    // Frames written by this module start with the size of their content, so
+    // the output is allocated once at exactly that size
+    let size = match zstd::zstd_safe::get_frame_content_size(data) {
+        zstd::zstd_safe::CONTENTSIZE_ERROR => {
+            return Err(Mol2Error::Compression(
+                "blob is not a zstd frame".to_owned(),
+            ))
+        }
+        // Frames from other writers may leave the size out, those are
+        // decompressed as a stream instead
+        zstd::zstd_safe::CONTENTSIZE_UNKNOWN => {
+            let mut buffer = Vec::new();
+            zstd::stream::read::Decoder::with_dictionary(
+                data,
+                dictionary.map_or(&[], |dictionary| &dictionary.data),
+            )
+            .and_then(|mut decoder| decoder.read_to_end(&mut buffer))
+            .map_err(compression_error)?;
+            return Ok(buffer);
+        }
+        size => usize::try_from(size).map_err(|_| {
+            Mol2Error::Compression(format!("blob of {} bytes does not fit in memory", size))
+        })?,
+    };
+    let setup = dictionary.map(|dictionary| dictionary.key);
+    DECOMPRESSOR
+        .with(|context| {
+            let (decompressor, current) = &mut *context.borrow_mut();
+            if *current != setup {
+                decompressor
+                    .set_dictionary(dictionary.map_or(&[], |dictionary| &dictionary.data))?;
+                *current = setup;
+            }
+            decompressor.decompress(data, size)
+        })
+        .map_err(compression_error)
+}
+
+// Columns written for every structure, bound as ?1 to ?23 by write_row
+const WRITTEN_COLUMNS: &str = "mol_name, num_atoms, num_bonds, num_subst, num_feat, num_sets, mol_type, charge_type, status_bits, mol_comment, atom, bond, substructure, compression, desc, source, molecular_weight, rotatable_bonds, extras, alt_type, dict_id, codec, crysin";
+const WRITTEN_VALUES: &str =
+    "?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23";
+
+fn write_row<T>(
+    entry: &Mol2,
+    compression: i32,
+    codec: Codec,
+    dictionary: Option<&Dictionary>,
+    write: impl FnOnce(&[&dyn rusqlite::ToSql]) -> rusqlite::Result<T>,
+) -> Result<T> {
+    // Encode a structure and hand the values of WRITTEN_COLUMNS to a statement
+    // Input:
+    //     entry: structure to write
+    //     compression: compression level, 0 means no compression
+    //     codec: codec to compress the blobs with
+    //     dictionary: dictionary to compress the blobs with, if any
+    //     write: runs the statement with the values
+    let row = RawRow::encode(entry, compression, codec, dictionary)?;
+    let molecule = &row.molecule;
+    Ok(write(rusqlite::params![
+        molecule.mol_name,
+        molecule.num_atoms,
+        molecule.num_bonds,
+        molecule.num_subst,
+        molecule.num_feat,
+        molecule.num_sets,
+        molecule.mol_type,
+        molecule.charge_type,
+        molecule.status_bits,
+        molecule.mol_comment,
+        row.atom,
+        row.bond,
+        row.substructure,
+        row.compression,
+        row.desc,
+        row.source,
+        // Left empty for atom types of unknown elements
+        elements::molecular_weight(entry).ok(),
+        graph::rotatable_bonds(entry).len(),
+        row.extras,
+        row.alt_type,
+        row.dict_id,
+        row.codec,
+        row.crysin,
+    ])?)
+}
+
+fn update_row(
+    statement: &mut rusqlite::Statement,
+    id: i64,
+    entry: &Mol2,
+    compression: i32,
+    codec: Codec,
+    dictionary: Option<&Dictionary>,
+) -> Result<usize> {
+    // Overwrite a row with a statement prepared from update_cmd, returns the
+    // number of updated rows
+    write_row(entry, compression, codec, dictionary, |params| {
+        let mut params = params.to_vec();
+        params.push(&id);
+        statement.execute(&params[..])
+    })
+}
+
+fn update_cmd() -> String {
+    format!(
+        "UPDATE structures SET ({}) = ({}) WHERE id = ?24",
+        WRITTEN_COLUMNS, WRITTEN_VALUES
+    )
+}
+
+fn source_path(source_file: &str) -> Option<String> {
+    // Absolute path of a file structures are read from, as given if the
+    // file cannot be found and none if not known
+    match source_file {
+        "" => None,
+        source_file => Some(
+            std::fs::canonicalize(source_file)
+                .ok()
+                .and_then(|path| path.to_str().map(str::to_owned))
+                .unwrap_or_else(|| source_file.to_owned()),
+        ),
+    }
+}
+
+fn insert_structures(
+    db: &rusqlite::Connection,
+    mol2_list: &[Mol2],
+    compression: i32,
+    codec: Codec,
+    replace: bool,
+    source_file: &str,
+) -> Result<Vec<i64>> {
+    // Insert structures using an already open connection, returns their row ids
+    // Input:
+    //     db: connection to the database
+    //     mol2_list: structures to insert
+    //     compression: compression level, 0 means no compression
+    //     codec: codec to compress the blobs with
+    //     replace: overwrite the first row with the same mol_name and desc
+    //              instead of adding another one
+    //     source_file: file the structures come from, empty if not known
+    let source_file = source_path(source_file);
+    // All rows go in one transaction, otherwise SQLite syncs to disk after
+    // every row. Nothing is inserted if one of the rows fails. Within a
+    // transaction of the caller, e.g. of a Mol2Db, the rows are part of it.
+    let transaction = match db.is_autocommit() {
+        true => Some(db.unchecked_transaction()?),
+        false => None,
+    };
+    let mut ids: Vec<i64> = Vec::with_capacity(mol2_list.len());
+    // Statements are finished before the commit
+    {
+        // Rows are stamped with the time of insertion in UTC
+        let insert_cmd = format!(
+            "INSERT INTO structures ({}, inserted_at, source_file) VALUES ({}, datetime('now'), ?24)",
+            WRITTEN_COLUMNS, WRITTEN_VALUES
+        );
+        let mut statement = db.prepare_cached(&insert_cmd)?;
+        if replace {
+            // Looking rows up by name would otherwise scan the whole table
+            db.execute(
+                "CREATE INDEX IF NOT EXISTS structures_name_desc ON structures (mol_name, desc)",
+                [],
+            )?;
+        }
+        let mut lookup = db.prepare_cached(
+            "SELECT id FROM structures WHERE mol_name = ?1 AND desc IS ?2 ORDER BY id LIMIT 1",
+        )?;
+        let mut update = db.prepare_cached(&update_cmd())?;
+        // Replaced rows come from the new file now
+        let mut provenance = db.prepare_cached(
+            "UPDATE structures SET inserted_at = datetime('now'), source_file = ?1 WHERE id = ?2",
+        )?;
+        let properties = properties(db)?;
+        let dictionary = latest_dictionary(db)?;
+        for entry in mol2_list.iter() {
+            let existing = match (replace, &entry.molecule) {
+                (true, Some(molecule)) => lookup
+                    .query_row(rusqlite::params![molecule.mol_name, entry.desc], |row| {
+                        row.get::<_, i64>(0)
+                    })
+                    .optional()?,
+                _ => None,
+            };
+            let id = match existing {
+                Some(id) => {
+                    update_row(
+                        &mut update,
+                        id,
+                        entry,
+                        compression,
+                        codec,
+                        dictionary.as_ref(),
+                    )?;
+                    provenance.execute(rusqlite::params![source_file, id])?;
+                    id
+                }
+                None => write_row(entry, compression, codec, dictionary.as_ref(), |params| {
+                    let mut params = params.to_vec();
+                    params.push(&source_file);
+                    statement.insert(&params[..])
+                })?,
+            };
+            write_properties(db, &properties, id, entry)?;
+            ids.push(id);
+        }
+    }
+    if let Some(transaction) = transaction {
+        transaction.commit()?;
+    }
+    Ok(ids)
+}
+
+#[pyfunction(
+    mol2_list,
+    filename,
+    compression = "3",
+    shm = "true",
+    replace = "false",
+    source_file = "\"\"",
+    codec = "\"zstd\""
+)]
+#[pyo3(name = "db_insert")]
+#[allow(clippy::too_many_arguments)]
+fn py_db_insert(
+    mol2_list: Vec<Mol2>,
+    filename: &str,
+    compression: i32,
+    shm: bool,
+    replace: bool,
+    source_file: &str,
+    codec: &str,
+) -> PyResult<Vec<i64>> {
+    Ok(db_insert(
+        mol2_list,
+        filename,
+        compression,
+        shm,
+        replace,
+        source_file,
+        Codec::from_name(codec)?,
+    )?)
+}
+
+pub fn db_update(
+    filename: &str,
+    id: i64,
+    mol2: &Mol2,
+    compression: i32,
+    shm: bool,
+    codec: Codec,
+) -> Result<()> {
+    // Overwrite a stored structure with a new version of it, e.g. with fixed
+    // charges, keeping its row id
+    // Input:
+    //     filename: path to the database
+    //     id: row id of the structure, the id column of the structures table
+    //     mol2: structure to store in its place
+    //     compression: compression level, 0 means no compression
+    //     shm: should be try and use a database out from a temporary location
+    //     codec: codec to compress the blobs with, see db_insert
+    let db = get_db(filename, shm)?;
+    let mut statement = db.prepare(&update_cmd())?;
+    let dictionary = latest_dictionary(&db)?;
+    let updated = update_row(
+        &mut statement,
+        id,
+        mol2,
+        compression,
+        codec,
+        dictionary.as_ref(),
+    )?;
+    drop(statement);
+    write_properties(&db, &properties(&db)?, id, mol2)?;
+    db_cleanup(filename, &db)?;
+    match updated {
+        0 => Err(Mol2Error::InvalidArgument(format!(
+            "no structure with id {}",
+            id
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[pyfunction(
+    filename,
+    id,
+    mol2,
+    compression = "3",
+    shm = "true",
+    codec = "\"zstd\""
+)]
+#[pyo3(name = "db_update")]
+fn py_db_update(
+    filename: &str,
+    id: i64,
+    mol2: Mol2,
+    compression: i32,
+    shm: bool,
+    codec: &str,
+) -> PyResult<()> {
+    Ok(db_update(
+        filename,
+        id,
+        &mol2,
+        compression,
+        shm,
+        Codec::from_name(codec)?,
+    )?)
+}
+
+pub fn db_train_dictionary(
+    filename: &str,
+    samples: usize,
+    max_size: usize,
+    shm: bool,
+) -> Result<i64> {
+    // Train a zstd dictionary on the blobs of randomly chosen structures and
+    // store it, returns its row id. Structures inserted or updated afterwards
+    // are compressed with the dictionary, stored ones are left as they are.
+    // Input:
+    //     filename: path to the database
+    //     samples: number of structures to train on
+    //     max_size: maximum size of the dictionary in bytes
+    //     shm: should be try and use a database out from a temporary location
+    if samples == 0 || max_size == 0 {
+        return Err(Mol2Error::InvalidArgument(
+            "number of samples and dictionary size have to be at least 1".to_owned(),
+        ));
+    }
+    let db = get_db(filename, shm)?;
+    let dictionaries = dictionaries(&db)?;
+    let mut blobs: Vec<Vec<u8>> = Vec::with_capacity(3 * samples);
+    {
+        // Choosing ids first keeps the blobs of other rows from being read
+        let query = format!(
+            "SELECT {} FROM structures WHERE id IN (SELECT id FROM structures ORDER BY random() LIMIT ?1)",
+            STRUCTURE_COLUMNS
+        );
+        let mut stmt = db.prepare(&query)?;
+        let rows = stmt.query_map([samples as i64], RawRow::from_row)?;
+        for row in rows {
+            let row = row?.decompress(&dictionaries)?;
+            blobs.push(row.atom);
+            blobs.push(row.bond);
+            blobs.push(row.substructure);
+        }
+    }
+    let dictionary = zstd::dict::from_samples(&blobs, max_size).map_err(|error| {
+        Mol2Error::Compression(format!(
+            "failed to train a dictionary on {} structures: {}",
+            blobs.len() / 3,
+            error
+        ))
+    })?;
+    db.execute(
+        "INSERT INTO dictionaries (dictionary) VALUES (?1)",
+        [dictionary],
+    )?;
+    let id = db.last_insert_rowid();
+    db_cleanup(filename, &db)?;
+    Ok(id)
+}
+
+#[pyfunction(filename, samples = "1000", max_size = "112640", shm = "true")]
+#[pyo3(name = "db_train_dictionary")]
+fn py_db_train_dictionary(
+    filename: &str,
+    samples: usize,
+    max_size: usize,
+    shm: bool,
+) -> PyResult<i64> {
+    Ok(db_train_dictionary(filename, samples, max_size, shm)?)
+}
+
+// Types a property column can be declared with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PropertyType {
+    Real,
+    Integer,
+    Text,
+}
+
+impl PropertyType {
+    pub fn from_name(name: &str) -> Result<PropertyType> {
+        // Translate a user given property type, regardless of case
+        match &name.to_uppercase()[..] {
+            "REAL" => Ok(PropertyType::Real),
+            "INTEGER" => Ok(PropertyType::Integer),
+            "TEXT" => Ok(PropertyType::Text),
+            _ => Err(Mol2Error::InvalidArgument(format!(
+                "unknown property type {}, expected one of REAL, INTEGER, TEXT",
+                name.to_uppercase()
+            ))),
+        }
+    }
+    pub fn name(self) -> &'static str {
+        // Column type in SQL
+        match self {
+            PropertyType::Real => "REAL",
+            PropertyType::Integer => "INTEGER",
+            PropertyType::Text => "TEXT",
+        }
+    }
+}
+
+pub fn db_add_property(filename: &str, name: &str, kind: PropertyType, shm: bool) -> Result<()> {
+    // Add a typed column to the structures table, e.g. for docking scores,
+    // that can be set per structure and used to filter and order reads
+    // Input:
+    //     filename: path to the database
+    //     name: column name, letters, digits and underscores
+    //     kind: type of the column
+    //     shm: should be try and use a database out from a temporary location
+    let db = get_db(filename, shm)?;
+    add_property(&db, name, kind)?;
+    db_cleanup(filename, &db)
+}
+
+fn add_property(db: &rusqlite::Connection, name: &str, kind: PropertyType) -> Result<()> {
+    // Add a property column using an already open connection, see
+    // db_add_property
+    // The name ends up in SQL, so only plain identifiers are accepted
+    if name.is_empty()
+        || name.starts_with(|c: char| c.is_ascii_digit())
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(Mol2Error::InvalidArgument(format!(
+            "invalid property name {}",
+            name
+        )));
+    }
+    // Column names are case insensitive in SQLite
+    let exists: bool = db.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('structures') WHERE name = ?1 COLLATE NOCASE",
+        [name],
+        |row| row.get(0),
+    )?;
+    if exists {
+        return Err(Mol2Error::InvalidArgument(format!(
+            "column {} already exists",
+            name
+        )));
+    }
+    db.execute(
+        &format!(
+            "ALTER TABLE structures ADD COLUMN \"{}\" {}",
+            name,
+            kind.name()
+        ),
+        [],
+    )?;
+    db.execute(
+        "INSERT INTO properties (name, type) VALUES (?1, ?2)",
+        [name, kind.name()],
+    )?;
+    Ok(())
+}
+
+#[pyfunction(filename, name, kind, shm = "true")]
+#[pyo3(name = "db_add_property")]
+fn py_db_add_property(filename: &str, name: &str, kind: &str, shm: bool) -> PyResult<()> {
+    Ok(db_add_property(
+        filename,
+        name,
+        PropertyType::from_name(kind)?,
+        shm,
+    )?)
+}
+
+fn properties(db: &rusqlite::Connection) -> Result<Vec<(String, String)>> {
+    // Declared property columns and their types, in the order of declaration
+    let mut stmt = db.prepare_cached("SELECT name, type FROM properties ORDER BY rowid")?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    Ok(rows.collect::<rusqlite::Result<Vec<(String, String)>>>()?)
+}
+
+fn check_property(properties: &[(String, String)], name: &str) -> Result<()> {
+    // Make sure a user given name is a declared property before it goes
+    // into SQL
+    match properties.iter().any(|(property, _)| property == name) {
+        true => Ok(()),
+        false => Err(Mol2Error::InvalidArgument(format!(
+            "unknown property {}",
+            name
+        ))),
+    }
+}
+
+pub fn db_properties(filename: &str, shm: bool, read_only: bool) -> Result<Vec<(String, String)>> {
+    // List property columns of a database as pairs of name and type
+    // Input:
+    //     filename: path to the database
+    //     shm: should we try and use the database out of a temporary location?
+    //     read_only: open the database read-only where it is, shm is ignored
+    let db = open_db(filename, shm, read_only)?;
+    properties(&db)
+}
+
+#[pyfunction(filename, shm = "false", read_only = "false")]
+#[pyo3(name = "db_properties")]
+fn py_db_properties(filename: &str, shm: bool, read_only: bool) -> PyResult<Vec<(String, String)>> {
+    Ok(db_properties(filename, shm, read_only)?)
+}
+
+fn property_value(value: &serde_json::Value) -> rusqlite::types::Value {
+    // SQL value of a property given in the extras of a structure
+    match value {
+        serde_json::Value::Null => rusqlite::types::Value::Null,
+        serde_json::Value::Bool(value) => rusqlite::types::Value::Integer(*value as i64),
+        serde_json::Value::Number(number) => match number.as_i64() {
+            Some(number) => rusqlite::types::Value::Integer(number),
+            None => rusqlite::types::Value::Real(number.as_f64().unwrap_or(f64::NAN)),
+        },
+        serde_json::Value::String(text) => rusqlite::types::Value::Text(text.to_owned()),
+        // Nested values are kept as JSON text
+        value => rusqlite::types::Value::Text(value.to_string()),
+    }
+}
+
+fn write_properties(
+    db: &rusqlite::Connection,
+    properties: &[(String, String)],
+    id: i64,
+    entry: &Mol2,
+) -> Result<()> {
+    // Copy extras named like property columns into the columns of a row,
+    // properties missing from the extras are left as they are
+    if let Some(extras) = &entry.extras {
+        for (name, _) in properties {
+            if let Some(value) = extras.get(name) {
+                db.prepare_cached(&format!(
+                    "UPDATE structures SET \"{}\" = ?1 WHERE id = ?2",
+                    name
+                ))?
+                .execute(rusqlite::params![property_value(value), id])?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn db_set_property(
+    filename: &str,
+    name: &str,
+    values: &[(i64, rusqlite::types::Value)],
+    shm: bool,
+) -> Result<()> {
+    // Set a property of stored structures, e.g. scores computed after the
+    // structures were inserted
+    // Input:
+    //     filename: path to the database
+    //     name: property declared with db_add_property
+    //     values: pairs of row id and value
+    //     shm: should be try and use a database out from a temporary location
+    let db = get_db(filename, shm)?;
+    check_property(&properties(&db)?, name)?;
+    let mut statement = db.prepare(&format!(
+        "UPDATE structures SET \"{}\" = ?1 WHERE id = ?2",
+        name
+    ))?;
+    let mut missing: Vec<i64> = Vec::new();
+    for (id, value) in values {
+        if statement.execute(rusqlite::params![value, id])? == 0 {
+            missing.push(*id);
+        }
+    }
+    drop(statement);
+    db_cleanup(filename, &db)?;
+    match missing.first() {
+        None => Ok(()),
+        Some(id) => Err(Mol2Error::InvalidArgument(format!(
+            "no structure with id {}",
+            id
+        ))),
+    }
+}
+
+// Property value given from python
+#[derive(FromPyObject)]
+enum PyPropertyValue {
+    Integer(i64),
+    Real(f64),
+    Text(String),
+}
+
+#[pyfunction(filename, name, values, shm = "true")]
+#[pyo3(name = "db_set_property")]
+fn py_db_set_property(
+    filename: &str,
+    name: &str,
+    values: Vec<(i64, Option<PyPropertyValue>)>,
+    shm: bool,
+) -> PyResult<()> {
+    let values: Vec<(i64, rusqlite::types::Value)> = values
+        .into_iter()
+        .map(|(id, value)| {
+            (
+                id,
+                match value {
+                    None => rusqlite::types::Value::Null,
+                    Some(PyPropertyValue::Integer(value)) => rusqlite::types::Value::Integer(value),
+                    Some(PyPropertyValue::Real(value)) => rusqlite::types::Value::Real(value),
+                    Some(PyPropertyValue::Text(value)) => rusqlite::types::Value::Text(value),
+                },
+            )
+        })
+        .collect();
+    Ok(db_set_property(filename, name, &values, shm)?)
+}
+
+pub fn db_tag(filename: &str, ids: &[i64], tag: &str, shm: bool) -> Result<()> {
+    // Add a tag to stored structures, e.g. to put them in a collection next
+    // to the one given by desc. Tagging a structure twice has no effect.
+    // Input:
+    //     filename: path to the database
+    //     ids: row ids of the structures
+    //     tag: name of the tag
+    //     shm: should be try and use a database out from a temporary location
+    if tag.is_empty() {
+        return Err(Mol2Error::InvalidArgument("tag cannot be empty".to_owned()));
+    }
+    let db = get_db(filename, shm)?;
+    // Nothing is tagged unless all the structures exist
+    let mut exists = db.prepare("SELECT COUNT(*) > 0 FROM structures WHERE id = ?1")?;
+    for &id in ids {
+        if !exists.query_row([id], |row| row.get::<_, bool>(0))? {
+            return Err(Mol2Error::InvalidArgument(format!(
+                "no structure with id {}",
+                id
+            )));
+        }
+    }
+    drop(exists);
+    db.execute("INSERT OR IGNORE INTO tags (name) VALUES (?1)", [tag])?;
+    let tag_id: i64 = db.query_row("SELECT id FROM tags WHERE name = ?1", [tag], |row| {
+        row.get(0)
+    })?;
+    let mut statement =
+        db.prepare("INSERT OR IGNORE INTO structure_tags (tag_id, structure_id) VALUES (?1, ?2)")?;
+    for &id in ids {
+        statement.execute([tag_id, id])?;
+    }
+    drop(statement);
+    db_cleanup(filename, &db)
+}
+
+#[pyfunction(filename, ids, tag, shm = "true")]
+#[pyo3(name = "db_tag")]
+fn py_db_tag(filename: &str, ids: Vec<i64>, tag: &str, shm: bool) -> PyResult<()> {
+    Ok(db_tag(filename, &ids, tag, shm)?)
+}
+
+pub fn db_untag(filename: &str, ids: &[i64], tag: &str, shm: bool) -> Result<()> {
+    // Remove a tag from stored structures, structures without the tag are
+    // left as they are. A tag no structure has any more is forgotten.
+    // Input:
+    //     filename: path to the database
+    //     ids: row ids of the structures
+    //     tag: name of the tag
+    //     shm: should be try and use a database out from a temporary location
+    let db = get_db(filename, shm)?;
+    let mut statement = db.prepare(
+        "DELETE FROM structure_tags WHERE tag_id = (SELECT id FROM tags WHERE name = ?1) AND structure_id = ?2",
+    )?;
+    for &id in ids {
+        statement.execute(rusqlite::params![tag, id])?;
+    }
+    drop(statement);
+    db.execute(
+        "DELETE FROM tags WHERE name = ?1 AND NOT EXISTS (SELECT 1 FROM structure_tags WHERE tag_id = tags.id)",
+        [tag],
+    )?;
+    db_cleanup(filename, &db)
+}
+
+#[pyfunction(filename, ids, tag, shm = "true")]
+#[pyo3(name = "db_untag")]
+fn py_db_untag(filename: &str, ids: Vec<i64>, tag: &str, shm: bool) -> PyResult<()> {
+    Ok(db_untag(filename, &ids, tag, shm)?)
+}
+
+// How db_merge treats structures of the source already in the destination
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnConflict {
+    // Copy every structure
+    #[default]
+    Append,
+    // Leave out structures with the same structure hash, coordinates and
+    // desc included, as one in the destination or copied before
+    SkipDuplicates,
+}
+
+impl OnConflict {
+    pub fn from_name(name: &str) -> Result<OnConflict> {
+        // Translate a user given conflict mode
+        match name {
+            "append" => Ok(OnConflict::Append),
+            "skip_duplicates" => Ok(OnConflict::SkipDuplicates),
+            _ => Err(Mol2Error::InvalidArgument(format!(
+                "unknown conflict mode {}, expected one of append, skip_duplicates",
+                name
+            ))),
+        }
+    }
+}
+
+pub fn db_merge(src: &str, dst: &str, on_conflict: OnConflict, shm: bool) -> Result<usize> {
+    // Copy the structures of one database into another, e.g. to combine
+    // databases written by separate jobs into one. Rows are copied with their
+    // blobs as they are, so structures keep their compression level, codec
+    // and dictionary, together with their properties, tags and provenance.
+    // Returns the number of structures copied.
+    // Input:
+    //     src: path to the database to copy from, it is only read
+    //     dst: path to the database to copy into, created if missing
+    //     on_conflict: whether structures already in the destination are copied again
+    //     shm: should be try and use the destination out from a temporary location
+    if std::path::Path::new(dst).exists()
+        && std::fs::canonicalize(src)? == std::fs::canonicalize(dst)?
+    {
+        return Err(Mol2Error::InvalidArgument(
+            "cannot merge a database into itself".to_owned(),
+        ));
+    }
+    let source = open_db(src, false, true)?;
+    let db = get_db(dst, shm)?;
+    let copied = merge_structures(&source, &db, on_conflict == OnConflict::SkipDuplicates)?;
+    db_cleanup(dst, &db)?;
+    Ok(copied)
+}
+
+fn merge_structures(
+    source: &rusqlite::Connection,
+    db: &rusqlite::Connection,
+    skip_duplicates: bool,
+) -> Result<usize> {
+    // Copy structures between open connections, see db_merge. Nothing is
+    // copied if one of the rows fails.
+    let transaction = db.unchecked_transaction()?;
+    // Properties missing in the destination are added, the ones it has
+    // already keep their type
+    let source_properties = properties(source)?;
+    let db_properties = properties(db)?;
+    for (name, kind) in &source_properties {
+        if !db_properties
+            .iter()
+            .any(|(property, _)| property.eq_ignore_ascii_case(name))
+        {
+            add_property(db, name, PropertyType::from_name(kind)?)?;
+        }
+    }
+    // Dictionaries of the copied rows, reused if the destination has the
+    // same one already, by source id
+    let mut dictionary_ids: HashMap<i64, i64> = HashMap::new();
+    {
+        let mut stmt = source.prepare(
+            "SELECT id, dictionary FROM dictionaries WHERE id IN (SELECT dict_id FROM structures) ORDER BY id",
+        )?;
+        let mut lookup = db.prepare("SELECT id FROM dictionaries WHERE dictionary = ?1")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?))
+        })?;
+        for row in rows {
+            let (id, data) = row?;
+            let new_id = match lookup.query_row([&data], |row| row.get(0)).optional()? {
+                Some(new_id) => new_id,
+                None => {
+                    db.execute("INSERT INTO dictionaries (dictionary) VALUES (?1)", [&data])?;
+                    db.last_insert_rowid()
+                }
+            };
+            dictionary_ids.insert(id, new_id);
+        }
+    }
+    // Structure hashes of the destination, only decoded to skip duplicates
+    let mut seen: HashSet<String> = HashSet::new();
+    if skip_duplicates {
+        let dictionaries = dictionaries(db)?;
+        let mut stmt = db.prepare(&format!("SELECT {} FROM structures", STRUCTURE_COLUMNS))?;
+        for row in stmt.query_map([], RawRow::from_row)? {
+            let mol2 = row?.decode(&dictionaries)?;
+            seen.insert(canonical::structure_hash(&mol2, true, true));
+        }
+    }
+    // Everything stored with a structure, in the order of the values read
+    let mut columns: Vec<String> = WRITTEN_COLUMNS.split(", ").map(str::to_owned).collect();
+    columns.push("inserted_at".to_owned());
+    columns.push("source_file".to_owned());
+    // Property names were checked when they were declared
+    columns.extend(
+        source_properties
+            .iter()
+            .map(|(name, _)| format!("\"{}\"", name)),
+    );
+    let dict_column = columns
+        .iter()
+        .position(|column| column == "dict_id")
+        .expect("dict_id is a written column");
+    let placeholders: Vec<String> = (1..=columns.len()).map(|i| format!("?{}", i)).collect();
+    let mut insert = db.prepare(&format!(
+        "INSERT INTO structures ({}) VALUES ({})",
+        columns.join(", "),
+        placeholders.join(", ")
+    ))?;
+    // The columns of a RawRow go first so that rows can be decoded, the
+    // values copied come after the id
+    let mut stmt = source.prepare(&format!(
+        "SELECT {}, id, {} FROM structures ORDER BY id",
+        STRUCTURE_COLUMNS,
+        columns.join(", ")
+    ))?;
+    let mut source_tags = source.prepare(
+        "SELECT tags.name FROM structure_tags JOIN tags ON tags.id = structure_tags.tag_id WHERE structure_tags.structure_id = ?1",
+    )?;
+    let mut link =
+        db.prepare("INSERT OR IGNORE INTO structure_tags (tag_id, structure_id) VALUES (?1, ?2)")?;
+    let mut tag_ids: HashMap<String, i64> = HashMap::new();
+    let source_dictionaries = match skip_duplicates {
+        true => dictionaries(source)?,
+        false => Dictionaries::new(),
+    };
+    let first_value = STRUCTURE_COLUMNS.split(", ").count() + 1;
+    let mut rows = stmt.query([])?;
+    let mut copied = 0;
+    while let Some(row) = rows.next()? {
+        if skip_duplicates {
+            let mol2 = RawRow::from_row(row)?.decode(&source_dictionaries)?;
+            if !seen.insert(canonical::structure_hash(&mol2, true, true)) {
+                continue;
+            }
+        }
+        let mut values: Vec<rusqlite::types::Value> = (0..columns.len())
+            .map(|i| row.get(first_value + i))
+            .collect::<rusqlite::Result<_>>()?;
+        if let rusqlite::types::Value::Integer(id) = values[dict_column] {
+            let new_id = dictionary_ids
+                .get(&id)
+                .ok_or_else(|| Mol2Error::Compression(format!("dictionary {} is missing", id)))?;
+            values[dict_column] = rusqlite::types::Value::Integer(*new_id);
+        }
+        let id = insert.insert(rusqlite::params_from_iter(values))?;
+        let names = source_tags
+            .query_map([row.get::<_, i64>(first_value - 1)?], |row| {
+                row.get::<_, String>(0)
+            })?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        for name in names {
+            let tag_id = match tag_ids.get(&name) {
+                Some(&tag_id) => tag_id,
+                None => {
+                    db.execute("INSERT OR IGNORE INTO tags (name) VALUES (?1)", [&name])?;
+                    let tag_id: i64 =
+                        db.query_row("SELECT id FROM tags WHERE name = ?1", [&name], |row| {
+                            row.get(0)
+                        })?;
+                    tag_ids.insert(name, tag_id);
+                    tag_id
+                }
+            };
+            link.execute([tag_id, id])?;
+        }
+        copied += 1;
+    }
+    drop(rows);
+    drop((stmt, insert, source_tags, link));
+    transaction.commit()?;
+    Ok(copied)
+}
+
+#[pyfunction(src, dst, on_conflict = "\"append\"", shm = "true")]
+#[pyo3(name = "db_merge")]
+fn py_db_merge(src: &str, dst: &str, on_conflict: &str, shm: bool) -> PyResult<usize> {
+    Ok(db_merge(
+        src,
+        dst,
+        OnConflict::from_name(on_conflict)?,
+        shm,
+    )?)
+}
+
+// Columns of a structures table row, before the blobs are decoded.
+// Serialized as a whole it is also the binary record of Mol2.to_bytes.
+#[derive(Clone, Serialize, Deserialize)]
+struct RawRow {
+    molecule: Molecule,
+    atom: Vec<u8>,
+    bond: Vec<u8>,
+    substructure: Vec<u8>,
+    compression: i32,
+    desc: Option<String>,
+    source: Option<Vec<u8>>,
+    // Extras as JSON text, not compressed so SQL can look into them
+    extras: Option<String>,
+    alt_type: Option<Vec<u8>>,
+    // Dictionary the blobs are compressed with, never used by binary records
+    #[serde(skip)]
+    dict_id: Option<i64>,
+    // Binary records are always compressed with zstd
+    #[serde(skip)]
+    codec: Codec,
+    // CRYSIN section as JSON text
+    crysin: Option<String>,
+}
+
+// How the desc and comment filters of database reads match
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FilterMode {
+    // The value contains the filter, case sensitive and without wildcards
+    #[default]
+    Substring,
+    // The filter is a glob pattern of the whole value, e.g. "set?" or "*ligand*"
+    Glob,
+    // The filter is a regular expression matched anywhere in the value
+    Regex,
+}
+
+impl FilterMode {
+    pub fn from_name(name: &str) -> Result<FilterMode> {
+        // Translate a user given filter mode, empty means substring
+        match &name.to_lowercase()[..] {
+            "" | "substring" => Ok(FilterMode::Substring),
+            "glob" => Ok(FilterMode::Glob),
+            "regex" => Ok(FilterMode::Regex),
+            _ => Err(Mol2Error::InvalidArgument(format!(
+                "Unknown filter mode: {}",
+                name
+            ))),
+        }
+    }
+
+    fn check(self, filters: &[&str]) -> Result<()> {
+        // Reject malformed regular expressions before they reach the query
+        if self == FilterMode::Regex {
+            for filter in filters {
+                regex::Regex::new(filter).map_err(|error| {
+                    Mol2Error::InvalidArgument(format!("Invalid filter {}: {}", filter, error))
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    fn clause(self) -> String {
+        // Filters on desc and the molecule comment, given as :desc and
+        // :comment. An empty filter matches everything in all modes.
+        // Filtering in SQL spares decompressing the rows that do not match.
+        let condition = |column: &str, filter: &str| match self {
+            FilterMode::Substring => format!("instr({}, {}) > 0", column, filter),
+            FilterMode::Glob => format!("{} GLOB {}", column, filter),
+            // Calls the regexp function registered in get_db
+            FilterMode::Regex => format!("{} REGEXP {}", column, filter),
+        };
+        format!(
+            "(:desc = '' OR {}) AND (:comment = '' OR {})",
+            condition("desc", ":desc"),
+            condition("mol_comment", ":comment")
+        )
+    }
+}
+
+// Order in which database reads return structures
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum OrderBy {
+    // Insertion order
+    #[default]
+    Id,
+    Name,
+    NumAtoms,
+    Desc,
+    // A column declared with db_add_property
+    Property(String),
+}
+
+impl OrderBy {
+    pub fn from_name(name: &str) -> OrderBy {
+        // Translate a user given order, empty means by id. Other names are
+        // properties, which are checked when the database is read.
+        match &name.to_lowercase()[..] {
+            "" | "id" => OrderBy::Id,
+            "name" => OrderBy::Name,
+            "num_atoms" => OrderBy::NumAtoms,
+            "desc" => OrderBy::Desc,
+            _ => OrderBy::Property(name.to_owned()),
+        }
+    }
+
+    fn check(&self, properties: &[(String, String)]) -> Result<()> {
+        // Orders by a property need the property to be declared
+        match self {
+            OrderBy::Property(name) => check_property(properties, name)
+                .map_err(|_| Mol2Error::InvalidArgument(format!("Unknown order: {}", name))),
+            _ => Ok(()),
+        }
+    }
+
+    fn column(&self) -> String {
+        match self {
+            OrderBy::Id => "id".to_owned(),
+            OrderBy::Name => "mol_name".to_owned(),
+            OrderBy::NumAtoms => "num_atoms".to_owned(),
+            OrderBy::Desc => "desc".to_owned(),
+            OrderBy::Property(name) => format!("\"{}\"", name),
+        }
+    }
+
+    fn clause(&self) -> String {
+        // Ties are broken by id so that the order is always the same, empty
+        // values come last
+        match self {
+            OrderBy::Id => "id".to_owned(),
+            order_by => format!("{0} IS NULL, {0}, id", order_by.column()),
+        }
+    }
+}
+
+// Which structures a database read returns and in which order, taken by
+// read_db_all, read_db_rows, read_db_iter, db_count and the same methods of
+// Mol2Db. The default is every structure in insertion order.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DbQuery {
+    // Only structures with desc, the molecule comment and the molecule name
+    // containing these, empty for no filter
+    pub desc: String,
+    pub comment: String,
+    pub name: String,
+    // How desc and comment match. The name always matches as a substring.
+    pub filter_mode: FilterMode,
+    pub order_by: OrderBy,
+    // At most limit structures after skipping offset of them, a zero limit
+    // means no limit
+    pub limit: usize,
+    pub offset: usize,
+    // Bounds on the numbers of atoms and bonds, a zero maximum means no
+    // upper bound
+    pub min_atoms: usize,
+    pub max_atoms: usize,
+    pub min_bonds: usize,
+    pub max_bonds: usize,
+    // Property name, minimum and maximum, None for no bound
+    pub property_ranges: Vec<(String, Option<f64>, Option<f64>)>,
+    // Comparisons of extras, e.g. "$.vendor == 'enamine'"
+    pub where_extras: String,
+    // Only structures with this tag
+    pub tag: String,
+    // Only structures from a file with the path containing source_file and
+    // inserted in a time range, as "YYYY-MM-DD HH:MM:SS" in UTC or a prefix
+    // of it
+    pub source_file: String,
+    pub inserted_after: String,
+    pub inserted_before: String,
+}
+
+impl DbQuery {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn from_args(
+        desc: &str,
+        comment: &str,
+        name: &str,
+        filter_mode: &str,
+        order_by: &str,
+        limit: usize,
+        offset: usize,
+        min_atoms: usize,
+        max_atoms: usize,
+        min_bonds: usize,
+        max_bonds: usize,
+        property_ranges: Vec<(String, Option<f64>, Option<f64>)>,
+        where_extras: &str,
+        tag: &str,
+        source_file: &str,
+        inserted_after: &str,
+        inserted_before: &str,
+    ) -> Result<DbQuery> {
+        // Query out of the arguments of the python functions, which name the
+        // filter mode and the order. All of them build their query here.
+        Ok(DbQuery {
+            desc: desc.to_owned(),
+            comment: comment.to_owned(),
+            name: name.to_owned(),
+            filter_mode: FilterMode::from_name(filter_mode)?,
+            order_by: OrderBy::from_name(order_by),
+            limit,
+            offset,
+            min_atoms,
+            max_atoms,
+            min_bonds,
+            max_bonds,
+            property_ranges,
+            where_extras: where_extras.to_owned(),
+            tag: tag.to_owned(),
+            source_file: source_file.to_owned(),
+            inserted_after: inserted_after.to_owned(),
+            inserted_before: inserted_before.to_owned(),
+        })
+    }
+}
+
+// Values bound to the named parameters of a query
+type QueryValues = Vec<(String, rusqlite::types::Value)>;
+
+fn select(
+    db: &rusqlite::Connection,
+    query: &DbQuery,
+    columns: &str,
+    after: Condition,
+) -> Result<(String, QueryValues)> {
+    // Build the SELECT of columns of the structures matching query and the
+    // values to bind to it. All database reads and counts go through here.
+    // Input:
+    //     db: connection the properties of the query are checked against
+    //     query: filters, order, limit and offset
+    //     columns: columns to select, e.g. STRUCTURE_COLUMNS
+    //     after: condition on top of the query, e.g. to continue after rows
+    //     already read
+    query.filter_mode.check(&[&query.desc, &query.comment])?;
+    let properties = properties(db)?;
+    query.order_by.check(&properties)?;
+    let mut conditions = after;
+    conditions.extend(Condition::sizes(query));
+    conditions.extend(Condition::property_ranges(
+        &properties,
+        &query.property_ranges,
+    )?);
+    conditions.extend(Condition::extras(&query.where_extras)?);
+    conditions.extend(Condition::tag(&query.tag));
+    conditions.extend(Condition::provenance(
+        &query.source_file,
+        &query.inserted_after,
+        &query.inserted_before,
+    ));
+    // Filters go first so that limit and offset count matching structures
+    let sql = format!(
+        "SELECT {} FROM structures WHERE {} AND (:name = '' OR instr(mol_name, :name) > 0){} ORDER BY {} LIMIT :limit OFFSET :offset",
+        columns,
+        query.filter_mode.clause(),
+        conditions.clause,
+        query.order_by.clause()
+    );
+    let limit: i64 = match query.limit {
+        0 => -1,
+        limit => limit as i64,
+    };
+    let text = |value: &str| rusqlite::types::Value::Text(value.to_owned());
+    let integer = |value: usize| rusqlite::types::Value::Integer(value as i64);
+    let mut values: QueryValues = vec![
+        (":desc".to_owned(), text(&query.desc)),
+        (":comment".to_owned(), text(&query.comment)),
+        (":name".to_owned(), text(&query.name)),
+        (":limit".to_owned(), rusqlite::types::Value::Integer(limit)),
+        (":offset".to_owned(), integer(query.offset)),
+    ];
+    values.extend(conditions.values);
+    Ok((sql, values))
+}
+
+fn bind(values: &[(String, rusqlite::types::Value)]) -> Vec<(&str, &dyn rusqlite::ToSql)> {
+    // Named parameters for rusqlite from the values of a query
+    values
+        .iter()
+        .map(|(parameter, value)| (&parameter[..], value as &dyn rusqlite::ToSql))
+        .collect()
+}
+
+// Conditions of database reads given by the user, as SQL to append to the
+// other filters and the values bound to it
+#[derive(Default)]
+struct Condition {
+    clause: String,
+    values: Vec<(String, rusqlite::types::Value)>,
+}
+
+impl Condition {
+    fn sizes(query: &DbQuery) -> Condition {
+        // Bounds on the numbers of atoms and bonds, only those that are set.
+        // Counts left out of the counts line are empty and never in a range.
+        let mut clause = String::new();
+        let mut values: Vec<(String, rusqlite::types::Value)> = Vec::new();
+        for (column, value, operator, parameter) in [
+            ("num_atoms", query.min_atoms, ">=", ":min_atoms"),
+            ("num_atoms", query.max_atoms, "<=", ":max_atoms"),
+            ("num_bonds", query.min_bonds, ">=", ":min_bonds"),
+            ("num_bonds", query.max_bonds, "<=", ":max_bonds"),
+        ] {
+            if value > 0 {
+                clause.push_str(&format!(" AND {} {} {}", column, operator, parameter));
+                values.push((
+                    parameter.to_owned(),
+                    rusqlite::types::Value::Integer(value as i64),
+                ));
+            }
+        }
+        Condition { clause, values }
+    }
+
+    fn property_ranges(
+        properties: &[(String, String)],
+        ranges: &[(String, Option<f64>, Option<f64>)],
+    ) -> Result<Condition> {
+        // Ranges are given as property name, minimum and maximum, a missing
+        // bound means no bound. Empty values are never in a range.
+        let mut clause = String::new();
+        let mut values: Vec<(String, rusqlite::types::Value)> = Vec::new();
+        for (index, (name, min, max)) in ranges.iter().enumerate() {
+            check_property(properties, name)?;
+            clause.push_str(&format!(" AND \"{}\" IS NOT NULL", name));
+            for (bound, value, operator) in [("min", min, ">="), ("max", max, "<=")] {
+                if let Some(value) = value {
+                    let parameter = format!(":property_{}_{}", bound, index);
+                    clause.push_str(&format!(" AND \"{}\" {} {}", name, operator, parameter));
+                    values.push((parameter, rusqlite::types::Value::Real(*value)));
+                }
+            }
+        }
+        Ok(Condition { clause, values })
+    }
+
+    fn extras(condition: &str) -> Result<Condition> {
+        // Comparisons of values in the extras, e.g.
+        // "$.vendor == 'enamine' and $.price < 10", looked up with the
+        // json_extract function of SQLite. Empty means no condition.
+        let invalid = |reason: &str| {
+            Mol2Error::InvalidArgument(format!(
+                "invalid extras condition {}: {}",
+                condition, reason
+            ))
+        };
+        let mut tokens = extras_tokens(condition)
+            .map_err(|reason| invalid(&reason))?
+            .into_iter();
+        let mut clause = String::new();
+        let mut values: Vec<(String, rusqlite::types::Value)> = Vec::new();
+        let mut index = 0;
+        loop {
+            let path = match tokens.next() {
+                None if index == 0 => break,
+                Some(ExtrasToken::Path(path)) => path,
+                _ => return Err(invalid("expected a path starting with $")),
+            };
+            let operator = match tokens.next() {
+                Some(ExtrasToken::Operator(operator)) => operator,
+                _ => return Err(invalid("expected a comparison after the path")),
+            };
+            let value = match tokens.next() {
+                Some(ExtrasToken::Value(value)) => value,
+                _ => return Err(invalid("expected a value after the comparison")),
+            };
+            let operator = match (&operator[..], &value) {
+                ("==" | "=", rusqlite::types::Value::Null) => "IS",
+                ("!=" | "<>", rusqlite::types::Value::Null) => "IS NOT",
+                (_, rusqlite::types::Value::Null) => {
+                    return Err(invalid("null can only be compared with == or !="))
+                }
+                ("==" | "=", _) => "=",
+                ("!=" | "<>", _) => "!=",
+                ("<" | "<=" | ">" | ">=", _) => &operator[..],
+                _ => return Err(invalid(&format!("unknown comparison {}", operator))),
+            };
+            let path_parameter = format!(":extras_path_{}", index);
+            let value_parameter = format!(":extras_value_{}", index);
+            clause.push_str(&format!(
+                " AND json_extract(extras, {}) {} {}",
+                path_parameter, operator, value_parameter
+            ));
+            values.push((path_parameter, rusqlite::types::Value::Text(path)));
+            values.push((value_parameter, value));
+            index += 1;
+            match tokens.next() {
+                None => break,
+                Some(ExtrasToken::And) => {}
+                _ => return Err(invalid("expected and between comparisons")),
+            }
+        }
+        Ok(Condition { clause, values })
+    }
+
+    fn tag(tag: &str) -> Condition {
+        // Structures with a tag given with db_tag, empty means no condition
+        match tag {
+            "" => Condition {
+                clause: String::new(),
+                values: Vec::new(),
+            },
+            tag => Condition {
+                clause: " AND id IN (SELECT structure_id FROM structure_tags WHERE tag_id = (SELECT id FROM tags WHERE name = :tag))".to_owned(),
+                values: vec![(":tag".to_owned(), rusqlite::types::Value::Text(tag.to_owned()))],
+            },
+        }
+    }
+
+    fn provenance(source_file: &str, inserted_after: &str, inserted_before: &str) -> Condition {
+        // Structures from a file with the path containing source_file and
+        // inserted in a time range, e.g. "2024-01-01" or "2024-01-01 12:00:00"
+        // in UTC. Empty strings mean no condition.
+        let mut condition = Condition {
+            clause: String::new(),
+            values: Vec::new(),
+        };
+        for (parameter, value, comparison) in [
+            (
+                ":source_file",
+                source_file,
+                "instr(source_file, :source_file) > 0",
+            ),
+            (
+                ":inserted_after",
+                inserted_after,
+                "inserted_at >= :inserted_after",
+            ),
+            (
+                ":inserted_before",
+                inserted_before,
+                "inserted_at < :inserted_before",
+            ),
+        ] {
+            if !value.is_empty() {
+                condition.clause.push_str(&format!(" AND {}", comparison));
+                condition.values.push((
+                    parameter.to_owned(),
+                    rusqlite::types::Value::Text(value.to_owned()),
+                ));
+            }
+        }
+        condition
+    }
+
+    fn extend(&mut self, other: Condition) {
+        self.clause.push_str(&other.clause);
+        self.values.extend(other.values);
+    }
+}
+
+enum ExtrasToken {
+    Path(String),
+    Operator(String),
+    Value(rusqlite::types::Value),
+    And,
+}
+
+fn extras_tokens(condition: &str) -> std::result::Result<Vec<ExtrasToken>, String> {
+    // Split an extras condition into paths, comparisons, values and the and
+    // between comparisons
+    const OPERATOR_CHARS: &str = "=!<>";
+    let mut tokens: Vec<ExtrasToken> = Vec::new();
+    let mut chars = condition.chars().peekable();
+    while let Some(&first) = chars.peek() {
+        if first.is_whitespace() {
+            chars.next();
+        } else if first == '\'' || first == '"' {
+            // Quoted text, up to the same quote
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some(c) if c == first => break,
+                    Some(c) => text.push(c),
+                    None => return Err(format!("missing closing {}", first)),
+                }
+            }
+            tokens.push(ExtrasToken::Value(rusqlite::types::Value::Text(text)));
+        } else {
+            let operator = OPERATOR_CHARS.contains(first);
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || OPERATOR_CHARS.contains(c) != operator {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(if operator {
+                ExtrasToken::Operator(word)
+            } else if word.starts_with('$') {
+                ExtrasToken::Path(word)
+            } else if word.eq_ignore_ascii_case("and") {
+                ExtrasToken::And
+            } else {
+                // JSON true and false come out of json_extract as 1 and 0
+                ExtrasToken::Value(match &word.to_lowercase()[..] {
+                    "true" => rusqlite::types::Value::Integer(1),
+                    "false" => rusqlite::types::Value::Integer(0),
+                    "null" => rusqlite::types::Value::Null,
+                    _ => match (word.parse::<i64>(), word.parse::<f64>()) {
+                        (Ok(number), _) => rusqlite::types::Value::Integer(number),
+                        (_, Ok(number)) => rusqlite::types::Value::Real(number),
+                        _ => return Err(format!("unknown value {}", word)),
+                    },
+                })
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+// Columns read into a RawRow, in the order from_row expects them
+const STRUCTURE_COLUMNS: &str = "mol_name, num_atoms, num_bonds, num_subst, num_feat, num_sets, mol_type, charge_type, status_bits, mol_comment, atom, bond, substructure, compression, desc, source, extras, alt_type, dict_id, codec, crysin";
+
+impl RawRow {
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<RawRow> {
+        // Expects columns in the order of STRUCTURE_COLUMNS
+        Ok(RawRow {
+            molecule: Molecule {
+                mol_name: row.get(0)?,
+                num_atoms: row.get(1)?,
+                num_bonds: row.get(2)?,
+                num_subst: row.get(3)?,
+                num_feat: row.get(4)?,
+                num_sets: row.get(5)?,
+                mol_type: row.get(6)?,
+                charge_type: row.get(7)?,
+                status_bits: row.get(8)?,
+                mol_comment: row.get(9)?,
+            },
+            atom: row.get(10)?,
+            bond: row.get(11)?,
+            substructure: row.get(12)?,
+            compression: row.get(13)?,
+            desc: row.get(14)?,
+            source: row.get(15)?,
+            extras: row.get(16)?,
+            alt_type: row.get(17)?,
+            dict_id: row.get(18)?,
+            codec: row.get::<_, Option<Codec>>(19)?.unwrap_or_default(),
+            crysin: row.get(20)?,
+        })
+    }
+    fn encode(
+        entry: &Mol2,
+        compression: i32,
+        codec: Codec,
+        dictionary: Option<&Dictionary>,
+    ) -> Result<RawRow> {
+        // Serialize and compress the sections of a structure into blobs
+        // Input:
+        //     entry: structure with a MOLECULE section
+        //     compression: compression level, 0 means no compression
+        //     codec: codec to compress the blobs with
+        //     dictionary: zstd dictionary to compress the blobs with, if any
+        let molecule = entry.molecule.clone().ok_or_else(|| {
+            Mol2Error::InvalidArgument(
+                "Cannot insert a structure without a MOLECULE section".to_owned(),
+            )
+        })?;
+        // Handle compression levels, uncompressed rows have no codec
+        let compression_level = codec.level(compression);
+        let codec = match compression_level {
+            0 => Codec::None,
+            _ => codec,
+        };
+        let mut atom = bincode::serialize(&entry.atom)?;
+        let mut bond = bincode::serialize(&entry.bond)?;
+        let mut subs = bincode::serialize(&entry.substructure)?;
+        // Source of modified structures is out of date and not stored
+        let mut source = entry
+            .unedited_source()
+            .map(|source| source.as_bytes().to_vec());
+        let mut alt_type = match entry.alt_type.is_empty() {
+            true => None,
+            false => Some(bincode::serialize(&entry.alt_type)?),
+        };
+        let dictionary = dictionary.filter(|_| codec == Codec::Zstd);
+        if compression_level > 0 {
+            atom = compress_blob(&atom, codec, compression_level, dictionary)?;
+            bond = compress_blob(&bond, codec, compression_level, dictionary)?;
+            subs = compress_blob(&subs, codec, compression_level, dictionary)?;
+            if let Some(text) = source {
+                source = Some(compress_blob(&text, codec, compression_level, dictionary)?);
+            }
+            if let Some(types) = alt_type {
+                alt_type = Some(compress_blob(&types, codec, compression_level, dictionary)?);
+            }
+        }
+        Ok(RawRow {
+            molecule,
+            atom,
+            bond,
+            substructure: subs,
+            compression: compression_level,
+            desc: entry.desc.clone(),
+            source,
+            extras: entry
+                .extras
+                .as_ref()
+                .map(serde_json::to_string)
+                .transpose()
+                .expect("Failed to translate extras into json format"),
+            alt_type,
+            dict_id: dictionary.map(|dictionary| dictionary.id),
+            codec,
+            crysin: entry
+                .crysin
+                .as_ref()
+                .map(serde_json::to_string)
+                .transpose()
+                .expect("Failed to translate the CRYSIN section into json format"),
+        })
+    }
+    fn decompress(mut self, dictionaries: &Dictionaries) -> Result<RawRow> {
+        // Decompress the blobs, the row is left without compression
+        // Input:
+        //     dictionaries: dictionaries of the database the row comes from
+        if self.compression > 0 {
+            let dictionary = match self.dict_id {
+                Some(id) => Some(dictionaries.get(&id).ok_or_else(|| {
+                    Mol2Error::Compression(format!("dictionary {} is missing", id))
+                })?),
+                None => None,
+            };
+            let codec = self.codec;
+            self.atom = decompress_blob(&self.atom, codec, dictionary)?;
+            self.bond = decompress_blob(&self.bond, codec, dictionary)?;
+            self.substructure = decompress_blob(&self.substructure, codec, dictionary)?;
+            if let Some(text) = self.source {
+                self.source = Some(decompress_blob(&text, codec, dictionary)?);
+            }
+            if let Some(types) = self.alt_type {
+                self.alt_type = Some(decompress_blob(&types, codec, dictionary)?);
+            }
+            self.compression = 0;
+            self.dict_id = None;
+            self.codec = Codec::None;
+        }
+        Ok(self)
+    }
+    fn decode(self, dictionaries: &Dictionaries) -> Result<Mol2> {
+        // Decompress and deserialize the blobs into a structure
+        // Input:
+        //     dictionaries: dictionaries of the database the row comes from
+        let row = self.decompress(dictionaries)?;
+        let mut mol2 = Mol2 {
+            molecule: Some(row.molecule),
+            atom: bincode::deserialize(&row.atom)?,
+            bond: bincode::deserialize(&row.bond)?,
+            substructure: bincode::deserialize(&row.substructure)?,
+            desc: row.desc,
+            alt_type: match row.alt_type {
+                Some(types) => bincode::deserialize(&types)?,
+                None => Vec::new(),
+            },
+            crysin: row
+                .crysin
+                .map(|crysin| serde_json::from_str(&crysin))
+                .transpose()
+                .map_err(|error| Mol2Error::Parse {
+                    line: error.line(),
+                    message: error.to_string(),
+                })?,
+            source: None,
+            source_hash: None,
+            adjacency: OnceLock::new(),
+            extras: row
+                .extras
+                .map(|extras| serde_json::from_str(&extras))
+                .transpose()
+                .map_err(|error| Mol2Error::Parse {
+                    line: error.line(),
+                    message: error.to_string(),
+                })?,
+        };
+        if let Some(source) = row.source {
+            mol2.keep_source(
+                String::from_utf8(source)
+                    .map_err(|error| Mol2Error::Compression(error.to_string()))?,
+            );
+        }
+        Ok(mol2)
+    }
+}
+
+pub fn read_db_all(
+    filename: &str,
+    shm: bool,
+    query: &DbQuery,
+    threads: usize,
+    read_only: bool,
+) -> Result<Vec<Mol2>> {
+    // Read all structures from a database and return as a vector
+    // Input:
+    //     filename: path to the database
+    //     shm: should we try and use the database out of a temporary location?
+    //     query: which structures to read and in which order, see DbQuery
+    //     threads: number of threads decoding structures while rows are
+    //     fetched, 1 means decoding on the current thread and 0 all cores
+    //     read_only: open the database read-only where it is, shm is ignored
+    let db = open_db(filename, shm, read_only)?;
+    read_structures(&db, query, threads)
+}
+
+fn read_structures(
+    db: &rusqlite::Connection,
+    query: &DbQuery,
+    threads: usize,
+) -> Result<Vec<Mol2>> {
+    // Read structures using an already open connection, see read_db_all
+    let (sql, values) = select(db, query, STRUCTURE_COLUMNS, Condition::default())?;
+    let mut stmt = db.prepare(&sql)?;
+    let structure_iter = stmt.query_map(&bind(&values)[..], RawRow::from_row)?;
+    let dictionaries = dictionaries(db)?;
+    if threads != 1 {
+        return decode_parallel(structure_iter, &dictionaries, &thread_pool(threads)?);
+    }
+    let mut mol2_list: Vec<Mol2> = Vec::new();
+    for structure in structure_iter {
+        mol2_list.push(structure?.decode(&dictionaries)?);
+    }
+
+    Ok(mol2_list)
+}
+
+// Rows handed to the decoding threads at a time by decode_parallel
+const DECODE_CHUNK: usize = 1024;
+
+fn decode_batch(
+    rows: Vec<RawRow>,
+    dictionaries: &Dictionaries,
+    pool: Option<&rayon::ThreadPool>,
+) -> Vec<Result<Mol2>> {
+    // Decompress and deserialize rows, on the threads of a pool if given.
+    // The structures come out in the order of the rows.
+    match pool {
+        Some(pool) => pool.install(|| {
+            rows.into_par_iter()
+                .map(|row| row.decode(dictionaries))
+                .collect()
+        }),
+        None => rows
+            .into_iter()
+            .map(|row| row.decode(dictionaries))
+            .collect(),
+    }
+}
+
+fn decode_parallel(
+    rows: impl Iterator<Item = rusqlite::Result<RawRow>>,
+    dictionaries: &Dictionaries,
+    pool: &rayon::ThreadPool,
+) -> Result<Vec<Mol2>> {
+    // Decode rows in order on a pool of threads while the current thread,
+    // which owns the connection, keeps fetching rows
+    std::thread::scope(|scope| {
+        // Bounded so that fetched rows do not pile up if decoding is slower
+        let (sender, receiver) = std::sync::mpsc::sync_channel::<Vec<RawRow>>(2);
+        let decoder = scope.spawn(move || {
+            let mut mol2_list: Vec<Mol2> = Vec::new();
+            for chunk in receiver {
+                for mol2 in decode_batch(chunk, dictionaries, Some(pool)) {
+                    mol2_list.push(mol2?);
+                }
+            }
+            Ok::<Vec<Mol2>, Mol2Error>(mol2_list)
+        });
+        // Sending fails only after the decoder stopped at an error, which
+        // joining it returns
+        let mut chunk: Vec<RawRow> = Vec::with_capacity(DECODE_CHUNK);
+        for row in rows {
+            chunk.push(row?);
+            if chunk.len() == DECODE_CHUNK && sender.send(std::mem::take(&mut chunk)).is_err() {
+                break;
+            }
+        }
+        if !chunk.is_empty() {
+            sender.send(chunk).ok();
+        }
+        drop(sender);
+        decoder.join().expect("Decoding thread panicked")
+    })
+}
+
+#[pyfunction(
+    filename,
+    shm = "false",
+    desc = "\"\"",
+    comment = "\"\"",
+    name = "\"\"",
+    limit = "0",
+    offset = "0",
+    filter_mode = "\"substring\"",
+    order_by = "\"id\"",
+    min_atoms = "0",
+    max_atoms = "0",
+    min_bonds = "0",
+    max_bonds = "0",
+    property_ranges = "Vec::new()",
+    where_extras = "\"\"",
+    tag = "\"\"",
+    source_file = "\"\"",
+    inserted_after = "\"\"",
+    inserted_before = "\"\"",
+    threads = "1",
+    read_only = "false"
+)]
+#[pyo3(name = "read_db_all")]
+#[allow(clippy::too_many_arguments)]
+fn py_read_db_all(
+    filename: &str,
+    shm: bool,
+    desc: &str,
+    comment: &str,
+    name: &str,
+    limit: usize,
+    offset: usize,
+    filter_mode: &str,
+    order_by: &str,
+    min_atoms: usize,
+    max_atoms: usize,
+    min_bonds: usize,
+    max_bonds: usize,
+    property_ranges: Vec<(String, Option<f64>, Option<f64>)>,
+    where_extras: &str,
+    tag: &str,
+    source_file: &str,
+    inserted_after: &str,
+    inserted_before: &str,
+    threads: usize,
+    read_only: bool,
+) -> PyResult<Vec<Mol2>> {
+    let query = DbQuery::from_args(
+        desc,
+        comment,
+        name,
+        filter_mode,
+        order_by,
+        limit,
+        offset,
+        min_atoms,
+        max_atoms,
+        min_bonds,
+        max_bonds,
+        property_ranges,
+        where_extras,
+        tag,
+        source_file,
+        inserted_after,
+        inserted_before,
+    )?;
+    Ok(read_db_all(filename, shm, &query, threads, read_only)?)
+}
+
+// Structure read from a database with its blobs still compressed. The
+// header columns are read as they are, the atoms, bonds and substructures
+// are decompressed only when the structure is asked for.
+#[pyclass]
+pub struct Mol2Row {
+    // Row id, the id column of the structures table
+    #[pyo3(get)]
+    id: i64,
+    row: RawRow,
+    // Dictionaries of the database, shared by the rows of a read
+    dictionaries: std::sync::Arc<Dictionaries>,
+}
+
+#[pymethods]
+impl Mol2Row {
+    #[pyo3(name = "header")]
+    fn py_header(&self) -> Molecule {
+        self.header().clone()
+    }
+    #[getter]
+    pub fn desc(&self) -> Option<String> {
+        self.row.desc.clone()
+    }
+    pub fn to_mol2(&self) -> Result<Mol2> {
+        // Decompress and deserialize the whole structure, the row is kept
+        // as it is so this can be called again
+        self.row.clone().decode(&self.dictionaries)
+    }
+}
+
+impl Mol2Row {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+    pub fn header(&self) -> &Molecule {
+        // MOLECULE section of the structure, without decoding any blobs
+        &self.row.molecule
+    }
+    pub fn mol_name(&self) -> &str {
+        &self.header().mol_name
     }
-    write_string(&text, filename, append);
 }
 
-pub fn db_insert(mol2_list: Vec<Mol2>, filename: &str, compression: i32, shm: bool) {
-    // Insert vector of structures into a database. Append if the database exists.
+pub fn read_db_rows(
+    filename: &str,
+    shm: bool,
+    query: &DbQuery,
+    read_only: bool,
+) -> Result<Vec<Mol2Row>> {
+    // Read matching rows from a database without decoding them, so scans of
+    // names, descriptions and header counts skip decompression. Filters are
+    // the same as in read_db_all.
     // Input:
-    //     mol2_list: vector of structures
     //     filename: path to the database
-    //     compression: level of zstd compression. NOTE: 0 means no compression and not default level as in zstd library
-    //     shm: should be try and use a database out from a temporary location
-    let db = get_db(filename, shm);
-    let _ = create_table(&db);
-    let mut insert_cmd: String = String::new();
-    insert_cmd.push_str("INSERT INTO structures (mol_name, num_atoms, num_bonds, num_subst, num_feat, num_sets, mol_type, charge_type, status_bits, mol_comment, atom, bond, substructure, compression, desc) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)");
-    // Handle compression levels
-    let mut compression_level = compression;
-    if compression_level > 9 {
-        compression_level = 9;
-    }
-    let mut statement = db
-        .prepare(&insert_cmd)
-        .expect("Failed to prepare an sql statement");
-    for entry in mol2_list.iter() {
-        let mut atom = bincode::serialize(&entry.atom).expect("Failed to serialize into binary");
-        let mut bond = bincode::serialize(&entry.bond).expect("Failed to serialize into binary");
-        let mut subs =
-            bincode::serialize(&entry.substructure).expect("Failed to serialize into binary");
-        if compression_level > 0 {
-            atom = zstd::block::Compressor::new()
-                .compress(&atom, compression_level)
-                .expect("Compression failed");
-            bond = zstd::block::Compressor::new()
-                .compress(&bond, compression_level)
-                .expect("Compression failed");
-            subs = zstd::block::Compressor::new()
-                .compress(&subs, compression_level)
-                .expect("Compression failed");
-        }
-        statement
-            .execute(rusqlite::params![
-                entry.molecule.as_ref().unwrap().mol_name,
-                entry.molecule.as_ref().unwrap().num_atoms,
-                entry.molecule.as_ref().unwrap().num_bonds,
-                entry.molecule.as_ref().unwrap().num_subst,
-                entry.molecule.as_ref().unwrap().num_feat,
-                entry.molecule.as_ref().unwrap().num_sets,
-                entry.molecule.as_ref().unwrap().mol_type,
-                entry.molecule.as_ref().unwrap().charge_type,
-                entry.molecule.as_ref().unwrap().status_bits,
-                entry.molecule.as_ref().unwrap().mol_comment,
-                atom,
-                bond,
-                subs,
-                compression_level,
-                entry.desc,
-            ])
-            .expect("Failed to insert data to db");
-    }
-
-    db_cleanup(filename, &db);
-}
-
-#[pyfunction(mol2_list, filename, compression = "3", shm = "true")]
-#[pyo3(name = "db_insert")]
-fn py_db_insert(mol2_list: Vec<Mol2>, filename: &str, compression: i32, shm: bool) {
-    db_insert(mol2_list, filename, compression, shm)
+    //     shm: should we try and use the database out of a temporary location?
+    //     query: which structures to read and in which order, see DbQuery
+    //     read_only: open the database read-only where it is, shm is ignored
+    let db = open_db(filename, shm, read_only)?;
+    // The id goes after the columns from_row reads
+    let (sql, values) = select(
+        &db,
+        query,
+        &format!("{}, id", STRUCTURE_COLUMNS),
+        Condition::default(),
+    )?;
+    let dictionaries = std::sync::Arc::new(dictionaries(&db)?);
+    let mut stmt = db.prepare(&sql)?;
+    let row_iter = stmt.query_map(&bind(&values)[..], |row| {
+        Ok(Mol2Row {
+            id: row.get(STRUCTURE_COLUMNS.split(", ").count())?,
+            row: RawRow::from_row(row)?,
+            dictionaries: dictionaries.clone(),
+        })
+    })?;
+    let mut rows: Vec<Mol2Row> = Vec::new();
+    for row in row_iter {
+        rows.push(row?);
+    }
+    Ok(rows)
 }
 
-pub fn read_db_all(
+#[pyfunction(
+    filename,
+    shm = "false",
+    desc = "\"\"",
+    comment = "\"\"",
+    name = "\"\"",
+    limit = "0",
+    offset = "0",
+    filter_mode = "\"substring\"",
+    order_by = "\"id\"",
+    min_atoms = "0",
+    max_atoms = "0",
+    min_bonds = "0",
+    max_bonds = "0",
+    property_ranges = "Vec::new()",
+    where_extras = "\"\"",
+    tag = "\"\"",
+    source_file = "\"\"",
+    inserted_after = "\"\"",
+    inserted_before = "\"\"",
+    read_only = "false"
+)]
+#[pyo3(name = "read_db_rows")]
+#[allow(clippy::too_many_arguments)]
+fn py_read_db_rows(
     filename: &str,
     shm: bool,
     desc: &str,
     comment: &str,
+    name: &str,
     limit: usize,
     offset: usize,
-) -> Vec<Mol2> {
-    // Read all structures from a database and return as a vector
+    filter_mode: &str,
+    order_by: &str,
+    min_atoms: usize,
+    max_atoms: usize,
+    min_bonds: usize,
+    max_bonds: usize,
+    property_ranges: Vec<(String, Option<f64>, Option<f64>)>,
+    where_extras: &str,
+    tag: &str,
+    source_file: &str,
+    inserted_after: &str,
+    inserted_before: &str,
+    read_only: bool,
+) -> PyResult<Vec<Mol2Row>> {
+    let query = DbQuery::from_args(
+        desc,
+        comment,
+        name,
+        filter_mode,
+        order_by,
+        limit,
+        offset,
+        min_atoms,
+        max_atoms,
+        min_bonds,
+        max_bonds,
+        property_ranges,
+        where_extras,
+        tag,
+        source_file,
+        inserted_after,
+        inserted_before,
+    )?;
+    Ok(read_db_rows(filename, shm, &query, read_only)?)
+}
+
+pub fn read_db_ids(
+    filename: &str,
+    ids: &[i64],
+    shm: bool,
+    read_only: bool,
+) -> Result<Vec<(i64, Mol2)>> {
+    // Read structures by their row ids, e.g. ids kept in an external index,
+    // and return pairs of row id and structure in the order of the ids.
+    // Ids without a row are left out.
     // Input:
     //     filename: path to the database
+    //     ids: row ids, the id column of the structures table
     //     shm: should we try and use the database out of a temporary location?
-    let db = get_db(filename, shm);
-    let mut query = "SELECT mol_name, num_atoms, num_bonds, num_subst, num_feat, num_sets, mol_type, charge_type, status_bits, mol_comment, atom, bond, substructure, compression, desc FROM structures".to_owned();
-    if limit > 0 {
-        query.push_str(&format!(" LIMIT {}", limit)[..]);
-        if offset > 0 {
-            query.push_str(&format!(" OFFSET {}", offset)[..]);
-        }
-    }
-    let mut stmt = db
-        .prepare(&query)
-        .expect("Failed to fetch from the database");
-    let structure_iter = stmt
-        .query_map([], |row| {
-            let compression: i32 = row.get(13).unwrap();
-            let mut atom: Vec<u8> = row.get(10).unwrap();
-            let mut bond: Vec<u8> = row.get(11).unwrap();
-            let mut subs: Vec<u8> = row.get(12).unwrap();
-            if compression > 0 {
-                atom = zstd::block::Decompressor::new()
-                    .decompress(&atom, DECOMPRESSOR_BUFFER)
-                    .expect("Failed to decompress");
-                bond = zstd::block::Decompressor::new()
-                    .decompress(&bond, DECOMPRESSOR_BUFFER)
-                    .expect("Failed to decompress");
-                subs = zstd::block::Decompressor::new()
-                    .decompress(&subs, DECOMPRESSOR_BUFFER)
-                    .expect("Failed to decompress");
-            }
-            let atom: Vec<Atom> =
-                bincode::deserialize(&atom).expect("Failed to deserialize &[u8] to Atom");
-            let bond: Vec<Bond> =
-                bincode::deserialize(&bond).expect("Failed to deserialize &[u8] to Bond");
-            let substructure: Vec<Substructure> =
-                bincode::deserialize(&subs).expect("Failed to deserialize &[u8] to Substructure");
-            Ok(Mol2 {
-                molecule: Some(Molecule {
-                    mol_name: row.get(0).unwrap(),
-                    num_atoms: row.get(1).unwrap(),
-                    num_bonds: row.get(2).unwrap(),
-                    num_subst: row.get(3).unwrap(),
-                    num_feat: row.get(4).unwrap(),
-                    num_sets: row.get(5).unwrap(),
-                    mol_type: row.get(6).unwrap(),
-                    charge_type: row.get(7).unwrap(),
-                    status_bits: row.get(8).unwrap(),
-                    mol_comment: row.get(9).unwrap(),
-                }),
-                atom,
-                bond,
-                substructure,
-                desc: row.get(14).unwrap(),
-            })
+    //     read_only: open the database read-only where it is, shm is ignored
+    let db = open_db(filename, shm, read_only)?;
+    let query = format!("SELECT {} FROM structures WHERE id = ?1", STRUCTURE_COLUMNS);
+    let mut stmt = db.prepare(&query)?;
+    let dictionaries = dictionaries(&db)?;
+    let mut mol2_list: Vec<(i64, Mol2)> = Vec::with_capacity(ids.len());
+    for &id in ids {
+        if let Some(row) = stmt.query_row([id], RawRow::from_row).optional()? {
+            mol2_list.push((id, row.decode(&dictionaries)?));
+        }
+    }
+    Ok(mol2_list)
+}
+
+#[pyfunction(filename, ids, shm = "false", read_only = "false")]
+#[pyo3(name = "read_db_ids")]
+fn py_read_db_ids(
+    filename: &str,
+    ids: Vec<i64>,
+    shm: bool,
+    read_only: bool,
+) -> PyResult<Vec<(i64, Mol2)>> {
+    Ok(read_db_ids(filename, &ids, shm, read_only)?)
+}
+
+// Row id, source file and time of insertion of a stored structure
+pub type Provenance = (i64, Option<String>, Option<String>);
+
+pub fn read_db_provenance(
+    filename: &str,
+    ids: &[i64],
+    shm: bool,
+    read_only: bool,
+) -> Result<Vec<Provenance>> {
+    // Where and when stored structures were inserted from, in the order of
+    // the ids. Ids without a row are left out as in read_db_ids.
+    // Input:
+    //     filename: path to the database
+    //     ids: row ids, the id column of the structures table
+    //     shm: should we try and use the database out of a temporary location?
+    //     read_only: open the database read-only where it is, shm is ignored
+    let db = open_db(filename, shm, read_only)?;
+    let mut stmt = db.prepare("SELECT source_file, inserted_at FROM structures WHERE id = ?1")?;
+    let mut provenance: Vec<Provenance> = Vec::with_capacity(ids.len());
+    for &id in ids {
+        if let Some(row) = stmt
+            .query_row([id], |row| Ok((id, row.get(0)?, row.get(1)?)))
+            .optional()?
+        {
+            provenance.push(row);
+        }
+    }
+    Ok(provenance)
+}
+
+#[pyfunction(filename, ids, shm = "false", read_only = "false")]
+#[pyo3(name = "read_db_provenance")]
+fn py_read_db_provenance(
+    filename: &str,
+    ids: Vec<i64>,
+    shm: bool,
+    read_only: bool,
+) -> PyResult<Vec<Provenance>> {
+    Ok(read_db_provenance(filename, &ids, shm, read_only)?)
+}
+
+// Connection used by more than one reader, e.g. a Mol2Db and its iterators
+type SharedDb = std::sync::Arc<std::sync::Mutex<Db>>;
+
+// Iterator over structures in a database, fetching rows in batches so that
+// only a batch is held in memory at a time
+#[pyclass]
+pub struct DbReader {
+    db: SharedDb,
+    query: DbQuery,
+    batch_size: usize,
+    // Structures still to be read with a limit
+    remaining: Option<usize>,
+    offset: usize,
+    // Rows are fetched in order after the last one fetched, given by its
+    // order column and id
+    last: Option<(rusqlite::types::Value, i64)>,
+    // Structures decoded from the rows fetched last
+    rows: VecDeque<Result<Mol2>>,
+    dictionaries: Dictionaries,
+    // Threads decoding the rows of a batch, none to decode them on the
+    // current thread
+    pool: Option<rayon::ThreadPool>,
+    done: bool,
+}
+
+impl DbReader {
+    fn new(
+        filename: &str,
+        shm: bool,
+        query: &DbQuery,
+        batch_size: usize,
+        threads: usize,
+        read_only: bool,
+    ) -> Result<DbReader> {
+        let db = open_db(filename, shm, read_only)?;
+        DbReader::with_connection(
+            std::sync::Arc::new(std::sync::Mutex::new(db)),
+            query,
+            batch_size,
+            threads,
+        )
+    }
+    fn with_connection(
+        db: SharedDb,
+        query: &DbQuery,
+        batch_size: usize,
+        threads: usize,
+    ) -> Result<DbReader> {
+        // Iterate over structures using a connection that may be shared,
+        // e.g. with a Mol2Db
+        if batch_size == 0 {
+            return Err(Mol2Error::InvalidArgument(
+                "batch size has to be at least 1".to_owned(),
+            ));
+        }
+        let connection = db.lock().unwrap_or_else(|error| error.into_inner());
+        // Invalid queries fail here rather than at the first batch
+        select(&connection, query, STRUCTURE_COLUMNS, Condition::default())?;
+        let dictionaries = dictionaries(&connection)?;
+        drop(connection);
+        let pool = match threads {
+            1 => None,
+            threads => Some(thread_pool(threads)?),
+        };
+        Ok(DbReader {
+            db,
+            query: query.clone(),
+            batch_size,
+            remaining: match query.limit {
+                0 => None,
+                limit => Some(limit),
+            },
+            offset: query.offset,
+            last: None,
+            rows: VecDeque::new(),
+            dictionaries,
+            pool,
+            done: false,
         })
-        .expect("Failed to fetch exact numbers from db");
-    let mut mol2_list: Vec<Mol2> = Vec::new();
-    for structure in structure_iter {
-        mol2_list.push(structure.expect("Failed to get structure after successful extraction...?"));
     }
-    if !desc.is_empty() {
-        mol2_list.retain(|mol2| mol2.desc.as_ref().unwrap_or(&String::new()).contains(desc));
+    fn fetch(&mut self) -> Result<()> {
+        // Read the next batch of rows, filters are applied in SQL the same
+        // way read_db_all applies them
+        let batch_size = match self.remaining {
+            Some(remaining) => remaining.min(self.batch_size),
+            None => self.batch_size,
+        };
+        let query = DbQuery {
+            limit: batch_size,
+            offset: self.offset,
+            ..self.query.clone()
+        };
+        // Continue after the last row fetched, the first batch starts at the
+        // beginning. Empty values come last, so after one only empty values
+        // are left.
+        let mut after = Condition::default();
+        let last_id = |id: &i64| (":last_id".to_owned(), rusqlite::types::Value::Integer(*id));
+        match (&self.last, &self.query.order_by) {
+            (None, _) => {}
+            (Some((_, id)), OrderBy::Id) => {
+                after.clause = " AND id > :last_id".to_owned();
+                after.values.push(last_id(id));
+            }
+            (Some((rusqlite::types::Value::Null, id)), order_by) => {
+                after.clause = format!(" AND {} IS NULL AND id > :last_id", order_by.column());
+                after.values.push(last_id(id));
+            }
+            (Some((key, id)), order_by) => {
+                after.clause = format!(
+                    " AND (({0}, id) > (:last_key, :last_id) OR {0} IS NULL)",
+                    order_by.column()
+                );
+                after.values.push((":last_key".to_owned(), key.clone()));
+                after.values.push(last_id(id));
+            }
+        }
+        let db = self.db.lock().unwrap_or_else(|error| error.into_inner());
+        let (sql, values) = select(
+            &db,
+            &query,
+            &format!(
+                "{}, id, {}",
+                STRUCTURE_COLUMNS,
+                self.query.order_by.column()
+            ),
+            after,
+        )?;
+        let mut stmt = db.prepare_cached(&sql)?;
+        let id_column = STRUCTURE_COLUMNS.split(", ").count();
+        let rows = stmt.query_map(&bind(&values)[..], |row| {
+            Ok((
+                row.get::<_, rusqlite::types::Value>(id_column + 1)?,
+                row.get::<_, i64>(id_column)?,
+                RawRow::from_row(row)?,
+            ))
+        })?;
+        let mut batch: Vec<RawRow> = Vec::with_capacity(batch_size);
+        for row in rows {
+            let (key, id, row) = row?;
+            self.last = Some((key, id));
+            batch.push(row);
+        }
+        // The offset only applies to the first batch
+        self.offset = 0;
+        if batch.len() < batch_size {
+            self.done = true;
+        }
+        self.rows
+            .extend(decode_batch(batch, &self.dictionaries, self.pool.as_ref()));
+        Ok(())
+    }
+}
+
+impl Iterator for DbReader {
+    type Item = Result<Mol2>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == Some(0) {
+            return None;
+        }
+        if self.rows.is_empty() && !self.done {
+            if let Err(error) = self.fetch() {
+                self.done = true;
+                return Some(Err(error));
+            }
+        }
+        let mol2 = self.rows.pop_front()?;
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining -= 1;
+        }
+        Some(mol2)
     }
-    if !comment.is_empty() {
-        mol2_list.retain(|mol2| {
-            mol2.molecule
-                .as_ref()
-                .unwrap_or(&Molecule::new())
-                .mol_comment
-                .as_ref()
-                .unwrap_or(&String::new())
-                .contains(comment)
-        });
+}
+
+#[pymethods]
+impl DbReader {
+    fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
     }
+    fn __next__(mut slf: PyRefMut<Self>) -> PyResult<Option<Mol2>> {
+        Ok(slf.next().transpose()?)
+    }
+}
 
-    mol2_list
+pub fn read_db_iter(
+    filename: &str,
+    shm: bool,
+    query: &DbQuery,
+    batch_size: usize,
+    threads: usize,
+    read_only: bool,
+) -> Result<DbReader> {
+    // Iterate over structures in a database one by one, see read_db_all
+    // Input:
+    //     filename: path to the database
+    //     shm: should we try and use the database out of a temporary location?
+    //     query: which structures to read and in which order, see DbQuery
+    //     batch_size: number of rows fetched from the database at a time
+    //     threads: number of threads decoding each batch
+    //     read_only: open the database read-only where it is, shm is ignored
+    DbReader::new(filename, shm, query, batch_size, threads, read_only)
 }
 
 #[pyfunction(
@@ -837,19 +5262,73 @@ pub fn read_db_all(
     shm = "false",
     desc = "\"\"",
     comment = "\"\"",
+    name = "\"\"",
     limit = "0",
-    offset = "0"
+    offset = "0",
+    batch_size = "1000",
+    filter_mode = "\"substring\"",
+    order_by = "\"id\"",
+    min_atoms = "0",
+    max_atoms = "0",
+    min_bonds = "0",
+    max_bonds = "0",
+    property_ranges = "Vec::new()",
+    where_extras = "\"\"",
+    tag = "\"\"",
+    source_file = "\"\"",
+    inserted_after = "\"\"",
+    inserted_before = "\"\"",
+    threads = "1",
+    read_only = "false"
 )]
-#[pyo3(name = "read_db_all")]
-fn py_read_db_all(
+#[pyo3(name = "read_db_iter")]
+#[allow(clippy::too_many_arguments)]
+fn py_read_db_iter(
     filename: &str,
     shm: bool,
     desc: &str,
     comment: &str,
+    name: &str,
     limit: usize,
     offset: usize,
-) -> Vec<Mol2> {
-    read_db_all(filename, shm, desc, comment, limit, offset)
+    batch_size: usize,
+    filter_mode: &str,
+    order_by: &str,
+    min_atoms: usize,
+    max_atoms: usize,
+    min_bonds: usize,
+    max_bonds: usize,
+    property_ranges: Vec<(String, Option<f64>, Option<f64>)>,
+    where_extras: &str,
+    tag: &str,
+    source_file: &str,
+    inserted_after: &str,
+    inserted_before: &str,
+    threads: usize,
+    read_only: bool,
+) -> PyResult<DbReader> {
+    let query = DbQuery::from_args(
+        desc,
+        comment,
+        name,
+        filter_mode,
+        order_by,
+        limit,
+        offset,
+        min_atoms,
+        max_atoms,
+        min_bonds,
+        max_bonds,
+        property_ranges,
+        where_extras,
+        tag,
+        source_file,
+        inserted_after,
+        inserted_before,
+    )?;
+    Ok(read_db_iter(
+        filename, shm, &query, batch_size, threads, read_only,
+    )?)
 }
 
 #[pyfunction(
@@ -857,56 +5336,223 @@ fn py_read_db_all(
     shm = "false",
     desc = "\"\"",
     comment = "\"\"",
+    name = "\"\"",
     limit = "0",
-    offset = "0"
+    offset = "0",
+    filter_mode = "\"substring\"",
+    order_by = "\"id\"",
+    min_atoms = "0",
+    max_atoms = "0",
+    min_bonds = "0",
+    max_bonds = "0",
+    property_ranges = "Vec::new()",
+    where_extras = "\"\"",
+    tag = "\"\"",
+    source_file = "\"\"",
+    inserted_after = "\"\"",
+    inserted_before = "\"\"",
+    threads = "1",
+    read_only = "false"
 )]
+#[allow(clippy::too_many_arguments)]
 fn read_db_all_serialized(
     filename: &str,
     shm: bool,
     desc: &str,
     comment: &str,
+    name: &str,
     limit: usize,
     offset: usize,
+    filter_mode: &str,
+    order_by: &str,
+    min_atoms: usize,
+    max_atoms: usize,
+    min_bonds: usize,
+    max_bonds: usize,
+    property_ranges: Vec<(String, Option<f64>, Option<f64>)>,
+    where_extras: &str,
+    tag: &str,
+    source_file: &str,
+    inserted_after: &str,
+    inserted_before: &str,
+    threads: usize,
+    read_only: bool,
 ) -> PyResult<Vec<PyObject>> {
     // Read all structures from a database and return as a vector, but
     // keep structures in a serialized python form rather than binary.
     // Input:
     //     filename: path to the database
     //     shm: should we try and use the database out of a temporary location?
-    let mol2_list = read_db_all(filename, shm, desc, comment, limit, offset);
+    //     read_only: open the database read-only where it is, shm is ignored
+    let query = DbQuery::from_args(
+        desc,
+        comment,
+        name,
+        filter_mode,
+        order_by,
+        limit,
+        offset,
+        min_atoms,
+        max_atoms,
+        min_bonds,
+        max_bonds,
+        property_ranges,
+        where_extras,
+        tag,
+        source_file,
+        inserted_after,
+        inserted_before,
+    )?;
+    let mol2_list = read_db_all(filename, shm, &query, threads, read_only)?;
     let mut result: Vec<PyObject> = Vec::new();
     for entry in &mol2_list {
-        result.push(
-            entry
-                .serialized()
-                .expect("Failed to serialize mol2 entry..."),
-        );
+        result.push(entry.serialized()?);
     }
     Ok(result)
 }
 
-#[pyfunction(filename, shm = "false")]
-pub fn desc_list(filename: &str, shm: bool) -> Vec<String> {
-    // Read all structures from a database and return as a vector
+// Columns of the structures table distinct_values can list
+const DISTINCT_COLUMNS: [&str; 4] = ["desc", "mol_name", "mol_type", "charge_type"];
+
+pub fn distinct_values(
+    filename: &str,
+    column: &str,
+    shm: bool,
+    read_only: bool,
+) -> Result<Vec<String>> {
+    // Sorted list of the different values of a column in a database, empty
+    // values are not listed
     // Input:
     //     filename: path to the database
+    //     column: desc, mol_name, mol_type or charge_type
     //     shm: should we try and use the database out of a temporary location?
-    let db = get_db(filename, shm);
-    let mut stmt = db
-        .prepare("SELECT desc FROM structures")
-        .expect("Failed to fetch from the database");
-    let desc_iter = stmt
-        .query_map([], |row| Ok(row.get(0).unwrap()))
-        .expect("Failed to fetch exact numbers from db");
-    let mut desc_list: Vec<String> = Vec::new();
-    for desc in desc_iter {
-        desc_list.push(desc.expect("Failed to get desc after successful extraction...?"));
+    //     read_only: open the database read-only where it is, shm is ignored
+    if !DISTINCT_COLUMNS.contains(&column) {
+        return Err(Mol2Error::InvalidArgument(format!(
+            "cannot list values of column {}, expected one of {}",
+            column,
+            DISTINCT_COLUMNS.join(", ")
+        )));
     }
+    let db = open_db(filename, shm, read_only)?;
+    // The column is one of the names above, so it is safe to format it in
+    let mut stmt = db.prepare(&format!(
+        "SELECT DISTINCT {0} FROM structures WHERE {0} IS NOT NULL ORDER BY {0}",
+        column
+    ))?;
+    let values = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    Ok(values.collect::<rusqlite::Result<Vec<String>>>()?)
+}
+
+#[pyfunction(filename, column, shm = "false", read_only = "false")]
+#[pyo3(name = "distinct_values")]
+fn py_distinct_values(
+    filename: &str,
+    column: &str,
+    shm: bool,
+    read_only: bool,
+) -> PyResult<Vec<String>> {
+    Ok(distinct_values(filename, column, shm, read_only)?)
+}
+
+pub fn desc_list(filename: &str, shm: bool, read_only: bool) -> Result<Vec<String>> {
+    // List the different descriptions in a database, see distinct_values
+    // Input:
+    //     filename: path to the database
+    //     shm: should we try and use the database out of a temporary location?
+    //     read_only: open the database read-only where it is, shm is ignored
+    distinct_values(filename, "desc", shm, read_only)
+}
 
-    desc_list.sort();
-    desc_list.dedup();
+#[pyfunction(filename, shm = "false", read_only = "false")]
+#[pyo3(name = "desc_list")]
+fn py_desc_list(filename: &str, shm: bool, read_only: bool) -> PyResult<Vec<String>> {
+    Ok(desc_list(filename, shm, read_only)?)
+}
+
+pub fn db_count(filename: &str, shm: bool, query: &DbQuery, read_only: bool) -> Result<usize> {
+    // Count structures in a database without reading them, the number of
+    // structures read_db_all would return for the same query
+    // Input:
+    //     filename: path to the database
+    //     shm: should we try and use the database out of a temporary location?
+    //     query: which structures to count, see DbQuery
+    //     read_only: open the database read-only where it is, shm is ignored
+    let db = open_db(filename, shm, read_only)?;
+    count_structures(&db, query)
+}
+
+fn count_structures(db: &rusqlite::Connection, query: &DbQuery) -> Result<usize> {
+    // Count structures using an already open connection, see db_count
+    let (sql, values) = select(db, query, "id", Condition::default())?;
+    let count = db.query_row(
+        &format!("SELECT COUNT(*) FROM ({})", sql),
+        &bind(&values)[..],
+        |row| row.get::<_, i64>(0),
+    )?;
+    Ok(count as usize)
+}
 
-    desc_list
+#[pyfunction(
+    filename,
+    shm = "false",
+    desc = "\"\"",
+    comment = "\"\"",
+    name = "\"\"",
+    filter_mode = "\"substring\"",
+    min_atoms = "0",
+    max_atoms = "0",
+    min_bonds = "0",
+    max_bonds = "0",
+    property_ranges = "Vec::new()",
+    where_extras = "\"\"",
+    tag = "\"\"",
+    source_file = "\"\"",
+    inserted_after = "\"\"",
+    inserted_before = "\"\"",
+    read_only = "false"
+)]
+#[pyo3(name = "db_count")]
+#[allow(clippy::too_many_arguments)]
+fn py_db_count(
+    filename: &str,
+    shm: bool,
+    desc: &str,
+    comment: &str,
+    name: &str,
+    filter_mode: &str,
+    min_atoms: usize,
+    max_atoms: usize,
+    min_bonds: usize,
+    max_bonds: usize,
+    property_ranges: Vec<(String, Option<f64>, Option<f64>)>,
+    where_extras: &str,
+    tag: &str,
+    source_file: &str,
+    inserted_after: &str,
+    inserted_before: &str,
+    read_only: bool,
+) -> PyResult<usize> {
+    let query = DbQuery::from_args(
+        desc,
+        comment,
+        name,
+        filter_mode,
+        "id",
+        0,
+        0,
+        min_atoms,
+        max_atoms,
+        min_bonds,
+        max_bonds,
+        property_ranges,
+        where_extras,
+        tag,
+        source_file,
+        inserted_after,
+        inserted_before,
+    )?;
+    Ok(db_count(filename, shm, &query, read_only)?)
 }
 
 pub fn read_file_to_db(
@@ -914,17 +5560,21 @@ pub fn read_file_to_db(
     db_name: &str,
     compression: i32,
     shm: bool,
-    desc: &str,
-    comment: &str,
-) {
-    // Convenience function. Read structures from a mol2 file and write directly to the database
+    options: &ReadOptions,
+    codec: Codec,
+) -> Result<Vec<SkippedMolecule>> {
+    // Convenience function. Read structures from a mol2 file and write directly to the database.
+    // Returns the structures skipped in the lenient mode.
     // Input:
     //     filename: path to the mol2 file
     //     db_name: path to the database
     //     compression: compression level
     //     shm: should we use the database out of a temporary location
-    let content = read_file(filename, desc, comment);
-    let _ = db_insert(content, db_name, compression, shm);
+    //     options: how to read the file, see read_file
+    //     codec: codec to compress the blobs with, see db_insert
+    let (content, skipped) = read_file(filename, options)?;
+    db_insert(content, db_name, compression, shm, false, filename, codec)?;
+    Ok(skipped)
 }
 
 #[pyfunction(
@@ -933,9 +5583,14 @@ pub fn read_file_to_db(
     compression = "3",
     shm = "true",
     desc = "\"\"",
-    comment = "\"\""
+    comment = "\"\"",
+    threads = "1",
+    mode = "\"strict\"",
+    keep_source = "false",
+    codec = "\"zstd\""
 )]
 #[pyo3(name = "read_file_to_db")]
+#[allow(clippy::too_many_arguments)]
 fn py_read_file_to_db(
     filename: &str,
     db_name: &str,
@@ -943,8 +5598,19 @@ fn py_read_file_to_db(
     shm: bool,
     desc: &str,
     comment: &str,
-) {
-    read_file_to_db(filename, db_name, compression, shm, desc, comment)
+    threads: usize,
+    mode: &str,
+    keep_source: bool,
+    codec: &str,
+) -> PyResult<Vec<SkippedMolecule>> {
+    Ok(read_file_to_db(
+        filename,
+        db_name,
+        compression,
+        shm,
+        &ReadOptions::from_args(desc, comment, "", threads, mode, keep_source)?,
+        Codec::from_name(codec)?,
+    )?)
 }
 
 pub fn read_file_to_db_batch(
@@ -952,33 +5618,135 @@ pub fn read_file_to_db_batch(
     db_name: &str,
     compression: i32,
     shm: bool,
-    desc: &str,
     filename_desc: bool,
-    comment: &str,
-) {
-    // Convenience function. Read structures from a set of files directly into the database
+    options: &ReadOptions,
+    codec: Codec,
+) -> Result<Vec<SkippedMolecule>> {
+    // Convenience function. Read structures from a set of files directly into the database.
+    // Returns the structures skipped in the lenient mode, file by file.
     // Input:
     //     filenames: vector of paths to mol2 files
     //     db_name: path to the database
     //     compression: compression level
     //     shm: should we use the database out of a temporary location
+    //     filename_desc: add the name of the file to the description of its structures
+    //     options: how to read the files, see read_file. Here threads is the number
+    //              of files parsed in parallel, 1 means one file at a time and 0 means
+    //              all cores, and every file is parsed on a single thread.
+    //     codec: codec to compress the blobs with, see db_insert
+    if options.threads != 1 {
+        return read_file_to_db_parallel(
+            filenames,
+            db_name,
+            compression,
+            shm,
+            filename_desc,
+            options,
+            codec,
+        );
+    }
+    let mut skipped: Vec<SkippedMolecule> = Vec::new();
     for filename in &filenames {
-        let mut description: String = desc.to_owned();
-        if filename_desc {
-            if !desc.is_empty() {
-                description.push_str("; ");
+        let (content, file_skipped) =
+            read_file(filename, &batch_options(options, filename, filename_desc))?;
+        db_insert(content, db_name, compression, shm, false, filename, codec)?;
+        skipped.extend(file_skipped);
+    }
+    Ok(skipped)
+}
+
+fn read_file_to_db_parallel(
+    filenames: Vec<&str>,
+    db_name: &str,
+    compression: i32,
+    shm: bool,
+    filename_desc: bool,
+    options: &ReadOptions,
+    codec: Codec,
+) -> Result<Vec<SkippedMolecule>> {
+    // Parse files on a pool of threads while the current thread is
+    // the only one writing to the database. Structures from one file
+    // are inserted together, and files in the order they were given,
+    // so that row ids are the same as when parsing one file at a time.
+    let pool = thread_pool(options.threads)?;
+    // Bounded so that parsed files do not pile up in memory if the database is slower
+    let (sender, receiver) = std::sync::mpsc::sync_channel(pool.current_num_threads());
+    let db = get_db(db_name, shm)?;
+    let inserted = std::thread::scope(|scope| {
+        scope.spawn(|| {
+            pool.install(|| {
+                // Stops early once the writer hangs up after an error
+                filenames.par_iter().enumerate().try_for_each_with(
+                    sender,
+                    |sender, (index, filename)| {
+                        let options = batch_options(options, filename, filename_desc);
+                        sender.send((index, read_file(filename, &options)))
+                    },
+                )
+            })
+        });
+        // Files parsed ahead of the next one to insert wait here, by index
+        let mut parsed: HashMap<usize, Result<Parsed>> = HashMap::new();
+        let mut next = 0;
+        let mut skipped: Vec<SkippedMolecule> = Vec::new();
+        for (index, content) in receiver {
+            parsed.insert(index, content);
+            while let Some(content) = parsed.remove(&next) {
+                let (content, file_skipped) = content?;
+                insert_structures(&db, &content, compression, codec, false, filenames[next])?;
+                skipped.extend(file_skipped);
+                next += 1;
             }
-            description.push_str(
-                std::path::Path::new(filename)
-                    .file_name()
-                    .unwrap_or_else(|| std::ffi::OsStr::new(filename))
-                    .to_str()
-                    .unwrap_or(filename),
-            );
         }
-        let content = read_file(filename, &description, comment);
-        let _ = db_insert(content, db_name, compression, shm);
+        Ok::<Vec<SkippedMolecule>, Mol2Error>(skipped)
+    });
+    // Files inserted before a failing one are kept, as when reading one
+    // file at a time, so the copy is put in place on errors too
+    db_cleanup(db_name, &db)?;
+    inserted
+}
+
+fn thread_pool(threads: usize) -> Result<rayon::ThreadPool> {
+    // Pool of worker threads, 0 means one thread per core
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .map_err(|error| Mol2Error::InvalidArgument(error.to_string()))
+}
+
+fn batch_options(options: &ReadOptions, filename: &str, filename_desc: bool) -> ReadOptions {
+    // Options for reading one of the files in a batch, on a single thread
+    // Input:
+    //     options: options given by the user
+    //     filename: path to the file structures are coming from
+    //     filename_desc: should we add the filename to the description?
+    ReadOptions {
+        desc: batch_desc(&options.desc, filename, filename_desc),
+        threads: 1,
+        ..options.clone()
+    }
+}
+
+fn batch_desc(desc: &str, filename: &str, filename_desc: bool) -> String {
+    // Description for structures read from one of the files in a batch
+    // Input:
+    //     desc: description given by the user
+    //     filename: path to the file structures are coming from
+    //     filename_desc: should we add the filename to the description?
+    let mut description: String = desc.to_owned();
+    if filename_desc {
+        if !desc.is_empty() {
+            description.push_str("; ");
+        }
+        description.push_str(
+            std::path::Path::new(filename)
+                .file_name()
+                .unwrap_or_else(|| std::ffi::OsStr::new(filename))
+                .to_str()
+                .unwrap_or(filename),
+        );
     }
+    description
 }
 
 #[pyfunction(
@@ -988,9 +5756,14 @@ pub fn read_file_to_db_batch(
     shm = "true",
     desc = "\"\"",
     filename_desc = "false",
-    comment = "\"\""
+    comment = "\"\"",
+    threads = "1",
+    mode = "\"strict\"",
+    keep_source = "false",
+    codec = "\"zstd\""
 )]
 #[pyo3(name = "read_file_to_db_batch")]
+#[allow(clippy::too_many_arguments)]
 fn py_read_file_to_db_batch(
     filenames: Vec<&str>,
     db_name: &str,
@@ -999,87 +5772,324 @@ fn py_read_file_to_db_batch(
     desc: &str,
     filename_desc: bool,
     comment: &str,
-) {
-    read_file_to_db_batch(
+    threads: usize,
+    mode: &str,
+    keep_source: bool,
+    codec: &str,
+) -> PyResult<Vec<SkippedMolecule>> {
+    Ok(read_file_to_db_batch(
         filenames,
         db_name,
         compression,
         shm,
-        desc,
         filename_desc,
-        comment,
-    )
+        &ReadOptions::from_args(desc, comment, "", threads, mode, keep_source)?,
+        Codec::from_name(codec)?,
+    )?)
 }
 
-pub fn read_file(filename: &str, desc: &str, comment: &str) -> Vec<Mol2> {
-    // Read a mol2 file and return a vector of structures
+pub fn read_file(filename: &str, options: &ReadOptions) -> Result<Parsed> {
+    // Read a mol2 file and return a vector of structures, together with
+    // the structures skipped in the lenient mode
     // Input:
     //     filename: path to a mol2 file
-    let file = File::open(filename).expect("Failed to open the input file");
-    let reader = BufReader::with_capacity(100 * 1024 * 1024, file);
-    let mut section_name: String = String::new();
-    let mut section_index: usize = 0;
+    //     options: description, comment, compression, threads and parse mode, see ReadOptions
+    //
+    // With more than one thread the whole file is kept in memory and
+    // split into chunks on MOLECULE boundaries, which are then parsed
+    // in parallel. With the "mmap" feature uncompressed files are
+    // memory mapped instead of read line by line.
+    #[cfg(feature = "mmap")]
+    if FileCompression::detect(filename, options.compression) == FileCompression::None {
+        return read_file_mmap(filename, options);
+    }
+    if options.threads != 1 {
+        let mut text = String::new();
+        compression::open_input(filename, options.compression)?.read_to_string(&mut text)?;
+        return parse_text(&text, options);
+    }
+    let mut reader = Mol2Reader::new(filename, options)?;
+    let mol2 = (&mut reader).collect::<Result<Vec<Mol2>>>()?;
+
+    Ok((mol2, reader.parser.skipped))
+}
+
+#[cfg(feature = "mmap")]
+fn read_file_mmap(filename: &str, options: &ReadOptions) -> Result<Parsed> {
+    // Parse a memory mapped file, lines are slices of the mapping so
+    // there is no copying or allocation per line
+    let file = std::fs::File::open(filename)?;
+    // SAFETY: the mapping is only read and dropped before returning.
+    // Modifying the file while it is being parsed is not supported.
+    let map = unsafe { memmap2::Mmap::map(&file) }?;
+    let text = std::str::from_utf8(&map)
+        .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error.to_string()))?;
+    parse_text(text, options)
+}
+
+fn warn_skipped(py: Python, skipped: &[SkippedMolecule]) -> PyResult<()> {
+    // Raise a python UserWarning for every structure dropped in the lenient mode
+    for entry in skipped {
+        user_warning(py, &format!("skipped {}", entry))?;
+    }
+    Ok(())
+}
+
+fn user_warning(py: Python, message: &str) -> PyResult<()> {
+    // Raise a python UserWarning, which is an exception only if the warning
+    // filters say so
+    let category = py.import("builtins")?.getattr("UserWarning")?;
+    PyErr::warn(py, category, message, 1)
+}
+
+type Parsed = (Vec<Mol2>, Vec<SkippedMolecule>);
+
+fn parse_text(text: &str, options: &ReadOptions) -> Result<Parsed> {
+    // Parse mol2 text already in memory. With more than one thread
+    // chunks of it are parsed in parallel, keeping the order of
+    // structures
+    if options.threads == 1 {
+        return parse_chunk(text, options, 0);
+    }
+    let pool = thread_pool(options.threads)?;
+    let chunks = split_molecules(text, pool.current_num_threads());
+    let parsed: Vec<Result<Parsed>> = pool.install(|| {
+        // Line numbers where chunks start, so that errors point to the right place
+        let line_counts: Vec<usize> = chunks
+            .par_iter()
+            .map(|chunk| chunk.bytes().filter(|byte| *byte == b'\n').count())
+            .collect();
+        let first_lines: Vec<usize> = line_counts
+            .iter()
+            .scan(0, |total, count| {
+                let first_line = *total;
+                *total += count;
+                Some(first_line)
+            })
+            .collect();
+        chunks
+            .par_iter()
+            .zip(first_lines)
+            .map(|(chunk, first_line)| parse_chunk(chunk, options, first_line))
+            .collect()
+    });
+
+    let mut mol2: Vec<Mol2> = Vec::new();
+    let mut skipped: Vec<SkippedMolecule> = Vec::new();
+    for chunk in parsed {
+        let (chunk_mol2, chunk_skipped) = chunk?;
+        mol2.extend(chunk_mol2);
+        skipped.extend(chunk_skipped);
+    }
+    Ok((mol2, skipped))
+}
+
+fn parse_chunk(text: &str, options: &ReadOptions, first_line: usize) -> Result<Parsed> {
+    // Parse all structures in a piece of mol2 text
+    // Input:
+    //     first_line: number of lines in the file before this piece
+    let mut parser = Mol2Parser::new(options, first_line);
     let mut mol2: Vec<Mol2> = Vec::new();
-    let mut entry = Mol2::new(desc);
-    for (index, line) in reader.lines().enumerate() {
-        let line =
-            line.expect(&format!("Failed to read {} line from the input file", index + 1)[..]);
+    for line in text.split_inclusive('\n') {
+        mol2.extend(parser.parse_line(line)?);
+    }
+    mol2.extend(parser.finish());
+
+    Ok((mol2, parser.skipped))
+}
 
-        let mut section_start = false;
-        if line.len() > 11 {
-            section_start = &line[0..9] == "@<TRIPOS>";
+fn split_molecules(text: &str, nchunks: usize) -> Vec<&str> {
+    // Split mol2 text into roughly equal chunks, each starting at a MOLECULE section
+    // Input:
+    //     text: content of a mol2 file
+    //     nchunks: desired number of chunks
+    let mut chunks: Vec<&str> = Vec::new();
+    let chunk_size = text.len() / nchunks.max(1) + 1;
+    let mut start = 0;
+    while start < text.len() {
+        let mut target = (start + chunk_size).min(text.len());
+        while !text.is_char_boundary(target) {
+            target += 1;
         }
-        if section_start {
-            section_name = (&line[9..]).to_owned();
-            // make sure to not use any extra characters...
-            section_name = section_name.split_whitespace().next().unwrap().to_owned();
-            section_index = index;
-            if section_name == "MOLECULE" && entry.molecule.is_some() {
-                entry.add_comment(comment);
-                mol2.push(entry);
-                entry = Mol2::new(desc);
+        let end = match text[target..].find("\n@<TRIPOS>MOLECULE") {
+            Some(position) => target + position + 1,
+            None => text.len(),
+        };
+        chunks.push(&text[start..end]);
+        start = end;
+    }
+
+    chunks
+}
+
+pub fn read_file_iter(filename: &str, options: &ReadOptions) -> Result<Mol2Reader> {
+    // Read a mol2 file lazily, one structure at a time
+    // Input:
+    //     filename: path to a mol2 file
+    //     options: how to read the file, see read_file. The file is always
+    //              streamed, so threads is not used.
+    Mol2Reader::new(filename, options)
+}
+
+// Iterator over structures in a mol2 file, parsing sections as they are read
+#[pyclass]
+pub struct Mol2Reader {
+    reader: Box<dyn BufRead + Send>,
+    buffer: String,
+    parser: Mol2Parser,
+    done: bool,
+}
+
+impl Mol2Reader {
+    fn new(filename: &str, options: &ReadOptions) -> Result<Mol2Reader> {
+        Ok(Mol2Reader {
+            reader: compression::open_input(filename, options.compression)?,
+            buffer: String::new(),
+            parser: Mol2Parser::new(options, 0),
+            done: false,
+        })
+    }
+    pub fn skipped(&self) -> &[SkippedMolecule] {
+        // Structures skipped so far in the lenient mode
+        &self.parser.skipped
+    }
+}
+
+impl Iterator for Mol2Reader {
+    type Item = Result<Mol2>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            // Reusing a single buffer so that we do not allocate for every line
+            self.buffer.clear();
+            match self.reader.read_line(&mut self.buffer) {
+                Ok(0) => {
+                    self.done = true;
+                    return self.parser.finish().map(Ok);
+                }
+                Ok(_) => match self.parser.parse_line(&self.buffer) {
+                    Ok(Some(entry)) => return Some(Ok(entry)),
+                    Ok(None) => continue,
+                    Err(error) => {
+                        self.done = true;
+                        return Some(Err(error));
+                    }
+                },
+                Err(error) => {
+                    self.done = true;
+                    return Some(Err(error.into()));
+                }
             }
-        } else if section_name != String::new() {
-            let subsection_index = index - section_index - 1;
-            match &section_name[..] {
-                "MOLECULE" => read_molecule_section(subsection_index, &line, &mut entry),
-                "ATOM" => read_atom_section(&line, &mut entry),
-                "BOND" => read_bond_section(&line, &mut entry),
-                "SUBSTRUCTURE" => read_substructure_section(&line, &mut entry),
-                _ => continue,
-            };
         }
+        None
     }
-    entry.add_comment(comment);
-    mol2.push(entry); // if we are just at the end of the file
+}
 
-    mol2
+#[pymethods]
+impl Mol2Reader {
+    fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+    fn __next__(mut slf: PyRefMut<Self>) -> PyResult<Option<Mol2>> {
+        Ok(slf.next().transpose()?)
+    }
+    #[getter(skipped)]
+    fn py_skipped(&self) -> Vec<SkippedMolecule> {
+        self.skipped().to_vec()
+    }
 }
 
-#[pyfunction(filename, desc = "\"\"", comment = "\"\"")]
+#[pyfunction(
+    filename,
+    desc = "\"\"",
+    comment = "\"\"",
+    compression = "\"\"",
+    threads = "1",
+    mode = "\"strict\"",
+    keep_source = "false",
+    conformers = "false"
+)]
 #[pyo3(name = "read_file")]
-fn py_read_file(filename: &str, desc: &str, comment: &str) -> Vec<Mol2> {
-    read_file(filename, desc, comment)
+#[allow(clippy::too_many_arguments)]
+fn py_read_file(
+    py: Python,
+    filename: &str,
+    desc: &str,
+    comment: &str,
+    compression: &str,
+    threads: usize,
+    mode: &str,
+    keep_source: bool,
+    conformers: bool,
+) -> PyResult<PyObject> {
+    // With conformers repeated molecules are collapsed, see conformers::collapse
+    let (mol2_list, skipped) = read_file(
+        filename,
+        &ReadOptions::from_args(desc, comment, compression, threads, mode, keep_source)?,
+    )?;
+    warn_skipped(py, &skipped)?;
+    Ok(match conformers {
+        true => collapse_conformers(mol2_list).into_py(py),
+        false => mol2_list.into_py(py),
+    })
 }
 
-#[pyfunction(filename, desc = "\"\"", comment = "\"\"")]
-fn read_file_serialized(filename: &str, desc: &str, comment: &str) -> PyResult<Vec<PyObject>> {
+#[pyfunction(
+    filename,
+    desc = "\"\"",
+    comment = "\"\"",
+    compression = "\"\"",
+    mode = "\"strict\""
+)]
+fn read_file_serialized(
+    py: Python,
+    filename: &str,
+    desc: &str,
+    comment: &str,
+    compression: &str,
+    mode: &str,
+) -> PyResult<Vec<PyObject>> {
     // Read a mol2 file and return a vector of structures, but
     // serialized python structures rather than a binary form.
     // Input:
     //     filename: path to a mol2 file
-    let mol2_list = read_file(filename, desc, comment);
+    //     compression: compression of the file, empty means guess from the extension
+    //     mode: "strict" or "lenient", see read_file
+    let (mol2_list, skipped) = read_file(
+        filename,
+        &ReadOptions::from_args(desc, comment, compression, 1, mode, false)?,
+    )?;
+    warn_skipped(py, &skipped)?;
     let mut result: Vec<PyObject> = Vec::new();
     for entry in &mol2_list {
-        result.push(
-            entry
-                .serialized()
-                .expect("Failed to serialize mol2 entry..."),
-        );
+        result.push(entry.serialized()?);
     }
     Ok(result)
 }
 
+#[pyfunction(
+    filename,
+    desc = "\"\"",
+    comment = "\"\"",
+    compression = "\"\"",
+    mode = "\"strict\"",
+    keep_source = "false"
+)]
+#[pyo3(name = "read_file_iter")]
+fn py_read_file_iter(
+    filename: &str,
+    desc: &str,
+    comment: &str,
+    compression: &str,
+    mode: &str,
+    keep_source: bool,
+) -> PyResult<Mol2Reader> {
+    Ok(read_file_iter(
+        filename,
+        &ReadOptions::from_args(desc, comment, compression, 1, mode, keep_source)?,
+    )?)
+}
+
 #[pymodule]
 fn serde_mol2(_py: Python, m: &PyModule) -> PyResult<()> {
     // Define a python module.
@@ -1087,16 +6097,62 @@ fn serde_mol2(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<Atom>()?;
     m.add_class::<Bond>()?;
     m.add_class::<Substructure>()?;
+    m.add_class::<AltType>()?;
+    m.add_class::<Crysin>()?;
+    m.add_class::<residues::Residue>()?;
+    m.add_class::<conformers::Conformers>()?;
     m.add_class::<Mol2>()?;
+    m.add_class::<Mol2Reader>()?;
+    m.add_class::<DbReader>()?;
+    m.add_class::<Mol2Db>()?;
+    m.add_class::<Mol2Row>()?;
+    m.add_class::<SectionIter>()?;
+    m.add_class::<Violation>()?;
+    m.add_class::<diff::Difference>()?;
+    m.add_class::<SkippedMolecule>()?;
     m.add_wrapped(wrap_pyfunction!(py_read_file))?;
     m.add_wrapped(wrap_pyfunction!(read_file_serialized))?;
+    m.add_wrapped(wrap_pyfunction!(py_read_file_iter))?;
+    m.add_wrapped(wrap_pyfunction!(py_db_pragmas))?;
     m.add_wrapped(wrap_pyfunction!(py_db_insert))?;
+    m.add_wrapped(wrap_pyfunction!(py_db_update))?;
+    m.add_wrapped(wrap_pyfunction!(py_db_train_dictionary))?;
+    m.add_wrapped(wrap_pyfunction!(py_db_migrate))?;
+    m.add_wrapped(wrap_pyfunction!(py_db_tmp_dir))?;
+    m.add_wrapped(wrap_pyfunction!(py_db_add_property))?;
+    m.add_wrapped(wrap_pyfunction!(py_db_set_property))?;
+    m.add_wrapped(wrap_pyfunction!(py_db_properties))?;
+    m.add_wrapped(wrap_pyfunction!(py_db_tag))?;
+    m.add_wrapped(wrap_pyfunction!(py_db_untag))?;
+    m.add_wrapped(wrap_pyfunction!(py_db_merge))?;
     m.add_wrapped(wrap_pyfunction!(py_read_db_all))?;
+    m.add_wrapped(wrap_pyfunction!(py_read_db_rows))?;
+    m.add_wrapped(wrap_pyfunction!(py_read_db_iter))?;
+    m.add_wrapped(wrap_pyfunction!(py_read_db_ids))?;
+    m.add_wrapped(wrap_pyfunction!(py_read_db_provenance))?;
     m.add_wrapped(wrap_pyfunction!(read_db_all_serialized))?;
     m.add_wrapped(wrap_pyfunction!(py_read_file_to_db))?;
     m.add_wrapped(wrap_pyfunction!(py_read_file_to_db_batch))?;
-    m.add_wrapped(wrap_pyfunction!(write_mol2))?;
-    m.add_wrapped(wrap_pyfunction!(desc_list))?;
+    m.add_wrapped(wrap_pyfunction!(py_write_mol2))?;
+    m.add_wrapped(wrap_pyfunction!(py_write_ndjson))?;
+    m.add_wrapped(wrap_pyfunction!(py_mol2_json_schema))?;
+    m.add_wrapped(wrap_pyfunction!(py_deduplicate))?;
+    m.add_wrapped(wrap_pyfunction!(py_collapse_conformers))?;
+    #[cfg(feature = "arrow")]
+    m.add_wrapped(wrap_pyfunction!(py_to_arrow))?;
+    #[cfg(feature = "hdf5")]
+    m.add_wrapped(wrap_pyfunction!(py_write_hdf5))?;
+    #[cfg(feature = "npz")]
+    m.add_wrapped(wrap_pyfunction!(py_write_npz))?;
+    m.add_wrapped(wrap_pyfunction!(py_read_sdf))?;
+    m.add_wrapped(wrap_pyfunction!(py_write_sdf))?;
+    m.add_wrapped(wrap_pyfunction!(py_read_pdb))?;
+    m.add_wrapped(wrap_pyfunction!(py_write_pdb))?;
+    m.add_wrapped(wrap_pyfunction!(py_read_xyz))?;
+    m.add_wrapped(wrap_pyfunction!(py_write_xyz))?;
+    m.add_wrapped(wrap_pyfunction!(py_desc_list))?;
+    m.add_wrapped(wrap_pyfunction!(py_distinct_values))?;
+    m.add_wrapped(wrap_pyfunction!(py_db_count))?;
 
     Ok(())
 }
@@ -0,0 +1,136 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::error::Result;
+use crate::Mol2;
+use std::io::Write;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+fn npy_header(descr: &str, shape: &[usize]) -> Vec<u8> {
+    // Header of a version 1.0 npy file, padded so that the data starts at a
+    // multiple of 64 bytes like numpy does
+    // Input:
+    //     descr: numpy type description, e.g. "<f8"
+    //     shape: dimensions of the array
+    let shape = match shape {
+        [length] => format!("({},)", length),
+        _ => format!(
+            "({})",
+            shape
+                .iter()
+                .map(|dim| dim.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+    };
+    let mut dict = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        descr, shape
+    );
+    // magic, version and header length take 10 bytes, the header ends with a newline
+    let padding = (64 - (10 + dict.len() + 1) % 64) % 64;
+    dict.push_str(&" ".repeat(padding));
+    dict.push('\n');
+    let mut header = b"\x93NUMPY\x01\x00".to_vec();
+    header.extend_from_slice(&(dict.len() as u16).to_le_bytes());
+    header.extend_from_slice(dict.as_bytes());
+    header
+}
+
+fn write_npy<W: Write + std::io::Seek>(
+    archive: &mut ZipWriter<W>,
+    name: &str,
+    descr: &str,
+    shape: &[usize],
+    data: &[u8],
+) -> Result<()> {
+    // Add a single array to the archive
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    archive.start_file(format!("{}.npy", name), options)?;
+    archive.write_all(&npy_header(descr, shape))?;
+    archive.write_all(data)?;
+    Ok(())
+}
+
+pub fn write_npz(mol2_list: &[Mol2], filename: &str) -> Result<()> {
+    // Write coordinates, charges and atom types of structures into a
+    // compressed npz archive. Arrays of a structure are prefixed with the
+    // position of the structure in the list, padded with zeros so that they
+    // sort in the same order, e.g. "molecule_0/coordinates".
+    // Input:
+    //     mol2_list: structures to write
+    //     filename: path to the npz file, an existing file is overwritten
+    let mut archive = ZipWriter::new(std::fs::File::create(filename)?);
+    let width = mol2_list.len().saturating_sub(1).to_string().len();
+    for (index, mol2) in mol2_list.iter().enumerate() {
+        let prefix = format!("molecule_{:0width$}", index, width = width);
+        let num_atoms = mol2.atom.len();
+
+        let coordinates: Vec<u8> = mol2
+            .atom
+            .iter()
+            .flat_map(|atom| [atom.x, atom.y, atom.z])
+            .flat_map(f64::to_le_bytes)
+            .collect();
+        write_npy(
+            &mut archive,
+            &format!("{}/coordinates", prefix),
+            "<f8",
+            &[num_atoms, 3],
+            &coordinates,
+        )?;
+
+        // Missing charges are stored as NaN
+        let charges: Vec<u8> = mol2
+            .atom
+            .iter()
+            .flat_map(|atom| atom.charge.unwrap_or(f32::NAN).to_le_bytes())
+            .collect();
+        write_npy(
+            &mut archive,
+            &format!("{}/charges", prefix),
+            "<f4",
+            &[num_atoms],
+            &charges,
+        )?;
+
+        // Fixed width unicode strings, so that no pickling is needed to load them
+        let width = mol2
+            .atom
+            .iter()
+            .map(|atom| atom.atom_type.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        let mut atom_types = Vec::with_capacity(num_atoms * width * 4);
+        for atom in &mol2.atom {
+            let length = atom.atom_type.chars().count();
+            for character in atom.atom_type.chars() {
+                atom_types.extend_from_slice(&(character as u32).to_le_bytes());
+            }
+            atom_types.resize(atom_types.len() + (width - length) * 4, 0);
+        }
+        write_npy(
+            &mut archive,
+            &format!("{}/atom_types", prefix),
+            &format!("<U{}", width),
+            &[num_atoms],
+            &atom_types,
+        )?;
+    }
+    archive.finish()?;
+    Ok(())
+}
@@ -0,0 +1,494 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::error::{Mol2Error, Result};
+use crate::sdf::element;
+use crate::{Atom, Bond, CoordFloat, IdInt, Mol2, Molecule, Substructure};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+
+pub fn residue_name(subst_name: &str) -> &str {
+    // Residue name of a substructure name, e.g. "SER" for "SER139"
+    let name = subst_name.trim_end_matches(|c: char| c.is_ascii_digit());
+    if name.is_empty() {
+        subst_name
+    } else {
+        name
+    }
+}
+
+fn residue_number(subst_name: &str, subst_id: IdInt) -> String {
+    // Residue number of a substructure name, e.g. "139" for "SER139",
+    // the substructure id if the name does not end with a number
+    let name = residue_name(subst_name);
+    match &subst_name[name.len()..] {
+        "" => subst_id.to_string(),
+        number => number.to_owned(),
+    }
+}
+
+fn atom_name(name: &str, element: &str) -> String {
+    // Atom name in the four columns of a PDB record, names of single
+    // letter elements start in the second column, e.g. " CA " but "MG  "
+    if element.len() == 1 && name.len() < 4 {
+        format!(" {:<3}", name)
+    } else {
+        format!("{:<4}", name.chars().take(4).collect::<String>())
+    }
+}
+
+fn atom_records(mol2: &Mol2) -> Result<Vec<(String, bool)>> {
+    // ATOM and HETATM records up to the temperature factor, shared by PDB
+    // and PDBQT, and whether each of them is a HETATM record
+    // Input:
+    //     mol2: structure to write
+    let molecule = mol2.molecule.as_ref().ok_or_else(|| {
+        Mol2Error::InvalidArgument("Cannot write a structure without a MOLECULE section".to_owned())
+    })?;
+    let substructures: HashMap<IdInt, &Substructure> = mol2
+        .substructure
+        .iter()
+        .map(|substructure| (substructure.subst_id, substructure))
+        .collect();
+
+    // Only residues of biopolymers are written as ATOM records
+    let biopolymer = matches!(
+        molecule.mol_type.as_deref(),
+        Some("PROTEIN" | "NUCLEIC_ACID" | "BIOPOLYMER")
+    );
+
+    let mut records: Vec<(String, bool)> = Vec::new();
+    for (index, atom) in mol2.atom.iter().enumerate() {
+        let substructure = atom.subst_id.and_then(|id| substructures.get(&id));
+        let subst_name = atom.subst_name.as_deref().unwrap_or("UNL");
+        let subst_id = atom.subst_id.unwrap_or(1);
+        let (record, name, number, chain) = match substructure {
+            Some(substructure) => (
+                match substructure.subst_type.as_deref() {
+                    Some("RESIDUE") if biopolymer => "ATOM",
+                    _ => "HETATM",
+                },
+                match substructure.sub_type.as_deref() {
+                    Some(sub_type) if sub_type != "****" => sub_type,
+                    _ => residue_name(&substructure.subst_name),
+                },
+                residue_number(&substructure.subst_name, substructure.subst_id),
+                match substructure.chain.as_deref() {
+                    Some(chain) if chain != "****" => chain,
+                    _ => " ",
+                },
+            ),
+            None => (
+                "HETATM",
+                residue_name(subst_name),
+                residue_number(subst_name, subst_id),
+                " ",
+            ),
+        };
+        records.push((
+            format!(
+                "{:<6}{:>5} {} {:>3} {:1}{:>4}    {:>8.3}{:>8.3}{:>8.3}{:>6.2}{:>6.2}",
+                record,
+                index + 1,
+                atom_name(&atom.atom_name, pdb_element(&atom.atom_type)),
+                name.chars().take(3).collect::<String>(),
+                chain.chars().next().unwrap_or(' '),
+                number.chars().take(4).collect::<String>(),
+                atom.x,
+                atom.y,
+                atom.z,
+                1.0,
+                0.0,
+            ),
+            record == "HETATM",
+        ));
+    }
+
+    Ok(records)
+}
+
+fn pdb_element(atom_type: &str) -> &str {
+    // Element of a SYBYL atom type, empty for atoms without an element
+    match element(atom_type) {
+        "*" => "",
+        symbol => symbol,
+    }
+}
+
+pub fn write_pdb_entry<W: Write>(mol2: &Mol2, writer: &mut W) -> Result<()> {
+    // Write a structure as PDB records, substructures become residues
+    // Input:
+    //     mol2: structure to write
+    //     writer: where to write to
+    let records = atom_records(mol2)?;
+    // Atoms are numbered by their position, counted from 1
+    let serials: HashMap<IdInt, usize> = mol2
+        .atom
+        .iter()
+        .enumerate()
+        .map(|(index, atom)| (atom.atom_id, index + 1))
+        .collect();
+
+    if let Some(molecule) = &mol2.molecule {
+        writeln!(writer, "COMPND    {}", molecule.mol_name)?;
+    }
+    let mut hetatm: Vec<bool> = Vec::new();
+    for ((record, is_hetatm), atom) in records.iter().zip(&mol2.atom) {
+        hetatm.push(*is_hetatm);
+        writeln!(
+            writer,
+            "{}          {:>2}",
+            record,
+            pdb_element(&atom.atom_type).to_uppercase()
+        )?;
+    }
+
+    // Connectivity is only written for bonds involving HETATM records,
+    // bonds within and between residues follow from the residue names
+    let mut connections: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for bond in &mol2.bond {
+        if let (Some(&origin), Some(&target)) = (
+            serials.get(&bond.origin_atom_id),
+            serials.get(&bond.target_atom_id),
+        ) {
+            if bond.bond_type != "nc" && (hetatm[origin - 1] || hetatm[target - 1]) {
+                connections.entry(origin).or_default().push(target);
+                connections.entry(target).or_default().push(origin);
+            }
+        }
+    }
+    for (serial, bonded) in &connections {
+        // At most four bonded atoms fit on a single CONECT record
+        for chunk in bonded.chunks(4) {
+            write!(writer, "CONECT{:>5}", serial)?;
+            for other in chunk {
+                write!(writer, "{:>5}", other)?;
+            }
+            writeln!(writer)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Residue being read, atoms of consecutive records with the same key
+// belong to the same substructure
+#[derive(PartialEq)]
+struct ResidueKey {
+    chain: String,
+    number: String,
+    insertion: String,
+    name: String,
+}
+
+// Structure being read, a file has one per MODEL
+struct PdbModel {
+    mol2: Mol2,
+    name: String,
+    residue: Option<ResidueKey>,
+    // Atom ids of atom serial numbers, used by CONECT records
+    serials: HashMap<String, IdInt>,
+    // Bonded pairs of atom ids, smaller id first
+    bonded: HashSet<(IdInt, IdInt)>,
+    has_atom_records: bool,
+}
+
+fn field(line: &str, start: usize, end: usize) -> &str {
+    // Fixed-width column of a PDB record, empty if the line is too short
+    line.get(start..end.min(line.len()))
+        .or_else(|| line.get(start..))
+        .unwrap_or("")
+        .trim()
+}
+
+fn parse_error(line: usize, message: String) -> Mol2Error {
+    Mol2Error::Parse { line, message }
+}
+
+fn element_symbol(line: &str) -> String {
+    // Element of an atom record, from the element columns or, when those
+    // are missing, from the atom name
+    let symbol = match field(line, 76, 78) {
+        "" => {
+            let name = line.get(12..16).unwrap_or("");
+            let letters: String = name
+                .trim()
+                .chars()
+                .take_while(|c| c.is_ascii_alphabetic())
+                .collect();
+            // Names of single letter elements start in the second column
+            match name.starts_with(' ') {
+                true => letters.chars().take(1).collect(),
+                false => letters.chars().take(2).collect(),
+            }
+        }
+        symbol => symbol.to_owned(),
+    };
+    let mut chars = symbol.chars();
+    match chars.next() {
+        Some(first) => {
+            first.to_ascii_uppercase().to_string() + &chars.as_str().to_ascii_lowercase()
+        }
+        None => "Du".to_owned(),
+    }
+}
+
+impl PdbModel {
+    fn new(desc: &str) -> PdbModel {
+        PdbModel {
+            mol2: Mol2::new(desc),
+            name: String::new(),
+            residue: None,
+            serials: HashMap::new(),
+            bonded: HashSet::new(),
+            has_atom_records: false,
+        }
+    }
+    fn read_atom(&mut self, number: usize, line: &str) -> Result<()> {
+        // Add an ATOM or HETATM record to the structure
+        // Input:
+        //     number: line number, for error messages
+        //     line: the record
+        // Only the first alternate location of an atom is kept
+        if !matches!(field(line, 16, 17), "" | "A") {
+            return Ok(());
+        }
+        let coordinate = |start: usize, name: &str| -> Result<CoordFloat> {
+            let word = field(line, start, start + 8);
+            word.parse::<CoordFloat>().map_err(|_| {
+                parse_error(
+                    number,
+                    format!("Failed to parse {} from \"{}\"", name, word),
+                )
+            })
+        };
+        let (x, y, z) = (
+            coordinate(30, "x coordinate")?,
+            coordinate(38, "y coordinate")?,
+            coordinate(46, "z coordinate")?,
+        );
+        let atom_id = IdInt::try_from(self.mol2.atom.len() + 1)
+            .map_err(|_| parse_error(number, "Too many atoms in a single model".to_owned()))?;
+        let key = ResidueKey {
+            chain: field(line, 21, 22).to_owned(),
+            number: field(line, 22, 26).to_owned(),
+            insertion: field(line, 26, 27).to_owned(),
+            name: field(line, 17, 21).to_owned(),
+        };
+        let subst_name = format!("{}{}{}", key.name, key.number, key.insertion);
+        let polymer = line.starts_with("ATOM");
+        self.has_atom_records |= polymer;
+        if self.residue.as_ref() != Some(&key) {
+            self.mol2.substructure.push(Substructure {
+                subst_id: (self.mol2.substructure.len() + 1) as IdInt,
+                subst_name: subst_name.clone(),
+                root_atom: atom_id,
+                subst_type: Some(if polymer { "RESIDUE" } else { "GROUP" }.to_owned()),
+                dict_type: Some(polymer as i64),
+                chain: Some(match key.chain.as_str() {
+                    "" => "****".to_owned(),
+                    chain => chain.to_owned(),
+                }),
+                sub_type: Some(key.name.clone()),
+                inter_bonds: None,
+                status: None,
+                comment: None,
+            });
+            self.residue = Some(key);
+        }
+        self.serials.insert(field(line, 6, 11).to_owned(), atom_id);
+        self.mol2.atom.push(Atom {
+            atom_id,
+            atom_name: field(line, 12, 16).to_owned(),
+            x,
+            y,
+            z,
+            atom_type: element_symbol(line),
+            subst_id: Some(self.mol2.substructure.len() as IdInt),
+            subst_name: Some(subst_name),
+            charge: None,
+            status_bit: None,
+        });
+        Ok(())
+    }
+
+    fn read_conect(&mut self, line: &str) {
+        // Add bonds of a CONECT record, bonds listed twice are added once
+        let origin = match self.serials.get(field(line, 6, 11)) {
+            Some(&origin) => origin,
+            None => return,
+        };
+        for start in [11, 16, 21, 26] {
+            let target = match self.serials.get(field(line, start, start + 5)) {
+                Some(&target) => target,
+                None => continue,
+            };
+            if origin != target && self.bonded.insert((origin.min(target), origin.max(target))) {
+                self.mol2.bond.push(Bond {
+                    bond_id: (self.mol2.bond.len() + 1) as IdInt,
+                    origin_atom_id: origin,
+                    target_atom_id: target,
+                    bond_type: "1".to_owned(),
+                    status_bit: None,
+                });
+            }
+        }
+    }
+
+    fn finish(self, comment: &str) -> Mol2 {
+        // Fill in the MOLECULE section once all records are read
+        let mut mol2 = self.mol2;
+        mol2.molecule = Some(Molecule {
+            mol_name: self.name,
+            num_atoms: Some(mol2.atom.len()),
+            num_bonds: Some(mol2.bond.len()),
+            num_subst: Some(mol2.substructure.len()),
+            num_feat: None,
+            num_sets: None,
+            mol_type: Some(
+                if self.has_atom_records {
+                    "PROTEIN"
+                } else {
+                    "SMALL"
+                }
+                .to_owned(),
+            ),
+            charge_type: Some("NO_CHARGES".to_owned()),
+            status_bits: None,
+            mol_comment: None,
+        });
+        mol2.add_comment(comment);
+        mol2
+    }
+}
+
+pub fn read_pdb_text(
+    text: &str,
+    name: &str,
+    desc: &str,
+    comment: &str,
+    conect: bool,
+) -> Result<Vec<Mol2>> {
+    // Translate PDB records into structures, one per MODEL
+    // Input:
+    //     text: content of a PDB file
+    //     name: molecule name used when there is no HEADER or COMPND record
+    //     desc: description added to all structures
+    //     comment: comment added to the molecule comment field
+    //     conect: add bonds from CONECT records
+    let mut models: Vec<PdbModel> = Vec::new();
+    let mut name = name.to_owned();
+    let mut model = PdbModel::new(desc);
+    for (index, line) in text.lines().enumerate() {
+        match line.get(0..6).unwrap_or(line).trim_end() {
+            "HEADER" if !field(line, 62, 66).is_empty() => name = field(line, 62, 66).to_owned(),
+            // Only COMPND records written by to_pdb have the plain name
+            "COMPND" if !line.contains(':') => name = field(line, 10, 80).to_owned(),
+            "ATOM" | "HETATM" => model.read_atom(index + 1, line)?,
+            // CONECT records usually follow all models, their bonds are
+            // then added to every structure of the file
+            "CONECT" if conect && model.mol2.atom.is_empty() => {
+                for finished in models.iter_mut() {
+                    finished.read_conect(line);
+                }
+            }
+            "CONECT" if conect => model.read_conect(line),
+            "ENDMDL" | "END" if !model.mol2.atom.is_empty() => {
+                model.name = name.clone();
+                models.push(std::mem::replace(&mut model, PdbModel::new(desc)));
+            }
+            _ => (),
+        }
+    }
+    if !model.mol2.atom.is_empty() {
+        model.name = name;
+        models.push(model);
+    }
+
+    Ok(models
+        .into_iter()
+        .map(|model| model.finish(comment))
+        .collect())
+}
+
+fn ad4_type(atom_type: &str, polar_hydrogen: bool) -> String {
+    // Default AutoDock 4 type of a SYBYL atom type
+    // Input:
+    //     atom_type: SYBYL atom type
+    //     polar_hydrogen: is the atom a hydrogen bonded to a nitrogen or an oxygen?
+    match atom_type {
+        "C.ar" => "A",
+        // Nitrogens without room for a hydrogen are acceptors
+        "N.ar" | "N.1" | "N.2" => "NA",
+        "H" if polar_hydrogen => "HD",
+        _ => match element(atom_type) {
+            "O" => "OA",
+            "S" => "SA",
+            "*" => "X",
+            symbol => symbol,
+        },
+    }
+    .to_owned()
+}
+
+pub fn write_pdbqt_entry<W: Write>(
+    mol2: &Mol2,
+    writer: &mut W,
+    type_map: &HashMap<String, String>,
+) -> Result<()> {
+    // Write a structure as PDBQT records with partial charges and AutoDock atom types
+    // Input:
+    //     mol2: structure to write
+    //     writer: where to write to
+    //     type_map: AutoDock types of SYBYL atom types, replacing the default ones
+    let records = atom_records(mol2)?;
+    let elements: HashMap<IdInt, &str> = mol2
+        .atom
+        .iter()
+        .map(|atom| (atom.atom_id, element(&atom.atom_type)))
+        .collect();
+    let mut polar_hydrogens: HashSet<IdInt> = HashSet::new();
+    for bond in &mol2.bond {
+        for (hydrogen, other) in [
+            (bond.origin_atom_id, bond.target_atom_id),
+            (bond.target_atom_id, bond.origin_atom_id),
+        ] {
+            if elements.get(&hydrogen) == Some(&"H")
+                && matches!(elements.get(&other), Some(&"N") | Some(&"O"))
+            {
+                polar_hydrogens.insert(hydrogen);
+            }
+        }
+    }
+
+    if let Some(molecule) = &mol2.molecule {
+        writeln!(writer, "REMARK  Name = {}", molecule.mol_name)?;
+    }
+    for ((record, _), atom) in records.iter().zip(&mol2.atom) {
+        let ad_type = match type_map.get(&atom.atom_type) {
+            Some(ad_type) => ad_type.to_owned(),
+            None => ad4_type(&atom.atom_type, polar_hydrogens.contains(&atom.atom_id)),
+        };
+        writeln!(
+            writer,
+            "{}    {:>+6.3} {:<2}",
+            record,
+            atom.charge.unwrap_or(0.0),
+            ad_type
+        )?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,159 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::pdb::residue_name;
+use crate::{Atom, IdInt, Mol2};
+use pyo3::prelude::*;
+use std::collections::HashMap;
+
+// One letter codes of amino acids, including common protonation variants
+const ONE_LETTER: &[(&str, char)] = &[
+    ("ALA", 'A'),
+    ("ARG", 'R'),
+    ("ASN", 'N'),
+    ("ASP", 'D'),
+    ("ASH", 'D'),
+    ("CYS", 'C'),
+    ("CYX", 'C'),
+    ("CYM", 'C'),
+    ("GLN", 'Q'),
+    ("GLU", 'E'),
+    ("GLH", 'E'),
+    ("GLY", 'G'),
+    ("HIS", 'H'),
+    ("HID", 'H'),
+    ("HIE", 'H'),
+    ("HIP", 'H'),
+    ("HSD", 'H'),
+    ("HSE", 'H'),
+    ("HSP", 'H'),
+    ("ILE", 'I'),
+    ("LEU", 'L'),
+    ("LYS", 'K'),
+    ("LYN", 'K'),
+    ("MET", 'M'),
+    ("MSE", 'M'),
+    ("PHE", 'F'),
+    ("PRO", 'P'),
+    ("SER", 'S'),
+    ("THR", 'T'),
+    ("TRP", 'W'),
+    ("TYR", 'Y'),
+    ("VAL", 'V'),
+    ("SEC", 'U'),
+    ("PYL", 'O'),
+];
+
+// Atoms of a single substructure, e.g. an amino acid of a protein chain
+#[pyclass]
+#[derive(Debug, Clone)]
+pub struct Residue {
+    #[pyo3(get)]
+    pub subst_id: IdInt,
+    // Substructure name, e.g. SER139
+    #[pyo3(get)]
+    pub name: String,
+    // Substructure name without the residue number, e.g. SER
+    #[pyo3(get)]
+    pub residue_name: String,
+    #[pyo3(get)]
+    pub chain: Option<String>,
+    #[pyo3(get)]
+    pub atoms: Vec<Atom>,
+}
+
+#[pymethods]
+impl Residue {
+    fn __len__(&self) -> usize {
+        self.atoms.len()
+    }
+    pub fn atom_ids(&self) -> Vec<IdInt> {
+        // Ids of the atoms of the residue
+        self.atoms.iter().map(|atom| atom.atom_id).collect()
+    }
+    pub fn one_letter(&self) -> Option<char> {
+        // One letter code of an amino acid, None for other residues
+        one_letter(&self.residue_name)
+    }
+}
+
+fn one_letter(residue_name: &str) -> Option<char> {
+    ONE_LETTER
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(residue_name))
+        .map(|(_, code)| *code)
+}
+
+pub fn residues(mol2: &Mol2) -> Vec<Residue> {
+    // Atoms grouped by substructure id, in the order the substructures first
+    // appear among the atoms. Atoms without a substructure id are left out.
+    // Names and chains come from the SUBSTRUCTURE section, or from the atoms
+    // for substructures missing from it.
+    let substructures: HashMap<IdInt, (&str, Option<&String>)> = mol2
+        .substructure
+        .iter()
+        .map(|substructure| {
+            (
+                substructure.subst_id,
+                (&substructure.subst_name[..], substructure.chain.as_ref()),
+            )
+        })
+        .collect();
+    let mut residues: Vec<Residue> = Vec::new();
+    let mut positions: HashMap<IdInt, usize> = HashMap::new();
+    for atom in &mol2.atom {
+        let subst_id = match atom.subst_id {
+            Some(subst_id) => subst_id,
+            None => continue,
+        };
+        let position = *positions.entry(subst_id).or_insert_with(|| {
+            let (name, chain) = match substructures.get(&subst_id) {
+                Some((name, chain)) => (name.to_string(), chain.cloned()),
+                None => (atom.subst_name.clone().unwrap_or_default(), None),
+            };
+            residues.push(Residue {
+                subst_id,
+                residue_name: residue_name(&name).to_owned(),
+                name,
+                chain,
+                atoms: Vec::new(),
+            });
+            residues.len() - 1
+        });
+        residues[position].atoms.push(atom.clone());
+    }
+    residues
+}
+
+pub fn sequences(mol2: &Mol2) -> Vec<(Option<String>, String)> {
+    // One letter amino acid sequence of every chain, in the order the chains
+    // first appear. Residues other than amino acids are left out, so are
+    // chains without any.
+    let mut sequences: Vec<(Option<String>, String)> = Vec::new();
+    for residue in residues(mol2) {
+        let code = match one_letter(&residue.residue_name) {
+            Some(code) => code,
+            None => continue,
+        };
+        match sequences
+            .iter_mut()
+            .find(|(chain, _)| *chain == residue.chain)
+        {
+            Some((_, sequence)) => sequence.push(code),
+            None => sequences.push((residue.chain, code.to_string())),
+        }
+    }
+    sequences
+}
@@ -0,0 +1,559 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::error::{Mol2Error, Result};
+use crate::{Atom, Bond, CoordFloat, IdInt, Mol2, Molecule};
+use std::collections::HashMap;
+use std::io::Write;
+
+// Counts lines of V2000 molfiles have three digits for atoms and bonds
+static V2000_MAX_ENTRIES: usize = 999;
+
+pub(crate) fn element(atom_type: &str) -> &str {
+    // Element symbol of a SYBYL atom type, e.g. "C" for "C.ar"
+    // Input:
+    //     atom_type: SYBYL atom type
+    match atom_type.split('.').next().unwrap_or("") {
+        // Dummy atoms, lone pairs and generic types have no element
+        "" | "Du" | "LP" | "Any" | "Hal" | "Het" | "Hev" => "*",
+        symbol => symbol,
+    }
+}
+
+fn bond_order(bond_type: &str) -> Option<u8> {
+    // MDL bond order of a SYBYL bond type, None for bonds that are not written
+    // Input:
+    //     bond_type: SYBYL bond type
+    match bond_type {
+        "1" | "am" => Some(1),
+        "2" => Some(2),
+        "3" => Some(3),
+        "ar" => Some(4),
+        // Not connected
+        "nc" => None,
+        // "du", "un" and anything unexpected
+        _ => Some(8),
+    }
+}
+
+fn write_data_field<W: Write>(writer: &mut W, name: &str, value: &Option<String>) -> Result<()> {
+    // Write an SDF data item, nothing if there is no value
+    if let Some(value) = value {
+        // Blank lines would end the data item early
+        let value = value
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .collect::<Vec<&str>>()
+            .join("\n");
+        write!(writer, "> <{}>\n{}\n\n", name, value)?;
+    }
+    Ok(())
+}
+
+fn write_v2000_ctab<W: Write>(
+    mol2: &Mol2,
+    bonds: &[(usize, usize, u8)],
+    writer: &mut W,
+) -> Result<()> {
+    // Write the counts line, atom and bond blocks of a V2000 molfile
+    // Input:
+    //     mol2: structure to write
+    //     bonds: bonds as positions of atoms and MDL bond orders
+    //     writer: where to write to
+    writeln!(
+        writer,
+        "{:>3}{:>3}  0  0  0  0  0  0  0  0999 V2000",
+        mol2.atom.len(),
+        bonds.len()
+    )?;
+    for atom in &mol2.atom {
+        writeln!(
+            writer,
+            "{:>10.4}{:>10.4}{:>10.4} {:<3} 0  0  0  0  0  0  0  0  0  0  0  0",
+            atom.x,
+            atom.y,
+            atom.z,
+            element(&atom.atom_type)
+        )?;
+    }
+    for (origin, target, order) in bonds {
+        writeln!(writer, "{:>3}{:>3}{:>3}  0", origin, target, order)?;
+    }
+    Ok(())
+}
+
+fn write_v3000_ctab<W: Write>(
+    mol2: &Mol2,
+    bonds: &[(usize, usize, u8)],
+    writer: &mut W,
+) -> Result<()> {
+    // Write the empty counts line and the CTAB block of a V3000 molfile
+    // Input:
+    //     mol2: structure to write
+    //     bonds: bonds as positions of atoms and MDL bond orders
+    //     writer: where to write to
+    writeln!(writer, "  0  0  0     0  0            999 V3000")?;
+    writeln!(writer, "M  V30 BEGIN CTAB")?;
+    writeln!(
+        writer,
+        "M  V30 COUNTS {} {} 0 0 0",
+        mol2.atom.len(),
+        bonds.len()
+    )?;
+    writeln!(writer, "M  V30 BEGIN ATOM")?;
+    for (index, atom) in mol2.atom.iter().enumerate() {
+        writeln!(
+            writer,
+            "M  V30 {} {} {:.4} {:.4} {:.4} 0",
+            index + 1,
+            element(&atom.atom_type),
+            atom.x,
+            atom.y,
+            atom.z
+        )?;
+    }
+    writeln!(writer, "M  V30 END ATOM")?;
+    if !bonds.is_empty() {
+        writeln!(writer, "M  V30 BEGIN BOND")?;
+        for (index, (origin, target, order)) in bonds.iter().enumerate() {
+            writeln!(
+                writer,
+                "M  V30 {} {} {} {}",
+                index + 1,
+                order,
+                origin,
+                target
+            )?;
+        }
+        writeln!(writer, "M  V30 END BOND")?;
+    }
+    writeln!(writer, "M  V30 END CTAB")?;
+    Ok(())
+}
+
+pub fn write_sdf_entry<W: Write>(mol2: &Mol2, writer: &mut W) -> Result<()> {
+    // Write a structure as a single SDF record, with a V2000 molfile when
+    // it fits and a V3000 molfile for larger structures
+    // Input:
+    //     mol2: structure to write
+    //     writer: where to write to
+    let molecule = mol2.molecule.as_ref().ok_or_else(|| {
+        Mol2Error::InvalidArgument("Cannot write a structure without a MOLECULE section".to_owned())
+    })?;
+    // Bonds refer to atoms by their position in the atom block, counted from 1
+    let positions: HashMap<IdInt, usize> = mol2
+        .atom
+        .iter()
+        .enumerate()
+        .map(|(index, atom)| (atom.atom_id, index + 1))
+        .collect();
+    let position = |atom_id: IdInt| {
+        positions.get(&atom_id).copied().ok_or_else(|| {
+            Mol2Error::InvalidArgument(format!(
+                "{}: bond references a missing atom {}",
+                molecule.mol_name, atom_id
+            ))
+        })
+    };
+    let bonds: Vec<(usize, usize, u8)> = mol2
+        .bond
+        .iter()
+        .filter_map(|bond| bond_order(&bond.bond_type).map(|order| (bond, order)))
+        .map(|(bond, order)| {
+            Ok((
+                position(bond.origin_atom_id)?,
+                position(bond.target_atom_id)?,
+                order,
+            ))
+        })
+        .collect::<Result<Vec<(usize, usize, u8)>>>()?;
+    // Header block, the name line is limited to 80 characters
+    let name: String = molecule.mol_name.chars().take(80).collect();
+    writeln!(writer, "{}", name)?;
+    writeln!(writer, "  serdemol          3D")?;
+    writeln!(writer)?;
+    if mol2.atom.len() > V2000_MAX_ENTRIES || bonds.len() > V2000_MAX_ENTRIES {
+        write_v3000_ctab(mol2, &bonds, writer)?;
+    } else {
+        write_v2000_ctab(mol2, &bonds, writer)?;
+    }
+    writeln!(writer, "M  END")?;
+    write_data_field(writer, "desc", &mol2.desc)?;
+    write_data_field(writer, "mol_comment", &molecule.mol_comment)?;
+    writeln!(writer, "$$$$")?;
+
+    Ok(())
+}
+
+// Lines of a single SDF record together with their line numbers in the file
+type Record<'a> = Vec<(usize, &'a str)>;
+
+pub fn read_sdf_text(text: &str, desc: &str, comment: &str) -> Result<Vec<Mol2>> {
+    // Translate SDF records into structures
+    // Input:
+    //     text: content of an SDF file
+    //     desc: description added to all structures, if empty taken from the desc data field
+    //     comment: comment added to the molecule comment field
+    let mut mol2_list: Vec<Mol2> = Vec::new();
+    let mut record: Record = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        if line.trim_end() == "$$$$" {
+            mol2_list.push(read_record(&record, desc, comment)?);
+            record.clear();
+        } else {
+            record.push((index + 1, line));
+        }
+    }
+    // The last record does not need to be terminated
+    if record.iter().any(|(_, line)| !line.trim().is_empty()) {
+        mol2_list.push(read_record(&record, desc, comment)?);
+    }
+
+    Ok(mol2_list)
+}
+
+fn field(line: &str, start: usize, end: usize) -> &str {
+    // Fixed-width column of a molfile line, empty if the line is too short
+    line.get(start..end.min(line.len()))
+        .or_else(|| line.get(start..))
+        .unwrap_or("")
+        .trim()
+}
+
+fn parse_error(line: usize, message: String) -> Mol2Error {
+    Mol2Error::Parse { line, message }
+}
+
+fn parse_word<T: std::str::FromStr>(number: usize, word: &str, name: &str) -> Result<T> {
+    // Parse a single value of a molfile line
+    // Input:
+    //     number: line number, for error messages
+    //     word: text of the value
+    //     name: what the value is, for error messages
+    word.parse::<T>().map_err(|_| {
+        parse_error(
+            number,
+            format!("Failed to parse {} from \"{}\"", name, word),
+        )
+    })
+}
+
+fn parse_column<T: std::str::FromStr>(
+    (number, line): (usize, &str),
+    start: usize,
+    end: usize,
+    name: &str,
+) -> Result<T> {
+    // Parse a fixed-width column of a molfile line
+    parse_word(number, field(line, start, end), name)
+}
+
+// Atoms and bonds of a molfile
+#[derive(Default)]
+struct ConnectionTable {
+    elements: Vec<String>,
+    coords: Vec<[CoordFloat; 3]>,
+    // Bonds as positions of atoms, counted from 1, and MDL bond orders
+    bonds: Vec<(usize, usize, u8)>,
+}
+
+fn read_v2000_ctab(record: &[(usize, &str)]) -> Result<(ConnectionTable, usize)> {
+    // Read the atom and bond blocks of a V2000 molfile
+    // Input:
+    //     record: lines of the SDF record, starting with the header block
+    // Returns the connection table and the number of lines it took
+    let last_line = record.last().map(|(number, _)| *number).unwrap_or(0);
+    let counts = record[3];
+    let num_atoms: usize = parse_column(counts, 0, 3, "number of atoms")?;
+    let num_bonds: usize = parse_column(counts, 3, 6, "number of bonds")?;
+    if record.len() < 4 + num_atoms + num_bonds {
+        return Err(parse_error(
+            last_line,
+            format!(
+                "Expected {} atoms and {} bonds in the molfile",
+                num_atoms, num_bonds
+            ),
+        ));
+    }
+
+    let mut ctab = ConnectionTable::default();
+    for &line in &record[4..4 + num_atoms] {
+        ctab.coords.push([
+            parse_column(line, 0, 10, "x coordinate")?,
+            parse_column(line, 10, 20, "y coordinate")?,
+            parse_column(line, 20, 30, "z coordinate")?,
+        ]);
+        ctab.elements.push(field(line.1, 31, 34).to_owned());
+    }
+    for &line in &record[4 + num_atoms..4 + num_atoms + num_bonds] {
+        let origin: usize = parse_column(line, 0, 3, "first bond atom")?;
+        let target: usize = parse_column(line, 3, 6, "second bond atom")?;
+        let order: u8 = parse_column(line, 6, 9, "bond type")?;
+        if origin == 0 || target == 0 || origin > num_atoms || target > num_atoms {
+            return Err(parse_error(
+                line.0,
+                format!("Bond between missing atoms {} and {}", origin, target),
+            ));
+        }
+        ctab.bonds.push((origin, target, order));
+    }
+
+    Ok((ctab, 4 + num_atoms + num_bonds))
+}
+
+fn read_v3000_ctab(record: &[(usize, &str)]) -> Result<(ConnectionTable, usize)> {
+    // Read the CTAB block of a V3000 molfile, other blocks are skipped
+    // Input:
+    //     record: lines of the SDF record, starting with the header block
+    // Returns the connection table and the number of lines it took
+    let last_line = record.last().map(|(number, _)| *number).unwrap_or(0);
+    let mut ctab = ConnectionTable::default();
+    // Atoms are referred to by their index, which does not have to be
+    // their position in the atom block
+    let mut positions: HashMap<usize, usize> = HashMap::new();
+    let mut block = String::new();
+    let mut content = String::new();
+    for (offset, &(number, line)) in record.iter().enumerate().skip(4) {
+        if line.trim_end() == "M  END" {
+            return Ok((ctab, offset + 1));
+        }
+        let part = match line.strip_prefix("M  V30 ") {
+            Some(part) => part.trim_end(),
+            None => continue,
+        };
+        // Lines ending with a dash continue on the next line
+        if let Some(part) = part.strip_suffix('-') {
+            content.push_str(part);
+            continue;
+        }
+        content.push_str(part);
+        let words: Vec<&str> = content.split_whitespace().collect();
+        match words.as_slice() {
+            ["BEGIN", name] => block = name.to_string(),
+            ["END", _] => block.clear(),
+            [index, element, x, y, z, ..] if block == "ATOM" => {
+                let index: usize = parse_word(number, index, "atom index")?;
+                ctab.coords.push([
+                    parse_word(number, x, "x coordinate")?,
+                    parse_word(number, y, "y coordinate")?,
+                    parse_word(number, z, "z coordinate")?,
+                ]);
+                ctab.elements.push(element.to_string());
+                positions.insert(index, ctab.elements.len());
+            }
+            [_, order, origin, target, ..] if block == "BOND" => {
+                let order: u8 = parse_word(number, order, "bond type")?;
+                let position = |word: &str| -> Result<usize> {
+                    let index: usize = parse_word(number, word, "bond atom")?;
+                    positions.get(&index).copied().ok_or_else(|| {
+                        parse_error(number, format!("Bond to a missing atom {}", index))
+                    })
+                };
+                ctab.bonds
+                    .push((position(origin)?, position(target)?, order));
+            }
+            _ if block == "ATOM" || block == "BOND" => {
+                return Err(parse_error(
+                    number,
+                    format!("Incomplete {} line \"{}\"", block, content),
+                ));
+            }
+            _ => (),
+        }
+        content.clear();
+    }
+
+    Err(parse_error(
+        last_line,
+        "Missing M  END in the V3000 molfile".to_owned(),
+    ))
+}
+
+fn read_record(record: &[(usize, &str)], desc: &str, comment: &str) -> Result<Mol2> {
+    // Translate a single SDF record, a molfile followed by data items
+    let last_line = record.last().map(|(number, _)| *number).unwrap_or(0);
+    if record.len() < 4 {
+        return Err(parse_error(
+            last_line,
+            "Incomplete molfile header".to_owned(),
+        ));
+    }
+    let (ctab, length) = if record[3].1.contains("V3000") {
+        read_v3000_ctab(record)?
+    } else {
+        read_v2000_ctab(record)?
+    };
+    let ConnectionTable {
+        elements,
+        coords,
+        bonds,
+    } = ctab;
+    let data = read_data_items(&record[length..]);
+
+    let mut mol2 = Mol2::new(desc);
+    if desc.is_empty() {
+        mol2.desc = data.get("desc").cloned();
+    }
+    let molfile_comment = record[2].1.trim();
+    mol2.molecule = Some(Molecule {
+        mol_name: record[0].1.trim().to_owned(),
+        num_atoms: Some(elements.len()),
+        num_bonds: Some(bonds.len()),
+        num_subst: None,
+        num_feat: None,
+        num_sets: None,
+        mol_type: Some("SMALL".to_owned()),
+        charge_type: Some("NO_CHARGES".to_owned()),
+        status_bits: None,
+        mol_comment: data
+            .get("mol_comment")
+            .cloned()
+            .or_else(|| (!molfile_comment.is_empty()).then(|| molfile_comment.to_owned())),
+    });
+    let mut counters: HashMap<&str, usize> = HashMap::new();
+    for (index, (element, [x, y, z])) in elements.iter().zip(coords).enumerate() {
+        // Atom names are the element followed by a counter, e.g. C1, C2, O1
+        let counter = counters.entry(element.as_str()).or_insert(0);
+        *counter += 1;
+        mol2.atom.push(Atom {
+            atom_id: (index + 1) as IdInt,
+            atom_name: format!("{}{}", element, counter),
+            x,
+            y,
+            z,
+            atom_type: sybyl_atom_type(index + 1, &elements, &bonds),
+            subst_id: None,
+            subst_name: None,
+            charge: None,
+            status_bit: None,
+        });
+    }
+    for (index, (origin, target, order)) in bonds.iter().enumerate() {
+        mol2.bond.push(Bond {
+            bond_id: (index + 1) as IdInt,
+            origin_atom_id: *origin as IdInt,
+            target_atom_id: *target as IdInt,
+            bond_type: sybyl_bond_type(*order).to_owned(),
+            status_bit: None,
+        });
+    }
+    mol2.add_comment(comment);
+
+    Ok(mol2)
+}
+
+fn read_data_items(lines: &[(usize, &str)]) -> HashMap<String, String> {
+    // Data items following the molfile, values of multiple lines are joined
+    // Input:
+    //     lines: record lines after the bond block
+    let mut data: HashMap<String, String> = HashMap::new();
+    let mut name: Option<String> = None;
+    for (_, line) in lines {
+        if line.starts_with('>') {
+            name = line
+                .split_once('<')
+                .and_then(|(_, rest)| rest.split_once('>'))
+                .map(|(name, _)| name.to_owned());
+        } else if line.trim().is_empty() {
+            name = None;
+        } else if let Some(name) = &name {
+            let value = data.entry(name.to_owned()).or_default();
+            if !value.is_empty() {
+                value.push('\n');
+            }
+            value.push_str(line);
+        }
+    }
+    data
+}
+
+pub(crate) fn sybyl_bond_type(order: u8) -> &'static str {
+    // SYBYL bond type of an MDL bond order, query types are unknown
+    match order {
+        1 => "1",
+        2 => "2",
+        3 => "3",
+        4 => "ar",
+        _ => "un",
+    }
+}
+
+pub(crate) fn sybyl_atom_type(
+    atom: usize,
+    elements: &[String],
+    bonds: &[(usize, usize, u8)],
+) -> String {
+    // Best guess of the SYBYL atom type from the element and bonds of an atom
+    // Input:
+    //     atom: position of the atom, counted from 1
+    //     elements: element symbols of all atoms
+    //     bonds: bonds as positions of atoms and MDL bond orders
+    let neighbours: Vec<(usize, u8)> = bonds
+        .iter()
+        .filter_map(|&(origin, target, order)| match atom {
+            _ if atom == origin => Some((target, order)),
+            _ if atom == target => Some((origin, order)),
+            _ => None,
+        })
+        .collect();
+    let count = |order: u8| {
+        neighbours
+            .iter()
+            .filter(|(_, other)| *other == order)
+            .count()
+    };
+    let (double, triple, aromatic) = (count(2), count(3), count(4));
+    let element = elements[atom - 1].as_str();
+    let hybridization = match element {
+        "C" if aromatic > 0 => "ar",
+        "C" if triple > 0 || double > 1 => "1",
+        "C" if double > 0 => "2",
+        "C" => "3",
+        "N" if aromatic > 0 => "ar",
+        "N" if triple > 0 => "1",
+        "N" if double > 0 => "2",
+        "N" if neighbours.len() == 4 => "4",
+        "N" if neighbours
+            .iter()
+            .any(|&(other, _)| is_carbonyl_carbon(other, elements, bonds)) =>
+        {
+            "am"
+        }
+        "N" => "3",
+        "O" if double > 0 => "2",
+        "O" => "3",
+        "S" if double > 1 => "O2",
+        "S" if double > 0 => "2",
+        "S" => "3",
+        "P" => "3",
+        "*" | "" => return "Du".to_owned(),
+        _ => return element.to_owned(),
+    };
+
+    format!("{}.{}", element, hybridization)
+}
+
+fn is_carbonyl_carbon(atom: usize, elements: &[String], bonds: &[(usize, usize, u8)]) -> bool {
+    // Carbon atom double bonded to an oxygen or sulfur, as in amides
+    elements[atom - 1] == "C"
+        && bonds.iter().any(|&(origin, target, order)| {
+            let other = match atom {
+                _ if atom == origin => target,
+                _ if atom == target => origin,
+                _ => return false,
+            };
+            order == 2 && matches!(elements[other - 1].as_str(), "O" | "S")
+        })
+}
@@ -0,0 +1,731 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::error::{Mol2Error, Result};
+use crate::sdf::{element, sybyl_atom_type, sybyl_bond_type};
+use crate::{Atom, Bond, IdInt, Mol2, Molecule};
+use std::collections::{BTreeSet, HashMap};
+
+// Elements written without brackets when they are not charged
+static ORGANIC_SUBSET: [&str; 10] = ["B", "C", "N", "O", "P", "S", "F", "Cl", "Br", "I"];
+
+// Elements written in lowercase when aromatic
+static AROMATIC_SUBSET: [&str; 6] = ["B", "C", "N", "O", "P", "S"];
+
+// Atom of the graph a SMILES is written from, hydrogens bonded to a
+// single heavy atom are counted on that atom instead
+struct GraphAtom {
+    symbol: String,
+    aromatic: bool,
+    hydrogens: usize,
+    charge: i8,
+}
+
+impl GraphAtom {
+    fn as_smiles(&self) -> String {
+        // Atom in SMILES, in brackets when the valence alone is not enough
+        let symbol = match self.aromatic && AROMATIC_SUBSET.contains(&self.symbol.as_str()) {
+            true => self.symbol.to_lowercase(),
+            false => self.symbol.clone(),
+        };
+        // Aromatic nitrogens with a hydrogen, as in pyrrole, need it written
+        let bare = ORGANIC_SUBSET.contains(&self.symbol.as_str())
+            && self.charge == 0
+            && !(self.aromatic && self.symbol == "N" && self.hydrogens > 0);
+        if bare || self.symbol == "*" {
+            return symbol;
+        }
+        let hydrogens = match self.hydrogens {
+            0 => String::new(),
+            1 => "H".to_owned(),
+            count => format!("H{}", count),
+        };
+        let charge = match self.charge {
+            0 => String::new(),
+            1 => "+".to_owned(),
+            -1 => "-".to_owned(),
+            charge => format!("{:+}", charge),
+        };
+        format!("[{}{}{}]", symbol, hydrogens, charge)
+    }
+}
+
+// Heavy atoms and the bonds between them, bonds are given by the
+// other atom and the SMILES bond symbol
+struct Graph {
+    atoms: Vec<GraphAtom>,
+    neighbours: Vec<Vec<(usize, &'static str)>>,
+}
+
+fn build_graph(mol2: &Mol2) -> Graph {
+    // Translate atoms and bonds of a structure into a SMILES graph
+    // Input:
+    //     mol2: structure to translate
+    let positions: HashMap<IdInt, usize> = mol2
+        .atom
+        .iter()
+        .enumerate()
+        .map(|(index, atom)| (atom.atom_id, index))
+        .collect();
+    let types: Vec<&str> = mol2
+        .atom
+        .iter()
+        .map(|atom| atom.atom_type.as_str())
+        .collect();
+    let elements: Vec<&str> = types.iter().map(|atom_type| element(atom_type)).collect();
+    // Not connected bonds and bonds to missing atoms are left out
+    let bonds: Vec<(usize, usize, &str)> = mol2
+        .bond
+        .iter()
+        .filter(|bond| bond.bond_type != "nc")
+        .filter_map(|bond| {
+            match (
+                positions.get(&bond.origin_atom_id),
+                positions.get(&bond.target_atom_id),
+            ) {
+                (Some(&origin), Some(&target)) if origin != target => {
+                    Some((origin, target, bond.bond_type.as_str()))
+                }
+                _ => None,
+            }
+        })
+        .collect();
+    let mut bonded: Vec<Vec<usize>> = vec![Vec::new(); mol2.atom.len()];
+    for &(origin, target, _) in &bonds {
+        bonded[origin].push(target);
+        bonded[target].push(origin);
+    }
+
+    // Hydrogens bonded to exactly one heavy atom are implicit
+    let implicit: Vec<bool> = (0..mol2.atom.len())
+        .map(|index| {
+            elements[index] == "H" && bonded[index].len() == 1 && elements[bonded[index][0]] != "H"
+        })
+        .collect();
+    // Heteroatoms of aromatic rings, like the sulfur of thiophene, are not
+    // always typed as aromatic, but have aromatic bonds to aromatic atoms
+    let aromatic: Vec<bool> = (0..mol2.atom.len())
+        .map(|index| {
+            types[index].ends_with(".ar")
+                || (matches!(elements[index], "N" | "O" | "S" | "P")
+                    && bonds
+                        .iter()
+                        .filter(|&&(origin, target, bond_type)| {
+                            bond_type == "ar"
+                                && ((origin == index && types[target].ends_with(".ar"))
+                                    || (target == index && types[origin].ends_with(".ar")))
+                        })
+                        .count()
+                        >= 2)
+        })
+        .collect();
+
+    let mut charges: Vec<i8> = types
+        .iter()
+        .map(|&atom_type| match atom_type {
+            "N.4" => 1,
+            _ => 0,
+        })
+        .collect();
+    // Carboxylate and phosphate oxygens, as well as amidinium and
+    // guanidinium nitrogens, share a delocalized charge. The first of them
+    // gets a double bond, the charge goes to the oxygens with a single bond
+    // or the nitrogen with a double bond.
+    let mut double_bonded: Vec<bool> = vec![false; mol2.atom.len()];
+    let mut symbols: Vec<&'static str> = Vec::new();
+    for &(origin, target, bond_type) in &bonds {
+        let symbol = match bond_type {
+            "2" => "=",
+            "3" => "#",
+            "ar" if aromatic[origin] && aromatic[target] => "",
+            "ar" if types[origin] == "O.co2" || types[target] == "O.co2" => {
+                let (oxygen, center) = match types[origin] {
+                    "O.co2" => (origin, target),
+                    _ => (target, origin),
+                };
+                if double_bonded[center] {
+                    charges[oxygen] = -1;
+                    ""
+                } else {
+                    double_bonded[center] = true;
+                    "="
+                }
+            }
+            "ar" if types[origin] == "C.cat" || types[target] == "C.cat" => {
+                let (nitrogen, center) = match types[origin] {
+                    "C.cat" => (target, origin),
+                    _ => (origin, target),
+                };
+                if double_bonded[center] {
+                    ""
+                } else {
+                    double_bonded[center] = true;
+                    charges[nitrogen] = 1;
+                    "="
+                }
+            }
+            // Single bonds between aromatic atoms have to be explicit
+            _ if aromatic[origin] && aromatic[target] => "-",
+            _ => "",
+        };
+        symbols.push(symbol);
+    }
+
+    // Positions of heavy atoms in the graph
+    let mut graph_positions: Vec<Option<usize>> = vec![None; mol2.atom.len()];
+    let mut graph = Graph {
+        atoms: Vec::new(),
+        neighbours: Vec::new(),
+    };
+    for index in 0..mol2.atom.len() {
+        if implicit[index] {
+            continue;
+        }
+        graph_positions[index] = Some(graph.atoms.len());
+        graph.atoms.push(GraphAtom {
+            symbol: elements[index].to_owned(),
+            aromatic: aromatic[index],
+            hydrogens: bonded[index]
+                .iter()
+                .filter(|&&other| implicit[other])
+                .count(),
+            charge: charges[index],
+        });
+        graph.neighbours.push(Vec::new());
+    }
+    for (&(origin, target, _), symbol) in bonds.iter().zip(symbols) {
+        if let (Some(origin), Some(target)) = (graph_positions[origin], graph_positions[target]) {
+            graph.neighbours[origin].push((target, symbol));
+            graph.neighbours[target].push((origin, symbol));
+        }
+    }
+    // Hydrogens of charged nitrogens are written explicitly, structures
+    // without hydrogens get the ones needed for a valence of four
+    for (atom, neighbours) in graph.atoms.iter_mut().zip(&graph.neighbours) {
+        if atom.symbol == "N" && atom.charge == 1 && !atom.aromatic {
+            let valence: usize = neighbours
+                .iter()
+                .map(|&(_, symbol)| match symbol {
+                    "=" => 2,
+                    "#" => 3,
+                    _ => 1,
+                })
+                .sum();
+            atom.hydrogens = atom.hydrogens.max(4_usize.saturating_sub(valence));
+        }
+    }
+
+    graph
+}
+
+fn rank_of<K: Ord + Clone>(keys: &[K]) -> Vec<usize> {
+    // Position of each key among the sorted distinct keys
+    let sorted: Vec<K> = keys
+        .iter()
+        .cloned()
+        .collect::<BTreeSet<K>>()
+        .into_iter()
+        .collect();
+    keys.iter()
+        .map(|key| sorted.binary_search(key).unwrap_or(0))
+        .collect()
+}
+
+fn canonical_ranks(graph: &Graph) -> Vec<usize> {
+    // Rank atoms by their element and surroundings, refined by the ranks
+    // of their neighbours until no more atoms can be told apart. Atoms
+    // that stay equivalent keep the order they have in the structure.
+    let mut ranks = rank_of(
+        &graph
+            .atoms
+            .iter()
+            .zip(&graph.neighbours)
+            .map(|(atom, neighbours)| {
+                (
+                    atom.symbol.clone(),
+                    neighbours.len(),
+                    atom.hydrogens,
+                    atom.aromatic,
+                    atom.charge,
+                )
+            })
+            .collect::<Vec<_>>(),
+    );
+    let classes = |ranks: &[usize]| ranks.iter().collect::<BTreeSet<_>>().len();
+    loop {
+        let refined = rank_of(
+            &graph
+                .neighbours
+                .iter()
+                .enumerate()
+                .map(|(index, neighbours)| {
+                    let mut around: Vec<(usize, &str)> = neighbours
+                        .iter()
+                        .map(|&(other, symbol)| (ranks[other], symbol))
+                        .collect();
+                    around.sort_unstable();
+                    (ranks[index], around)
+                })
+                .collect::<Vec<_>>(),
+        );
+        if classes(&refined) == classes(&ranks) {
+            break;
+        }
+        ranks = refined;
+    }
+    ranks
+}
+
+fn ring_label(number: usize) -> String {
+    // Ring closure label, two digit numbers are prefixed with %
+    match number {
+        0..=9 => number.to_string(),
+        _ => format!("%{}", number),
+    }
+}
+
+pub fn to_smiles(mol2: &Mol2) -> String {
+    // Write a SMILES of a structure from its atoms and bonds, elements are
+    // taken from the SYBYL atom types. Atoms are visited in the order of
+    // their canonical ranks, so the same structure gives the same SMILES
+    // as long as its atoms can be told apart. No stereochemistry is written.
+    // Input:
+    //     mol2: structure to write
+    let graph = build_graph(mol2);
+    let ranks = canonical_ranks(&graph);
+    let mut sorted: Vec<Vec<(usize, &str)>> = graph.neighbours.clone();
+    for neighbours in sorted.iter_mut() {
+        neighbours.sort_by_key(|&(other, _)| (ranks[other], other));
+    }
+    let mut starts: Vec<usize> = (0..graph.atoms.len()).collect();
+    starts.sort_by_key(|&index| (ranks[index], index));
+
+    let mut order: Vec<Option<usize>> = vec![None; graph.atoms.len()];
+    let mut counter: usize = 0;
+    let mut components: Vec<String> = Vec::new();
+    for start in starts {
+        if order[start].is_some() {
+            continue;
+        }
+        // Depth first search for the spanning tree, bonds to atoms visited
+        // earlier become ring closures
+        let mut parent: Vec<Option<usize>> = vec![None; graph.atoms.len()];
+        let mut children: HashMap<usize, Vec<(usize, &str)>> = HashMap::new();
+        let mut closures: HashMap<usize, Vec<(usize, &str)>> = HashMap::new();
+        let mut stack: Vec<(usize, usize)> = vec![(start, 0)];
+        order[start] = Some(counter);
+        counter += 1;
+        while let Some((atom, next)) = stack.last_mut() {
+            let atom = *atom;
+            let (other, symbol) = match sorted[atom].get(*next) {
+                Some(&neighbour) => neighbour,
+                None => {
+                    stack.pop();
+                    continue;
+                }
+            };
+            *next += 1;
+            match order[other] {
+                None => {
+                    order[other] = Some(counter);
+                    counter += 1;
+                    parent[other] = Some(atom);
+                    children.entry(atom).or_default().push((other, symbol));
+                    stack.push((other, 0));
+                }
+                Some(other_order)
+                    if other_order < order[atom].unwrap_or(0) && parent[atom] != Some(other) =>
+                {
+                    closures.entry(atom).or_default().push((other, symbol));
+                    closures.entry(other).or_default().push((atom, symbol));
+                }
+                _ => (),
+            }
+        }
+
+        // Write the tree in the same order, branches in parentheses
+        let mut text = String::new();
+        let mut open_rings: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut tasks: Vec<(Option<usize>, &str)> = vec![(Some(start), "")];
+        while let Some((atom, symbol)) = tasks.pop() {
+            let atom = match atom {
+                Some(atom) => atom,
+                None => {
+                    text.push_str(symbol);
+                    continue;
+                }
+            };
+            text.push_str(symbol);
+            text.push_str(&graph.atoms[atom].as_smiles());
+            let mut ring_bonds = closures.remove(&atom).unwrap_or_default();
+            ring_bonds.sort_by_key(|&(other, _)| order[other]);
+            // Closing rings first frees their labels for the rings opened here
+            for &(other, _) in &ring_bonds {
+                if let Some(number) = open_rings.remove(&(other, atom)) {
+                    text.push_str(&ring_label(number));
+                }
+            }
+            for &(other, symbol) in &ring_bonds {
+                if order[other] > order[atom] {
+                    let number = (1..)
+                        .find(|number| !open_rings.values().any(|open| open == number))
+                        .unwrap_or(1);
+                    open_rings.insert((atom, other), number);
+                    text.push_str(symbol);
+                    text.push_str(&ring_label(number));
+                }
+            }
+            let branches = children.remove(&atom).unwrap_or_default();
+            for (position, &(child, symbol)) in branches.iter().enumerate().rev() {
+                if position + 1 == branches.len() {
+                    tasks.push((Some(child), symbol));
+                } else {
+                    tasks.push((None, ")"));
+                    tasks.push((Some(child), symbol));
+                    tasks.push((None, "("));
+                }
+            }
+        }
+        components.push(text);
+    }
+
+    components.join(".")
+}
+
+// Atom read from a SMILES, hydrogens are None for atoms outside brackets
+struct SmilesAtom {
+    symbol: String,
+    aromatic: bool,
+    hydrogens: Option<usize>,
+}
+
+fn smiles_error(position: usize, message: &str) -> Mol2Error {
+    Mol2Error::InvalidArgument(format!("SMILES position {}: {}", position + 1, message))
+}
+
+fn read_bracket_atom(chars: &[char], position: &mut usize) -> Result<SmilesAtom> {
+    // Read a bracket atom, e.g. [NH4+] or [13CH3], the position points
+    // after the opening bracket and is moved after the closing one.
+    // Isotopes, chirality, charges and atom classes are skipped.
+    let start = *position;
+    let next = |position: &usize| chars.get(*position).copied();
+    while next(position).is_some_and(|c| c.is_ascii_digit()) {
+        *position += 1;
+    }
+    let mut symbol = String::new();
+    match next(position) {
+        Some(c) if c.is_ascii_alphabetic() || c == '*' => {
+            symbol.push(c);
+            *position += 1;
+        }
+        _ => return Err(smiles_error(*position, "missing element in brackets")),
+    }
+    // Second letter of two letter elements, "se" and "as" when aromatic
+    if let Some(c) = next(position) {
+        if c.is_ascii_lowercase() && symbol != "*" {
+            symbol.push(c);
+            *position += 1;
+        }
+    }
+    let aromatic = symbol.starts_with(|c: char| c.is_ascii_lowercase());
+    let mut hydrogens: usize = 0;
+    while let Some(c) = next(position) {
+        *position += 1;
+        match c {
+            ']' => {
+                let mut chars = symbol.chars();
+                let symbol = match chars.next() {
+                    Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                    None => symbol,
+                };
+                return Ok(SmilesAtom {
+                    symbol,
+                    aromatic,
+                    hydrogens: Some(hydrogens),
+                });
+            }
+            'H' => {
+                let mut count = String::new();
+                while let Some(digit) = next(position).filter(|c| c.is_ascii_digit()) {
+                    count.push(digit);
+                    *position += 1;
+                }
+                hydrogens = count.parse().unwrap_or(1);
+            }
+            _ => (),
+        }
+    }
+    Err(smiles_error(start, "unclosed bracket atom"))
+}
+
+fn implicit_hydrogens(atom: &SmilesAtom, bonds: &[(usize, usize, u8)], index: usize) -> usize {
+    // Hydrogens of an atom outside brackets, filling up its lowest normal
+    // valence that is not exceeded by its bonds
+    // Input:
+    //     atom: the atom
+    //     bonds: bonds as positions of atoms, counted from 1, and bond orders, 4 for aromatic
+    //     index: position of the atom, counted from 1
+    if let Some(hydrogens) = atom.hydrogens {
+        return hydrogens;
+    }
+    let valences: &[usize] = match atom.symbol.as_str() {
+        "B" => &[3],
+        "C" => &[4],
+        "N" | "P" => &[3, 5],
+        "O" => &[2],
+        "S" => &[2, 4, 6],
+        "F" | "Cl" | "Br" | "I" => &[1],
+        _ => &[],
+    };
+    // Aromatic bonds count as single bonds, plus one for the aromatic system
+    let mut used: usize = bonds
+        .iter()
+        .filter(|&&(origin, target, _)| origin == index || target == index)
+        .map(|&(_, _, order)| match order {
+            4 => 1,
+            order => order as usize,
+        })
+        .sum();
+    if atom.aromatic {
+        used += 1;
+    }
+    valences
+        .iter()
+        .find(|&&valence| valence >= used)
+        .map_or(0, |valence| valence - used)
+}
+
+pub fn from_smiles(smiles: &str) -> Result<Mol2> {
+    // Build a structure from a SMILES, with hydrogens added as atoms,
+    // SYBYL atom types guessed from elements and bonds, and all
+    // coordinates set to zero. Stereochemistry and charges are not kept.
+    // Input:
+    //     smiles: the SMILES
+    let chars: Vec<char> = smiles.trim().chars().collect();
+    let mut atoms: Vec<SmilesAtom> = Vec::new();
+    // Bonds as positions of atoms, counted from 1, and MDL bond orders
+    let mut bonds: Vec<(usize, usize, u8)> = Vec::new();
+    let mut previous: Option<usize> = None;
+    let mut branches: Vec<Option<usize>> = Vec::new();
+    let mut bond: Option<u8> = None;
+    let mut rings: HashMap<usize, (usize, Option<u8>)> = HashMap::new();
+    let mut position: usize = 0;
+    while position < chars.len() {
+        let start = position;
+        let c = chars[position];
+        position += 1;
+        let atom = match c {
+            '(' => {
+                branches.push(previous);
+                continue;
+            }
+            ')' => {
+                previous = branches
+                    .pop()
+                    .ok_or_else(|| smiles_error(start, "unmatched closing parenthesis"))?;
+                continue;
+            }
+            '.' => {
+                previous = None;
+                continue;
+            }
+            '-' | '/' | '\\' => {
+                bond = Some(1);
+                continue;
+            }
+            '=' => {
+                bond = Some(2);
+                continue;
+            }
+            '#' => {
+                bond = Some(3);
+                continue;
+            }
+            ':' => {
+                bond = Some(4);
+                continue;
+            }
+            '0'..='9' | '%' => {
+                let number: usize = match c {
+                    '%' => {
+                        let digits: String = chars.iter().skip(position).take(2).collect();
+                        position += 2;
+                        digits
+                            .parse()
+                            .map_err(|_| smiles_error(start, "invalid ring closure number"))?
+                    }
+                    _ => c as usize - '0' as usize,
+                };
+                let atom =
+                    previous.ok_or_else(|| smiles_error(start, "ring closure without an atom"))?;
+                match rings.remove(&number) {
+                    Some((other, _)) if other == atom => {
+                        return Err(smiles_error(
+                            start,
+                            &format!("ring {} closes on the atom it opened at", number),
+                        ))
+                    }
+                    Some((other, _))
+                        if bonds.iter().any(|&(first, second, _)| {
+                            (first, second) == (other, atom) || (first, second) == (atom, other)
+                        }) =>
+                    {
+                        return Err(smiles_error(
+                            start,
+                            &format!("ring {} repeats an existing bond", number),
+                        ))
+                    }
+                    Some((other, other_bond)) => {
+                        let order = bond.or(other_bond).unwrap_or(
+                            match atoms[atom - 1].aromatic && atoms[other - 1].aromatic {
+                                true => 4,
+                                false => 1,
+                            },
+                        );
+                        bonds.push((other, atom, order));
+                    }
+                    None => {
+                        rings.insert(number, (atom, bond));
+                    }
+                }
+                bond = None;
+                continue;
+            }
+            '[' => read_bracket_atom(&chars, &mut position)?,
+            '*' => SmilesAtom {
+                symbol: "*".to_owned(),
+                aromatic: false,
+                hydrogens: Some(0),
+            },
+            'C' if chars.get(position) == Some(&'l') => {
+                position += 1;
+                SmilesAtom {
+                    symbol: "Cl".to_owned(),
+                    aromatic: false,
+                    hydrogens: None,
+                }
+            }
+            'B' if chars.get(position) == Some(&'r') => {
+                position += 1;
+                SmilesAtom {
+                    symbol: "Br".to_owned(),
+                    aromatic: false,
+                    hydrogens: None,
+                }
+            }
+            'B' | 'C' | 'N' | 'O' | 'P' | 'S' | 'F' | 'I' => SmilesAtom {
+                symbol: c.to_string(),
+                aromatic: false,
+                hydrogens: None,
+            },
+            'b' | 'c' | 'n' | 'o' | 'p' | 's' => SmilesAtom {
+                symbol: c.to_ascii_uppercase().to_string(),
+                aromatic: true,
+                hydrogens: None,
+            },
+            _ => {
+                return Err(smiles_error(
+                    start,
+                    &format!("unexpected character '{}'", c),
+                ))
+            }
+        };
+        atoms.push(atom);
+        let index = atoms.len();
+        if let Some(other) = previous {
+            let order = bond.unwrap_or(
+                match atoms[other - 1].aromatic && atoms[index - 1].aromatic {
+                    true => 4,
+                    false => 1,
+                },
+            );
+            bonds.push((other, index, order));
+        }
+        previous = Some(index);
+        bond = None;
+    }
+    if !branches.is_empty() {
+        return Err(smiles_error(chars.len(), "unclosed branch"));
+    }
+    if let Some(number) = rings.keys().min() {
+        return Err(smiles_error(
+            chars.len(),
+            &format!("unclosed ring {}", number),
+        ));
+    }
+    if atoms.is_empty() {
+        return Err(smiles_error(0, "no atoms"));
+    }
+
+    // Hydrogens are added as atoms after the heavy atoms
+    let mut elements: Vec<String> = atoms.iter().map(|atom| atom.symbol.clone()).collect();
+    let heavy_bonds = bonds.clone();
+    for (index, atom) in atoms.iter().enumerate() {
+        for _ in 0..implicit_hydrogens(atom, &heavy_bonds, index + 1) {
+            elements.push("H".to_owned());
+            bonds.push((index + 1, elements.len(), 1));
+        }
+    }
+    if elements.len() > IdInt::MAX as usize || bonds.len() > IdInt::MAX as usize {
+        return Err(Mol2Error::InvalidArgument(format!(
+            "SMILES gives {} atoms and {} bonds, more than {} are not supported",
+            elements.len(),
+            bonds.len(),
+            IdInt::MAX
+        )));
+    }
+
+    let mut mol2 = Mol2::new("");
+    mol2.molecule = Some(Molecule {
+        mol_name: smiles.trim().to_owned(),
+        num_atoms: Some(elements.len()),
+        num_bonds: Some(bonds.len()),
+        num_subst: None,
+        num_feat: None,
+        num_sets: None,
+        mol_type: Some("SMALL".to_owned()),
+        charge_type: Some("NO_CHARGES".to_owned()),
+        status_bits: None,
+        mol_comment: None,
+    });
+    let mut counters: HashMap<&str, usize> = HashMap::new();
+    for (index, element) in elements.iter().enumerate() {
+        let counter = counters.entry(element.as_str()).or_insert(0);
+        *counter += 1;
+        mol2.atom.push(Atom {
+            atom_id: (index + 1) as IdInt,
+            atom_name: format!("{}{}", element, counter),
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+            atom_type: sybyl_atom_type(index + 1, &elements, &bonds),
+            subst_id: None,
+            subst_name: None,
+            charge: None,
+            status_bit: None,
+        });
+    }
+    for (index, (origin, target, order)) in bonds.iter().enumerate() {
+        mol2.bond.push(Bond {
+            bond_id: (index + 1) as IdInt,
+            origin_atom_id: *origin as IdInt,
+            target_atom_id: *target as IdInt,
+            bond_type: sybyl_bond_type(*order).to_owned(),
+            status_bit: None,
+        });
+    }
+
+    Ok(mol2)
+}
@@ -0,0 +1,253 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::error::{Mol2Error, Result};
+use crate::{AltType, IdInt, Mol2};
+use std::collections::HashMap;
+
+// Built-in tables translate single atom types without looking at their
+// neighbours, so they give the most common type of every SYBYL type, e.g.
+// hc for all hydrogens. Programs like antechamber assign types with the
+// chemical environment in mind.
+const SYBYL_TO_GAFF: &[(&str, &str)] = &[
+    ("C.3", "c3"),
+    ("C.2", "c2"),
+    ("C.1", "c1"),
+    ("C.ar", "ca"),
+    ("C.cat", "c"),
+    ("N.3", "n3"),
+    ("N.2", "n2"),
+    ("N.1", "n1"),
+    ("N.ar", "nb"),
+    ("N.am", "n"),
+    ("N.pl3", "nh"),
+    ("N.4", "n4"),
+    ("O.3", "oh"),
+    ("O.2", "o"),
+    ("O.co2", "o"),
+    ("S.3", "ss"),
+    ("S.2", "s"),
+    ("S.O", "s4"),
+    ("S.O2", "s6"),
+    ("P.3", "p5"),
+    ("H", "hc"),
+    ("F", "f"),
+    ("Cl", "cl"),
+    ("Br", "br"),
+    ("I", "i"),
+];
+
+const GAFF_TO_SYBYL: &[(&str, &str)] = &[
+    ("c", "C.2"),
+    ("c1", "C.1"),
+    ("c2", "C.2"),
+    ("c3", "C.3"),
+    ("ca", "C.ar"),
+    ("cc", "C.2"),
+    ("cd", "C.2"),
+    ("ce", "C.2"),
+    ("cf", "C.2"),
+    ("cg", "C.1"),
+    ("ch", "C.1"),
+    ("cp", "C.ar"),
+    ("cq", "C.ar"),
+    ("cu", "C.2"),
+    ("cv", "C.2"),
+    ("cx", "C.3"),
+    ("cy", "C.3"),
+    ("cz", "C.cat"),
+    ("n", "N.am"),
+    ("n1", "N.1"),
+    ("n2", "N.2"),
+    ("n3", "N.3"),
+    ("n4", "N.4"),
+    ("na", "N.pl3"),
+    ("nb", "N.ar"),
+    ("nc", "N.2"),
+    ("nd", "N.2"),
+    ("ne", "N.2"),
+    ("nf", "N.2"),
+    ("nh", "N.pl3"),
+    ("no", "N.pl3"),
+    ("o", "O.2"),
+    ("oh", "O.3"),
+    ("os", "O.3"),
+    ("ow", "O.3"),
+    ("s", "S.2"),
+    ("s2", "S.2"),
+    ("s4", "S.O"),
+    ("s6", "S.O2"),
+    ("sh", "S.3"),
+    ("ss", "S.3"),
+    ("sx", "S.O"),
+    ("sy", "S.O2"),
+    ("p2", "P.3"),
+    ("p3", "P.3"),
+    ("p4", "P.3"),
+    ("p5", "P.3"),
+    ("h1", "H"),
+    ("h2", "H"),
+    ("h3", "H"),
+    ("h4", "H"),
+    ("h5", "H"),
+    ("ha", "H"),
+    ("hc", "H"),
+    ("hn", "H"),
+    ("ho", "H"),
+    ("hp", "H"),
+    ("hs", "H"),
+    ("hw", "H"),
+    ("f", "F"),
+    ("cl", "Cl"),
+    ("br", "Br"),
+    ("i", "I"),
+];
+
+// AMBER protein force field types
+const SYBYL_TO_AMBER: &[(&str, &str)] = &[
+    ("C.3", "CT"),
+    ("C.2", "C"),
+    ("C.ar", "CA"),
+    ("C.cat", "CA"),
+    ("N.3", "N3"),
+    ("N.4", "N3"),
+    ("N.am", "N"),
+    ("N.pl3", "N2"),
+    ("N.ar", "NB"),
+    ("N.2", "NC"),
+    ("O.3", "OH"),
+    ("O.2", "O"),
+    ("O.co2", "O2"),
+    ("S.3", "S"),
+    ("P.3", "P"),
+    ("H", "H"),
+    ("F", "F"),
+    ("Cl", "Cl"),
+    ("Br", "Br"),
+    ("I", "I"),
+];
+
+const AMBER_TO_SYBYL: &[(&str, &str)] = &[
+    ("C", "C.2"),
+    ("CA", "C.ar"),
+    ("CB", "C.ar"),
+    ("CC", "C.ar"),
+    ("CN", "C.ar"),
+    ("CR", "C.ar"),
+    ("CV", "C.ar"),
+    ("CW", "C.ar"),
+    ("C*", "C.ar"),
+    ("CT", "C.3"),
+    ("CX", "C.3"),
+    ("2C", "C.3"),
+    ("3C", "C.3"),
+    ("C8", "C.3"),
+    ("CO", "C.2"),
+    ("N", "N.am"),
+    ("N2", "N.pl3"),
+    ("N3", "N.4"),
+    ("NA", "N.pl3"),
+    ("NB", "N.ar"),
+    ("NC", "N.2"),
+    ("O", "O.2"),
+    ("O2", "O.co2"),
+    ("OH", "O.3"),
+    ("OS", "O.3"),
+    ("OW", "O.3"),
+    ("S", "S.3"),
+    ("SH", "S.3"),
+    ("P", "P.3"),
+    ("H", "H"),
+    ("H1", "H"),
+    ("H2", "H"),
+    ("H3", "H"),
+    ("H4", "H"),
+    ("H5", "H"),
+    ("HA", "H"),
+    ("HC", "H"),
+    ("HO", "H"),
+    ("HP", "H"),
+    ("HS", "H"),
+    ("HW", "H"),
+    ("F", "F"),
+    ("Cl", "Cl"),
+    ("Br", "Br"),
+    ("I", "I"),
+];
+
+pub fn builtin_table(name: &str) -> Result<(&'static str, HashMap<String, String>)> {
+    // Scheme the types are translated to and the table of a built-in translation
+    // Input:
+    //     name: sybyl_to_gaff, gaff_to_sybyl, sybyl_to_amber or amber_to_sybyl
+    let (scheme, pairs) = match name {
+        "sybyl_to_gaff" => ("GAFF", SYBYL_TO_GAFF),
+        "gaff_to_sybyl" => ("SYBYL", GAFF_TO_SYBYL),
+        "sybyl_to_amber" => ("AMBER", SYBYL_TO_AMBER),
+        "amber_to_sybyl" => ("SYBYL", AMBER_TO_SYBYL),
+        _ => {
+            return Err(Mol2Error::InvalidArgument(format!(
+                "unknown type table {}, expected sybyl_to_gaff, gaff_to_sybyl, sybyl_to_amber or amber_to_sybyl",
+                name
+            )))
+        }
+    };
+    Ok((
+        scheme,
+        pairs
+            .iter()
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+            .collect(),
+    ))
+}
+
+pub fn translate_types(
+    mol2: &mut Mol2,
+    table: &HashMap<String, String>,
+    scheme: &str,
+    replace: bool,
+) -> usize {
+    // Translate atom types with a table, returns the number of translated
+    // atoms. Types are matched exactly and atoms with types missing from the
+    // table are left out.
+    // Input:
+    //     mol2: structure to modify
+    //     table: atom type in the current scheme to the type in the new one
+    //     scheme: name of the new scheme, e.g. GAFF, used for the ALT_TYPE set
+    //     replace: replace atom types instead of adding an ALT_TYPE set,
+    //              atoms missing from the table keep their type
+    if replace {
+        let mut translated = 0;
+        for atom in mol2.atom.iter_mut() {
+            if let Some(atom_type) = table.get(&atom.atom_type) {
+                atom.atom_type = atom_type.clone();
+                translated += 1;
+            }
+        }
+        return translated;
+    }
+    let types: Vec<(IdInt, String)> = mol2
+        .atom
+        .iter()
+        .filter_map(|atom| Some((atom.atom_id, table.get(&atom.atom_type)?.clone())))
+        .collect();
+    let translated = types.len();
+    // A scheme translated again replaces its earlier set
+    mol2.alt_type.retain(|set| set.scheme != scheme);
+    mol2.alt_type.push(AltType {
+        scheme: scheme.to_owned(),
+        types,
+    });
+    translated
+}
@@ -0,0 +1,276 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::aromatic::aromatic_atoms;
+use crate::elements::lookup;
+use crate::graph::rings;
+use crate::{IdInt, Mol2};
+use std::collections::{HashMap, HashSet};
+
+// Bond orders used while typing, aromatic bonds are 4 and bonds of unknown
+// order 0
+struct Neighbours {
+    symbols: HashMap<IdInt, &'static str>,
+    bonded: HashMap<IdInt, Vec<(IdInt, u8)>>,
+}
+
+impl Neighbours {
+    fn new(mol2: &Mol2) -> Neighbours {
+        let mut neighbours = Neighbours {
+            symbols: mol2
+                .atom
+                .iter()
+                .filter_map(|atom| {
+                    lookup(&atom.atom_type).map(|(symbol, _, _)| (atom.atom_id, symbol))
+                })
+                .collect(),
+            bonded: HashMap::new(),
+        };
+        for bond in &mol2.bond {
+            let order = match bond.bond_type.as_str() {
+                "1" | "am" => 1,
+                "2" => 2,
+                "3" => 3,
+                "ar" => 4,
+                // Not connected
+                "nc" => continue,
+                _ => 0,
+            };
+            let (origin, target) = (bond.origin_atom_id, bond.target_atom_id);
+            neighbours
+                .bonded
+                .entry(origin)
+                .or_default()
+                .push((target, order));
+            neighbours
+                .bonded
+                .entry(target)
+                .or_default()
+                .push((origin, order));
+        }
+        neighbours
+    }
+
+    fn of(&self, atom_id: IdInt) -> &[(IdInt, u8)] {
+        self.bonded
+            .get(&atom_id)
+            .map(|bonded| &bonded[..])
+            .unwrap_or(&[])
+    }
+
+    fn symbol(&self, atom_id: IdInt) -> &str {
+        self.symbols.get(&atom_id).copied().unwrap_or("")
+    }
+
+    fn degree(&self, atom_id: IdInt) -> usize {
+        self.of(atom_id).len()
+    }
+
+    fn count(&self, atom_id: IdInt, order: u8) -> usize {
+        self.of(atom_id)
+            .iter()
+            .filter(|(_, other)| *other == order)
+            .count()
+    }
+
+    fn terminal(&self, atom_id: IdInt, symbols: &[&str]) -> usize {
+        // Number of bonded atoms of the given elements with no other bonds,
+        // e.g. carbonyl or carboxylate oxygens
+        self.of(atom_id)
+            .iter()
+            .filter(|(other, _)| symbols.contains(&self.symbol(*other)) && self.degree(*other) == 1)
+            .count()
+    }
+}
+
+fn connectivity_aromatic_atoms(neighbours: &Neighbours, rings: &[Vec<IdInt>]) -> HashSet<IdInt> {
+    // Atoms of aromatic rings when bond orders are not known. Every atom
+    // is assumed to have all its hydrogens, carbons with three neighbours
+    // give one pi electron unless they carry a terminal oxygen or sulfur,
+    // nitrogens with two neighbours one, nitrogens with three neighbours
+    // and divalent oxygens and sulfurs their lone pair.
+    let mut aromatic: HashSet<IdInt> = HashSet::new();
+    for ring in rings.iter().filter(|ring| (5..=7).contains(&ring.len())) {
+        let electrons: Option<usize> = ring
+            .iter()
+            .map(|atom_id| {
+                let degree = neighbours.degree(*atom_id);
+                match neighbours.symbol(*atom_id) {
+                    "C" if degree == 3 && neighbours.terminal(*atom_id, &["O", "S"]) > 0 => Some(0),
+                    "C" if degree == 3 => Some(1),
+                    "N" if degree == 2 => Some(1),
+                    "N" if degree == 3 => Some(2),
+                    "O" | "S" if degree == 2 => Some(2),
+                    _ => None,
+                }
+            })
+            .sum();
+        if electrons.is_some_and(|electrons| electrons % 4 == 2) {
+            aromatic.extend(ring.iter().copied());
+        }
+    }
+    aromatic
+}
+
+fn carbonyl_carbon(neighbours: &Neighbours, atom_id: IdInt, orders_known: bool) -> bool {
+    // Carbon double bonded to an oxygen or sulfur, as in amides
+    neighbours.symbol(atom_id) == "C"
+        && match orders_known {
+            true => neighbours.of(atom_id).iter().any(|(other, order)| {
+                *order == 2 && matches!(neighbours.symbol(*other), "O" | "S")
+            }),
+            false => {
+                neighbours.degree(atom_id) == 3 && neighbours.terminal(atom_id, &["O", "S"]) > 0
+            }
+        }
+}
+
+fn unsaturated(
+    neighbours: &Neighbours,
+    atom_id: IdInt,
+    orders_known: bool,
+    aromatic: &HashSet<IdInt>,
+) -> bool {
+    // Atom with a double, triple or aromatic bond, or in an aromatic ring
+    aromatic.contains(&atom_id)
+        || match orders_known {
+            true => neighbours
+                .of(atom_id)
+                .iter()
+                .any(|(_, order)| matches!(order, 2..=4)),
+            false => match neighbours.symbol(atom_id) {
+                "C" => neighbours.degree(atom_id) < 4,
+                "N" => neighbours.degree(atom_id) < 3,
+                _ => false,
+            },
+        }
+}
+
+fn sybyl_type(
+    neighbours: &Neighbours,
+    atom_id: IdInt,
+    orders_known: bool,
+    aromatic: &HashSet<IdInt>,
+) -> Option<String> {
+    // SYBYL type of a single atom, None for atoms without an element
+    let symbol = neighbours.symbols.get(&atom_id)?;
+    let degree = neighbours.degree(atom_id);
+    let (double, triple, aromatic_bonds) = match orders_known {
+        true => (
+            neighbours.count(atom_id, 2),
+            neighbours.count(atom_id, 3),
+            neighbours.count(atom_id, 4),
+        ),
+        // Multiple bonds follow from missing neighbours
+        false => match (*symbol, degree) {
+            ("C", 3) | ("N", 2) => (1, 0, 0),
+            ("C", 2) | ("N", 1) => (0, 1, 0),
+            _ => (0, 0, 0),
+        },
+    };
+    let bonded = neighbours.of(atom_id);
+    let is = |other: IdInt, symbol: &str| neighbours.symbol(other) == symbol;
+    let hybridization = match *symbol {
+        "C" if aromatic.contains(&atom_id) => "ar",
+        "C" if degree == 3
+            && bonded
+                .iter()
+                .all(|(other, _)| is(*other, "N") && neighbours.degree(*other) == 3)
+            && (double > 0 || aromatic_bonds > 0) =>
+        {
+            "cat"
+        }
+        "C" if triple > 0 || double > 1 => "1",
+        "C" if double > 0 || aromatic_bonds > 0 => "2",
+        "C" => "3",
+        "N" if aromatic.contains(&atom_id) => "ar",
+        "N" if triple > 0 => "1",
+        "N" if degree == 4 => "4",
+        "N" if degree == 3 && (double > 0 || aromatic_bonds > 0) => "pl3",
+        "N" if double > 0 => "2",
+        "N" if bonded
+            .iter()
+            .any(|(other, _)| carbonyl_carbon(neighbours, *other, orders_known)) =>
+        {
+            "am"
+        }
+        "N" if neighbours.terminal(atom_id, &["O"]) > 1
+            || bonded
+                .iter()
+                .any(|(other, _)| unsaturated(neighbours, *other, orders_known, aromatic)) =>
+        {
+            "pl3"
+        }
+        "N" => "3",
+        "O" if degree == 1 => {
+            let (partner, order) = bonded[0];
+            match neighbours.symbol(partner) {
+                "C" if neighbours.degree(partner) == 3
+                    && neighbours.terminal(partner, &["O"]) == 2 =>
+                {
+                    "co2"
+                }
+                "P" if neighbours.terminal(partner, &["O"]) > 1 => "co2",
+                // Nitro groups
+                "N" if neighbours.terminal(partner, &["O"]) > 1 => "2",
+                _ if orders_known && order == 2 => "2",
+                _ if !orders_known
+                    && (unsaturated(neighbours, partner, orders_known, aromatic)
+                        || matches!(neighbours.symbol(partner), "S" | "P" | "N")) =>
+                {
+                    "2"
+                }
+                _ => "3",
+            }
+        }
+        "O" if double > 0 => "2",
+        "O" => "3",
+        "S" if degree == 4 && neighbours.terminal(atom_id, &["O"]) > 1 => "O2",
+        "S" if degree == 3 && neighbours.terminal(atom_id, &["O"]) > 0 => "O",
+        "S" if degree == 1 || (orders_known && double > 0) => "2",
+        "S" => "3",
+        "P" => "3",
+        "Cr" | "Co" => "oh",
+        _ => return Some(symbol.to_string()),
+    };
+    Some(format!("{}.{}", symbol, hybridization))
+}
+
+pub fn assign_sybyl_types(mol2: &mut Mol2) -> usize {
+    // Set atom types from the elements of the current types and the bond
+    // graph, returns the number of atoms with a new type. Bond orders are
+    // used when the structure has any multiple or aromatic bonds, otherwise
+    // hybridization follows from the number of bonded atoms, which needs
+    // all hydrogens to be present. Atoms without an element keep their type.
+    let neighbours = Neighbours::new(mol2);
+    let orders_known = mol2
+        .bond
+        .iter()
+        .any(|bond| matches!(bond.bond_type.as_str(), "2" | "3" | "ar" | "am"));
+    let aromatic = match orders_known {
+        true => aromatic_atoms(mol2),
+        false => connectivity_aromatic_atoms(&neighbours, &rings(mol2)),
+    };
+    let mut changed = 0;
+    for atom in mol2.atom.iter_mut() {
+        if let Some(atom_type) = sybyl_type(&neighbours, atom.atom_id, orders_known, &aromatic) {
+            if atom.atom_type != atom_type {
+                atom.atom_type = atom_type;
+                changed += 1;
+            }
+        }
+    }
+    changed
+}
@@ -0,0 +1,191 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::{IdInt, Mol2};
+use pyo3::prelude::*;
+use std::collections::HashSet;
+
+// Atom types of the Tripos mol2 format specification
+static SYBYL_ATOM_TYPES: [&str; 53] = [
+    "C.3", "C.2", "C.1", "C.ar", "C.cat", "N.3", "N.2", "N.1", "N.ar", "N.am", "N.pl3", "N.4",
+    "O.3", "O.2", "O.co2", "O.spc", "O.t3p", "S.3", "S.2", "S.O", "S.O2", "P.3", "F", "H", "H.spc",
+    "H.t3p", "LP", "Du", "Du.C", "Any", "Hal", "Het", "Hev", "Li", "Na", "Mg", "Al", "Si", "K",
+    "Ca", "Cr.th", "Cr.oh", "Mn", "Fe", "Co.oh", "Cu", "Cl", "Br", "I", "Zn", "Se", "Mo", "Sn",
+];
+
+// Bond types of the Tripos mol2 format specification
+static SYBYL_BOND_TYPES: [&str; 8] = ["1", "2", "3", "am", "ar", "du", "un", "nc"];
+
+// Single inconsistency found in a structure
+#[pyclass]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    // Section the offending record belongs to
+    #[pyo3(get)]
+    section: String,
+    // Id of the offending record, None for problems with the section as a whole
+    #[pyo3(get)]
+    id: Option<IdInt>,
+    #[pyo3(get)]
+    message: String,
+}
+
+impl Violation {
+    fn new(section: &str, id: Option<IdInt>, message: String) -> Violation {
+        Violation {
+            section: section.to_owned(),
+            id,
+            message,
+        }
+    }
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.id {
+            Some(id) => write!(f, "{} {}: {}", self.section, id, self.message),
+            None => write!(f, "{}: {}", self.section, self.message),
+        }
+    }
+}
+
+#[pymethods]
+impl Violation {
+    fn __str__(&self) -> String {
+        self.to_string()
+    }
+}
+
+fn check_count(section: &str, declared: Option<usize>, found: usize) -> Option<Violation> {
+    // Compare a count from the MOLECULE section with the number of records
+    // Input:
+    //     section: name of the counted section
+    //     declared: count given in the MOLECULE section, None if missing
+    //     found: number of records actually read
+    match declared {
+        Some(declared) if declared != found => Some(Violation::new(
+            "MOLECULE",
+            None,
+            format!(
+                "declares {} {} records but {} were found",
+                declared, section, found
+            ),
+        )),
+        _ => None,
+    }
+}
+
+pub fn validate(mol2: &Mol2) -> Vec<Violation> {
+    // Cross-check counts and references within a single structure
+    // Input:
+    //     mol2: structure to check
+    let mut violations: Vec<Violation> = Vec::new();
+
+    match &mol2.molecule {
+        None => violations.push(Violation::new(
+            "MOLECULE",
+            None,
+            "missing MOLECULE section".to_owned(),
+        )),
+        Some(molecule) => violations.extend(
+            [
+                check_count("ATOM", molecule.num_atoms, mol2.atom.len()),
+                check_count("BOND", molecule.num_bonds, mol2.bond.len()),
+                check_count("SUBSTRUCTURE", molecule.num_subst, mol2.substructure.len()),
+            ]
+            .into_iter()
+            .flatten(),
+        ),
+    }
+
+    let atom_ids: HashSet<IdInt> = mol2.atom.iter().map(|atom| atom.atom_id).collect();
+    let subst_ids: HashSet<IdInt> = mol2
+        .substructure
+        .iter()
+        .map(|substructure| substructure.subst_id)
+        .collect();
+
+    // SUBSTRUCTURE section is optional, substructure ids of atoms can
+    // only be checked when it is present
+    if !mol2.substructure.is_empty() {
+        for atom in &mol2.atom {
+            if let Some(subst_id) = atom.subst_id {
+                if !subst_ids.contains(&subst_id) {
+                    violations.push(Violation::new(
+                        "ATOM",
+                        Some(atom.atom_id),
+                        format!("references missing substructure {}", subst_id),
+                    ));
+                }
+            }
+        }
+    }
+    for bond in &mol2.bond {
+        for (end, atom_id) in [
+            ("origin", bond.origin_atom_id),
+            ("target", bond.target_atom_id),
+        ] {
+            if !atom_ids.contains(&atom_id) {
+                violations.push(Violation::new(
+                    "BOND",
+                    Some(bond.bond_id),
+                    format!("{} atom {} does not exist", end, atom_id),
+                ));
+            }
+        }
+    }
+    for substructure in &mol2.substructure {
+        if !atom_ids.contains(&substructure.root_atom) {
+            violations.push(Violation::new(
+                "SUBSTRUCTURE",
+                Some(substructure.subst_id),
+                format!("root atom {} does not exist", substructure.root_atom),
+            ));
+        }
+    }
+
+    violations
+}
+
+pub fn validate_types(mol2: &Mol2) -> Vec<Violation> {
+    // Check atom and bond types against those of the mol2 format
+    // specification. Atom types are compared regardless of case, as both
+    // Cl and CL or S.o2 and S.O2 are common.
+    // Input:
+    //     mol2: structure to check
+    let mut violations: Vec<Violation> = Vec::new();
+    for atom in &mol2.atom {
+        if !SYBYL_ATOM_TYPES
+            .iter()
+            .any(|atom_type| atom_type.eq_ignore_ascii_case(&atom.atom_type))
+        {
+            violations.push(Violation::new(
+                "ATOM",
+                Some(atom.atom_id),
+                format!("unknown atom type {}", atom.atom_type),
+            ));
+        }
+    }
+    for bond in &mol2.bond {
+        if !SYBYL_BOND_TYPES.contains(&bond.bond_type.as_str()) {
+            violations.push(Violation::new(
+                "BOND",
+                Some(bond.bond_id),
+                format!("unknown bond type {}", bond.bond_type),
+            ));
+        }
+    }
+    violations
+}
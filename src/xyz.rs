@@ -0,0 +1,148 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::error::{Mol2Error, Result};
+use crate::sdf::element;
+use crate::{Atom, CoordFloat, IdInt, Mol2, Molecule};
+use std::collections::HashMap;
+use std::io::Write;
+
+pub fn write_xyz_entry<W: Write>(mol2: &Mol2, writer: &mut W) -> Result<()> {
+    // Write a structure as a single XYZ frame, the comment line is the molecule name
+    // Input:
+    //     mol2: structure to write
+    //     writer: where to write to
+    let name = match &mol2.molecule {
+        Some(molecule) => molecule.mol_name.as_str(),
+        None => "",
+    };
+    writeln!(writer, "{}", mol2.atom.len())?;
+    writeln!(writer, "{}", name)?;
+    for atom in &mol2.atom {
+        // Atoms without an element are written as dummy atoms
+        let symbol = match element(&atom.atom_type) {
+            "*" => "X",
+            symbol => symbol,
+        };
+        writeln!(
+            writer,
+            "{:<2} {:>14.6} {:>14.6} {:>14.6}",
+            symbol, atom.x, atom.y, atom.z
+        )?;
+    }
+    Ok(())
+}
+
+fn parse_error(line: usize, message: String) -> Mol2Error {
+    Mol2Error::Parse { line, message }
+}
+
+pub fn read_xyz_text(text: &str, name: &str, desc: &str, comment: &str) -> Result<Vec<Mol2>> {
+    // Translate XYZ frames into structures without bonds
+    // Input:
+    //     text: content of an XYZ file
+    //     name: molecule name used when the comment line of a frame is empty
+    //     desc: description added to all structures
+    //     comment: comment added to the molecule comment field
+    let mut mol2_list: Vec<Mol2> = Vec::new();
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line));
+    while let Some((number, line)) = lines.next() {
+        // Blank lines between frames are skipped
+        if line.trim().is_empty() {
+            continue;
+        }
+        let num_atoms: usize = line.trim().parse().map_err(|_| {
+            parse_error(
+                number,
+                format!("Failed to parse number of atoms from \"{}\"", line.trim()),
+            )
+        })?;
+        let frame_name = match lines.next() {
+            Some((_, line)) if !line.trim().is_empty() => line.trim(),
+            Some(_) => name,
+            None => return Err(parse_error(number, "Missing comment line".to_owned())),
+        };
+
+        let mut mol2 = Mol2::new(desc);
+        mol2.molecule = Some(Molecule {
+            mol_name: frame_name.to_owned(),
+            num_atoms: Some(num_atoms),
+            num_bonds: Some(0),
+            num_subst: None,
+            num_feat: None,
+            num_sets: None,
+            mol_type: Some("SMALL".to_owned()),
+            charge_type: Some("NO_CHARGES".to_owned()),
+            status_bits: None,
+            mol_comment: None,
+        });
+        let mut counters: HashMap<String, usize> = HashMap::new();
+        for index in 0..num_atoms {
+            let (number, line) = lines.next().ok_or_else(|| {
+                parse_error(number, format!("Expected {} atoms in the frame", num_atoms))
+            })?;
+            // Columns after the coordinates, e.g. forces, are ignored
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.len() < 4 {
+                return Err(parse_error(
+                    number,
+                    format!("Incomplete atom line \"{}\"", line),
+                ));
+            }
+            let coordinate = |word: &str, axis: &str| -> Result<CoordFloat> {
+                word.parse::<CoordFloat>().map_err(|_| {
+                    parse_error(
+                        number,
+                        format!("Failed to parse {} coordinate from \"{}\"", axis, word),
+                    )
+                })
+            };
+            // Element symbols are normalized, e.g. "CL" to "Cl"
+            let mut chars = words[0].chars();
+            let symbol = match chars.next() {
+                Some(first) => {
+                    first.to_ascii_uppercase().to_string() + &chars.as_str().to_ascii_lowercase()
+                }
+                None => String::new(),
+            };
+            let counter = counters.entry(symbol.clone()).or_insert(0);
+            *counter += 1;
+            let atom_id = IdInt::try_from(index + 1)
+                .map_err(|_| parse_error(number, "Too many atoms in a single frame".to_owned()))?;
+            mol2.atom.push(Atom {
+                atom_id,
+                atom_name: format!("{}{}", symbol, counter),
+                x: coordinate(words[1], "x")?,
+                y: coordinate(words[2], "y")?,
+                z: coordinate(words[3], "z")?,
+                atom_type: match symbol.as_str() {
+                    "X" => "Du".to_owned(),
+                    _ => symbol,
+                },
+                subst_id: None,
+                subst_name: None,
+                charge: None,
+                status_bit: None,
+            });
+        }
+        mol2.add_comment(comment);
+        mol2_list.push(mol2);
+    }
+
+    Ok(mol2_list)
+}
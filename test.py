@@ -1,19 +1,36 @@
 #!/usr/bin/env python3
 import serde_mol2
 import argparse
+import sys
 
 def main(args):
     '''main...'''
 
-    if args.input and args.sqlite:
-        serde_mol2.read_file_to_db_batch(args.input, args.sqlite, shm = not args.no_shm, desc = args.desc, comment = args.comment, compression = int(args.compress))
+    serde_mol2.db_pragmas(wal = args.wal, synchronous = args.synchronous, cache_size = None if args.cache_size is None else int(args.cache_size), mmap_size = None if args.mmap_size is None else int(args.mmap_size))
+    if args.tmp_dir:
+        serde_mol2.db_tmp_dir(args.tmp_dir)
+
+    if args.input and args.sqlite and args.format == 'xyz':
+        for filename in args.input:
+            serde_mol2.db_insert(serde_mol2.read_xyz(filename, desc = args.desc, comment = args.comment), args.sqlite, compression = int(args.compress), shm = not args.no_shm, codec = args.codec)
+    elif args.input and args.sqlite:
+        skipped = serde_mol2.read_file_to_db_batch(args.input, args.sqlite, shm = not args.no_shm, desc = args.desc, comment = args.comment, compression = int(args.compress), threads = int(args.threads), mode = args.parse_mode, keep_source = args.keep_source, codec = args.codec)
+        for entry in skipped:
+            print(f"Skipped {entry}", file=sys.stderr)
+
+    if args.merge and args.sqlite:
+        for filename in args.merge:
+            serde_mol2.db_merge(filename, args.sqlite, on_conflict = args.on_conflict, shm = not args.no_shm)
 
     if args.output and args.sqlite:
-        m = serde_mol2.read_db_all(args.sqlite, desc = args.desc, comment = args.comment, limit = int(args.limit), offset = int(args.offset))
-        serde_mol2.write_mol2(m, args.output)
+        m = serde_mol2.read_db_all(args.sqlite, desc = args.desc, comment = args.comment, limit = int(args.limit), offset = int(args.offset), filter_mode = args.filter_mode, order_by = args.order_by, min_atoms = int(args.min_atoms), max_atoms = int(args.max_atoms), min_bonds = int(args.min_bonds), max_bonds = int(args.max_bonds), where_extras = args.where_extras, tag = args.tag, source_file = args.source_file, inserted_after = args.inserted_after, inserted_before = args.inserted_before, threads = int(args.threads), read_only = args.read_only)
+        if args.format == 'xyz':
+            serde_mol2.write_xyz(m, args.output, append = args.append)
+        else:
+            serde_mol2.write_mol2(m, args.output, append = args.append, aligned = args.aligned, coord_precision = int(args.coord_precision), charge_precision = int(args.charge_precision))
 
     if args.list_desc and args.sqlite:
-        for desc in serde_mol2.desc_list(args.sqlite):
+        for desc in serde_mol2.desc_list(args.sqlite, read_only = args.read_only):
             print(desc)
 
 if __name__ == "__main__":
@@ -40,6 +57,30 @@ if __name__ == "__main__":
         default='3',
         help="Sqlite database to write to"
     )
+    parser.add_argument(
+        '--codec',
+        default='zstd',
+        choices=['none', 'zstd', 'lz4', 'gzip'],
+        help="Codec compressing BLOB data"
+    )
+    parser.add_argument(
+        '-t',
+        '--threads',
+        default='1',
+        help="Number of threads parsing input files. Zero means all cores."
+    )
+    parser.add_argument(
+        '--parse-mode',
+        default='strict',
+        choices=['strict', 'lenient'],
+        help="Fail on malformed lines (strict) or skip structures containing them (lenient)"
+    )
+    parser.add_argument(
+        '--format',
+        default='mol2',
+        choices=['mol2', 'xyz'],
+        help="Format of input and output files"
+    )
     parser.add_argument(
         '--limit',
         default='0',
@@ -48,13 +89,134 @@ if __name__ == "__main__":
     parser.add_argument(
         '--offset',
         default='0',
-        help="Offset when limiting the number of structures retrieved from the database. Zero means no offset."
+        help="Skip this many matching structures when reading from the database. Zero means no offset."
+    )
+    parser.add_argument(
+        '--filter-mode',
+        default='substring',
+        choices=['substring', 'glob', 'regex'],
+        help="How --desc and --comment filter structures read from the database"
+    )
+    parser.add_argument(
+        '--order-by',
+        default='id',
+        help="Order of structures read from the database, by id, name, num_atoms, desc or a property column. Ties are kept in insertion order."
+    )
+    parser.add_argument(
+        '--min-atoms',
+        default='0',
+        help="Minimum number of atoms of structures read from the database."
+    )
+    parser.add_argument(
+        '--max-atoms',
+        default='0',
+        help="Maximum number of atoms of structures read from the database. Zero means no maximum."
+    )
+    parser.add_argument(
+        '--min-bonds',
+        default='0',
+        help="Minimum number of bonds of structures read from the database."
+    )
+    parser.add_argument(
+        '--max-bonds',
+        default='0',
+        help="Maximum number of bonds of structures read from the database. Zero means no maximum."
+    )
+    parser.add_argument(
+        '--where-extras',
+        default='',
+        help="Only read structures with matching extras from the database, e.g. \"$.vendor == 'enamine'\""
+    )
+    parser.add_argument(
+        '--tag',
+        default='',
+        help="Only read structures with this tag from the database"
+    )
+    parser.add_argument(
+        '--source-file',
+        default='',
+        help="Only read structures from a file with the path containing this from the database"
+    )
+    parser.add_argument(
+        '--inserted-after',
+        default='',
+        help="Only read structures inserted at or after this UTC time from the database, e.g. \"2024-01-01\""
+    )
+    parser.add_argument(
+        '--inserted-before',
+        default='',
+        help="Only read structures inserted before this UTC time from the database, e.g. \"2024-01-01 12:00\""
+    )
+    parser.add_argument(
+        '-a',
+        '--append',
+        action="store_true",
+        help="Append to mol2 files when writing rather than truncate"
+    )
+    parser.add_argument(
+        '--aligned',
+        action="store_true",
+        help="Write mol2 records in fixed-width columns"
+    )
+    parser.add_argument(
+        '--coord-precision',
+        default='4',
+        help="Number of decimals of atom coordinates in written mol2 files"
+    )
+    parser.add_argument(
+        '--charge-precision',
+        default='4',
+        help="Number of decimals of atom charges in written mol2 files"
+    )
+    parser.add_argument(
+        '--keep-source',
+        action="store_true",
+        help="Store the original text of structures to write them back unchanged"
     )
     parser.add_argument(
         '--no-shm',
         action="store_true",
         help="Do not use shm device for temporary storage"
     )
+    parser.add_argument(
+        '--tmp-dir',
+        help="Directory for temporary copies of databases instead of shm"
+    )
+    parser.add_argument(
+        '--read-only',
+        action="store_true",
+        help="Open the database read-only where it is"
+    )
+    parser.add_argument(
+        '--wal',
+        action="store_true",
+        help="Switch the database to the write-ahead log journal mode"
+    )
+    parser.add_argument(
+        '--synchronous',
+        default='',
+        choices=['', 'off', 'normal', 'full', 'extra'],
+        help="How carefully SQLite syncs the database to disk"
+    )
+    parser.add_argument(
+        '--cache-size',
+        help="SQLite page cache size in pages, or in KiB if negative"
+    )
+    parser.add_argument(
+        '--mmap-size',
+        help="Memory map up to this many bytes of the database"
+    )
+    parser.add_argument(
+        '--merge',
+        nargs='+',
+        help="Databases to copy into the --sqlite database"
+    )
+    parser.add_argument(
+        '--on-conflict',
+        default='append',
+        choices=['append', 'skip_duplicates'],
+        help="Copy every structure when merging or leave out ones already in the database"
+    )
     parser.add_argument(
         '--list-desc',
         action="store_true",
@@ -10,6 +10,21 @@ exit_handler() {
 }
 trap exit_handler EXIT
 
+baseline_db() {
+    # Copy the structures of database $1 into a new database $2 with the
+    # layout of 0.1.x, from before columns were added and versions recorded
+    python3 - "$1" "$2" <<'EOF'
+import sqlite3
+import sys
+
+db = sqlite3.connect(sys.argv[2])
+db.execute("CREATE TABLE structures (id INTEGER PRIMARY KEY, mol_name TEXT, num_atoms INTEGER, num_bonds INTEGER, num_subst INTEGER, num_feat INTEGER, num_sets INTEGER, mol_type TEXT, charge_type TEXT, status_bits TEXT, mol_comment TEXT, atom BLOB, bond BLOB, substructure BLOB, extras BLOB, compression INTEGER, desc TEXT)")
+db.execute("ATTACH DATABASE ? AS src", (sys.argv[1],))
+db.execute("INSERT INTO structures SELECT id, mol_name, num_atoms, num_bonds, num_subst, num_feat, num_sets, mol_type, charge_type, status_bits, mol_comment, atom, bond, substructure, extras, compression, desc FROM src.structures")
+db.commit()
+EOF
+}
+
 for binary in ./test.py ./target/release/serde-mol2 ; do
     error="(${binary}) Failed simple reading mol2 to a db"
     "${binary}" -i example.mol2 -s db-py-simple.sqlite
@@ -55,9 +70,200 @@ for binary in ./test.py ./target/release/serde-mol2 ; do
     [ "$(grep -c MOLECULE out.mol2)" == 2 ]
     grep -q desc1 out.mol2
     grep -q desc2 out.mol2
+    error="(${binary}) Failed parallel reading of a batch of files"
+    "${binary}" -i example.mol2 example.mol2 example.mol2 -s db-py-threads.sqlite --threads 2
+    "${binary}" -o out.mol2 -s db-py-threads.sqlite
+    [ "$(grep -c MOLECULE out.mol2)" == "$(( 3 * $(grep -c MOLECULE example.mol2) ))" ]
+    error="(${binary}) Failed parallel reading of a single file"
+    cat example.mol2 example.mol2 example.mol2 > threads.mol2
+    "${binary}" -i threads.mol2 -s db-py-chunks.sqlite --threads 2
+    "${binary}" -o out.mol2 -s db-py-chunks.sqlite
+    [ "$(grep -c MOLECULE out.mol2)" == "$(( 3 * $(grep -c MOLECULE example.mol2) ))" ]
+    error="(${binary}) Failed skipping a malformed structure in the lenient parse mode"
+    sed '0,/^      1 /s/^      1 /      x /' example.mol2 > malformed.mol2
+    cat malformed.mol2 example.mol2 > lenient.mol2
+    ! "${binary}" -i lenient.mol2 -s db-py-strict.sqlite 2> /dev/null
+    "${binary}" -i lenient.mol2 -s db-py-lenient.sqlite --parse-mode lenient
+    "${binary}" -o out.mol2 -s db-py-lenient.sqlite
+    [ "$(grep -c MOLECULE out.mol2)" == "$(grep -c MOLECULE example.mol2)" ]
+    error="(${binary}) Failed writing and reading back aligned mol2"
+    "${binary}" -o aligned.mol2 -s db-py-simple.sqlite --aligned
+    grep -q '^      1 MG  ' aligned.mol2
+    "${binary}" -i aligned.mol2 -s db-py-aligned.sqlite
+    "${binary}" -o out.mol2 -s db-py-aligned.sqlite
+    [ "$(grep -c MOLECULE out.mol2)" == "$(grep -c MOLECULE example.mol2)" ]
+    error="(${binary}) Failed writing mol2 with a given precision"
+    "${binary}" -o out.mol2 -s db-py-simple.sqlite --coord-precision 2 --charge-precision 1
+    grep -q '^1 MG -1.09 0.80 -6.28 Mg 1 MG340 0.0$' out.mol2
+    error="(${binary}) Failed reproducing the input file with the original text kept"
+    "${binary}" -i threads.mol2 -s db-py-source.sqlite --keep-source
+    "${binary}" -o out.mol2 -s db-py-source.sqlite
+    cmp -s out.mol2 threads.mol2
+    error="(${binary}) Failed writing and reading back compressed mol2"
+    "${binary}" -o out.mol2.zst -s db-py-simple.sqlite
+    "${binary}" -i out.mol2.zst -s db-py-zstd.sqlite
+    "${binary}" -o out.mol2 -s db-py-zstd.sqlite
+    [ "$(grep -c MOLECULE out.mol2)" == "$(grep -c MOLECULE example.mol2)" ]
+    error="(${binary}) Failed appending to the same mol2 file from several processes"
+    for _ in 1 2 3 4 ; do
+        "${binary}" -o append.mol2 -s db-py-threads.sqlite -a &
+    done
+    wait
+    "${binary}" -i append.mol2 -s db-py-append.sqlite
+    "${binary}" -o out.mol2 -s db-py-append.sqlite
+    [ "$(grep -c MOLECULE out.mol2)" == "$(( 12 * $(grep -c MOLECULE example.mol2) ))" ]
+    error="(${binary}) Failed writing and reading back xyz"
+    "${binary}" -o out.xyz -s db-py-simple.sqlite --format xyz
+    [ "$(head -1 out.xyz)" == "$(grep -A2 MOLECULE example.mol2 | sed -n '3p' | awk '{print $1}')" ]
+    "${binary}" -i out.xyz -s db-py-xyz.sqlite --format xyz
+    "${binary}" -o out.mol2 -s db-py-xyz.sqlite
+    [ "$(grep -c MOLECULE out.mol2)" == "$(grep -c MOLECULE example.mol2)" ]
+    error="(${binary}) Failed writing and reading back rows of every codec"
+    for codec in none zstd lz4 gzip ; do
+        "${binary}" -i example.mol2 -s "db-py-codec-${codec}.sqlite" --codec "${codec}"
+        "${binary}" -o out.mol2 -s "db-py-codec-${codec}.sqlite"
+        [ "$(grep -c MOLECULE out.mol2)" == "$(grep -c MOLECULE example.mol2)" ]
+        grep -q '^1 MG -1.0950 0.8010 -6.2770 Mg 1 MG340 0.0000$' out.mol2
+    done
+    error="(${binary}) Failed keeping the CRYSIN section in a db"
+    cp example.mol2 crysin.mol2
+    printf '@<TRIPOS>CRYSIN\n   10.0000   20.0000   30.0000   90.0000   90.0000   90.0000 1 1\n' >> crysin.mol2
+    "${binary}" -i crysin.mol2 -s db-py-crysin.sqlite
+    "${binary}" -o out.mol2 -s db-py-crysin.sqlite
+    grep -A1 '^@<TRIPOS>CRYSIN$' out.mol2 | grep -q '^10.0000 20.0000 30.0000 90.0000 90.0000 90.0000 1 1$'
+
+    error="(${binary}) Failed reading a database of an older version read-only"
+    baseline_db db-py-desc.sqlite db-py-baseline.sqlite
+    cp db-py-baseline.sqlite baseline.sqlite.orig
+    "${binary}" -o out.mol2 -s db-py-baseline.sqlite --read-only --desc example
+    [ "$(grep -c MOLECULE out.mol2)" == "$(grep -c MOLECULE example.mol2)" ]
+    "${binary}" -o out.mol2 -s db-py-baseline.sqlite --read-only --tag something
+    [ "$(grep -c MOLECULE out.mol2)" == 0 ]
+    cmp -s db-py-baseline.sqlite baseline.sqlite.orig
+    error="(${binary}) Failed merging databases"
+    "${binary}" -s db-py-merged.sqlite --merge db-py-simple.sqlite db-py-desc.sqlite
+    "${binary}" -o out.mol2 -s db-py-merged.sqlite
+    [ "$(grep -c MOLECULE out.mol2)" == "$(( 2 * $(grep -c MOLECULE example.mol2) ))" ]
+    "${binary}" -o out.mol2 -s db-py-merged.sqlite --desc example
+    [ "$(grep -c MOLECULE out.mol2)" == "$(grep -c MOLECULE example.mol2)" ]
+    error="(${binary}) Failed merging databases without duplicates, or from an older version"
+    "${binary}" -s db-py-merged.sqlite --merge db-py-baseline.sqlite --on-conflict skip_duplicates
+    "${binary}" -o out.mol2 -s db-py-merged.sqlite
+    [ "$(grep -c MOLECULE out.mol2)" == "$(( 2 * $(grep -c MOLECULE example.mol2) ))" ]
+    "${binary}" -s db-py-merged.sqlite --merge db-py-baseline.sqlite --on-conflict append
+    "${binary}" -o out.mol2 -s db-py-merged.sqlite --desc example
+    [ "$(grep -c MOLECULE out.mol2)" == "$(( 2 * $(grep -c MOLECULE example.mol2) ))" ]
+    cmp -s db-py-baseline.sqlite baseline.sqlite.orig
+    error="(${binary}) Failed concurrent inserts into databases copied to the same temporary directory"
+    mkdir scratch
+    pids=()
+    for i in 1 2 3 4 ; do
+        "${binary}" -i example.mol2 -s "db-py-scratch-${i}.sqlite" --tmp-dir scratch &
+        pids+=("$!")
+    done
+    for pid in "${pids[@]}" ; do
+        wait "${pid}"
+    done
+    [ -z "$(ls -A scratch)" ]
+    for i in 1 2 3 4 ; do
+        "${binary}" -o out.mol2 -s "db-py-scratch-${i}.sqlite"
+        [ "$(grep -c MOLECULE out.mol2)" == "$(grep -c MOLECULE example.mol2)" ]
+    done
+    rmdir scratch
+    error="(${binary}) Failed reading a database written in the write-ahead log journal mode"
+    "${binary}" -i example.mol2 -s db-py-wal.sqlite --wal
+    "${binary}" -i example.mol2 -s db-py-wal.sqlite --wal
+    [ "$(python3 -c 'import sqlite3; print(sqlite3.connect("db-py-wal.sqlite").execute("PRAGMA journal_mode").fetchone()[0])')" == wal ]
+    "${binary}" -o out.mol2 -s db-py-wal.sqlite
+    [ "$(grep -c MOLECULE out.mol2)" == "$(( 2 * $(grep -c MOLECULE example.mol2) ))" ]
 
     rm -- *.sqlite
-    rm out.mol2
+    rm threads.mol2 malformed.mol2 lenient.mol2 aligned.mol2 append.mol2 crysin.mol2 baseline.sqlite.orig
+    rm out.mol2 out.mol2.zst out.xyz
 done
 
+error="(./test.py) Failed keeping several inserts in one transaction of a Mol2Db"
+python3 - <<'EOF'
+import serde_mol2
+
+mol2_list = serde_mol2.read_file("example.mol2")
+n = len(mol2_list)
+filename = "db-py-transaction.sqlite"
+serde_mol2.db_insert(mol2_list, filename)
+with serde_mol2.Mol2Db(filename, shm=True) as db:
+    # Uncommitted inserts are seen through the same connection only
+    db.begin()
+    db.insert(mol2_list)
+    db.insert(mol2_list, source_file="rolled_back.mol2")
+    assert db.count() == 3 * n
+    assert len(db.query()) == 3 * n
+    assert len(list(db.iter(batch_size=1))) == 3 * n
+    db.rollback()
+    assert db.count() == n
+    assert db.count(source_file="rolled_back.mol2") == 0
+    db.begin()
+    db.insert(mol2_list)
+    db.commit()
+    assert db.count() == 2 * n
+    # Left open, committed when the block finishes
+    db.begin()
+    db.insert(mol2_list)
+    # The temporary copy is only copied back on close
+    assert serde_mol2.db_count(filename, read_only=True) == n
+assert serde_mol2.db_count(filename, read_only=True) == 3 * n
+try:
+    with serde_mol2.Mol2Db(filename) as db:
+        db.begin()
+        db.insert(mol2_list, source_file="failed.mol2")
+        raise KeyError("failed")
+except KeyError:
+    pass
+assert serde_mol2.db_count(filename) == 3 * n
+assert serde_mol2.db_count(filename, source_file="failed.mol2") == 0
+try:
+    db.count()
+    raise AssertionError("closed database was used")
+except ValueError:
+    pass
+EOF
+rm db-py-transaction.sqlite
+
+error="(./test.py) Failed inserting and reading back structures compressed with a trained dictionary"
+python3 - <<'EOF'
+import serde_mol2
+
+smiles = ["C" * n for n in range(1, 30)] + ["c1ccccc1" + "C" * n for n in range(20)]
+mol2_list = [serde_mol2.Mol2.from_smiles(s) for s in smiles]
+filename = "db-py-dictionary.sqlite"
+serde_mol2.db_insert(mol2_list, filename)
+serde_mol2.db_train_dictionary(filename, max_size=4096)
+serde_mol2.db_insert(mol2_list, filename)
+back = serde_mol2.read_db_all(filename)
+assert len(back) == 2 * len(mol2_list)
+# Rows from before the training keep their compression
+assert [m.to_smiles() for m in back] == 2 * [m.to_smiles() for m in mol2_list]
+assert len(list(serde_mol2.read_db_iter(filename, batch_size=7))) == len(back)
+EOF
+rm db-py-dictionary.sqlite
+
+error="(./test.py) Failed migrating a database of an older version"
+./target/release/serde-mol2 -i example.mol2 -s db-py-current.sqlite --desc example
+baseline_db db-py-current.sqlite db-py-migrate.sqlite
+python3 - <<'EOF'
+import sqlite3
+import serde_mol2
+
+filename = "db-py-migrate.sqlite"
+before = [m.as_string() for m in serde_mol2.read_db_all(filename, read_only=True)]
+assert serde_mol2.db_migrate(filename) == 0
+columns = [row[1] for row in sqlite3.connect(filename).execute("PRAGMA table_info(structures)")]
+assert "codec" in columns and "crysin" in columns
+assert serde_mol2.db_migrate(filename) == serde_mol2.db_migrate(filename) > 0
+assert [m.as_string() for m in serde_mol2.read_db_all(filename, read_only=True)] == before
+serde_mol2.db_insert(serde_mol2.read_file("example.mol2", desc="migrated"), filename)
+assert serde_mol2.db_count(filename, desc="migrated") == len(before)
+assert serde_mol2.db_count(filename, desc="example") == len(before)
+EOF
+rm db-py-current.sqlite db-py-migrate.sqlite
+
 ok=1
@@ -0,0 +1,281 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Known answers for the graph, SMILES, hashing and geometry functions
+
+use serde_mol2::{deduplicate, read_file, AtomOrder, Mol2, Mol2Builder, ReadOptions};
+
+fn smiles(smiles: &str) -> Mol2 {
+    Mol2::from_smiles(smiles).expect("Failed to parse the SMILES")
+}
+
+fn coordinates(mol2: &Mol2) -> Vec<[f64; 3]> {
+    // Coordinates of all atoms in file order
+    mol2.atoms()
+        .iter()
+        .map(|atom| [atom.x, atom.y, atom.z])
+        .collect()
+}
+
+fn assert_close(value: f64, expected: f64) {
+    assert!(
+        (value - expected).abs() < 1e-3,
+        "{} is not close to {}",
+        value,
+        expected
+    );
+}
+
+#[test]
+fn naphthalene_has_two_six_membered_rings() {
+    let rings = smiles("c1ccc2ccccc2c1").rings();
+    assert_eq!(rings.len(), 2);
+    assert!(rings.iter().all(|ring| ring.len() == 6));
+}
+
+#[test]
+fn cubane_has_five_four_membered_rings() {
+    // Six faces, but only five of them are independent
+    let rings = smiles("C12C3C4C1C5C2C3C45").rings();
+    assert_eq!(rings.len(), 5);
+    assert!(rings.iter().all(|ring| ring.len() == 4));
+}
+
+#[test]
+fn acyclic_structures_have_no_rings() {
+    assert!(smiles("CC(=O)O").rings().is_empty());
+}
+
+#[test]
+fn sections_are_borrowed() {
+    let mol2 = smiles("CC(=O)O");
+    let types: Vec<_> = mol2
+        .atoms()
+        .iter()
+        .map(|atom| atom.atom_type.as_str())
+        .collect();
+    assert_eq!(types, ["C.3", "C.2", "O.2", "O.3", "H", "H", "H", "H"]);
+    let double = mol2
+        .bonds()
+        .iter()
+        .find(|bond| bond.bond_type == "2")
+        .unwrap();
+    assert_eq!((double.origin_atom_id, double.target_atom_id), (2, 3));
+    assert_eq!(mol2.molecule().unwrap().num_atoms, Some(8));
+}
+
+#[test]
+fn adjacency_follows_edits() {
+    let mut mol2 = smiles("CCO");
+    assert_eq!(mol2.neighbors(1).unwrap()[0], 2);
+    assert_eq!(mol2.degree(1).unwrap(), 4);
+    // Removing the second carbon leaves the first one with its hydrogens
+    mol2.remove_atoms(vec![2]).unwrap();
+    assert_eq!(mol2.degree(1).unwrap(), 3);
+    assert!(mol2
+        .adjacency()
+        .values()
+        .all(|neighbours| neighbours.len() <= 3));
+}
+
+#[test]
+fn malformed_ring_closures_are_rejected() {
+    for input in ["C11", "C1C1", "C1CC", "CC(C"] {
+        assert!(Mol2::from_smiles(input).is_err(), "{} was accepted", input);
+    }
+}
+
+#[test]
+fn smiles_round_trip() {
+    for input in ["CC(=O)O", "c1ccc2ccccc2c1", "C#CCC", "CCCc1ccccc1"] {
+        let output = smiles(input).to_smiles();
+        assert_eq!(output, input);
+        assert_eq!(smiles(&output).to_smiles(), output);
+    }
+}
+
+#[test]
+fn structure_hash_is_stable_under_renumbering() {
+    for input in [
+        "CC(=O)O",
+        "c1ccc2ccccc2c1",
+        "CCCc1ccccc1",
+        "C12C3C4C1C5C2C3C45",
+    ] {
+        let mol2 = smiles(input);
+        let mut reordered = mol2.clone();
+        reordered.sort_atoms(AtomOrder::Name);
+        reordered.renumber();
+        assert_eq!(
+            reordered.structure_hash(false, false),
+            mol2.structure_hash(false, false)
+        );
+        assert_eq!(reordered.to_smiles(), mol2.to_smiles());
+    }
+}
+
+#[test]
+fn structure_hash_tells_isomers_apart() {
+    assert_ne!(
+        smiles("CCCC").structure_hash(false, false),
+        smiles("CC(C)C").structure_hash(false, false)
+    );
+}
+
+#[test]
+fn structure_hash_tells_regular_graphs_apart() {
+    // Every atom has the same neighbourhood in each pair
+    for (first, second) in [
+        ("C1CC1.C1CC1", "C1CCCCC1"),
+        ("C1CCC2CCCCC2C1", "C1CCC(C1)C1CCCC1"),
+    ] {
+        assert_ne!(
+            smiles(first).structure_hash(false, false),
+            smiles(second).structure_hash(false, false)
+        );
+        let kept = deduplicate(vec![smiles(first), smiles(second)], false, false);
+        assert_eq!(kept.len(), 2);
+    }
+}
+
+#[test]
+fn structure_hash_is_stable() {
+    // Hashes are stored, so they must not change between versions
+    assert_eq!(
+        smiles("CCO").structure_hash(false, false),
+        "94f2154e9eb5a109"
+    );
+}
+
+#[test]
+fn rotatable_bonds() {
+    assert_eq!(smiles("CCCC").num_rotatable_bonds(), 1);
+    assert_eq!(smiles("CCCc1ccccc1").num_rotatable_bonds(), 2);
+    // Amide bonds, bonds next to triple bonds and ring bonds do not count
+    assert_eq!(smiles("CC(=O)NC").num_rotatable_bonds(), 0);
+    assert_eq!(smiles("C#CCC").num_rotatable_bonds(), 0);
+    assert_eq!(smiles("c1ccccc1").num_rotatable_bonds(), 0);
+}
+
+#[test]
+fn substructure_matching() {
+    let phenol = smiles("Oc1ccccc1");
+    assert!(phenol.contains_substructure(&smiles("c1ccccc1"), false));
+    // Bond types must match, a saturated ring is not aromatic
+    assert!(!phenol.contains_substructure(&smiles("C1CCCCC1"), false));
+    assert!(!smiles("C1CCCCC1").contains_substructure(&smiles("c1ccccc1"), false));
+
+    let ethanol = smiles("CCO");
+    let mapping = ethanol.match_atoms(&smiles("CO"), false).unwrap();
+    assert_eq!(mapping.len(), 2);
+    assert_eq!(mapping[&1], 2);
+    assert_eq!(mapping[&2], 3);
+    // With hydrogens the methyl group of methanol has one hydrogen too many
+    assert!(ethanol.match_atoms(&smiles("CO"), true).is_none());
+    assert!(ethanol.contains_substructure(&smiles("CCO"), true));
+}
+
+const CUBIC_CELL: [f64; 6] = [10.0, 10.0, 10.0, 90.0, 90.0, 90.0];
+
+fn split_molecule() -> Mol2 {
+    // Two bonded carbons on opposite sides of a cubic cell of 10 Å
+    Mol2Builder::new()
+        .name("SPLIT")
+        .atom(1, "C1", "C.3", [9.6, 5.0, 5.0])
+        .atom(2, "C2", "C.3", [0.4, 5.0, 5.0])
+        .bond(1, 2, "1")
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn unwrap_makes_split_molecules_whole() {
+    let mut mol2 = split_molecule();
+    assert_close(mol2.distance(1, 2).unwrap(), 9.2);
+    mol2.unwrap(Some(CUBIC_CELL)).unwrap();
+    assert_close(mol2.distance(1, 2).unwrap(), 0.8);
+    // The first atom stays where it was
+    assert_close(coordinates(&mol2)[0][0], 9.6);
+}
+
+#[test]
+fn wrap_moves_atoms_into_the_cell() {
+    let mut mol2 = split_molecule();
+    mol2.unwrap(Some(CUBIC_CELL)).unwrap();
+    mol2.translate(-10.0, 12.0, 0.0);
+    mol2.wrap(Some(CUBIC_CELL)).unwrap();
+    let coordinates = coordinates(&mol2);
+    assert_close(coordinates[0][0], 9.6);
+    assert_close(coordinates[1][0], 0.4);
+    assert_close(coordinates[0][1], 7.0);
+}
+
+#[test]
+fn wrap_rejects_invalid_cells() {
+    let mut mol2 = split_molecule();
+    assert!(mol2
+        .wrap(Some([10.0, 10.0, 0.0, 90.0, 90.0, 90.0]))
+        .is_err());
+    assert!(mol2
+        .wrap(Some([10.0, 10.0, 10.0, 90.0, 90.0, 180.0]))
+        .is_err());
+}
+
+#[test]
+fn wrap_defaults_to_the_crysin_cell() {
+    let mut mol2 = split_molecule();
+    assert!(mol2.wrap(None).is_err());
+    // Read back with a CRYSIN section of the cubic cell
+    let text = format!(
+        "{}@<TRIPOS>CRYSIN\n   10.0000   10.0000   10.0000   90.0000   90.0000   90.0000 1 1\n",
+        mol2.as_string(false, 4, 4)
+    );
+    let path = std::env::temp_dir().join(format!("crysin-{}.mol2", std::process::id()));
+    std::fs::write(&path, text).unwrap();
+    let (structures, _) = read_file(path.to_str().unwrap(), &ReadOptions::default()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let mut mol2 = structures.into_iter().next().unwrap();
+    assert!(mol2.as_string(false, 4, 4).contains("@<TRIPOS>CRYSIN\n"));
+    mol2.unwrap(None).unwrap();
+    assert_close(mol2.distance(1, 2).unwrap(), 0.8);
+    mol2.translate(-10.0, 0.0, 0.0);
+    mol2.wrap(None).unwrap();
+    assert_close(coordinates(&mol2)[0][0], 9.6);
+}
+
+#[test]
+fn principal_axes_of_a_linear_molecule() {
+    // Two carbons 1.5 Å apart along the diagonal, moments of inertia are
+    // zero along the bond and 2 * 12.011 * 0.75^2 across it
+    let offset = 0.75 / 3f64.sqrt();
+    let mut mol2 = Mol2Builder::new()
+        .name("DIAGONAL")
+        .atom(1, "C1", "C.3", [1.0 - offset, 2.0 - offset, 3.0 - offset])
+        .atom(2, "C2", "C.3", [1.0 + offset, 2.0 + offset, 3.0 + offset])
+        .bond(1, 2, "1")
+        .build()
+        .unwrap();
+    let (smallest, middle, largest) = mol2.align_principal_axes().unwrap();
+    assert_close(smallest, 0.0);
+    assert_close(middle, 13.512);
+    assert_close(largest, 13.512);
+    // The bond now lies along x, centered at the origin
+    for [x, y, z] in coordinates(&mol2) {
+        assert_close(x.abs(), 0.75);
+        assert_close(y, 0.0);
+        assert_close(z, 0.0);
+    }
+    assert_close(mol2.distance(1, 2).unwrap(), 1.5);
+}
@@ -0,0 +1,129 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Reading structures into databases and filtering them back out
+
+use serde_mol2::{
+    db_count, db_insert, db_update, read_db_all, read_file, read_file_to_db_batch, Codec, DbQuery,
+    ReadOptions,
+};
+
+// The second structure leaves the number of bonds out of the counts line
+const TWO_MOLECULES: &str = "@<TRIPOS>MOLECULE
+WATER
+3 2
+SMALL
+NO_CHARGES
+
+@<TRIPOS>ATOM
+      1 O1          0.0000    0.0000    0.0000 O.3       1  HOH1       0.0000
+      2 H1          0.9570    0.0000    0.0000 H         1  HOH1       0.0000
+      3 H2         -0.2400    0.9270    0.0000 H         1  HOH1       0.0000
+@<TRIPOS>BOND
+     1     1     2 1
+     2     1     3 1
+@<TRIPOS>MOLECULE
+ARGON
+1
+SMALL
+NO_CHARGES
+
+@<TRIPOS>ATOM
+      1 AR          0.0000    0.0000    0.0000 Ar        1  AR1        0.0000
+";
+
+fn scratch(name: &str) -> String {
+    // Path for a file of a test, removed if left over from an earlier run
+    let path = std::env::temp_dir().join(format!("serde-mol2-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    path.to_str().unwrap().to_owned()
+}
+
+#[test]
+fn structures_without_a_bond_count_are_read() {
+    let mol2_file = scratch("counts.mol2");
+    std::fs::write(&mol2_file, TWO_MOLECULES).unwrap();
+    let (structures, _) = read_file(&mol2_file, &ReadOptions::default()).unwrap();
+    let db = scratch("counts.sqlite");
+    let ids = db_insert(structures, &db, 3, false, false, "", Codec::Zstd).unwrap();
+    assert_eq!(ids, [1, 2]);
+    let all = DbQuery::default();
+    assert_eq!(read_db_all(&db, false, &all, 1, false).unwrap().len(), 2);
+    assert_eq!(db_count(&db, false, &all, false).unwrap(), 2);
+    // Only a bound on the bonds leaves out the structure without a count
+    let bonded = DbQuery {
+        min_bonds: 1,
+        ..Default::default()
+    };
+    assert_eq!(db_count(&db, false, &bonded, false).unwrap(), 1);
+    let small = DbQuery {
+        max_atoms: 2,
+        ..Default::default()
+    };
+    let read = read_db_all(&db, false, &small, 1, false).unwrap();
+    assert_eq!(read.len(), 1);
+    assert_eq!(read[0].to_xyz().unwrap().lines().count(), 3);
+    std::fs::remove_file(&mol2_file).unwrap();
+    std::fs::remove_file(&db).unwrap();
+}
+
+#[test]
+fn files_before_a_failing_one_are_kept() {
+    let good = scratch("good.mol2");
+    std::fs::write(&good, TWO_MOLECULES).unwrap();
+    let bad = scratch("bad.mol2");
+    std::fs::write(&bad, TWO_MOLECULES.replace("      1 AR", "      x AR")).unwrap();
+    let db = scratch("batch.sqlite");
+    let options = ReadOptions {
+        threads: 2,
+        ..Default::default()
+    };
+    let result = read_file_to_db_batch(
+        vec![&good, &bad],
+        &db,
+        3,
+        true,
+        false,
+        &options,
+        Codec::Zstd,
+    );
+    assert!(result.is_err());
+    assert_eq!(
+        read_db_all(&db, false, &DbQuery::default(), 1, false)
+            .unwrap()
+            .len(),
+        2
+    );
+    for path in [&good, &bad, &db] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn updated_structures_replace_the_row() {
+    let path = scratch("update.mol2");
+    std::fs::write(&path, TWO_MOLECULES).unwrap();
+    let (mut structures, _) = read_file(&path, &ReadOptions::default()).unwrap();
+    let argon = structures.pop().unwrap();
+    let db = scratch("update.sqlite");
+    let ids = db_insert(structures, &db, 3, false, false, "", Codec::Zstd).unwrap();
+    db_update(&db, ids[0], &argon, 3, false, Codec::Zstd).unwrap();
+    let read = read_db_all(&db, false, &DbQuery::default(), 1, false).unwrap();
+    assert_eq!(read.len(), 1);
+    assert_eq!(read[0].molecule().unwrap().mol_name, "ARGON");
+    for path in [&path, &db] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,250 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Edits of structures and comparisons between them
+
+use serde_mol2::{collapse_conformers, read_file, Conformers, Mol2, ReadOptions};
+
+// A water molecule and a sodium ion in substructures of their own
+const SOLVATED: &str = "@<TRIPOS>MOLECULE
+SOLVATED
+4 2 2
+SMALL
+USER_CHARGES
+
+@<TRIPOS>ATOM
+      1 O1          0.0000    0.0000    0.0000 O.3       1  HOH1      -0.8340
+      2 H1          0.9570    0.0000    0.0000 H         1  HOH1       0.4170
+      3 H2         -0.2400    0.9270    0.0000 H         1  HOH1       0.4170
+      4 NA          3.0000    0.0000    0.0000 Na        2  NA2        1.0000
+@<TRIPOS>BOND
+     1     1     2 1
+     2     1     3 1
+@<TRIPOS>SUBSTRUCTURE
+     1 HOH1        1 GROUP             0 ****  ****    0 ROOT
+     2 NA2         4 GROUP             0 ****  ****    0 ROOT
+";
+
+fn from_text(text: &str) -> Mol2 {
+    // First structure of a mol2 file with the given content
+    let path = std::env::temp_dir().join(format!("serde-mol2-{}-edited.mol2", std::process::id()));
+    std::fs::write(&path, text).unwrap();
+    let (mut structures, _) = read_file(path.to_str().unwrap(), &ReadOptions::default()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    structures.remove(0)
+}
+
+fn solvated() -> Mol2 {
+    from_text(SOLVATED)
+}
+
+fn atom_ids(mol2: &Mol2) -> Vec<(u16, String)> {
+    // Ids and names of all atoms in file order
+    mol2.atoms()
+        .iter()
+        .map(|atom| (atom.atom_id, atom.atom_name.clone()))
+        .collect()
+}
+
+fn positions(mol2: &Mol2) -> Vec<[f64; 3]> {
+    // Coordinates of all atoms in file order, rounded to the precision of the file
+    mol2.atoms()
+        .iter()
+        .map(|atom| [atom.x, atom.y, atom.z].map(|value| (value * 1e4).round() / 1e4 + 0.0))
+        .collect()
+}
+
+fn counts(mol2: &Mol2) -> (Option<usize>, Option<usize>, Option<usize>) {
+    // Numbers of atoms, bonds and substructures in the MOLECULE section
+    let molecule = mol2.molecule().unwrap();
+    (molecule.num_atoms, molecule.num_bonds, molecule.num_subst)
+}
+
+#[test]
+fn removing_atoms_fixes_up_bonds_ids_and_counts() {
+    let mut mol2 = solvated();
+    mol2.remove_atoms(vec![2]).unwrap();
+    assert_eq!(
+        atom_ids(&mol2),
+        [
+            (1, "O1".to_owned()),
+            (2, "H2".to_owned()),
+            (3, "NA".to_owned())
+        ]
+    );
+    let bonds: Vec<(u16, u16, u16)> = mol2
+        .bonds()
+        .iter()
+        .map(|bond| (bond.bond_id, bond.origin_atom_id, bond.target_atom_id))
+        .collect();
+    assert_eq!(bonds, [(1, 1, 2)]);
+    assert_eq!(mol2.substructures()[1].root_atom, 3);
+    assert_eq!(counts(&mol2), (Some(3), Some(1), Some(2)));
+
+    // Substructures without atoms left are removed, roots move to the first atom left
+    mol2.remove_atoms(vec![1, 3]).unwrap();
+    assert_eq!(atom_ids(&mol2), [(1, "H2".to_owned())]);
+    assert!(mol2.bonds().is_empty());
+    assert_eq!(mol2.substructures().len(), 1);
+    assert_eq!(mol2.substructures()[0].root_atom, 1);
+    assert_eq!(counts(&mol2), (Some(1), Some(0), Some(1)));
+
+    // Unknown ids leave the structure as it was
+    assert!(mol2.remove_atoms(vec![1, 7]).is_err());
+    assert_eq!(atom_ids(&mol2), [(1, "H2".to_owned())]);
+}
+
+#[test]
+fn normalize_reports_and_fixes_counts_ids_and_names() {
+    let messy = SOLVATED
+        .replace("SOLVATED\n4 2 2", " SOLVATED \n5 3")
+        .replace("      1 O1", "     10 O1")
+        .replace("     1     1     2 1", "     1    10     2 1")
+        .replace("     2     1     3 1", "     2    10     3 1")
+        .replace("HOH1        1 GROUP", "HOH1       10 GROUP");
+    let mut mol2 = from_text(&messy);
+    let changes = mol2.normalize(true, true, true);
+    assert_eq!(
+        changes,
+        [
+            "trimmed molecule name",
+            "renumbered 1 atoms",
+            "num_atoms changed from 5 to 4",
+            "num_bonds changed from 3 to 2",
+            "num_subst set to 2",
+        ]
+    );
+    assert_eq!(mol2.molecule().unwrap().mol_name, "SOLVATED");
+    assert_eq!(mol2.bonds()[0].origin_atom_id, 1);
+    assert_eq!(mol2.substructures()[0].root_atom, 1);
+    assert_eq!(counts(&mol2), (Some(4), Some(2), Some(2)));
+    // A clean structure is left as it is
+    assert!(mol2.normalize(true, true, true).is_empty());
+    assert!(solvated().normalize(true, true, true).is_empty());
+}
+
+#[test]
+fn transforms_move_all_atoms() {
+    let mut mol2 = solvated();
+    mol2.translate(1.0, -2.0, 0.5);
+    assert_eq!(positions(&mol2)[3], [4.0, -2.0, 0.5]);
+    mol2.translate(-1.0, 2.0, -0.5);
+    assert_eq!(positions(&mol2), positions(&solvated()));
+
+    // A quarter turn around z takes x to y
+    mol2.rotate([0.0, 0.0, 2.0], std::f64::consts::FRAC_PI_2)
+        .unwrap();
+    assert_eq!(positions(&mol2)[1], [0.0, 0.957, 0.0]);
+    assert_eq!(positions(&mol2)[3], [0.0, 3.0, 0.0]);
+    assert!(mol2.rotate([0.0; 3], 1.0).is_err());
+
+    // The same quarter turn back followed by a shift as one matrix
+    mol2.transform([
+        [0.0, 1.0, 0.0, 1.0],
+        [-1.0, 0.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ])
+    .unwrap();
+    assert_eq!(positions(&mol2)[3], [4.0, 0.0, 0.0]);
+    assert!(mol2.transform([[1.0; 4]; 4]).is_err());
+}
+
+#[test]
+fn filtering_atoms_keeps_the_bonds_between_them() {
+    let mol2 = solvated();
+    let heavy = mol2.filter_atoms(|atom| atom.atom_type != "H");
+    assert_eq!(
+        atom_ids(&heavy),
+        [(1, "O1".to_owned()), (2, "NA".to_owned())]
+    );
+    assert!(heavy.bonds().is_empty());
+    assert_eq!(heavy.substructures()[1].root_atom, 2);
+    assert_eq!(counts(&heavy), (Some(2), Some(0), Some(2)));
+
+    let water = mol2.filter_atoms(|atom| atom.subst_name.as_deref() == Some("HOH1"));
+    assert_eq!(water.bonds().len(), 2);
+    assert_eq!(water.substructures().len(), 1);
+    assert_eq!(counts(&water), (Some(3), Some(2), Some(1)));
+    // The structure filtered is left as it was
+    assert_eq!(atom_ids(&mol2).len(), 4);
+}
+
+fn pose(shift: f64, comment: &str) -> Mol2 {
+    // SOLVATED moved along x with a score in the comment
+    let mut mol2 = from_text(&SOLVATED.replace(
+        "USER_CHARGES\n\n",
+        &format!("USER_CHARGES\n\n{}\n", comment),
+    ));
+    mol2.translate(shift, 0.0, 0.0);
+    mol2
+}
+
+#[test]
+fn conformers_collapse_and_expand() {
+    let poses = vec![pose(0.0, "score -7.1"), pose(1.0, "score -6.5")];
+    let mut other = pose(2.0, "score -6.0");
+    other.remove_atoms(vec![4]).unwrap();
+    let conformers = collapse_conformers(vec![poses[0].clone(), other.clone(), poses[1].clone()]);
+    assert_eq!(conformers.len(), 2);
+    // Frames come back with their own coordinates and comments
+    let expanded = conformers[0].expand();
+    assert_eq!(expanded.len(), 2);
+    for (frame, pose) in expanded.iter().zip(&poses) {
+        assert_eq!(frame.to_json(), pose.to_json());
+    }
+    assert_eq!(conformers[1].expand()[0].to_json(), other.to_json());
+
+    let mut built = Conformers::new(poses[0].clone());
+    built.push(&poses[1]).unwrap();
+    assert!(built.push(&other).is_err());
+    assert_eq!(built.get(1).unwrap().to_json(), poses[1].to_json());
+    assert!(built.get(2).is_none());
+}
+
+#[test]
+fn diff_reports_changes_beyond_the_tolerances() {
+    let changed = from_text(
+        &SOLVATED
+            .replace(
+                "0.0000    0.0000    0.0000 O.3",
+                "0.0005    0.0000    0.5000 O.3",
+            )
+            .replace(
+                "H1          0.9570    0.0000    0.0000 H         1  HOH1       0.4170",
+                "H1          0.9570    0.0000    0.0000 H         1  HOH1       0.5170",
+            )
+            .replace("4 2 2", "4 1 2")
+            .replace("     2     1     3 1\n", ""),
+    );
+    let differences = solvated().diff(&changed, 1e-3, 1e-3);
+    let shown: Vec<String> = differences
+        .iter()
+        .map(|difference| difference.to_string())
+        .collect();
+    // The shift of x by 0.0005 is within the tolerance
+    assert_eq!(
+        shown,
+        [
+            "MOLECULE num_bonds: 2 -> 1",
+            "ATOM 1 z: 0 -> 0.5",
+            "ATOM 2 charge: 0.417 -> 0.517",
+            "BOND 2 removed: 1-3 1",
+        ]
+    );
+    assert_eq!(differences[1].delta, Some(0.5));
+    assert_eq!(solvated().diff(&changed, 1.0, 1.0).len(), 2);
+    assert!(solvated().diff(&solvated(), 0.0, 0.0).is_empty());
+}
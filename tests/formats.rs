@@ -0,0 +1,378 @@
+// Copyright (C) 2022 CSC - IT Center for Science Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Round trips through the other file formats and structures built from rust
+
+use serde_mol2::{
+    mol2_json_schema, read_file, read_pdb, read_sdf, read_xyz, write_ndjson, write_pdb, write_sdf,
+    write_xyz, Mol2, Mol2Builder, ReadOptions,
+};
+
+const WATER: &str = "@<TRIPOS>MOLECULE
+WATER
+3 2 1
+SMALL
+USER_CHARGES
+
+@<TRIPOS>ATOM
+      1 O1          0.0000    0.0000    0.0000 O.3       1  HOH1      -0.8340
+      2 H1          0.9570    0.0000    0.0000 H         1  HOH1       0.4170
+      3 H2         -0.2400    0.9270    0.0000 H         1  HOH1       0.4170
+@<TRIPOS>BOND
+     1     1     2 1
+     2     1     3 1
+@<TRIPOS>SUBSTRUCTURE
+     1 HOH1        1 GROUP             0 ****  ****    0 ROOT
+";
+
+fn scratch(name: &str) -> String {
+    // Path for a file of a test, removed if left over from an earlier run
+    let path = std::env::temp_dir().join(format!("serde-mol2-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    path.to_str().unwrap().to_owned()
+}
+
+fn read_text(name: &str, text: &str) -> Vec<Mol2> {
+    // Structures of a mol2 file with the given content
+    let path = scratch(name);
+    std::fs::write(&path, text).unwrap();
+    let (structures, _) = read_file(&path, &ReadOptions::default()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    structures
+}
+
+fn water() -> Mol2 {
+    read_text("water.mol2", WATER).remove(0)
+}
+
+fn chain(length: usize) -> String {
+    // Mol2 text of a straight chain of carbons
+    let mut text = format!(
+        "@<TRIPOS>MOLECULE\nCHAIN\n{} {}\nSMALL\nNO_CHARGES\n\n@<TRIPOS>ATOM\n",
+        length,
+        length - 1
+    );
+    for id in 1..=length {
+        text.push_str(&format!(
+            "{} C{} {:.4} 0.0 0.0 C.3\n",
+            id,
+            id,
+            1.5 * id as f64
+        ));
+    }
+    text.push_str("@<TRIPOS>BOND\n");
+    for id in 1..length {
+        text.push_str(&format!("{} {} {} 1\n", id, id, id + 1));
+    }
+    text
+}
+
+fn positions(mol2: &Mol2) -> Vec<[f64; 3]> {
+    // Coordinates of all atoms in file order
+    mol2.atoms()
+        .iter()
+        .map(|atom| [atom.x, atom.y, atom.z])
+        .collect()
+}
+
+fn bonds(mol2: &Mol2) -> Vec<(u16, u16, String)> {
+    // Atoms and type of every bond in file order
+    mol2.bonds()
+        .iter()
+        .map(|bond| {
+            (
+                bond.origin_atom_id,
+                bond.target_atom_id,
+                bond.bond_type.clone(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn sdf_export_writes_a_v2000_molfile() {
+    let sdf = water().to_sdf().unwrap();
+    let lines: Vec<&str> = sdf.lines().collect();
+    assert_eq!(lines[0], "WATER");
+    assert!(lines[3].starts_with("  3  2"));
+    assert!(lines[3].ends_with("V2000"));
+    assert!(lines[4].starts_with("    0.0000    0.0000    0.0000 O "));
+    assert_eq!(lines[7], "  1  2  1  0");
+    assert!(lines.contains(&"M  END"));
+    assert_eq!(lines.last(), Some(&"$$$$"));
+}
+
+#[test]
+fn sdf_round_trip_keeps_atoms_and_bonds() {
+    let path = scratch("water.sdf");
+    write_sdf(vec![water()], &path, false, None).unwrap();
+    let read = read_sdf(&path, "", "").unwrap();
+    assert_eq!(read.len(), 1);
+    let types: Vec<&str> = read[0]
+        .atoms()
+        .iter()
+        .map(|atom| atom.atom_type.as_str())
+        .collect();
+    assert_eq!(types, ["O.3", "H", "H"]);
+    assert_eq!(positions(&read[0]), positions(&water()));
+    assert_eq!(bonds(&read[0]), bonds(&water()));
+    // Writing the structure that was read gives the same file again
+    assert_eq!(read[0].to_sdf().unwrap(), water().to_sdf().unwrap());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn large_structures_round_trip_through_v3000_molfiles() {
+    let large = read_text("chain.mol2", &chain(1000)).remove(0);
+    let sdf = large.to_sdf().unwrap();
+    assert!(sdf.lines().nth(3).unwrap().ends_with("V3000"));
+    assert!(sdf.contains("M  V30 COUNTS 1000 999 0 0 0"));
+    let path = scratch("chain.sdf");
+    write_sdf(vec![large.clone()], &path, false, None).unwrap();
+    let read = read_sdf(&path, "", "").unwrap();
+    assert_eq!(read[0].atoms().len(), 1000);
+    assert_eq!(positions(&read[0]), positions(&large));
+    assert_eq!(bonds(&read[0]), bonds(&large));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn pdb_export_writes_hetatm_and_conect_records() {
+    let pdb = water().to_pdb().unwrap();
+    let lines: Vec<&str> = pdb.lines().collect();
+    assert_eq!(lines[0], "COMPND    WATER");
+    assert_eq!(
+        lines[1],
+        "HETATM    1  O1  HOH     1       0.000   0.000   0.000  1.00  0.00           O"
+    );
+    assert_eq!(lines[4], "CONECT    1    2    3");
+    assert_eq!(lines.last(), Some(&"END"));
+}
+
+#[test]
+fn pdb_round_trip_reads_bonds_from_conect_records() {
+    let path = scratch("water.pdb");
+    write_pdb(vec![water()], &path, false, None).unwrap();
+    let read = read_pdb(&path, "", "", true).unwrap();
+    assert_eq!(read.len(), 1);
+    assert_eq!(positions(&read[0]), positions(&water()));
+    assert_eq!(bonds(&read[0]), bonds(&water()));
+    assert_eq!(read[0].to_pdb().unwrap(), water().to_pdb().unwrap());
+    // Without CONECT records the structure has no bonds
+    let unbonded = read_pdb(&path, "", "", false).unwrap();
+    assert!(unbonded[0].bonds().is_empty());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn xyz_round_trip_keeps_elements_and_coordinates() {
+    let path = scratch("water.xyz");
+    write_xyz(vec![water(), water()], &path, false, None).unwrap();
+    let read = read_xyz(&path, "", "").unwrap();
+    assert_eq!(read.len(), 2);
+    for frame in &read {
+        assert_eq!(frame.to_xyz().unwrap(), water().to_xyz().unwrap());
+        assert_eq!(positions(frame), positions(&water()));
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn pdbqt_export_writes_charges_and_autodock_types() {
+    let pdbqt = water().to_pdbqt(None).unwrap();
+    let lines: Vec<&str> = pdbqt.lines().collect();
+    assert_eq!(lines[0], "REMARK  Name = WATER");
+    assert!(lines[1].ends_with("    -0.834 OA"));
+    // Hydrogens bonded to oxygen are polar
+    assert!(lines[2].ends_with("    +0.417 HD"));
+    let type_map = [("H".to_owned(), "H".to_owned())].into_iter().collect();
+    let mapped = water().to_pdbqt(Some(type_map)).unwrap();
+    assert!(mapped.lines().nth(2).unwrap().ends_with("    +0.417 H "));
+}
+
+#[test]
+fn json_round_trip_keeps_the_structure() {
+    let json = water().to_json();
+    let read = Mol2::from_json(&json).unwrap();
+    assert_eq!(read.to_json(), json);
+    assert_eq!(read.to_sdf().unwrap(), water().to_sdf().unwrap());
+    assert!(Mol2::from_json("{\"atom\": 1}").is_err());
+}
+
+#[test]
+fn ndjson_has_one_structure_per_line() {
+    let path = scratch("water.ndjson");
+    write_ndjson(vec![water(), water()], &path, false, None).unwrap();
+    write_ndjson(vec![water()], &path, true, None).unwrap();
+    let text = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(text.lines().count(), 3);
+    for line in text.lines() {
+        assert_eq!(Mol2::from_json(line).unwrap().to_json(), water().to_json());
+    }
+    let pretty = water().to_json_pretty();
+    assert!(pretty.lines().count() > 1);
+    assert_eq!(
+        Mol2::from_json(&pretty).unwrap().to_json(),
+        water().to_json()
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "arrow")]
+#[test]
+fn arrow_tables_have_a_row_per_record() {
+    let tables = serde_mol2::to_arrow(&[water(), water()]).unwrap();
+    assert_eq!(tables.molecules.num_rows(), 2);
+    assert_eq!(tables.atoms.num_rows(), 6);
+    assert_eq!(tables.bonds.num_rows(), 4);
+    for column in ["molecule", "atom_id", "x", "y", "z", "atom_type", "charge"] {
+        assert!(tables.atoms.column_by_name(column).is_some(), "{}", column);
+    }
+}
+
+#[cfg(feature = "hdf5")]
+#[test]
+fn hdf5_file_has_a_group_per_structure() {
+    use hdf5::types::VarLenUnicode;
+    let path = scratch("water.h5");
+    serde_mol2::write_hdf5(vec![water(), water()], &path).unwrap();
+    let file = hdf5::File::open(&path).unwrap();
+    assert_eq!(file.member_names().unwrap(), ["molecule_0", "molecule_1"]);
+    let group = file.group("molecule_1").unwrap();
+    let mol_name: VarLenUnicode = group.attr("mol_name").unwrap().read_scalar().unwrap();
+    assert_eq!(mol_name.as_str(), "WATER");
+    let coordinates: Vec<[f64; 3]> = group.dataset("coordinates").unwrap().read_raw().unwrap();
+    assert_eq!(coordinates, positions(&water()));
+    let bonds: Vec<[u16; 2]> = group.dataset("bonds").unwrap().read_raw().unwrap();
+    assert_eq!(bonds, [[1, 2], [1, 3]]);
+    file.close().unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "npz")]
+#[test]
+fn npz_archive_has_arrays_of_each_structure() {
+    use std::io::Read;
+    let path = scratch("water.npz");
+    serde_mol2::write_npz(vec![water(), water()], &path).unwrap();
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(&path).unwrap()).unwrap();
+    assert_eq!(archive.len(), 6);
+    let mut npy = Vec::new();
+    archive
+        .by_name("molecule_1/coordinates.npy")
+        .unwrap()
+        .read_to_end(&mut npy)
+        .unwrap();
+    assert!(npy.starts_with(b"\x93NUMPY"));
+    let coordinates: Vec<u8> = positions(&water())
+        .into_iter()
+        .flatten()
+        .flat_map(f64::to_le_bytes)
+        .collect();
+    assert!(npy.ends_with(&coordinates));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn binary_records_round_trip() {
+    for compression in [0, 3] {
+        let bytes = water().to_bytes(compression).unwrap();
+        let read = Mol2::from_bytes(&bytes).unwrap();
+        assert_eq!(read.to_json(), water().to_json());
+    }
+    assert!(Mol2::from_bytes(b"not a record").is_err());
+}
+
+#[test]
+fn json_schema_describes_the_json_form() {
+    let schema: serde_json::Value = serde_json::from_str(&mol2_json_schema()).unwrap();
+    assert_eq!(schema["title"], "Mol2");
+    for field in ["molecule", "atom", "bond", "substructure", "desc"] {
+        assert!(schema["properties"].get(field).is_some(), "{}", field);
+    }
+    // Every top level field of the JSON form is described
+    let json: serde_json::Value = serde_json::from_str(&water().to_json()).unwrap();
+    for field in json.as_object().unwrap().keys() {
+        assert!(schema["properties"].get(field).is_some(), "{}", field);
+    }
+}
+
+#[test]
+fn builder_gives_the_structure_of_a_file() {
+    let built = Mol2Builder::new()
+        .name("WATER")
+        .atom(1, "O1", "O.3", [0.0, 0.0, 0.0])
+        .with_charge(-0.834)
+        .in_substructure(1, "HOH1")
+        .atom(2, "H1", "H", [0.957, 0.0, 0.0])
+        .with_charge(0.417)
+        .in_substructure(1, "HOH1")
+        .atom(3, "H2", "H", [-0.24, 0.927, 0.0])
+        .with_charge(0.417)
+        .in_substructure(1, "HOH1")
+        .bond(1, 2, "1")
+        .bond(1, 3, "1")
+        .substructure(1, "HOH1", 1, "GROUP")
+        .build()
+        .unwrap();
+    assert_eq!(
+        built.to_pdbqt(None).unwrap(),
+        water().to_pdbqt(None).unwrap()
+    );
+    assert_eq!(bonds(&built), bonds(&water()));
+    // Bonds to missing atoms are reported by build
+    let dangling = Mol2Builder::new()
+        .name("DANGLING")
+        .atom(1, "C1", "C.3", [0.0, 0.0, 0.0])
+        .bond(1, 2, "1")
+        .build();
+    assert!(dangling.is_err());
+}
+
+#[test]
+fn arrays_give_the_structure_of_a_file() {
+    let built = Mol2::from_arrays(
+        positions(&water()).iter().map(|xyz| xyz.to_vec()).collect(),
+        vec!["O.3".to_owned(), "H".to_owned(), "H".to_owned()],
+        vec!["O1".to_owned(), "H1".to_owned(), "H2".to_owned()],
+        vec![vec![0, 1, 1], vec![0, 2, 1]],
+        Some(vec![-0.834, 0.417, 0.417]),
+        "WATER",
+    )
+    .unwrap();
+    assert_eq!(positions(&built), positions(&water()));
+    assert_eq!(bonds(&built), bonds(&water()));
+    assert_eq!(built.to_sdf().unwrap(), water().to_sdf().unwrap());
+    // Bonds without an order are unknown and indices must be in range
+    let unknown = Mol2::from_arrays(
+        vec![vec![0.0; 3], vec![1.5, 0.0, 0.0]],
+        vec!["C.3".to_owned(); 2],
+        vec!["C1".to_owned(), "C2".to_owned()],
+        vec![vec![0, 1]],
+        None,
+        "ETHANE",
+    )
+    .unwrap();
+    assert_eq!(unknown.bonds()[0].bond_type, "un");
+    let out_of_range = Mol2::from_arrays(
+        vec![vec![0.0; 3]],
+        vec!["C.3".to_owned()],
+        vec!["C1".to_owned()],
+        vec![vec![0, 1]],
+        None,
+        "METHANE",
+    );
+    assert!(out_of_range.is_err());
+}